@@ -0,0 +1,40 @@
+//! cargo bench 入口，与bench子命令运行相同的基准测试
+//! 默认只运行不需要容器的compare与manifest，可以通过参数指定:
+//! cargo bench -- startup watcher compare manifest
+
+use anyhow::anyhow;
+use hellojudge3_judger::{
+    bench::{run_benchmarks, BENCHMARKS},
+    core::{config::JudgerConfig, misc::ResultType, runner::create_runner},
+};
+
+const ROUNDS: usize = 5;
+const DEFAULT_BENCHMARKS: &[&str] = &["compare", "manifest"];
+
+/// 当前目录下存在config.yaml时使用其中的配置(如docker_image、work_dir_root)
+fn load_config() -> ResultType<JudgerConfig> {
+    return match std::fs::read_to_string("config.yaml") {
+        Ok(content) => JudgerConfig::parse(&content)
+            .map_err(|e| anyhow!("Failed to deserialize configure file: {}", e)),
+        Err(_) => Ok(JudgerConfig::default()),
+    };
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> ResultType<()> {
+    // cargo bench会传入--bench等参数，只保留基准测试名
+    let mut only = std::env::args()
+        .skip(1)
+        .filter(|v| BENCHMARKS.contains(&v.as_str()))
+        .collect::<Vec<String>>();
+    if only.is_empty() {
+        only = DEFAULT_BENCHMARKS.iter().map(|v| v.to_string()).collect();
+    }
+    let mut config = load_config()?;
+    // 此处的当前可执行文件是基准测试本身，比较子进程需要使用评测机
+    if config.compare_helper_path.is_empty() {
+        config.compare_helper_path = env!("CARGO_BIN_EXE_hellojudge3-judger").to_string();
+    }
+    let runner = create_runner(&config)?;
+    return run_benchmarks(&config, &*runner, ROUNDS, &only).await;
+}
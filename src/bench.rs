@@ -0,0 +1,255 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::{
+    core::{
+        compare::builtin_comparator,
+        config::JudgerConfig,
+        misc::ResultType,
+        runner::{ExecuteOptions, Runner, TimelineOptions},
+        workdir::create_work_dir,
+    },
+    task::local::sync_state::migrate_legacy_sync_state,
+};
+
+pub const BENCHMARKS: &[&str] = &["startup", "watcher", "compare", "manifest"];
+// 比较器测试使用的输出大小
+const COMPARE_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
+// 同步记录测试使用的文件数与单个文件大小
+const MANIFEST_FILE_COUNT: usize = 200;
+const MANIFEST_FILE_SIZE: usize = 256 * 1024;
+// 监视开销测试运行的程序，只消耗CPU
+const BUSY_LOOP_COMMAND: &str = "i=0; while [ $i -lt 300000 ]; do i=$((i+1)); done";
+
+/// 多轮测量结果的统计
+struct BenchStats {
+    name: String,
+    samples: Vec<Duration>,
+}
+impl BenchStats {
+    fn new(name: &str) -> Self {
+        return Self {
+            name: name.to_string(),
+            samples: vec![],
+        };
+    }
+    fn median(&self) -> Duration {
+        let mut samples = self.samples.clone();
+        samples.sort();
+        return samples.get(samples.len() / 2).cloned().unwrap_or_default();
+    }
+    fn print(&mut self, extra: &str) {
+        if self.samples.is_empty() {
+            return;
+        }
+        self.samples.sort();
+        let ms = |v: &Duration| v.as_secs_f64() * 1000.0;
+        println!(
+            "{:<32} min {:>10.3} ms  median {:>10.3} ms  max {:>10.3} ms  {}",
+            self.name,
+            ms(&self.samples[0]),
+            ms(&self.samples[self.samples.len() / 2]),
+            ms(self.samples.last().unwrap()),
+            extra
+        );
+    }
+}
+
+/// 固定种子的伪随机数据，使每次测试的输入相同
+fn generate_lines(size: usize) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(size + 32);
+    let mut x: u64 = 1;
+    while ret.len() < size {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ret.extend_from_slice(format!("{} {}\n", x >> 40, (x >> 16) & 0xffff).as_bytes());
+    }
+    return ret;
+}
+
+/// 容器启动: 运行空命令，记录从创建容器到取得结果的总时间
+async fn bench_startup(
+    config: &JudgerConfig,
+    runner: &dyn Runner,
+    rounds: usize,
+) -> ResultType<()> {
+    let working_dir = create_work_dir(config)
+        .map_err(|e| anyhow!("Failed to create benchmark directory: {}", e))?;
+    let working_path = working_dir.path().to_str().ok_or(anyhow!("?"))?;
+    let mut total = BenchStats::new("startup/total");
+    let mut overhead = BenchStats::new("startup/overhead");
+    for _ in 0..rounds {
+        let begin = Instant::now();
+        let result = runner
+            .execute(
+                &config.docker_image,
+                working_path,
+                &vec!["true".to_string()],
+                256 * 1024 * 1024,
+                10 * 1000 * 1000,
+                1024,
+                &ExecuteOptions::default(),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run benchmark: {}", e))?;
+        total.samples.push(begin.elapsed());
+        overhead
+            .samples
+            .push(Duration::from_micros(result.startup_overhead as u64));
+    }
+    total.print("");
+    overhead.print("");
+    return Ok(());
+}
+
+/// 监视开销: 同一个程序在不同的监视选项下的运行时间
+async fn bench_watcher(
+    config: &JudgerConfig,
+    runner: &dyn Runner,
+    rounds: usize,
+) -> ResultType<()> {
+    let working_dir = create_work_dir(config)
+        .map_err(|e| anyhow!("Failed to create benchmark directory: {}", e))?;
+    let working_path = working_dir.path().to_str().ok_or(anyhow!("?"))?;
+    let command = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        BUSY_LOOP_COMMAND.to_string(),
+    ];
+    let modes = [
+        ("watcher/plain", ExecuteOptions::default()),
+        (
+            "watcher/detect_suspicious",
+            ExecuteOptions {
+                detect_suspicious: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "watcher/timeline_10ms",
+            ExecuteOptions {
+                timeline: Some(TimelineOptions {
+                    interval: 10,
+                    max_samples: 1000,
+                }),
+                ..Default::default()
+            },
+        ),
+    ];
+    for (name, options) in modes.iter() {
+        let mut time = BenchStats::new(&format!("{}/time", name));
+        let mut cpu = BenchStats::new(&format!("{}/cpu", name));
+        for _ in 0..rounds {
+            let result = runner
+                .execute(
+                    &config.docker_image,
+                    working_path,
+                    &command,
+                    256 * 1024 * 1024,
+                    60 * 1000 * 1000,
+                    1024,
+                    options,
+                )
+                .await
+                .map_err(|e| anyhow!("Failed to run benchmark: {}", e))?;
+            time.samples
+                .push(Duration::from_micros(result.time_cost as u64));
+            cpu.samples
+                .push(Duration::from_micros(result.cpu_time_cost as u64));
+        }
+        time.print("");
+        cpu.print("");
+    }
+    return Ok(());
+}
+
+/// 比较器吞吐量: 比较两份相同的大输出(最坏情况，需要比较全部内容)
+async fn bench_compare(config: &JudgerConfig, rounds: usize) -> ResultType<()> {
+    let data = Arc::new(generate_lines(COMPARE_OUTPUT_SIZE));
+    let size_mb = data.len() as f64 / 1024.0 / 1024.0;
+    for name in ["line", "token"] {
+        let comparator = builtin_comparator(config, name, false)?;
+        let mut stats = BenchStats::new(&format!("compare/{}", name));
+        for _ in 0..rounds {
+            let begin = Instant::now();
+            let result = comparator
+                .compare(data.clone(), data.clone(), Arc::new(vec![]), 100, None)
+                .await?;
+            stats.samples.push(begin.elapsed());
+            if result.score != 100 {
+                return Err(anyhow!("Unexpected compare result: {}", result.message));
+            }
+        }
+        let throughput = size_mb / stats.median().as_secs_f64();
+        stats.print(&format!("{:.1} MB/s", throughput));
+    }
+    return Ok(());
+}
+
+/// 同步记录: 将旧版本的lock文件转换为同步记录，主要开销为计算sha256
+async fn bench_manifest(config: &JudgerConfig, rounds: usize) -> ResultType<()> {
+    let content = generate_lines(MANIFEST_FILE_SIZE);
+    let mut stats = BenchStats::new("manifest/migrate");
+    for _ in 0..rounds {
+        let data_dir = create_work_dir(config)
+            .map_err(|e| anyhow!("Failed to create benchmark directory: {}", e))?;
+        let problem_dir = data_dir.path().join("1");
+        tokio::fs::create_dir(&problem_dir)
+            .await
+            .map_err(|e| anyhow!("Failed to create directory: {}", e))?;
+        for i in 0..MANIFEST_FILE_COUNT {
+            tokio::fs::write(problem_dir.join(format!("{}.in", i)), &content)
+                .await
+                .map_err(|e| anyhow!("Failed to write file: {}", e))?;
+            tokio::fs::write(problem_dir.join(format!("{}.in.lock", i)), "1")
+                .await
+                .map_err(|e| anyhow!("Failed to write file: {}", e))?;
+        }
+        let begin = Instant::now();
+        let summary = migrate_legacy_sync_state(data_dir.path()).await?;
+        stats.samples.push(begin.elapsed());
+        if summary.migrated != MANIFEST_FILE_COUNT {
+            return Err(anyhow!("Unexpected migrate result: {:?}", summary));
+        }
+    }
+    stats.print(&format!(
+        "{} files, {} KB each",
+        MANIFEST_FILE_COUNT,
+        MANIFEST_FILE_SIZE / 1024
+    ));
+    return Ok(());
+}
+
+/// 运行only中的基准测试，only为空时运行全部
+pub async fn run_benchmarks(
+    config: &JudgerConfig,
+    runner: &dyn Runner,
+    rounds: usize,
+    only: &[String],
+) -> ResultType<()> {
+    for name in only.iter() {
+        if !BENCHMARKS.contains(&name.as_str()) {
+            return Err(anyhow!("Unknown benchmark: {}", name));
+        }
+    }
+    for name in BENCHMARKS.iter() {
+        if !only.is_empty() && !only.iter().any(|v| v == name) {
+            continue;
+        }
+        info!("Running benchmark: {}", name);
+        match *name {
+            "startup" => bench_startup(config, runner, rounds).await?,
+            "watcher" => bench_watcher(config, runner, rounds).await?,
+            "compare" => bench_compare(config, rounds).await?,
+            "manifest" => bench_manifest(config, rounds).await?,
+            _ => unreachable!(),
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,621 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::{config::JudgerConfig, misc::ResultType, model::LanguageConfig, upload::sha256_hex};
+
+/// 调用服务端API时的错误
+#[derive(Debug)]
+pub enum ApiError {
+    // 请求未能发出或响应未能完整读取
+    Network(String),
+    // 响应不是预期的格式
+    Decode(String),
+    // 服务端返回的code不为0，或HTTP状态码表示失败
+    Server { code: i64, message: String },
+}
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Network(e) => write!(f, "Network error: {}", e),
+            ApiError::Decode(e) => write!(f, "Invalid response: {}", e),
+            ApiError::Server { code, message } => {
+                write!(f, "Server responded error {}: {}", code, message)
+            }
+        }
+    }
+}
+impl std::error::Error for ApiError {}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// 一次API请求，body不为None时以body为请求体，此时参数放在query中
+#[derive(Debug, Clone, Default)]
+pub struct ApiRequest {
+    // 不含api_prefix的路径，如judge/update
+    pub endpoint: String,
+    pub params: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+    // 不为0时使用Range请求从该位置开始下载
+    pub range_start: u64,
+    // 为true时endpoint是完整的外部地址(如webhook)，不拼接web_api_url
+    pub external: bool,
+    pub headers: Vec<(String, String)>,
+}
+impl ApiRequest {
+    pub fn new(endpoint: &str) -> Self {
+        return Self {
+            endpoint: endpoint.trim_start_matches('/').to_string(),
+            ..Default::default()
+        };
+    }
+    /// 发往外部地址的请求，不会带上评测机的uuid
+    pub fn external(url: &str) -> Self {
+        return Self {
+            endpoint: url.to_string(),
+            external: true,
+            ..Default::default()
+        };
+    }
+    pub fn param(mut self, key: &str, value: impl ToString) -> Self {
+        self.params.push((key.to_string(), value.to_string()));
+        return self;
+    }
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Some(body);
+        return self;
+    }
+    pub fn range_start(mut self, offset: u64) -> Self {
+        self.range_start = offset;
+        return self;
+    }
+    pub fn header(mut self, key: &str, value: impl ToString) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        return self;
+    }
+}
+
+/// 响应内容，可以分块读取
+#[async_trait]
+pub trait ApiBody: Send {
+    fn status(&self) -> u16;
+    /// 读取下一块内容，读完时返回None
+    async fn chunk(&mut self) -> ApiResult<Option<Vec<u8>>>;
+}
+
+/// 发送请求的方式，测试时可替换为MockTransport
+#[async_trait]
+pub trait ApiTransport: Send + Sync {
+    async fn send(&self, request: ApiRequest) -> ApiResult<Box<dyn ApiBody>>;
+}
+
+struct HttpBody {
+    response: reqwest::Response,
+}
+#[async_trait]
+impl ApiBody for HttpBody {
+    fn status(&self) -> u16 {
+        return self.response.status().as_u16();
+    }
+    async fn chunk(&mut self) -> ApiResult<Option<Vec<u8>>> {
+        return self
+            .response
+            .chunk()
+            .await
+            .map(|v| v.map(|v| v.to_vec()))
+            .map_err(|e| ApiError::Network(format!("Failed to read response: {}", e)));
+    }
+}
+
+/// 通过HTTP访问web_api_url
+pub struct HttpTransport {
+    http: reqwest::Client,
+    // web_api_url与api_prefix拼接后的地址
+    base_url: String,
+}
+impl HttpTransport {
+    /// 请求体为空时参数以表单编码发送，否则放在query中
+    fn build(&self, request: ApiRequest) -> reqwest::RequestBuilder {
+        let url = if request.external {
+            request.endpoint.clone()
+        } else {
+            format!(
+                "{}/{}",
+                self.base_url.trim_end_matches('/'),
+                request.endpoint
+            )
+        };
+        let mut builder = self.http.post(url);
+        builder = match request.body {
+            Some(body) => builder.query(&request.params).body(body),
+            None => builder.form(&request.params),
+        };
+        for (key, value) in request.headers.iter() {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+        if request.range_start > 0 {
+            builder = builder.header(
+                reqwest::header::RANGE,
+                format!("bytes={}-", request.range_start),
+            );
+        }
+        return builder;
+    }
+}
+#[async_trait]
+impl ApiTransport for HttpTransport {
+    async fn send(&self, request: ApiRequest) -> ApiResult<Box<dyn ApiBody>> {
+        let endpoint = request.endpoint.clone();
+        let response = self.build(request).send().await.map_err(|e| {
+            ApiError::Network(format!("Failed to send request to {}: {}", endpoint, e))
+        })?;
+        return Ok(Box::new(HttpBody { response }));
+    }
+}
+
+struct MockBody {
+    status: u16,
+    body: Option<Vec<u8>>,
+}
+#[async_trait]
+impl ApiBody for MockBody {
+    fn status(&self) -> u16 {
+        return self.status;
+    }
+    async fn chunk(&mut self) -> ApiResult<Option<Vec<u8>>> {
+        return Ok(self.body.take());
+    }
+}
+
+/// 返回预设响应并记录收到的请求，未预设的路径返回404
+/// 同一路径的多个预设响应按顺序返回，最后一个会一直返回
+#[derive(Default)]
+pub struct MockTransport {
+    responses: std::sync::Mutex<HashMap<String, VecDeque<(u16, Vec<u8>)>>>,
+    requests: std::sync::Mutex<Vec<ApiRequest>>,
+}
+impl MockTransport {
+    /// 预设endpoint返回code为0、数据为data的响应
+    pub fn respond(&self, endpoint: &str, data: serde_json::Value) {
+        let body = serde_json::json!({ "code": 0, "message": "", "data": data });
+        self.respond_raw(endpoint, 200, body.to_string().into_bytes());
+    }
+    pub fn respond_raw(&self, endpoint: &str, status: u16, body: Vec<u8>) {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_default()
+            .push_back((status, body));
+    }
+    /// 到目前为止收到的请求
+    pub fn requests(&self) -> Vec<ApiRequest> {
+        return self.requests.lock().unwrap().clone();
+    }
+}
+#[async_trait]
+impl ApiTransport for MockTransport {
+    async fn send(&self, request: ApiRequest) -> ApiResult<Box<dyn ApiBody>> {
+        let response = match self.responses.lock().unwrap().get_mut(&request.endpoint) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        };
+        self.requests.lock().unwrap().push(request);
+        let (status, body) = response.unwrap_or((404, vec![]));
+        return Ok(Box::new(MockBody {
+            status,
+            body: Some(body),
+        }));
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    pub code: i64,
+    pub message: Option<String>,
+    pub data: Option<T>,
+}
+
+/// HJ3服务端API的客户端，所有请求都会带上评测机的uuid
+#[derive(Clone)]
+pub struct Hj3ApiClient {
+    transport: Arc<dyn ApiTransport>,
+    judger_uuid: String,
+}
+impl Hj3ApiClient {
+    pub fn new(config: &JudgerConfig) -> ResultType<Self> {
+        return Ok(Self {
+            transport: Arc::new(HttpTransport {
+                http: reqwest::Client::new(),
+                base_url: config.suburl("")?,
+            }),
+            judger_uuid: config.judger_uuid.clone(),
+        });
+    }
+    pub fn with_transport(transport: Arc<dyn ApiTransport>, judger_uuid: &str) -> Self {
+        return Self {
+            transport,
+            judger_uuid: judger_uuid.to_string(),
+        };
+    }
+    /// 发送请求并返回未读取的响应，HTTP状态码由调用者检查
+    pub async fn open(&self, request: ApiRequest) -> ApiResult<Box<dyn ApiBody>> {
+        return self
+            .transport
+            .send(request.param("uuid", &self.judger_uuid))
+            .await;
+    }
+    /// 向外部地址发送请求，只检查HTTP状态码
+    pub async fn send_external(&self, request: ApiRequest) -> ApiResult<()> {
+        let endpoint = request.endpoint.clone();
+        let mut body = self.transport.send(request).await?;
+        if !(200..300).contains(&body.status()) {
+            return Err(ApiError::Server {
+                code: body.status() as i64,
+                message: format!("HTTP error when requesting {}", endpoint),
+            });
+        }
+        while body.chunk().await?.is_some() {}
+        return Ok(());
+    }
+    /// 发送请求并读取整个响应
+    pub async fn download(&self, request: ApiRequest) -> ApiResult<Vec<u8>> {
+        let endpoint = request.endpoint.clone();
+        let body = self.open(request).await?;
+        return read_body(&endpoint, body).await;
+    }
+    async fn call_raw<T: DeserializeOwned>(
+        &self,
+        request: ApiRequest,
+    ) -> ApiResult<ApiResponse<T>> {
+        let endpoint = request.endpoint.clone();
+        let text = self.download(request).await?;
+        return decode_response(&endpoint, &text);
+    }
+    /// 调用返回数据的API
+    pub async fn call<T: DeserializeOwned>(&self, request: ApiRequest) -> ApiResult<T> {
+        let endpoint = request.endpoint.clone();
+        return self
+            .call_raw::<T>(request)
+            .await?
+            .data
+            .ok_or(ApiError::Decode(format!(
+                "Missing data in response of {}",
+                endpoint
+            )));
+    }
+    /// 调用不返回数据的API
+    pub async fn call_empty(&self, request: ApiRequest) -> ApiResult<()> {
+        self.call_raw::<serde_json::Value>(request).await?;
+        return Ok(());
+    }
+
+    /// 带有缓存校验的语言定义请求，etag为上次响应的sha256
+    /// 服务端返回304时表示缓存仍然有效，返回None；否则返回新的语言定义及其etag
+    pub async fn fetch_language_config(
+        &self,
+        language_id: &str,
+        etag: Option<&str>,
+    ) -> ApiResult<Option<(LanguageConfig, String)>> {
+        let endpoint = "judge/get_lang_config_as_json";
+        let mut request = ApiRequest::new(endpoint).param("lang_id", language_id);
+        if let Some(etag) = etag {
+            request = request.header("If-None-Match", format!("\"{}\"", etag));
+        }
+        let body = self.open(request).await?;
+        if body.status() == 304 {
+            return Ok(None);
+        }
+        let text = read_body(endpoint, body).await?;
+        let config = decode_response::<LanguageConfig>(endpoint, &text)?
+            .data
+            .ok_or(ApiError::Decode(format!(
+                "Missing data in response of {}",
+                endpoint
+            )))?;
+        return Ok(Some((config, sha256_hex(&text))));
+    }
+    pub async fn send_heartbeat(&self, heartbeat: &Heartbeat) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("judge/heartbeat")
+                    .param("version", &heartbeat.version)
+                    .param("load", heartbeat.load)
+                    .param("max_load", heartbeat.max_load)
+                    .param("queue_depth", &heartbeat.queue_depth)
+                    .param("docker_available", heartbeat.docker_available)
+                    .param("disk_free", heartbeat.disk_free)
+                    .param("file_lock_stats", &heartbeat.file_lock_stats)
+                    .param("process_stats", &heartbeat.process_stats)
+                    .param("time_scale", heartbeat.time_scale),
+            )
+            .await;
+    }
+    pub async fn begin_artifact_upload(
+        &self,
+        submission_id: i64,
+        name: &str,
+        size: usize,
+        checksum: &str,
+    ) -> ApiResult<ArtifactUploadState> {
+        return self
+            .call(
+                ApiRequest::new("judge/artifact/begin")
+                    .param("submission_id", submission_id)
+                    .param("name", name)
+                    .param("size", size)
+                    .param("sha256", checksum),
+            )
+            .await;
+    }
+    /// 开始上传题目文件，同名文件上传完成后被替换
+    pub async fn begin_problem_file_upload(
+        &self,
+        problem_id: i64,
+        name: &str,
+        size: usize,
+        checksum: &str,
+    ) -> ApiResult<ArtifactUploadState> {
+        return self
+            .call(
+                ApiRequest::new("judge/problem_file/begin")
+                    .param("problem_id", problem_id)
+                    .param("filename", name)
+                    .param("size", size)
+                    .param("sha256", checksum),
+            )
+            .await;
+    }
+    /// 返回服务端已接收的字节数
+    pub async fn upload_artifact_chunk(
+        &self,
+        upload_id: &str,
+        offset: usize,
+        checksum: &str,
+        chunk: &[u8],
+    ) -> ApiResult<usize> {
+        #[derive(Deserialize)]
+        struct ChunkResult {
+            pub received: usize,
+        }
+        return Ok(self
+            .call::<ChunkResult>(
+                ApiRequest::new("judge/artifact/chunk")
+                    .param("upload_id", upload_id)
+                    .param("offset", offset)
+                    .param("sha256", checksum)
+                    .body(chunk.to_vec()),
+            )
+            .await?
+            .received);
+    }
+    /// 返回服务端的产物ID
+    pub async fn finish_artifact_upload(
+        &self,
+        upload_id: &str,
+        checksum: &str,
+    ) -> ApiResult<String> {
+        #[derive(Deserialize)]
+        struct FinishResult {
+            pub artifact_id: String,
+        }
+        return Ok(self
+            .call::<FinishResult>(
+                ApiRequest::new("judge/artifact/finish")
+                    .param("upload_id", upload_id)
+                    .param("sha256", checksum),
+            )
+            .await?
+            .artifact_id);
+    }
+}
+
+/// 读取整个响应，HTTP状态码不为2xx时返回错误
+async fn read_body(endpoint: &str, mut body: Box<dyn ApiBody>) -> ApiResult<Vec<u8>> {
+    if !(200..300).contains(&body.status()) {
+        return Err(ApiError::Server {
+            code: body.status() as i64,
+            message: format!("HTTP error when requesting {}", endpoint),
+        });
+    }
+    let mut ret = vec![];
+    while let Some(chunk) = body.chunk().await? {
+        ret.extend_from_slice(&chunk);
+    }
+    return Ok(ret);
+}
+
+fn decode_response<T: DeserializeOwned>(endpoint: &str, text: &[u8]) -> ApiResult<ApiResponse<T>> {
+    let parsed = serde_json::from_slice::<ApiResponse<T>>(text).map_err(|e| {
+        ApiError::Decode(format!(
+            "Failed to deserialize response of {}: {}",
+            endpoint, e
+        ))
+    })?;
+    if parsed.code != 0 {
+        return Err(ApiError::Server {
+            code: parsed.code,
+            message: parsed.message.unwrap_or("<Not available>".to_string()),
+        });
+    }
+    return Ok(parsed);
+}
+
+/// 心跳中汇报的评测机状态
+pub struct Heartbeat {
+    pub version: String,
+    pub load: usize,
+    pub max_load: usize,
+    // JSON，各个队列中等待的任务数
+    pub queue_depth: String,
+    pub docker_available: bool,
+    // in MB
+    pub disk_free: i64,
+    // JSON，题目数据目录锁的统计
+    pub file_lock_stats: String,
+    // JSON，评测机进程自身的资源占用
+    pub process_stats: String,
+    // 服务端未指定时使用的time_scale，可能来自启动时的校准
+    pub time_scale: f64,
+}
+
+#[derive(Deserialize)]
+pub struct ArtifactUploadState {
+    pub upload_id: String,
+    // 服务端已经收到的字节数
+    pub received: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ApiError, ApiRequest, Hj3ApiClient, HttpTransport, MockTransport};
+    use crate::core::{config::JudgerConfig, util::with_retry};
+
+    fn client(transport: &Arc<MockTransport>) -> Hj3ApiClient {
+        return Hj3ApiClient::with_transport(transport.clone(), "judger-uuid");
+    }
+
+    // 不等待的重试配置
+    fn retry_config() -> JudgerConfig {
+        return JudgerConfig {
+            api_retry_attempts: 3,
+            api_retry_base_delay: 0,
+            api_retry_max_delay: 0,
+            ..Default::default()
+        };
+    }
+
+    #[tokio::test]
+    async fn retry_on_server_error() {
+        let transport = Arc::new(MockTransport::default());
+        transport.respond_raw("judge/update", 503, vec![]);
+        transport.respond_raw("judge/update", 502, vec![]);
+        transport.respond("judge/update", serde_json::json!({}));
+        let api = client(&transport);
+        let config = retry_config();
+        with_retry(&config, "update", || {
+            api.call_empty(ApiRequest::new("judge/update"))
+        })
+        .await
+        .unwrap();
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_after_attempts() {
+        let transport = Arc::new(MockTransport::default());
+        transport.respond_raw("judge/update", 500, vec![]);
+        let api = client(&transport);
+        let config = retry_config();
+        let result = with_retry(&config, "update", || {
+            api.call_empty(ApiRequest::new("judge/update"))
+        })
+        .await;
+        assert!(matches!(result, Err(ApiError::Server { code: 500, .. })));
+        assert_eq!(
+            transport.requests().len(),
+            config.api_retry_attempts as usize + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn no_retry_on_client_error() {
+        let transport = Arc::new(MockTransport::default());
+        transport.respond_raw("judge/update", 403, vec![]);
+        let api = client(&transport);
+        let result = with_retry(&retry_config(), "update", || {
+            api.call_empty(ApiRequest::new("judge/update"))
+        })
+        .await;
+        assert!(matches!(result, Err(ApiError::Server { code: 403, .. })));
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn no_retry_on_api_error() {
+        // HTTP 200但code不为0，由服务端拒绝，重试没有意义
+        let transport = Arc::new(MockTransport::default());
+        let body = serde_json::json!({ "code": -1, "message": "denied" });
+        transport.respond_raw("judge/update", 200, body.to_string().into_bytes());
+        let api = client(&transport);
+        let result = with_retry(&retry_config(), "update", || {
+            api.call_empty(ApiRequest::new("judge/update"))
+        })
+        .await;
+        assert!(matches!(result, Err(ApiError::Server { code: -1, .. })));
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn params_carry_uuid() {
+        let transport = Arc::new(MockTransport::default());
+        transport.respond("judge/get_problem_info", serde_json::json!(1));
+        let api = client(&transport);
+        let value: i64 = api
+            .call(ApiRequest::new("/judge/get_problem_info").param("problem_id", 12))
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+        let requests = transport.requests();
+        assert_eq!(requests[0].endpoint, "judge/get_problem_info");
+        assert_eq!(
+            requests[0].params,
+            vec![
+                ("problem_id".to_string(), "12".to_string()),
+                ("uuid".to_string(), "judger-uuid".to_string())
+            ]
+        );
+    }
+
+    fn http_transport() -> HttpTransport {
+        return HttpTransport {
+            http: reqwest::Client::new(),
+            base_url: "http://localhost/api/".to_string(),
+        };
+    }
+
+    #[test]
+    fn form_encoding_without_body() {
+        let request = http_transport()
+            .build(
+                ApiRequest::new("judge/update")
+                    .param("message", "a b&c=d")
+                    .param("name", "测试"),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(request.url().as_str(), "http://localhost/api/judge/update");
+        assert_eq!(
+            request.body().unwrap().as_bytes().unwrap(),
+            b"message=a+b%26c%3Dd&name=%E6%B5%8B%E8%AF%95"
+        );
+    }
+
+    #[test]
+    fn query_encoding_with_body() {
+        let request = http_transport()
+            .build(
+                ApiRequest::new("judge/artifact/chunk")
+                    .param("upload_id", "x y")
+                    .param("offset", 10)
+                    .body(b"data".to_vec())
+                    .range_start(5),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "http://localhost/api/judge/artifact/chunk?upload_id=x+y&offset=10"
+        );
+        assert_eq!(request.body().unwrap().as_bytes().unwrap(), b"data");
+        assert_eq!(request.headers()["range"], "bytes=5-");
+    }
+}
@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use async_zip::read::{fs::ZipFileReader as FsZipFileReader, mem::ZipFileReader, ZipEntryReader};
+use log::info;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::misc::{AsyncStatusUpdater, ResultType};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const TAR_BLOCK_SIZE: usize = 512;
+// 解压时每隔多少个文件报告一次进度
+const PROGRESS_INTERVAL: usize = 10;
+// GNU长文件名与pax扩展头的大小上限
+const MAX_EXTENSION_HEADER_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarZst,
+}
+impl ArchiveFormat {
+    /// 根据文件头判断压缩包格式
+    pub fn detect(data: &[u8]) -> ResultType<Self> {
+        if data.starts_with(ZIP_MAGIC) {
+            return Ok(Self::Zip);
+        }
+        if data.starts_with(ZSTD_MAGIC) {
+            return Ok(Self::TarZst);
+        }
+        return Err(anyhow!("Unsupported archive format"));
+    }
+}
+
+/// 压缩包中的文件名转换为相对路径，拒绝绝对路径与..
+fn safe_entry_path(name: &str) -> ResultType<PathBuf> {
+    let path = PathBuf::from(name);
+    if path
+        .components()
+        .any(|v| !matches!(v, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!("Illegal file name in archive: {}", name));
+    }
+    return Ok(path);
+}
+
+/// 解析8进制数字段(tar头中的文件大小等)
+fn parse_octal(field: &[u8]) -> ResultType<u64> {
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    return u64::from_str_radix(text, 8).map_err(|_| anyhow!("Invalid tar header: {}", text));
+}
+
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|v| *v == 0).unwrap_or(field.len());
+    return String::from_utf8_lossy(&field[..end]).to_string();
+}
+
+/// 解析pax扩展头，每条记录为"<长度> <键>=<值>\n"
+fn parse_pax_records(data: &[u8]) -> ResultType<HashMap<String, String>> {
+    let mut ret = HashMap::new();
+    let mut rest = data;
+    while !rest.is_empty() && rest[0] != 0 {
+        let space = rest
+            .iter()
+            .position(|v| *v == b' ')
+            .ok_or(anyhow!("Invalid pax header"))?;
+        let length = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > space + 1 && *v <= rest.len())
+            .ok_or(anyhow!("Invalid pax record length"))?;
+        let record = &rest[space + 1..length];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        let eq = record
+            .iter()
+            .position(|v| *v == b'=')
+            .ok_or(anyhow!("Invalid pax record"))?;
+        ret.insert(
+            String::from_utf8_lossy(&record[..eq]).to_string(),
+            String::from_utf8_lossy(&record[eq + 1..]).to_string(),
+        );
+        rest = &rest[length..];
+    }
+    return Ok(ret);
+}
+
+fn read_extension_header(entry: &mut impl Read, size: u64) -> ResultType<Vec<u8>> {
+    if size > MAX_EXTENSION_HEADER_SIZE {
+        return Err(anyhow!("Tar extension header too large: {} bytes", size));
+    }
+    let mut buf = vec![];
+    entry
+        .read_to_end(&mut buf)
+        .map_err(|e| anyhow!("Failed to read tar entry: {}", e))?;
+    return Ok(buf);
+}
+
+/// 依次读取tar中的普通文件，on_file读取文件内容(可以不读完)
+/// 支持GNU长文件名与pax扩展头(path、size)；普通文件的总大小超过limit(不为0时)时出错
+fn for_each_tar_entry(
+    mut reader: impl Read,
+    limit: u64,
+    mut on_file: impl FnMut(&str, &mut dyn Read) -> ResultType<()>,
+) -> ResultType<()> {
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    // GNU格式的长文件名
+    let mut long_name: Option<String> = None;
+    // pax全局扩展头与下一个文件的扩展头
+    let mut global_pax = HashMap::<String, String>::new();
+    let mut local_pax = HashMap::<String, String>::new();
+    let mut total = 0u64;
+    loop {
+        reader
+            .read_exact(&mut header)
+            .map_err(|e| anyhow!("Failed to read tar header: {}", e))?;
+        if header.iter().all(|v| *v == 0) {
+            return Ok(());
+        }
+        let mut size = parse_octal(&header[124..136])?;
+        let type_flag = header[156];
+        let mut name = c_string(&header[0..100]);
+        if &header[257..262] == b"ustar" {
+            let prefix = c_string(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        let is_extension = matches!(type_flag, b'L' | b'x' | b'g');
+        if !is_extension {
+            if let Some(v) = long_name.take() {
+                name = v;
+            }
+            let pax = std::mem::take(&mut local_pax);
+            if let Some(v) = pax.get("path").or(global_pax.get("path")) {
+                name = v.clone();
+            }
+            if let Some(v) = pax.get("size").or(global_pax.get("size")) {
+                size = v
+                    .parse::<u64>()
+                    .map_err(|_| anyhow!("Invalid pax size: {}", v))?;
+            }
+        }
+        let mut entry = (&mut reader).take(size);
+        match type_flag {
+            b'0' | b'\0' => {
+                add_extracted(&mut total, size, limit)?;
+                on_file(&name, &mut entry)?
+            }
+            b'L' => {
+                long_name = Some(c_string(&read_extension_header(&mut entry, size)?));
+            }
+            b'x' => {
+                local_pax = parse_pax_records(&read_extension_header(&mut entry, size)?)?;
+            }
+            b'g' => {
+                global_pax.extend(parse_pax_records(&read_extension_header(
+                    &mut entry, size,
+                )?)?);
+            }
+            // 目录与链接均跳过
+            _ => {}
+        }
+        std::io::copy(&mut entry, &mut std::io::sink())
+            .map_err(|e| anyhow!("Failed to read tar entry: {}", e))?;
+        let padding =
+            (TAR_BLOCK_SIZE as u64 - size % TAR_BLOCK_SIZE as u64) % TAR_BLOCK_SIZE as u64;
+        std::io::copy(&mut (&mut reader).take(padding), &mut std::io::sink())
+            .map_err(|e| anyhow!("Failed to read tar entry: {}", e))?;
+    }
+}
+
+/// 检查zip中文件声明的解压后总大小，在解压任何文件之前拒绝超过limit的压缩包
+/// 未声明大小的文件不计入，解压时由add_extracted按实际写入的大小限制
+fn check_zip_size<'a>(
+    entries: impl Iterator<Item = &'a async_zip::read::ZipEntry>,
+    limit: u64,
+) -> ResultType<()> {
+    let total = entries
+        .map(|v| v.uncompressed_size().unwrap_or(0) as u64)
+        .sum::<u64>();
+    if limit != 0 && total > limit {
+        return Err(anyhow!("Archive content exceeds {} bytes", limit));
+    }
+    return Ok(());
+}
+
+/// 累计已解压的大小，超过limit(不为0时)时出错
+fn add_extracted(total: &mut u64, count: u64, limit: u64) -> ResultType<()> {
+    *total = total.saturating_add(count);
+    if limit != 0 && *total > limit {
+        return Err(anyhow!("Archive content exceeds {} bytes", limit));
+    }
+    return Ok(());
+}
+
+/// 解压zip中的一个文件写入output，解压出的数据必须与中央目录中声明的大小与CRC32一致
+/// 不信任压缩流本身的结束位置，超过声明的大小即停止；total为整个压缩包已解压的大小
+async fn copy_zip_entry<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut reader: ZipEntryReader<'_, R>,
+    name: &str,
+    output: &mut W,
+    total: &mut u64,
+    limit: u64,
+) -> ResultType<()> {
+    let expected_size = reader.entry().uncompressed_size().map(|v| v as u64);
+    let expected_crc = reader.entry().crc32();
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let count = reader
+            .read(&mut buf)
+            .await
+            .map_err(|e| anyhow!("Failed to decompress file: {}, {}", name, e))?;
+        if count == 0 {
+            break;
+        }
+        size += count as u64;
+        if expected_size.map(|v| size > v).unwrap_or(false) {
+            return Err(anyhow!(
+                "File {} is larger than declared in the archive",
+                name
+            ));
+        }
+        add_extracted(total, count as u64, limit)?;
+        hasher.update(&buf[..count]);
+        output
+            .write_all(&buf[..count])
+            .await
+            .map_err(|e| anyhow!("Failed to write file: {}, {}", name, e))?;
+    }
+    if expected_size.map(|v| size != v).unwrap_or(false) {
+        return Err(anyhow!("File {} is truncated in the archive", name));
+    }
+    if expected_crc
+        .map(|v| v != hasher.finalize())
+        .unwrap_or(false)
+    {
+        return Err(anyhow!("CRC32 mismatch of file {}", name));
+    }
+    return Ok(());
+}
+
+async fn report_progress(progress: Option<&dyn AsyncStatusUpdater>, count: usize, name: &str) {
+    if let Some(progress) = progress {
+        if count % PROGRESS_INTERVAL == 0 {
+            progress.update(&format!("Extracting: {}", name)).await;
+        }
+    }
+}
+
+/// 将压缩包解压到内存，filter返回false的文件被跳过；解压出的总大小超过limit(不为0时)时出错
+pub async fn extract_to_memory(
+    data: &[u8],
+    filter: &(dyn Fn(&str) -> bool + Sync),
+    limit: u64,
+    progress: Option<&dyn AsyncStatusUpdater>,
+) -> ResultType<HashMap<String, Vec<u8>>> {
+    let mut ret = HashMap::<String, Vec<u8>>::new();
+    match ArchiveFormat::detect(data)? {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipFileReader::new(data)
+                .await
+                .map_err(|e| anyhow!("Failed to read zip file: {}", e))?;
+            let names = zip
+                .entries()
+                .iter()
+                .map(|v| (v.name().to_string(), v.dir()))
+                .collect::<Vec<(String, bool)>>();
+            check_zip_size(
+                zip.entries()
+                    .iter()
+                    .filter(|v| !v.dir() && filter(v.name())),
+                limit,
+            )?;
+            let mut total = 0u64;
+            for (index, (name, dir)) in names.into_iter().enumerate() {
+                if dir || !filter(&name) {
+                    continue;
+                }
+                report_progress(progress, ret.len(), &name).await;
+                let reader = zip
+                    .entry_reader(index)
+                    .await
+                    .map_err(|e| anyhow!("Failed to read file: {}, {}", name, e))?;
+                let mut content = vec![];
+                copy_zip_entry(reader, &name, &mut content, &mut total, limit).await?;
+                ret.insert(name, content);
+            }
+        }
+        ArchiveFormat::TarZst => {
+            let decoder = zstd::stream::read::Decoder::new(data)
+                .map_err(|e| anyhow!("Failed to create zstd decoder: {}", e))?;
+            let handle = tokio::runtime::Handle::current();
+            tokio::task::block_in_place(|| {
+                for_each_tar_entry(decoder, limit, |name, reader| {
+                    if !filter(name) {
+                        return Ok(());
+                    }
+                    handle.block_on(report_progress(progress, ret.len(), name));
+                    let mut content = vec![];
+                    reader
+                        .read_to_end(&mut content)
+                        .map_err(|e| anyhow!("Failed to decompress file: {}, {}", name, e))?;
+                    ret.insert(name.to_string(), content);
+                    return Ok(());
+                })
+            })?;
+        }
+    }
+    info!("Extracted {} files", ret.len());
+    return Ok(ret);
+}
+
+/// 将磁盘上的压缩包逐个文件解压到dir，返回解压出的文件名；解压出的总大小超过limit(不为0时)时出错
+pub async fn extract_to_dir(
+    archive: &Path,
+    dir: &Path,
+    filter: &(dyn Fn(&str) -> bool + Sync),
+    limit: u64,
+    progress: Option<&dyn AsyncStatusUpdater>,
+) -> ResultType<Vec<String>> {
+    let mut ret = vec![];
+    let mut magic = vec![];
+    std::fs::File::open(archive)
+        .map_err(|e| anyhow!("Failed to open archive: {}", e))?
+        .take(4)
+        .read_to_end(&mut magic)
+        .map_err(|e| anyhow!("Failed to read archive: {}", e))?;
+    match ArchiveFormat::detect(&magic)? {
+        ArchiveFormat::Zip => {
+            let zip = FsZipFileReader::new(
+                archive
+                    .to_str()
+                    .ok_or(anyhow!("Invalid archive path"))?
+                    .to_string(),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to read zip file: {}", e))?;
+            let names = zip
+                .entries()
+                .iter()
+                .map(|v| (v.name().to_string(), v.dir()))
+                .collect::<Vec<(String, bool)>>();
+            check_zip_size(
+                zip.entries()
+                    .iter()
+                    .filter(|v| !v.dir() && filter(v.name())),
+                limit,
+            )?;
+            let mut total = 0u64;
+            for (index, (name, is_dir)) in names.into_iter().enumerate() {
+                if is_dir || !filter(&name) {
+                    continue;
+                }
+                let target = dir.join(safe_entry_path(&name)?);
+                report_progress(progress, ret.len(), &name).await;
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .map_err(|e| anyhow!("Failed to create directory: {}", e))?;
+                }
+                let mut file = tokio::fs::File::create(&target)
+                    .await
+                    .map_err(|e| anyhow!("Failed to create file: {}, {}", name, e))?;
+                let reader = zip
+                    .entry_reader(index)
+                    .await
+                    .map_err(|e| anyhow!("Failed to read file: {}, {}", name, e))?;
+                copy_zip_entry(reader, &name, &mut file, &mut total, limit).await?;
+                file.flush()
+                    .await
+                    .map_err(|e| anyhow!("Failed to write file: {}, {}", name, e))?;
+                ret.push(name);
+            }
+        }
+        ArchiveFormat::TarZst => {
+            let file = std::fs::File::open(archive)
+                .map_err(|e| anyhow!("Failed to open archive: {}", e))?;
+            let decoder = zstd::stream::read::Decoder::new(file)
+                .map_err(|e| anyhow!("Failed to create zstd decoder: {}", e))?;
+            let handle = tokio::runtime::Handle::current();
+            tokio::task::block_in_place(|| {
+                for_each_tar_entry(decoder, limit, |name, reader| {
+                    if !filter(name) {
+                        return Ok(());
+                    }
+                    let target = dir.join(safe_entry_path(name)?);
+                    handle.block_on(report_progress(progress, ret.len(), name));
+                    if let Some(parent) = target.parent() {
+                        std::fs::create_dir_all(parent)
+                            .map_err(|e| anyhow!("Failed to create directory: {}", e))?;
+                    }
+                    let mut file = std::fs::File::create(&target)
+                        .map_err(|e| anyhow!("Failed to create file: {}, {}", name, e))?;
+                    std::io::copy(reader, &mut file)
+                        .map_err(|e| anyhow!("Failed to decompress file: {}, {}", name, e))?;
+                    ret.push(name.to_string());
+                    return Ok(());
+                })
+            })?;
+        }
+    }
+    info!("Extracted {} files to {}", ret.len(), dir.display());
+    return Ok(ret);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Read};
+
+    use super::{
+        add_extracted, extract_to_memory, for_each_tar_entry, parse_octal, parse_pax_records,
+        safe_entry_path, TAR_BLOCK_SIZE,
+    };
+    use crate::core::misc::ResultType;
+
+    /// 构造一个ustar格式的tar头，不计算校验和(解析时不检查)
+    fn header(name: &str, size: usize, type_flag: u8, prefix: &str) -> Vec<u8> {
+        let mut block = vec![0u8; TAR_BLOCK_SIZE];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}", size);
+        block[124..124 + size.len()].copy_from_slice(size.as_bytes());
+        block[156] = type_flag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        return block;
+    }
+
+    /// 追加一个tar条目(头与按块对齐的内容)
+    fn entry(tar: &mut Vec<u8>, name: &str, type_flag: u8, content: &[u8]) {
+        tar.extend(header(name, content.len(), type_flag, ""));
+        tar.extend_from_slice(content);
+        tar.resize(
+            (tar.len() + TAR_BLOCK_SIZE - 1) / TAR_BLOCK_SIZE * TAR_BLOCK_SIZE,
+            0,
+        );
+    }
+
+    fn finish(mut tar: Vec<u8>) -> Vec<u8> {
+        tar.extend(vec![0u8; TAR_BLOCK_SIZE * 2]);
+        return tar;
+    }
+
+    fn pax_record(key: &str, value: &str) -> String {
+        // 长度包含长度字段本身
+        let body = format!(" {}={}\n", key, value);
+        let mut length = body.len() + 1;
+        while format!("{}{}", length, body).len() != length {
+            length += 1;
+        }
+        return format!("{}{}", length, body);
+    }
+
+    fn read_tar(tar: &[u8], limit: u64) -> ResultType<Vec<(String, Vec<u8>)>> {
+        let mut ret = vec![];
+        for_each_tar_entry(tar, limit, |name, reader| {
+            let mut content = vec![];
+            reader.read_to_end(&mut content).unwrap();
+            ret.push((name.to_string(), content));
+            return Ok(());
+        })?;
+        return Ok(ret);
+    }
+
+    fn files(pairs: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+        return pairs
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_vec()))
+            .collect();
+    }
+
+    #[test]
+    fn octal_fields() {
+        assert_eq!(parse_octal(b"00000001750\0").unwrap(), 1000);
+        assert_eq!(parse_octal(b"   17 \0\0").unwrap(), 15);
+        assert_eq!(parse_octal(b"\0\0\0\0").unwrap(), 0);
+        assert!(parse_octal(b"0000009\0").is_err());
+    }
+
+    #[test]
+    fn plain_entries() {
+        let mut tar = vec![];
+        entry(&mut tar, "1.in", b'0', b"1 2\n");
+        entry(&mut tar, "data/", b'5', b"");
+        entry(&mut tar, "1.out", b'\0', &[b'3'; TAR_BLOCK_SIZE]);
+        entry(&mut tar, "link", b'2', b"");
+        entry(&mut tar, "empty", b'0', b"");
+        let ret = read_tar(&finish(tar), 0).unwrap();
+        assert_eq!(
+            ret,
+            files(&[
+                ("1.in", b"1 2\n"),
+                ("1.out", &[b'3'; TAR_BLOCK_SIZE]),
+                ("empty", b""),
+            ])
+        );
+    }
+
+    #[test]
+    fn callback_may_skip_content() {
+        let mut tar = vec![];
+        entry(&mut tar, "a", b'0', &[b'a'; 1000]);
+        entry(&mut tar, "b", b'0', b"b");
+        let mut names = vec![];
+        for_each_tar_entry(&finish(tar)[..], 0, |name, _| {
+            names.push(name.to_string());
+            return Ok(());
+        })
+        .unwrap();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn ustar_prefix_and_gnu_long_name() {
+        let long_name = format!("{}/1.in", "d".repeat(150));
+        let mut tar = header("1.in", 1, b'0', "data/sub");
+        tar.extend_from_slice(b"x");
+        tar.resize(TAR_BLOCK_SIZE * 2, 0);
+        entry(
+            &mut tar,
+            "././@LongLink",
+            b'L',
+            format!("{}\0", long_name).as_bytes(),
+        );
+        entry(&mut tar, "truncated", b'0', b"y");
+        // 长文件名只作用于下一个文件
+        entry(&mut tar, "short", b'0', b"z");
+        let ret = read_tar(&finish(tar), 0).unwrap();
+        assert_eq!(
+            ret,
+            files(&[("data/sub/1.in", b"x"), (&long_name, b"y"), ("short", b"z")])
+        );
+    }
+
+    #[test]
+    fn pax_headers() {
+        let mut tar = vec![];
+        entry(
+            &mut tar,
+            "PaxHeaders/1",
+            b'x',
+            format!(
+                "{}{}",
+                pax_record("path", "测试/第一个.in"),
+                pax_record("mtime", "1.5")
+            )
+            .as_bytes(),
+        );
+        entry(&mut tar, "ignored", b'0', b"1");
+        entry(&mut tar, "2.in", b'0', b"2");
+        // pax的size优先于头中的大小
+        entry(
+            &mut tar,
+            "PaxHeaders/3",
+            b'x',
+            pax_record("size", "3").as_bytes(),
+        );
+        tar.extend(header("3.in", 0, b'0', ""));
+        tar.extend_from_slice(b"abc");
+        tar.resize(tar.len() + TAR_BLOCK_SIZE - 3, 0);
+        entry(
+            &mut tar,
+            "GlobalHead",
+            b'g',
+            pax_record("path", "global").as_bytes(),
+        );
+        entry(&mut tar, "4.in", b'0', b"4");
+        let ret = read_tar(&finish(tar), 0).unwrap();
+        assert_eq!(
+            ret,
+            files(&[
+                ("测试/第一个.in", b"1"),
+                ("2.in", b"2"),
+                ("3.in", b"abc"),
+                ("global", b"4")
+            ])
+        );
+    }
+
+    #[test]
+    fn pax_records() {
+        let data = format!("{}{}", pax_record("path", "a=b"), pax_record("size", "10"));
+        let ret = parse_pax_records(data.as_bytes()).unwrap();
+        assert_eq!(
+            ret,
+            HashMap::from([
+                ("path".to_string(), "a=b".to_string()),
+                ("size".to_string(), "10".to_string())
+            ])
+        );
+        // 末尾的填充
+        assert_eq!(parse_pax_records(b"12 path=ab\n\0\0\0").unwrap().len(), 1);
+        for invalid in [
+            &b"99 path=ab\n"[..],
+            b"2 path=ab\n",
+            b"x path=ab\n",
+            b"11 pathab\n",
+            b"path=ab\n",
+        ] {
+            assert!(parse_pax_records(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_tar() {
+        // 截断的文件内容与缺少结束块
+        let mut tar = header("1.in", 100, b'0', "");
+        tar.extend_from_slice(b"short");
+        assert!(read_tar(&tar, 0).is_err());
+        assert!(read_tar(&[0u8; 100], 0).is_err());
+        let mut tar = vec![];
+        entry(
+            &mut tar,
+            "PaxHeaders/1",
+            b'x',
+            pax_record("size", "-1").as_bytes(),
+        );
+        entry(&mut tar, "1.in", b'0', b"1");
+        assert!(read_tar(&finish(tar), 0).is_err());
+        // 过大的扩展头在读取前拒绝
+        let mut tar = header("././@LongLink", 2 * 1024 * 1024, b'L', "");
+        tar.extend(vec![b'a'; 2 * 1024 * 1024]);
+        assert!(read_tar(&finish(tar), 0).is_err());
+    }
+
+    #[test]
+    fn tar_size_limit() {
+        let mut tar = vec![];
+        entry(&mut tar, "1.in", b'0', &[b'1'; 600]);
+        entry(&mut tar, "1.out", b'0', &[b'2'; 400]);
+        let tar = finish(tar);
+        assert_eq!(read_tar(&tar, 1000).unwrap().len(), 2);
+        assert!(read_tar(&tar, 999).is_err());
+        // pax声明的巨大文件在读取内容前拒绝
+        let mut tar = vec![];
+        entry(
+            &mut tar,
+            "PaxHeaders/1",
+            b'x',
+            pax_record("size", &u64::MAX.to_string()).as_bytes(),
+        );
+        entry(&mut tar, "1.in", b'0', b"");
+        assert!(read_tar(&finish(tar), 1 << 30).is_err());
+    }
+
+    #[test]
+    fn extracted_size_limit() {
+        let mut total = 0;
+        add_extracted(&mut total, 600, 1000).unwrap();
+        add_extracted(&mut total, 400, 1000).unwrap();
+        assert!(add_extracted(&mut total, 1, 1000).is_err());
+        let mut total = 0;
+        add_extracted(&mut total, u64::MAX, 0).unwrap();
+        add_extracted(&mut total, u64::MAX, 0).unwrap();
+        assert!(add_extracted(&mut total, 1, u64::MAX - 1).is_err());
+    }
+
+    #[test]
+    fn entry_paths() {
+        assert!(safe_entry_path("a/b.in").is_ok());
+        assert!(safe_entry_path("./a").is_ok());
+        assert!(safe_entry_path("../a").is_err());
+        assert!(safe_entry_path("a/../../b").is_err());
+        assert!(safe_entry_path("/etc/passwd").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn extract_tar_zst_to_memory() {
+        let mut tar = vec![];
+        entry(&mut tar, "1.in", b'0', b"1 2\n");
+        entry(&mut tar, "1.out", b'0', b"3\n");
+        let data = zstd::stream::encode_all(&finish(tar)[..], 0).unwrap();
+        let ret = extract_to_memory(&data, &|name| name.ends_with(".in"), 0, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            ret,
+            HashMap::from([("1.in".to_string(), b"1 2\n".to_vec())])
+        );
+        // 被跳过的文件同样计入大小
+        assert!(extract_to_memory(&data, &|_| false, 5, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn extract_zip_to_memory() {
+        use async_zip::{
+            write::{EntryOptions, ZipFileWriter},
+            Compression,
+        };
+        let mut data = vec![];
+        let mut writer = ZipFileWriter::new(&mut data);
+        for (name, content) in [("1.in", &b"1 2\n"[..]), ("1.out", &[b'3'; 1000][..])] {
+            writer
+                .write_entry_whole(
+                    EntryOptions::new(name.to_string(), Compression::Deflate),
+                    content,
+                )
+                .await
+                .unwrap();
+        }
+        writer.close().await.unwrap();
+        let ret = extract_to_memory(&data, &|_| true, 0, None).await.unwrap();
+        assert_eq!(ret["1.in"], b"1 2\n");
+        assert_eq!(ret["1.out"], vec![b'3'; 1000]);
+        assert!(extract_to_memory(&data, &|_| true, 1003, None)
+            .await
+            .is_err());
+        // 只计入未被跳过的文件
+        let ret = extract_to_memory(&data, &|v| v == "1.in", 4, None)
+            .await
+            .unwrap();
+        assert_eq!(ret.len(), 1);
+    }
+}
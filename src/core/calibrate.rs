@@ -0,0 +1,112 @@
+use anyhow::anyhow;
+use log::info;
+
+use super::{
+    config::JudgerConfig,
+    misc::ResultType,
+    runner::{ExecuteOptions, Runner},
+    workdir::create_work_dir,
+};
+
+// 基准程序，整数运算与内存访问各占一部分
+const BENCHMARK_SOURCE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#define N 20000000
+int main() {
+    char *sieve = calloc(N + 1, 1);
+    int count = 0;
+    for (int i = 2; i <= N; i++) {
+        if (!sieve[i]) {
+            count++;
+            for (long long j = (long long)i * i; j <= N; j += i) sieve[j] = 1;
+        }
+    }
+    unsigned int x = 1;
+    for (int i = 0; i < 300000000; i++) x = x * 1103515245u + 12345u;
+    printf("%d %u\n", count, x);
+    return 0;
+}
+"#;
+const BENCHMARK_ROUNDS: usize = 3;
+
+/// 在评测镜像中编译并运行基准程序，返回多轮中最短的运行时间(微秒)
+/// 在基准机器(题目时间限制所针对的机器)上运行得到的时间即为calibration_reference_time
+pub async fn run_calibration_benchmark(
+    config: &JudgerConfig,
+    runner: &dyn Runner,
+) -> ResultType<i64> {
+    let working_dir = create_work_dir(config)
+        .map_err(|e| anyhow!("Failed to create calibration directory: {}", e))?;
+    let working_path = working_dir.path().to_str().ok_or(anyhow!("?"))?;
+    tokio::fs::write(working_dir.path().join("bench.c"), BENCHMARK_SOURCE)
+        .await
+        .map_err(|e| anyhow!("Failed to write benchmark source: {}", e))?;
+    let compile_result = runner
+        .execute(
+            &config.docker_image,
+            working_path,
+            &vec![
+                "gcc".to_string(),
+                "-O2".to_string(),
+                "-o".to_string(),
+                "bench".to_string(),
+                "bench.c".to_string(),
+            ],
+            1024 * 1024 * 1024,
+            30 * 1000 * 1000,
+            1024 * 1024,
+            &ExecuteOptions::default(),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to compile benchmark: {}", e))?;
+    if compile_result.exit_code != 0 {
+        return Err(anyhow!(
+            "Failed to compile benchmark:\n{}",
+            compile_result.output
+        ));
+    }
+    let mut best = i64::MAX;
+    for round in 0..BENCHMARK_ROUNDS {
+        let result = runner
+            .execute(
+                &config.docker_image,
+                working_path,
+                &vec!["./bench".to_string()],
+                512 * 1024 * 1024,
+                60 * 1000 * 1000,
+                1024,
+                &ExecuteOptions::default(),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run benchmark: {}", e))?;
+        if result.exit_code != 0 {
+            return Err(anyhow!(
+                "Benchmark exited with code {}: {}",
+                result.exit_code,
+                result.output
+            ));
+        }
+        info!("Benchmark round {}: {} us", round + 1, result.time_cost);
+        best = best.min(result.time_cost);
+    }
+    info!("Benchmark best: {} us", best);
+    return Ok(best);
+}
+
+/// 按照与基准机器的耗时之比得出time_scale，基准机器上的time_scale为1
+/// 未设置calibration_reference_time时拒绝校准
+pub async fn calibrate_time_scale(config: &JudgerConfig, runner: &dyn Runner) -> ResultType<f64> {
+    if config.calibration_reference_time <= 0 {
+        return Err(anyhow!(
+            "calibration_reference_time is not set, run `hellojudge3-judger calibrate` on the reference machine to measure it"
+        ));
+    }
+    let best = run_calibration_benchmark(config, runner).await?;
+    let time_scale = best as f64 / (config.calibration_reference_time * 1000) as f64;
+    info!(
+        "Calibrated time_scale: {:.4} (benchmark {} us, reference {} ms)",
+        time_scale, best, config.calibration_reference_time
+    );
+    return Ok(time_scale);
+}
@@ -0,0 +1,130 @@
+use super::{
+    compare::isolated::{BuiltinCompare, COMPARE_HELPER_COMMAND},
+    misc::ResultType,
+};
+use anyhow::anyhow;
+
+pub enum Command {
+    // 作为celery worker运行
+    Serve,
+    // 在本地重新评测一条日志中记录的提交
+    Replay {
+        submission_id: i64,
+        report: bool,
+    },
+    // 运行基准程序，输出本机的time_scale
+    Calibrate,
+    // 将旧版本的lock文件转换为题目数据的同步记录
+    MigrateSyncState,
+    // 运行容器启动、监视开销、比较器与同步记录的基准测试，only为空时运行全部
+    Bench {
+        rounds: usize,
+        only: Vec<String>,
+    },
+    // 校验评测信息中的结果水印，result为评测结果的JSON文件时同时校验其摘要
+    VerifyWatermark {
+        input: String,
+        result: Option<String>,
+    },
+    // 由评测机自身启动，在子进程中运行内置比较器
+    CompareHelper {
+        method: BuiltinCompare,
+        full_score: i64,
+    },
+}
+
+pub fn parse_command(args: &[String]) -> ResultType<Command> {
+    let mut iter = args.iter().skip(1);
+    let command = match iter.next().map(|v| v.as_str()) {
+        None | Some("serve") => Command::Serve,
+        Some("replay") => {
+            let mut submission_id = None;
+            let mut report = false;
+            while let Some(arg) = iter.next() {
+                match arg.as_str() {
+                    "--submission" => {
+                        let value = iter
+                            .next()
+                            .ok_or(anyhow!("Missing value for --submission"))?;
+                        submission_id = Some(
+                            value
+                                .parse::<i64>()
+                                .map_err(|e| anyhow!("Invalid submission id {}: {}", value, e))?,
+                        );
+                    }
+                    "--report" => report = true,
+                    other => return Err(anyhow!("Unknown argument: {}", other)),
+                }
+            }
+            Command::Replay {
+                submission_id: submission_id
+                    .ok_or(anyhow!("Usage: replay --submission <id> [--report]"))?,
+                report,
+            }
+        }
+        Some("calibrate") => Command::Calibrate,
+        Some("migrate-sync-state") => Command::MigrateSyncState,
+        Some("bench") => {
+            let mut rounds = 5;
+            let mut only = vec![];
+            while let Some(arg) = iter.next() {
+                match arg.as_str() {
+                    "--rounds" => {
+                        let value = iter.next().ok_or(anyhow!("Missing value for --rounds"))?;
+                        rounds = value
+                            .parse::<usize>()
+                            .map_err(|e| anyhow!("Invalid rounds {}: {}", value, e))?;
+                    }
+                    other if !other.starts_with("--") => only.push(other.to_string()),
+                    other => return Err(anyhow!("Unknown argument: {}", other)),
+                }
+            }
+            if rounds == 0 {
+                return Err(anyhow!("Usage: bench [--rounds <n>] [benchmark..]"));
+            }
+            Command::Bench { rounds, only }
+        }
+        Some("verify-watermark") => {
+            let mut input = None;
+            let mut result = None;
+            while let Some(arg) = iter.next() {
+                match arg.as_str() {
+                    "--result" => {
+                        result = Some(
+                            iter.next()
+                                .ok_or(anyhow!("Missing value for --result"))?
+                                .clone(),
+                        );
+                    }
+                    other if !other.starts_with("--") && input.is_none() => {
+                        input = Some(other.to_string())
+                    }
+                    other => return Err(anyhow!("Unknown argument: {}", other)),
+                }
+            }
+            Command::VerifyWatermark {
+                input: input.ok_or(anyhow!(
+                    "Usage: verify-watermark <message file> [--result <judge result file>]"
+                ))?,
+                result,
+            }
+        }
+        Some(COMPARE_HELPER_COMMAND) => {
+            let args = iter.collect::<Vec<&String>>();
+            if args.len() != 3 {
+                return Err(anyhow!(
+                    "Usage: {} <line|token> <argument> <full score>",
+                    COMPARE_HELPER_COMMAND
+                ));
+            }
+            Command::CompareHelper {
+                method: BuiltinCompare::from_args(args[0], args[1])?,
+                full_score: args[2]
+                    .parse::<i64>()
+                    .map_err(|e| anyhow!("Invalid full score {}: {}", args[2], e))?,
+            }
+        }
+        Some(other) => return Err(anyhow!("Unknown command: {}", other)),
+    };
+    return Ok(command);
+}
@@ -0,0 +1,260 @@
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use super::{simple, token, Comparator, CompareResult};
+use crate::core::misc::ResultType;
+use anyhow::anyhow;
+
+// 子进程的命令名，见cli.rs
+pub const COMPARE_HELPER_COMMAND: &str = "compare-helper";
+
+/// 内置比较器的参数
+#[derive(Debug, Clone)]
+pub enum BuiltinCompare {
+    Line { context_length: usize },
+    Token { case_insensitive: bool },
+}
+
+impl BuiltinCompare {
+    /// 作为子进程的命令行参数
+    pub fn to_args(&self) -> Vec<String> {
+        return match self {
+            BuiltinCompare::Line { context_length } => {
+                vec!["line".to_string(), context_length.to_string()]
+            }
+            BuiltinCompare::Token { case_insensitive } => {
+                vec!["token".to_string(), case_insensitive.to_string()]
+            }
+        };
+    }
+    pub fn from_args(kind: &str, arg: &str) -> ResultType<Self> {
+        return match kind {
+            "line" => Ok(BuiltinCompare::Line {
+                context_length: arg
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid context length {}: {}", arg, e))?,
+            }),
+            "token" => Ok(BuiltinCompare::Token {
+                case_insensitive: arg
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid case_insensitive {}: {}", arg, e))?,
+            }),
+            other => Err(anyhow!("Unknown comparator: {}", other)),
+        };
+    }
+    pub fn compare(
+        &self,
+        user_out: &[u8],
+        answer: &[u8],
+        full_score: i64,
+    ) -> ResultType<CompareResult> {
+        return match self {
+            BuiltinCompare::Line { context_length } => {
+                simple::compare(user_out, answer, full_score, *context_length)
+            }
+            BuiltinCompare::Token { case_insensitive } => {
+                token::compare(user_out, answer, full_score, *case_insensitive)
+            }
+        };
+    }
+}
+
+/// 在限制了内存与CPU时间的子进程中运行内置比较器，
+/// 避免恶意构造的超大输出在评测机进程内解码、切分时耗尽资源
+pub struct IsolatedComparator {
+    pub method: BuiltinCompare,
+    // 支持compare-helper命令的评测机可执行文件
+    pub helper: PathBuf,
+    // in bytes
+    pub memory_limit: u64,
+    // in seconds
+    pub time_limit: u64,
+}
+#[async_trait]
+impl Comparator for IsolatedComparator {
+    async fn compare(
+        &self,
+        user_out: Arc<Vec<u8>>,
+        answer: Arc<Vec<u8>>,
+        _input_data: Arc<Vec<u8>>,
+        full_score: i64,
+        _exit_code: Option<i32>,
+    ) -> ResultType<CompareResult> {
+        let memory_limit = self.memory_limit;
+        let time_limit = self.time_limit;
+        let mut command = tokio::process::Command::new(&self.helper);
+        command
+            .arg(COMPARE_HELPER_COMMAND)
+            .args(self.method.to_args())
+            .arg(full_score.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        unsafe {
+            command.pre_exec(move || {
+                let limits = [
+                    (libc::RLIMIT_AS, memory_limit),
+                    (libc::RLIMIT_CPU, time_limit),
+                ];
+                for (resource, value) in limits {
+                    let limit = libc::rlimit {
+                        rlim_cur: value,
+                        rlim_max: value,
+                    };
+                    if libc::setrlimit(resource, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                return Ok(());
+            });
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start compare helper: {}", e))?;
+        // 先写入用户输出的长度，再依次写入用户输出与答案
+        let mut stdin = child.stdin.take().ok_or(anyhow!("Missing stdin"))?;
+        let writer = tokio::spawn(async move {
+            stdin
+                .write_all(&(user_out.len() as u64).to_le_bytes())
+                .await?;
+            stdin.write_all(&user_out).await?;
+            stdin.write_all(&answer).await?;
+            return Ok::<(), std::io::Error>(());
+        });
+        let output = match tokio::time::timeout(
+            Duration::from_secs(time_limit * 2),
+            child.wait_with_output(),
+        )
+        .await
+        {
+            Ok(v) => v.map_err(|e| anyhow!("Failed to wait for compare helper: {}", e))?,
+            Err(_) => {
+                return Ok(CompareResult {
+                    score: 0,
+                    message: "比较超时".to_string(),
+                    feedback: vec![],
+                })
+            }
+        };
+        // 子进程提前退出时写入会失败，以子进程的结果为准
+        writer.abort();
+        if output.status.success() {
+            return serde_json::from_slice::<CompareResult>(&output.stdout)
+                .map_err(|e| anyhow!("Invalid compare helper output: {}", e));
+        }
+        if output.status.code().is_none() {
+            // 被信号终止，通常是超出了内存或CPU时间限制
+            return Ok(CompareResult {
+                score: 0,
+                message: "比较超出资源限制".to_string(),
+                feedback: vec![],
+            });
+        }
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+}
+
+/// 子进程的入口，从标准输入读取数据，向标准输出写入JSON格式的比较结果
+pub fn run_compare_helper(method: &BuiltinCompare, full_score: i64) -> ResultType<()> {
+    let mut input = vec![];
+    std::io::stdin()
+        .read_to_end(&mut input)
+        .map_err(|e| anyhow!("Failed to read stdin: {}", e))?;
+    let (user_out, answer) = split_helper_input(&input)?;
+    let result = method.compare(user_out, answer, full_score)?;
+    std::io::stdout()
+        .write_all(serde_json::to_string(&result)?.as_bytes())
+        .map_err(|e| anyhow!("Failed to write stdout: {}", e))?;
+    return Ok(());
+}
+
+/// 拆分子进程的输入: 8字节小端序的用户输出长度，用户输出，答案
+pub fn split_helper_input(input: &[u8]) -> ResultType<(&[u8], &[u8])> {
+    if input.len() < 8 {
+        return Err(anyhow!("Truncated input"));
+    }
+    let user_len = u64::from_le_bytes(input[..8].try_into().unwrap()) as usize;
+    if input.len() - 8 < user_len {
+        return Err(anyhow!("Truncated input"));
+    }
+    return Ok(input[8..].split_at(user_len));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::cli::{parse_command, Command};
+
+    fn helper_argv(method: &BuiltinCompare, full_score: i64) -> Vec<String> {
+        let mut argv = vec!["judger".to_string(), COMPARE_HELPER_COMMAND.to_string()];
+        argv.extend(method.to_args());
+        argv.push(full_score.to_string());
+        return argv;
+    }
+
+    #[test]
+    fn argv_round_trip() {
+        for method in [
+            BuiltinCompare::Line { context_length: 37 },
+            BuiltinCompare::Token {
+                case_insensitive: true,
+            },
+        ] {
+            match parse_command(&helper_argv(&method, 85)).unwrap() {
+                Command::CompareHelper {
+                    method: parsed,
+                    full_score,
+                } => {
+                    assert_eq!(parsed.to_args(), method.to_args());
+                    assert_eq!(full_score, 85);
+                }
+                _ => panic!("not a compare helper command"),
+            }
+        }
+    }
+
+    #[test]
+    fn argv_rejects_bad_arguments() {
+        let mut argv = helper_argv(&BuiltinCompare::Line { context_length: 1 }, 100);
+        argv[2] = "diff".to_string();
+        assert!(parse_command(&argv).is_err());
+        argv.pop();
+        assert!(parse_command(&argv).is_err());
+    }
+
+    #[test]
+    fn split_input() {
+        let mut input = 3u64.to_le_bytes().to_vec();
+        input.extend_from_slice(b"1 2\n1 2\n");
+        let (user_out, answer) = split_helper_input(&input).unwrap();
+        assert_eq!(user_out, b"1 2");
+        assert_eq!(answer, b"\n1 2\n");
+        // 空输出
+        let input = 0u64.to_le_bytes().to_vec();
+        assert_eq!(split_helper_input(&input).unwrap(), (&b""[..], &b""[..]));
+    }
+
+    #[test]
+    fn split_truncated_input() {
+        assert!(split_helper_input(b"").is_err());
+        assert!(split_helper_input(&[1, 0, 0]).is_err());
+        let mut input = 10u64.to_le_bytes().to_vec();
+        input.extend_from_slice(b"short");
+        assert!(split_helper_input(&input).is_err());
+        // 长度溢出usize时不能越界
+        let input = u64::MAX.to_le_bytes().to_vec();
+        assert!(split_helper_input(&input).is_err());
+    }
+}
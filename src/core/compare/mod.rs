@@ -1,11 +1,29 @@
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
-use super::misc::ResultType;
-use std::sync::Arc;
-#[derive(Debug)]
+use self::{
+    isolated::{BuiltinCompare, IsolatedComparator},
+    simple::SimpleLineComparator,
+    token::TokenComparator,
+};
+use super::{config::JudgerConfig, misc::ResultType};
+use anyhow::anyhow;
+use std::{path::PathBuf, sync::Arc};
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CompareResult {
     pub score: i64,
     pub message: String,
+    // SPJ写入feedback目录的文件
+    #[serde(default)]
+    pub feedback: Vec<FeedbackFile>,
+}
+
+/// SPJ输出的附加文件，如差异可视化、部分分明细，附加在测试点结果中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackFile {
+    pub name: String,
+    // base64
+    pub content: String,
 }
 #[async_trait]
 pub trait Comparator: Sync + Send {
@@ -15,8 +33,46 @@ pub trait Comparator: Sync + Send {
         answer: Arc<Vec<u8>>,
         input_data: Arc<Vec<u8>>,
         full_score: i64,
+        // 题目将退出代码视为答案的一部分时为用户程序的退出代码
+        exit_code: Option<i32>,
     ) -> ResultType<CompareResult>;
 }
 
+/// 按名称创建内置比较器("line"或"token"，空为"line")，开启isolated_compare时在子进程中比较
+pub fn builtin_comparator(
+    config: &JudgerConfig,
+    name: &str,
+    case_insensitive: bool,
+) -> ResultType<Box<dyn Comparator>> {
+    let method = match name {
+        "" | "line" => BuiltinCompare::Line {
+            context_length: config.compare_context_length,
+        },
+        "token" => BuiltinCompare::Token { case_insensitive },
+        other => return Err(anyhow!("Unknown comparator: {}", other)),
+    };
+    if config.isolated_compare {
+        if config.compare_helper_path.is_empty() {
+            return Err(anyhow!("compare_helper_path is not resolved"));
+        }
+        return Ok(Box::new(IsolatedComparator {
+            method,
+            helper: PathBuf::from(&config.compare_helper_path),
+            memory_limit: config.isolated_compare_memory_limit * 1024 * 1024,
+            time_limit: config.isolated_compare_time_limit,
+        }));
+    }
+    return Ok(match method {
+        BuiltinCompare::Line { context_length } => {
+            Box::new(SimpleLineComparator::new(context_length))
+        }
+        BuiltinCompare::Token { case_insensitive } => {
+            Box::new(TokenComparator { case_insensitive })
+        }
+    });
+}
+
+pub mod isolated;
 pub mod simple;
 pub mod special;
+pub mod token;
@@ -6,7 +6,16 @@ use super::{Comparator, CompareResult};
 use crate::core::misc::ResultType;
 use anyhow::anyhow;
 
-pub struct SimpleLineComparator;
+/// 逐行比较，忽略行末空白与末尾空行
+pub struct SimpleLineComparator {
+    // 不一致时展示的上下文长度(字节)
+    pub context_length: usize,
+}
+impl SimpleLineComparator {
+    pub fn new(context_length: usize) -> Self {
+        return Self { context_length };
+    }
+}
 #[async_trait]
 impl Comparator for SimpleLineComparator {
     async fn compare(
@@ -15,25 +24,124 @@ impl Comparator for SimpleLineComparator {
         answer: Arc<Vec<u8>>,
         _input_data: Arc<Vec<u8>>,
         full_score: i64,
+        _exit_code: Option<i32>,
     ) -> ResultType<CompareResult> {
-        let resp = tokio::task::spawn_blocking(move || compare(&user_out, &answer, full_score))
-            .await
-            .map_err(|e| anyhow!("Failed to compare: {}", e))?;
+        let context_length = self.context_length;
+        let resp = tokio::task::spawn_blocking(move || {
+            compare(&user_out, &answer, full_score, context_length)
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to compare: {}", e))?;
         return resp;
     }
 }
-fn compare(user_out: &[u8], answer: &[u8], full_score: i64) -> ResultType<CompareResult> {
+/// 按行切分，同时记录每行在全文中的起始字节位置
+fn split_lines(text: &str) -> Vec<(usize, &str)> {
+    let mut ret = vec![];
+    let mut offset = 0;
+    for line in text.split("\n") {
+        ret.push((offset, line));
+        offset += line.len() + 1;
+    }
+    while !ret.is_empty() && ret.last().unwrap().1.trim_end() == "" {
+        ret.pop();
+    }
+    return ret;
+}
+/// 两个字符串第一个不同字符的字节位置
+fn first_difference(a: &str, b: &str) -> usize {
+    for ((pos, x), y) in a.char_indices().zip(b.chars()) {
+        if x != y {
+            return pos;
+        }
+    }
+    return a.len().min(b.len());
+}
+/// pos处所在的由空白分隔的单词，pos处为空白时取其后的单词，位于行末时为空
+fn token_at(line: &str, pos: usize) -> &str {
+    if pos >= line.len() {
+        return "";
+    }
+    let rest = &line[pos..];
+    let skipped = rest.len()
+        - rest
+            .trim_start_matches(|c: char| c.is_ascii_whitespace())
+            .len();
+    let (start, pos) = if skipped > 0 {
+        (pos + skipped, pos + skipped)
+    } else {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_ascii_whitespace())
+            .map(|v| v + 1)
+            .unwrap_or(0);
+        (start, pos)
+    };
+    let end = line[pos..]
+        .find(|c: char| c.is_ascii_whitespace())
+        .map(|v| pos + v)
+        .unwrap_or(line.len());
+    return &line[start..end];
+}
+/// pos前后各context字节的片段
+fn excerpt(text: &str, pos: usize, context: usize) -> String {
+    let mut start = pos.saturating_sub(context);
+    let mut end = pos.saturating_add(context).min(text.len());
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    return format!(
+        "{}{:?}{}",
+        if start > 0 { "..." } else { "" },
+        &text[start..end],
+        if end < text.len() { "..." } else { "" }
+    );
+}
+pub fn compare(
+    user_out: &[u8],
+    answer: &[u8],
+    full_score: i64,
+    context_length: usize,
+) -> ResultType<CompareResult> {
     let t1 =
         String::from_utf8(user_out.into()).map_err(|e| anyhow!("Failed to decode chars: {}", e))?;
     let t2 =
         String::from_utf8(answer.into()).map_err(|e| anyhow!("Failed to decode chars: {}", e))?;
-    let mut user_lines = t1.split("\n").collect::<Vec<&str>>();
-    let mut answer_lines = t2.split("\n").collect::<Vec<&str>>();
-    while !user_lines.is_empty() && user_lines.last().unwrap().trim_end() == "" {
-        user_lines.pop();
-    }
-    while !answer_lines.is_empty() && answer_lines.last().unwrap().trim_end() == "" {
-        answer_lines.pop();
+    let user_lines = split_lines(&t1);
+    let answer_lines = split_lines(&t2);
+    for (i, ((user_offset, user), (answer_offset, answer))) in
+        user_lines.iter().zip(answer_lines.iter()).enumerate()
+    {
+        let user = user.trim_end();
+        let answer = answer.trim_end();
+        if user != answer {
+            let pos = first_difference(user, answer);
+            let token_or_eol = |line: &str| {
+                let token = token_at(line, pos);
+                if token.is_empty() {
+                    return "<end of line>".to_string();
+                }
+                return format!("{:?}", token);
+            };
+            return Ok(CompareResult {
+                message: format!(
+                    "Different at line {} (from 0): expected {}, received {}\n\
+                    Byte offset: expected {}, received {}\n\
+                    Expected: {}\nReceived: {}",
+                    i,
+                    token_or_eol(answer),
+                    token_or_eol(user),
+                    answer_offset + pos,
+                    user_offset + pos,
+                    excerpt(&t2, answer_offset + pos, context_length),
+                    excerpt(&t1, user_offset + pos, context_length)
+                ),
+                score: 0,
+                feedback: vec![],
+            });
+        }
     }
     if user_lines.len() != answer_lines.len() {
         return Ok(CompareResult {
@@ -43,22 +151,108 @@ fn compare(user_out: &[u8], answer: &[u8], full_score: i64) -> ResultType<Compar
                 user_lines.len()
             ),
             score: 0,
+            feedback: vec![],
         });
     }
-    for (i, (user, answer)) in user_lines
-        .into_iter()
-        .zip(answer_lines.into_iter())
-        .enumerate()
-    {
-        if user.trim_end() != answer.trim_end() {
-            return Ok(CompareResult {
-                message: format!("Different at line {} (from 0)", i),
-                score: 0,
-            });
-        }
-    }
     return Ok(CompareResult {
         message: "OK!".to_string(),
         score: full_score,
+        feedback: vec![],
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, excerpt, first_difference, token_at};
+
+    #[test]
+    fn first_difference_is_byte_offset() {
+        assert_eq!(first_difference("abc", "abd"), 2);
+        assert_eq!(first_difference("abc", "abc"), 3);
+        assert_eq!(first_difference("ab", "abc"), 2);
+        assert_eq!(first_difference("", "abc"), 0);
+        // 每个汉字占3字节
+        assert_eq!(first_difference("答案正确", "答案错误"), 6);
+        assert_eq!(first_difference("答案", "答案正确"), 6);
+        // 首字节相同的不同字符
+        assert_eq!(first_difference("一丁", "一七"), 3);
+        assert_eq!(first_difference("a😀b", "a😀c"), 5);
+    }
+
+    #[test]
+    fn excerpt_keeps_char_boundaries() {
+        let text = "你好，世界😀 hello 再见";
+        for pos in (0..=text.len()).filter(|v| text.is_char_boundary(*v)) {
+            for context in 0..=text.len() + 1 {
+                // 切在多字节字符中间时会panic
+                excerpt(text, pos, context);
+            }
+        }
+        assert_eq!(excerpt(text, 3, 1), "\"你好\"...");
+        assert_eq!(excerpt(text, 6, 2), "...\"好，\"...");
+        assert_eq!(excerpt(text, 0, 0), "\"\"...");
+        assert_eq!(excerpt(text, text.len(), 0), "...\"\"");
+        assert_eq!(excerpt(text, 0, usize::MAX), format!("{:?}", text));
+    }
+
+    #[test]
+    fn token_at_multibyte() {
+        assert_eq!(token_at("结果 错误 答案", 7), "错误");
+        assert_eq!(token_at("结果 错误 答案", 10), "错误");
+        // 位于空白处时取其后的单词
+        assert_eq!(token_at("结果 错误", 6), "错误");
+        assert_eq!(token_at("结果 错误", 13), "");
+        assert_eq!(token_at("", 0), "");
+    }
+
+    #[test]
+    fn reports_offsets_of_cjk_output() {
+        let result = compare(
+            "第一行\n答案 正确\n".as_bytes(),
+            "第一行\n答案 错误\n".as_bytes(),
+            10,
+            4,
+        )
+        .unwrap();
+        assert_eq!(result.score, 0);
+        assert!(
+            result.message.contains("line 1 (from 0)"),
+            "{}",
+            result.message
+        );
+        assert!(result
+            .message
+            .contains("expected \"错误\", received \"正确\""));
+        // 第二行从第10字节开始，差异在行内第7字节
+        assert!(result
+            .message
+            .contains("Byte offset: expected 17, received 17"));
+    }
+
+    #[test]
+    fn reports_end_of_line() {
+        let result = compare("1 2\n".as_bytes(), "1 2 3\n".as_bytes(), 10, 20).unwrap();
+        assert!(result
+            .message
+            .contains("expected \"3\", received <end of line>"));
+        let result = compare("答".as_bytes(), "答案".as_bytes(), 10, 0).unwrap();
+        assert_eq!(result.score, 0);
+        assert!(result
+            .message
+            .contains("Byte offset: expected 3, received 3"));
+    }
+
+    #[test]
+    fn ignores_trailing_whitespace_and_lines() {
+        let result = compare("1 2  \n3\n\n\n".as_bytes(), "1 2\n3".as_bytes(), 10, 20).unwrap();
+        assert_eq!(result.score, 10);
+        let result = compare("1\n2\n".as_bytes(), "1\n2\n3\n".as_bytes(), 10, 20).unwrap();
+        assert_eq!(result.score, 0);
+        assert_eq!(result.message, "Expected 3 lines, received 2 lines");
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        assert!(compare(&[0xe4, 0xbd], "你".as_bytes(), 10, 20).is_err());
+    }
+}
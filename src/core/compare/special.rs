@@ -3,13 +3,25 @@ use std::{
     sync::Arc,
 };
 
-use crate::core::{misc::ResultType, model::LanguageConfig, runner::docker::execute_in_docker};
+use crate::core::{
+    config::{JudgerConfig, TrustedProfile},
+    limits::{MemoryLimit, TimeLimit},
+    misc::{input_error, ResultType},
+    model::LanguageConfig,
+    runner::{ExecuteOptions, Runner},
+    state::AppState,
+    upload::sha256_hex,
+    util::{copy_dir, evict_problem_program_cache, touch_cache_entry},
+};
 use anyhow::anyhow;
 use async_trait::async_trait;
-use log::info;
+use log::{error, info, warn};
 use tempfile::TempDir;
+use tokio::sync::Mutex;
 const SPJ_FILENAME: &str = "specialjudge";
-use super::{Comparator, CompareResult};
+// SPJ可以写入附加文件的目录
+const FEEDBACK_DIR: &str = "feedback";
+use super::{Comparator, CompareResult, FeedbackFile};
 
 /*
     SPJ可以为任何所支持的语言编写的程序，但是文件名格式应该为 spj_语言ID.xxx,扩展名不限
@@ -18,17 +30,88 @@ use super::{Comparator, CompareResult};
     评测时spj所在目录下将会有以下文件:
     user_out: 用户程序输出
     answer: 测试点标准答案
+    exit_code: 用户程序的退出代码(仅当题目将退出代码视为答案的一部分时存在)
     SPJ应该在限制的时间内将结果输出到以下文件
-    score: 该测试点得分(0~100,自动折合)
+    score: 该测试点得分(默认为0~100的整数,自动折合;题目可以通过spj_score_scale指定其他范围)
     message: 发送给用户的信息
+    feedback/: 可选，其中的文件(总大小不超过spj_feedback_size_limit)会附加在测试点结果中
 */
+/// SPJ写入score文件的分数的范围，由题目的spj_score_scale指定
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpjScoreScale {
+    // 0~100的整数，按比例折合(默认)
+    Percent,
+    // 0~N的数(可以为小数，如0~1)，按比例折合
+    Max(f64),
+    // 直接为测试点得分(0~测试点分值)，小数四舍五入
+    FullScore,
+}
+impl SpjScoreScale {
+    /// ""与"percent"为Percent，"full_score"为FullScore，正数N为0~N
+    pub fn parse(value: &str) -> ResultType<Self> {
+        return match value.trim() {
+            "" | "percent" => Ok(Self::Percent),
+            "full_score" => Ok(Self::FullScore),
+            v => match v.parse::<f64>() {
+                Ok(max) if max.is_finite() && max > 0.0 => Ok(Self::Max(max)),
+                _ => Err(anyhow!(
+                    "Invalid spj_score_scale `{}`: expected percent, full_score or a positive number",
+                    v
+                )),
+            },
+        };
+    }
+    fn describe(&self, full_score: i64) -> String {
+        return match self {
+            Self::Percent => "integer in [0, 100]".to_string(),
+            Self::Max(max) => format!("number in [0, {}]", max),
+            Self::FullScore => format!("number in [0, {}] (full score)", full_score),
+        };
+    }
+    /// 将score文件的内容换算为测试点得分，超出范围或无法解析时返回错误
+    pub fn convert(&self, raw: &str, full_score: i64) -> ResultType<i64> {
+        let raw = raw.trim();
+        let invalid = || {
+            anyhow!(
+                "Invalid SPJ score `{}`: expected {}",
+                raw,
+                self.describe(full_score)
+            )
+        };
+        let (score, max) = match self {
+            Self::Percent => (raw.parse::<i64>().map_err(|_| invalid())? as f64, 100.0),
+            Self::Max(max) => (raw.parse::<f64>().map_err(|_| invalid())?, *max),
+            Self::FullScore => (
+                raw.parse::<f64>().map_err(|_| invalid())?,
+                full_score as f64,
+            ),
+        };
+        if !score.is_finite() || score < 0.0 || score > max {
+            return Err(invalid());
+        }
+        if let Self::FullScore = self {
+            return Ok(score.round() as i64);
+        }
+        return Ok((score / max * (full_score as f64)).round() as i64);
+    }
+}
+
 pub struct SpecialJudgeComparator {
     spj_file: PathBuf,
     // status_updater: T,
     language_config: LanguageConfig,
-    run_time_limit: i64,
+    // 运行时使用的语言定义，jvm语言的堆大小适应容器的内存限制
+    run_language_config: LanguageConfig,
+    run_time_limit: TimeLimit,
+    memory_limit: MemoryLimit,
     docker_image: String,
+    runner: Arc<dyn Runner>,
     working_dir: TempDir,
+    // 所有测试点共用同一个工作目录，并行评测的子任务需要依次使用
+    working_dir_lock: Mutex<()>,
+    // feedback目录中收集的文件总大小上限(字节)
+    feedback_size_limit: u64,
+    score_scale: SpjScoreScale,
 }
 #[async_trait]
 impl Comparator for SpecialJudgeComparator {
@@ -38,50 +121,186 @@ impl Comparator for SpecialJudgeComparator {
         answer: Arc<Vec<u8>>,
         input_data: Arc<Vec<u8>>,
         full_score: i64,
+        exit_code: Option<i32>,
     ) -> ResultType<CompareResult> {
         return self
-            .my_compare(user_out, answer, input_data, full_score)
+            .my_compare(user_out, answer, input_data, full_score, exit_code)
             .await;
     }
 }
-impl SpecialJudgeComparator {
-    pub async fn compile(&self) -> ResultType<()> {
-        // let working_path = PathBuf::from("/spj");
-        let working_path = self.working_dir.path();
-        let source_filename = self.language_config.source(SPJ_FILENAME);
-        let output_filename = self.language_config.output(SPJ_FILENAME);
-        tokio::fs::copy(
-            self.spj_file.as_path(),
-            &working_path.join(&source_filename),
-        )
+/// 在working_path下以name为文件名编译题目提供的程序(SPJ、交互器等)
+pub async fn compile_problem_program(
+    runner: &dyn Runner,
+    docker_image: &str,
+    working_path: &Path,
+    source_file: &Path,
+    language_config: &LanguageConfig,
+    name: &str,
+    profile: &TrustedProfile,
+) -> ResultType<()> {
+    let source_filename = language_config.source(name);
+    let output_filename = language_config.output(name);
+    tokio::fs::copy(source_file, &working_path.join(&source_filename))
         .await
-        .map_err(|e| anyhow!("Failed to create special judge program: {}", e))?;
-        info!("SPJ working dir: {}", working_path.to_str().unwrap_or(""));
-        let compile_cmdline = self
-            .language_config
-            .compile_s(&source_filename, &output_filename, "")
-            .split_ascii_whitespace()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>();
-        let run_result = execute_in_docker(
-            &self.docker_image,
+        .map_err(|e| anyhow!("Failed to create {}: {}", name, e))?;
+    info!(
+        "Compiling {} at: {}",
+        name,
+        working_path.to_str().unwrap_or("")
+    );
+    let compile_cmdline = language_config
+        .compile_s(&source_filename, &output_filename, "")
+        .split_ascii_whitespace()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>();
+    let run_result = runner
+        .execute(
+            docker_image,
             working_path.to_str().unwrap_or(""),
             &compile_cmdline,
-            1024 * 1024 * 1024,
-            10 * 1000 * 1000,
+            profile.compile_memory_limit.as_bytes(),
+            profile.compile_time_limit.as_micros(),
             1024 * 1024,
+            &ExecuteOptions::default(),
         )
         .await
-        .map_err(|e| anyhow!("Failed to compile special judge program: {}", e))?;
-        info!("SPJ compile result:\n{:#?}", run_result);
-        if !working_path.join(output_filename).exists() || run_result.exit_code != 0 {
-            return Err(anyhow!(
-                "Failed to compile special judge program (exit code = {}):\n{}",
-                run_result.exit_code,
-                run_result.output
-            ));
+        .map_err(|e| anyhow!("Failed to compile {}: {}", name, e))?;
+    info!("Compile result of {}:\n{:#?}", name, run_result);
+    // 题目程序本身的编译错误，重试不会改变结果
+    if !working_path.join(output_filename).exists() || run_result.exit_code != 0 {
+        return Err(input_error(format!(
+            "Failed to compile {} (exit code = {}):\n{}",
+            name, run_result.exit_code, run_result.output
+        )));
+    }
+    return Ok(());
+}
+/// 带缓存地编译题目程序，编译后的工作目录保存在cache_root/<sha256>下
+/// 键由源代码、编译命令、评测镜像与程序名共同决定，任意一个变化都会重新编译
+pub async fn compile_problem_program_cached(
+    app: &AppState,
+    runner: &dyn Runner,
+    docker_image: &str,
+    cache_root: &Path,
+    working_path: &Path,
+    source_file: &Path,
+    language_config: &LanguageConfig,
+    name: &str,
+) -> ResultType<()> {
+    let language_config = &language_config.with_container_limit(
+        &app.config,
+        app.config.trusted_profile.compile_memory_limit.as_bytes(),
+    );
+    let source = tokio::fs::read(source_file)
+        .await
+        .map_err(|e| anyhow!("Failed to read source of {}: {}", name, e))?;
+    let mut key_data = source;
+    for part in [language_config.compile.as_str(), docker_image, name] {
+        key_data.push(0);
+        key_data.extend_from_slice(part.as_bytes());
+    }
+    let key = sha256_hex(&key_data);
+    let cache_dir = cache_root.join(&key);
+    let guard = app
+        .compile_cache_locks
+        .lock(&cache_dir.to_str().unwrap_or("").to_string())
+        .await;
+    if cache_dir.exists() {
+        info!("Using cached {}: {}", name, key);
+        touch_cache_entry(&cache_dir);
+        return copy_dir(&cache_dir, working_path).await;
+    }
+    compile_problem_program(
+        runner,
+        docker_image,
+        working_path,
+        source_file,
+        language_config,
+        name,
+        &app.config.trusted_profile,
+    )
+    .await?;
+    // 先复制到临时目录再改名，避免中途失败留下不完整的缓存；缓存失败不影响评测
+    let store = async {
+        let temp_dir = cache_root.join(format!("{}.tmp", key));
+        if temp_dir.exists() {
+            tokio::fs::remove_dir_all(&temp_dir).await?;
+        }
+        tokio::fs::create_dir_all(&temp_dir).await?;
+        copy_dir(working_path, &temp_dir).await?;
+        tokio::fs::rename(&temp_dir, &cache_dir).await?;
+        return ResultType::<()>::Ok(());
+    };
+    if let Err(e) = store.await {
+        error!("Failed to cache compiled {}: {}", name, e);
+    }
+    // 新的缓存项通常意味着旧版本的程序不再使用
+    drop(guard);
+    if let Err(e) = evict_problem_program_cache(app, cache_root).await {
+        error!("Failed to evict compile cache: {}", e);
+    }
+    return Ok(());
+}
+/// 按文件名顺序收集dir下的普通文件，总大小超过size_limit的文件被跳过
+async fn collect_feedback(dir: &Path, size_limit: u64) -> ResultType<Vec<FeedbackFile>> {
+    let mut entries = vec![];
+    let mut reader = tokio::fs::read_dir(dir)
+        .await
+        .map_err(|e| anyhow!("Failed to read feedback dir: {}", e))?;
+    while let Some(entry) = reader
+        .next_entry()
+        .await
+        .map_err(|e| anyhow!("Failed to read feedback dir: {}", e))?
+    {
+        entries.push(entry);
+    }
+    entries.sort_by_key(|v| v.file_name());
+    let mut ret = vec![];
+    let mut total = 0;
+    for entry in entries.into_iter() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // 不跟随符号链接，避免读取工作目录外的文件
+        let metadata = match entry.metadata().await {
+            Ok(v) if v.is_file() => v,
+            _ => {
+                warn!("Ignored feedback entry: {}", name);
+                continue;
+            }
+        };
+        if total + metadata.len() > size_limit {
+            warn!(
+                "Feedback file {} ({} bytes) exceeds size limit, skipped",
+                name,
+                metadata.len()
+            );
+            continue;
         }
-        return Ok(());
+        total += metadata.len();
+        let content = tokio::fs::read(entry.path())
+            .await
+            .map_err(|e| anyhow!("Failed to read feedback file {}: {}", name, e))?;
+        ret.push(FeedbackFile {
+            name,
+            content: base64::encode(content),
+        });
+    }
+    return Ok(ret);
+}
+impl SpecialJudgeComparator {
+    /// 编译SPJ，结果缓存在cache_root下
+    pub async fn compile(&self, app: &AppState, cache_root: &Path) -> ResultType<()> {
+        // let working_path = PathBuf::from("/spj");
+        return compile_problem_program_cached(
+            app,
+            &*self.runner,
+            &self.docker_image,
+            cache_root,
+            self.working_dir.path(),
+            self.spj_file.as_path(),
+            &self.language_config,
+            SPJ_FILENAME,
+        )
+        .await;
     }
     async fn my_compare(
         &self,
@@ -89,9 +308,21 @@ impl SpecialJudgeComparator {
         answer: Arc<Vec<u8>>,
         input_data: Arc<Vec<u8>>,
         full_score: i64,
+        exit_code: Option<i32>,
     ) -> ResultType<CompareResult> {
+        let _guard = self.working_dir_lock.lock().await;
         // let working_path = PathBuf::from("/spj");
         let working_path = self.working_dir.path();
+        let exit_code_file = working_path.join("exit_code");
+        if let Some(code) = exit_code {
+            tokio::fs::write(&exit_code_file, code.to_string())
+                .await
+                .map_err(|e| anyhow!("Failed to write exit_code: {}", e))?;
+        } else if exit_code_file.exists() {
+            tokio::fs::remove_file(&exit_code_file)
+                .await
+                .map_err(|e| anyhow!("Failed to remove exit_code: {}", e))?;
+        }
         tokio::fs::write(working_path.join("user_out"), &*user_out)
             .await
             .map_err(|e| anyhow!("Failed to write user_out: {}", e))?;
@@ -101,27 +332,40 @@ impl SpecialJudgeComparator {
         tokio::fs::write(working_path.join("input"), &*input_data)
             .await
             .map_err(|e| anyhow!("Failed to write input: {}", e))?;
+        let feedback_dir = working_path.join(FEEDBACK_DIR);
+        if feedback_dir.exists() {
+            tokio::fs::remove_dir_all(&feedback_dir)
+                .await
+                .map_err(|e| anyhow!("Failed to clear feedback dir: {}", e))?;
+        }
+        tokio::fs::create_dir(&feedback_dir)
+            .await
+            .map_err(|e| anyhow!("Failed to create feedback dir: {}", e))?;
         // let run_cmdline =
         //     .map(|v| v.to_string())
         //     .collect::<Vec<String>>();
         let run_cmdline = vec![
             "sh".to_string(),
             "-c".to_string(),
-            self.language_config
+            self.run_language_config
                 .run_s(&self.language_config.output(SPJ_FILENAME), ""),
         ];
         info!("Run special judge program: {:?}", run_cmdline);
-        let run_result = execute_in_docker(
-            &self.docker_image,
-            working_path.to_str().unwrap_or(""),
-            &run_cmdline,
-            2048 * 2048 * 2048,
-            self.run_time_limit,
-            1024 * 1024,
-        )
-        .await
-        .map_err(|e| anyhow!("Failed to run special judge program: {}", e))?;
+        let run_result = self
+            .runner
+            .execute(
+                &self.docker_image,
+                working_path.to_str().unwrap_or(""),
+                &run_cmdline,
+                self.memory_limit.as_bytes(),
+                self.run_time_limit.as_micros(),
+                1024 * 1024,
+                &ExecuteOptions::default(),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run special judge program: {}", e))?;
         info!("SPJ run result: {:#?}", run_result);
+        let feedback = collect_feedback(&feedback_dir, self.feedback_size_limit).await?;
         let usage_message = format!(
             "{} MB, {} ms",
             run_result.memory_cost / 1024 / 1024,
@@ -142,6 +386,7 @@ impl SpecialJudgeComparator {
                     run_result.exit_code, usage_message, message
                 ),
                 score: 0,
+                feedback,
             });
         }
         let score_file = working_path.join("score");
@@ -149,38 +394,46 @@ impl SpecialJudgeComparator {
             return Ok(CompareResult {
                 message: "SPJ exited with no score file".to_string(),
                 score: 0,
+                feedback,
             });
         } else {
             tokio::fs::read_to_string(score_file)
                 .await
                 .map_err(|e| anyhow!("Failed to read score: {}", e))?
         };
-        let score = i64::from_str_radix(&score_str, 10)
-            .map_err(|e| anyhow!("Failed to parse score: {}", e))?;
-
-        if score < 0 || score > 100 {
-            return Err(anyhow!("Invalid score: {}", score));
-        }
         return Ok(CompareResult {
             message,
-            score: (score as f64 / 100.0 * (full_score as f64)).round() as i64,
+            score: self.score_scale.convert(&score_str, full_score)?,
+            feedback,
         });
     }
     pub fn try_new(
         spj_file: &Path,
         // status_updater: T,
         language_config: &LanguageConfig,
-        run_time_limit: i64,
+        run_time_limit: TimeLimit,
         docker_image: String,
+        runner: Arc<dyn Runner>,
+        working_dir: TempDir,
+        feedback_size_limit: u64,
+        config: &JudgerConfig,
+        score_scale: SpjScoreScale,
     ) -> ResultType<Self> {
+        let profile = &config.trusted_profile;
         Ok(Self {
             docker_image,
+            runner,
             // status_updater,
             language_config: language_config.clone(),
-            run_time_limit,
+            run_language_config: language_config
+                .with_container_limit(config, profile.memory_limit.as_bytes()),
+            run_time_limit: profile.run_time_limit(run_time_limit),
+            memory_limit: profile.memory_limit,
             spj_file: spj_file.to_path_buf(),
-            working_dir: tempfile::tempdir()
-                .map_err(|e| anyhow!("Failed to create spj working directory: {}", e))?,
+            working_dir,
+            working_dir_lock: Mutex::new(()),
+            feedback_size_limit,
+            score_scale,
         })
     }
 }
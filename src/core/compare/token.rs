@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::{Comparator, CompareResult};
+use crate::core::misc::ResultType;
+use anyhow::anyhow;
+
+/// 按任意空白切分后逐个比较单词，忽略所有空白与空行的差异
+pub struct TokenComparator {
+    pub case_insensitive: bool,
+}
+#[async_trait]
+impl Comparator for TokenComparator {
+    async fn compare(
+        &self,
+        user_out: Arc<Vec<u8>>,
+        answer: Arc<Vec<u8>>,
+        _input_data: Arc<Vec<u8>>,
+        full_score: i64,
+        _exit_code: Option<i32>,
+    ) -> ResultType<CompareResult> {
+        let case_insensitive = self.case_insensitive;
+        let resp = tokio::task::spawn_blocking(move || {
+            compare(&user_out, &answer, full_score, case_insensitive)
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to compare: {}", e))?;
+        return resp;
+    }
+}
+pub fn compare(
+    user_out: &[u8],
+    answer: &[u8],
+    full_score: i64,
+    case_insensitive: bool,
+) -> ResultType<CompareResult> {
+    let t1 =
+        String::from_utf8(user_out.into()).map_err(|e| anyhow!("Failed to decode chars: {}", e))?;
+    let t2 =
+        String::from_utf8(answer.into()).map_err(|e| anyhow!("Failed to decode chars: {}", e))?;
+    let mut user_tokens = t1.split_whitespace();
+    let mut answer_tokens = t2.split_whitespace();
+    let mut i = 0;
+    loop {
+        match (user_tokens.next(), answer_tokens.next()) {
+            (None, None) => break,
+            (Some(user), Some(answer)) => {
+                let same = if case_insensitive {
+                    user.to_lowercase() == answer.to_lowercase()
+                } else {
+                    user == answer
+                };
+                if !same {
+                    return Ok(CompareResult {
+                        message: format!(
+                            "Different at token {} (from 0): expected {:?}, received {:?}",
+                            i, answer, user
+                        ),
+                        score: 0,
+                        feedback: vec![],
+                    });
+                }
+            }
+            (Some(user), None) => {
+                return Ok(CompareResult {
+                    message: format!(
+                        "Expected {} tokens, received more: {:?} at token {}",
+                        i, user, i
+                    ),
+                    score: 0,
+                    feedback: vec![],
+                });
+            }
+            (None, Some(answer)) => {
+                return Ok(CompareResult {
+                    message: format!(
+                        "Received {} tokens, expected more: {:?} at token {}",
+                        i, answer, i
+                    ),
+                    score: 0,
+                    feedback: vec![],
+                });
+            }
+        }
+        i += 1;
+    }
+    return Ok(CompareResult {
+        message: "OK!".to_string(),
+        score: full_score,
+        feedback: vec![],
+    });
+}
@@ -1,15 +1,320 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
+use super::{
+    limits::{
+        MemoryLimit, TimeLimit, INTERACTOR_MEMORY_LIMIT, PROBLEM_PROGRAM_COMPILE_MEMORY_LIMIT,
+        PROBLEM_PROGRAM_COMPILE_TIME_LIMIT, TRUSTED_PROGRAM_MEMORY_LIMIT,
+    },
+    misc::ResultType,
+    model::LanguageConfig,
+    runner::seccomp::DEFAULT_DENY_SYSCALLS,
+    runner::IoLimit,
+    scan::ForbiddenRule,
+    units,
+};
+
+// 未配置queues时消费的队列，与celery的默认队列相同
+pub const DEFAULT_QUEUE: &str = "celery";
+
+/// 评测机消费的celery队列
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QueueConfig {
+    pub name: String,
+    // 该队列最多同时取出的任务数(即该队列的prefetch_count)，至少为2
+    pub weight: u16,
+}
+
+/// 题目提供的可信程序(SPJ、交互器)的限制，与用户程序的限制分开配置
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TrustedProfile {
+    // 编译的内存限制(MB)与时间限制(ms)
+    #[serde(deserialize_with = "units::size_mb")]
+    pub compile_memory_limit: MemoryLimit,
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub compile_time_limit: TimeLimit,
+    // 运行的内存限制(MB)
+    #[serde(deserialize_with = "units::size_mb")]
+    pub memory_limit: MemoryLimit,
+    // 交互器运行的内存限制(MB)
+    #[serde(deserialize_with = "units::size_mb")]
+    pub interactor_memory_limit: MemoryLimit,
+    // SPJ运行时间限制(ms)的下限，服务端下发的限制更小时使用该值
+    #[serde(deserialize_with = "units::option_duration_ms")]
+    pub min_time_limit: Option<TimeLimit>,
+}
+impl Default for TrustedProfile {
+    fn default() -> Self {
+        return Self {
+            compile_memory_limit: PROBLEM_PROGRAM_COMPILE_MEMORY_LIMIT,
+            compile_time_limit: PROBLEM_PROGRAM_COMPILE_TIME_LIMIT,
+            memory_limit: TRUSTED_PROGRAM_MEMORY_LIMIT,
+            interactor_memory_limit: INTERACTOR_MEMORY_LIMIT,
+            min_time_limit: None,
+        };
+    }
+}
+impl TrustedProfile {
+    /// 实际使用的运行时间限制
+    pub fn run_time_limit(&self, time_limit: TimeLimit) -> TimeLimit {
+        return match self.min_time_limit {
+            Some(v) => v.max(time_limit),
+            None => time_limit,
+        };
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct JudgerConfig {
     pub broker_url: String,
     pub data_dir: String,
     pub web_api_url: String,
+    // API路径前缀，接在web_api_url之后
+    pub api_prefix: String,
     pub judger_uuid: String,
     pub docker_image: String,
+    // 启动时除docker_image与sql_image外还需检查的镜像
+    pub required_images: Vec<String>,
+    // 镜像 -> 期望的digest(sha256:...)，与镜像id或repo digest比较
+    pub image_digests: BTreeMap<String, String>,
+    // 镜像不存在时自动拉取
+    pub pull_missing_images: bool,
     pub logging_level: String,
+    // 日志格式，text或json(每行一个JSON对象)
+    pub log_format: String,
     pub prefetch_count: u16,
     pub max_tasks_sametime: usize,
+    // bytes
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub artifact_chunk_size: usize,
+    // 评测任务日志的目录，为空时不记录；日志包含源代码且不会自动删除
+    pub journal_dir: String,
+    // 检测用户程序遗留的后台进程与定时器，并在测试点信息中提示
+    pub detect_suspicious_processes: bool,
+    // 评测测试点时采样用户程序内存与CPU占用的间隔(毫秒)，0为不采样(仅docker后端)
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub resource_timeline_interval: u64,
+    // 每个测试点最多保留的采样数
+    pub resource_timeline_max_samples: usize,
+    // "docker" 或 "nsjail"
+    pub sandbox_backend: String,
+    pub nsjail_path: String,
+    // nsjail沙箱的根目录，必须是只包含运行环境的最小根文件系统，不能为宿主机的根目录
+    pub nsjail_chroot: String,
+    // 编译与同一个子任务的各测试点在同一个容器中以exec方式运行(仅docker后端)
+    pub reuse_container: bool,
+    // 容器结束后收集输出的时间上限(毫秒)与字节数上限(仅docker后端)
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub log_collect_timeout: u64,
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub log_collect_max_bytes: usize,
+    // 沙箱中程序的环境变量(KEY=VALUE)，如固定的语言区域与时区
+    pub sandbox_env: Vec<String>,
+    // 清除镜像设置的环境变量，只保留sandbox_env
+    pub scrub_sandbox_env: bool,
+    // 关闭地址空间随机化，使程序的行为可以重现(用于调试)
+    pub disable_aslr: bool,
+    // 容器内运行程序使用的用户，格式为uid或uid:gid，为空时使用镜像的默认用户(通常为root)
+    pub sandbox_user: String,
+    // 用户程序容器的seccomp配置: docker(docker默认配置)、deny_list(仅禁止seccomp_deny_syscalls中的调用)、unconfined、JSON字符串或JSON文件路径
+    pub seccomp_profile: String,
+    // 语言ID -> seccomp配置，格式同seccomp_profile
+    pub language_seccomp_profiles: BTreeMap<String, String>,
+    // seccomp_profile为deny_list时禁止的系统调用
+    pub seccomp_deny_syscalls: Vec<String>,
+    // 发布评测事件的Redis，为空时不发布
+    pub event_redis_url: String,
+    // Stream的key或Pub/Sub的频道名
+    pub event_channel: String,
+    // "stream" 或 "pubsub"
+    pub event_channel_type: String,
+    // 发布评测进度的Redis，为空时不发布
+    pub progress_redis_url: String,
+    // 进度频道名的前缀，频道名为前缀加提交ID
+    pub progress_channel_prefix: String,
+    // 一个提交因评测机自身的问题失败时最多评测的次数(包括第一次)
+    pub judge_max_attempts: u32,
+    // 评测失败后重试的间隔(秒)
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub judge_retry_delay: u32,
+    // 达到最大尝试次数后写入失败记录的Redis list(位于broker上)，为空时不写入
+    pub dead_letter_queue: String,
+    // 取消标记(位于broker上)的key前缀，key为前缀加提交ID
+    pub cancel_key_prefix: String,
+    // 转发管理命令(清空缓存等)的Pub/Sub频道(位于broker上)，为空时管理命令只在取出任务的评测机上执行
+    pub admin_broadcast_channel: String,
+    // 评测测试点时检查取消标记的间隔(毫秒)，0为不检查
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub cancel_poll_interval: u64,
+    // 评测时间限制的缩放系数，服务端未指定且未启用校准时使用
+    pub time_scale: f64,
+    // 启动时运行基准程序，自动得出time_scale
+    pub calibrate_time_scale: bool,
+    // 基准程序在基准机器上的运行时间, ms，0为未设置，此时不能校准
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub calibration_reference_time: i64,
+    // 同一个提交最多同时评测的子任务数(样例子任务总是最先单独评测)
+    pub parallel_subtasks: usize,
+    // 时间限制作用于CPU时间，墙上时间超过时间限制的该倍数时强制结束
+    pub wall_time_limit_factor: f64,
+    // memory_limit_policy为jvm的语言，运行时容器的内存限制为内存限制的该倍数再加上vm_memory_overhead(MB)
+    pub vm_memory_overhead_factor: f64,
+    #[serde(deserialize_with = "units::size_mb")]
+    pub vm_memory_overhead: i64,
+    // 限制磁盘IO的块设备(仅docker后端)，为空时不限制读写速率
+    pub io_throttle_device: String,
+    // 评测容器对io_throttle_device的读写速率上限(字节每秒)，0为不限制，题目可以单独指定
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub io_read_bps: i64,
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub io_write_bps: i64,
+    // 运行用户程序的容器内进程与线程总数的上限，0为不限制
+    pub pids_limit: i64,
+    // 测试数据生成器的输出文件与工作目录写入量上限(字节)
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub generator_output_limit: i64,
+    // 评测时用户程序向工作目录写入数据的总量上限(字节)，0为不限制，评测任务可以单独指定
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub workdir_size_limit: i64,
+    // 评测容器的blkio权重(10~1000)，0为使用docker默认值
+    pub io_weight: u16,
+    // 评测工作目录的存放位置，为空时使用系统临时目录；不为空时该目录应由本评测机独占，启动时会清理遗留的工作目录
+    pub work_dir_root: String,
+    // 创建工作目录时要求的最小可用空间(MB)
+    #[serde(deserialize_with = "units::size_mb")]
+    pub work_dir_min_free_space: i64,
+    // 逐行比较不一致时，向选手展示的期望输出与实际输出片段的上下文长度(字节)
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub compare_context_length: usize,
+    // 样例子任务(samples_subtask)运行错误时附加在测试点信息中的标准错误长度(字符)，0为不附加
+    pub stderr_excerpt_length: usize,
+    // SPJ的feedback目录中收集的文件总大小上限(字节)
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub spj_feedback_size_limit: u64,
+    // 在限制资源的子进程中运行内置比较器，避免恶意的超大输出影响评测机进程
+    pub isolated_compare: bool,
+    // 比较子进程的内存限制(MB)
+    #[serde(deserialize_with = "units::size_mb")]
+    pub isolated_compare_memory_limit: u64,
+    // 比较子进程的CPU时间限制(秒)，墙上时间限制为其两倍
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub isolated_compare_time_limit: u64,
+    // 运行比较子进程的可执行文件(评测机自身)，为空时在启动时取当前可执行文件
+    pub compare_helper_path: String,
+    // 提交的每个程序的代码长度上限(字节)，题目可以单独指定，0为不限制
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub max_code_length: usize,
+    // SQL题执行脚本的命令，{database}替换为数据库文件，{script}替换为脚本文件
+    pub sql_command: String,
+    // SQL题使用的镜像，为空时使用docker_image
+    pub sql_image: String,
+    // 启用的内置插件: telemetry_log
+    pub plugins: Vec<String>,
+    // 编译前对源代码的静态检查规则，与服务端随评测任务下发的规则一同生效
+    pub forbidden_rules: Vec<ForbiddenRule>,
+    // SPJ、交互器等题目程序的限制
+    pub trusted_profile: TrustedProfile,
+    // 用户程序编译结果的缓存目录，为空时不缓存；重测大量提交时可避免重复编译相同的代码
+    pub compile_cache_dir: String,
+    // 编译缓存的有效期(秒)
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub compile_cache_ttl: u64,
+    // 题目数据目录下SPJ、交互器与grader的编译缓存超过该时间(秒)未使用时删除，0为不删除
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub problem_program_cache_ttl: u64,
+    // 消费的队列，为空时只消费默认队列(celery)并使用prefetch_count
+    // 每个队列使用单独的连接，低优先级队列(如重测)设置较小的权重，避免其任务占满评测机
+    pub queues: Vec<QueueConfig>,
+    // 影子模式: 照常消费并评测任务，但不向服务端报告任何状态，用于在正式启用前对比新版本评测机的结果
+    pub shadow_mode: bool,
+    // 影子模式下接收最终评测结果(JSON)的地址，为空时只写入journal_dir
+    pub shadow_endpoint: String,
+    // 同一用户在该时间(秒)内向同一题目重复提交相同代码时直接沿用上次的评测结果，0为不启用
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub duplicate_submission_window: u64,
+    // 向服务端发送心跳的间隔(秒)，0为不发送
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub heartbeat_interval: u64,
+    // 定期输出评测机进程资源占用的间隔(秒)，0为不输出
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub process_stats_interval: u64,
+    // 在每个提交评测开始与结束时输出评测机进程的资源占用
+    pub log_phase_process_stats: bool,
+    // 评测机进程内存占用(MB)超过该值时警告，0为不检查
+    #[serde(deserialize_with = "units::size_mb")]
+    pub rss_warn_mb: i64,
+    // 评测机进程打开的文件描述符数超过该值时警告，0为不检查
+    pub open_fds_warn: usize,
+    // 工作目录总大小(MB)超过该值时警告，0为不检查
+    #[serde(deserialize_with = "units::size_mb")]
+    pub work_dir_warn_mb: u64,
+    // 测试点总数超过该值时逐个子任务报告评测结果，且只报告有变化的子任务，0为不启用(需要服务端支持partial)
+    pub report_chunk_threshold: usize,
+    // 逐个子任务报告时judge_result的编码: 为空时为普通的JSON，columnar为逐列编码(需要服务端支持)
+    pub report_encoding: String,
+    // 本地的语言定义，键为语言ID，优先于服务端的语言定义
+    pub languages: BTreeMap<String, LanguageConfig>,
+    // 本评测机支持的语言ID，为空时不限制，其他语言的任务转交给其他评测机
+    pub supported_languages: Vec<String>,
+    // 一个任务最多被转交的次数，达到后按评测失败处理
+    pub max_requeue: u32,
+    // 转交的任务发布到的队列，为空时使用默认队列
+    pub requeue_queue: String,
+    // 语言定义的缓存有效期(秒)，过期后向服务端确认是否有变化，0为每次都重新获取
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub language_config_ttl: u64,
+    // 调用服务端API遇到网络错误或HTTP 5xx时的最大重试次数
+    pub api_retry_attempts: u32,
+    // 重试间隔(毫秒)，从api_retry_base_delay开始倍增，不超过api_retry_max_delay
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub api_retry_base_delay: u64,
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub api_retry_max_delay: u64,
+    // 发送给服务端的状态信息的最大长度(字节)，超出时截断，0为不限制
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub status_message_limit: usize,
+    // 评测信息被截断时，将完整信息作为评测产物上传，并在信息中附上产物ID
+    pub upload_full_message: bool,
+    // 交互题中用户程序与交互器都在运行但超过该时间(毫秒)没有数据交互时判为空闲超时，0为不检测
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub interactive_idle_limit: i64,
+    // 题目数据的同步方式，files为逐个文件同步，archive为下载整个数据包
+    pub testdata_sync_mode: String,
+    // 整包同步时数据包的大小上限(字节)，超过时停止下载并退回逐个文件同步，0为不限制
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub data_archive_max_bytes: u64,
+    // 数据包解压出的文件总大小上限(字节)，0为不限制
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub archive_max_extracted_bytes: u64,
+    // 提交答案题的答案压缩包解压出的文件总大小上限(字节)，答案解压到内存中，0为不限制
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub answer_max_extracted_bytes: u64,
+    // 题目数据目录的大小上限(字节)，超过时删除最久未使用的题目数据，0为不限制
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub data_dir_max_bytes: u64,
+    // 接收评测机告警(评测出错、题目数据超出上限、磁盘空间不足、启动自检失败)的webhook地址
+    pub webhook_urls: Vec<String>,
+    // 不为空时使用HMAC-SHA256对请求体签名
+    pub webhook_secret: String,
+    // 等待发送的通知数上限，超过时丢弃新的通知
+    pub webhook_queue_size: usize,
+    // 发送失败时的最大重试次数
+    pub webhook_max_retries: u32,
+    // 同一种事件两次通知的最小间隔(秒)，期间的事件只计数
+    #[serde(deserialize_with = "units::duration_secs")]
+    pub webhook_min_interval: u64,
+    // 不为空时使用该密钥对最终评测结果签名，水印附在评测信息与finished事件中
+    pub result_watermark_key: String,
+    // 题目提供的post_judge脚本的运行时间限制(ms)，0为不运行该脚本
+    #[serde(deserialize_with = "units::duration_ms")]
+    pub post_judge_time_limit: i64,
+    // post_judge脚本写入的单个文件大小与工作目录写入量上限(字节)
+    #[serde(deserialize_with = "units::size_bytes")]
+    pub post_judge_disk_limit: i64,
 }
 
 impl Default for JudgerConfig {
@@ -18,26 +323,212 @@ impl Default for JudgerConfig {
             broker_url: "redis://127.0.0.1".to_string(),
             data_dir: "testdata".to_string(),
             web_api_url: "http://127.0.0.1:8080/".to_string(),
+            api_prefix: "/api".to_string(),
             judger_uuid: "7222dcd8-96fb-11ec-864e-9cda3efd56be".to_string(),
             docker_image: "python".to_string(),
+            required_images: vec![],
+            image_digests: BTreeMap::new(),
+            pull_missing_images: true,
             logging_level: "info".to_string(),
+            log_format: "text".to_string(),
             prefetch_count: 2,
             max_tasks_sametime: 1,
+            artifact_chunk_size: 1024 * 1024,
+            journal_dir: "".to_string(),
+            detect_suspicious_processes: false,
+            resource_timeline_interval: 0,
+            resource_timeline_max_samples: 100,
+            sandbox_backend: "docker".to_string(),
+            nsjail_path: "nsjail".to_string(),
+            nsjail_chroot: "".to_string(),
+            reuse_container: false,
+            log_collect_timeout: 3000,
+            log_collect_max_bytes: 4 * 1024 * 1024,
+            sandbox_env: vec![],
+            scrub_sandbox_env: false,
+            disable_aslr: false,
+            sandbox_user: "".to_string(),
+            seccomp_profile: "docker".to_string(),
+            language_seccomp_profiles: BTreeMap::new(),
+            seccomp_deny_syscalls: DEFAULT_DENY_SYSCALLS
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+            event_redis_url: "".to_string(),
+            event_channel: "hj3:judge_events".to_string(),
+            event_channel_type: "stream".to_string(),
+            progress_redis_url: "".to_string(),
+            progress_channel_prefix: "judge_progress:".to_string(),
+            judge_max_attempts: 3,
+            judge_retry_delay: 60,
+            dead_letter_queue: "hj3:dead_letter".to_string(),
+            cancel_key_prefix: "hj3:cancel:".to_string(),
+            admin_broadcast_channel: "hj3:admin".to_string(),
+            cancel_poll_interval: 1000,
+            time_scale: 1.02,
+            calibrate_time_scale: false,
+            calibration_reference_time: 0,
+            parallel_subtasks: 1,
+            wall_time_limit_factor: 2.0,
+            vm_memory_overhead_factor: 1.25,
+            vm_memory_overhead: 64,
+            io_throttle_device: "".to_string(),
+            io_read_bps: 0,
+            io_write_bps: 0,
+            pids_limit: 128,
+            generator_output_limit: 1024 * 1024 * 1024,
+            workdir_size_limit: 0,
+            io_weight: 0,
+            work_dir_root: "".to_string(),
+            work_dir_min_free_space: 512,
+            compare_context_length: 20,
+            stderr_excerpt_length: 0,
+            spj_feedback_size_limit: 64 * 1024,
+            isolated_compare: false,
+            isolated_compare_memory_limit: 2048,
+            isolated_compare_time_limit: 10,
+            compare_helper_path: "".to_string(),
+            max_code_length: 0,
+            sql_command: "sqlite3 -bail {database} < {script}".to_string(),
+            sql_image: "".to_string(),
+            plugins: vec![],
+            forbidden_rules: vec![],
+            trusted_profile: TrustedProfile::default(),
+            compile_cache_dir: "".to_string(),
+            compile_cache_ttl: 24 * 60 * 60,
+            problem_program_cache_ttl: 7 * 24 * 60 * 60,
+            queues: vec![],
+            shadow_mode: false,
+            shadow_endpoint: "".to_string(),
+            duplicate_submission_window: 0,
+            heartbeat_interval: 0,
+            process_stats_interval: 0,
+            log_phase_process_stats: false,
+            rss_warn_mb: 0,
+            open_fds_warn: 0,
+            work_dir_warn_mb: 0,
+            report_chunk_threshold: 0,
+            report_encoding: "".to_string(),
+            languages: BTreeMap::new(),
+            supported_languages: vec![],
+            max_requeue: 3,
+            requeue_queue: "".to_string(),
+            language_config_ttl: 300,
+            api_retry_attempts: 4,
+            api_retry_base_delay: 500,
+            api_retry_max_delay: 10000,
+            status_message_limit: 60000,
+            upload_full_message: true,
+            interactive_idle_limit: 0,
+            testdata_sync_mode: "files".to_string(),
+            data_archive_max_bytes: 4 * 1024 * 1024 * 1024,
+            archive_max_extracted_bytes: 16 * 1024 * 1024 * 1024,
+            answer_max_extracted_bytes: 256 * 1024 * 1024,
+            data_dir_max_bytes: 0,
+            webhook_urls: vec![],
+            webhook_secret: "".to_string(),
+            webhook_queue_size: 64,
+            webhook_max_retries: 5,
+            webhook_min_interval: 300,
+            result_watermark_key: "".to_string(),
+            post_judge_time_limit: 10000,
+            post_judge_disk_limit: 1024 * 1024 * 1024,
         }
     }
 }
 
+/// 找出无法解析的配置项: 逐个配置项单独解析，出错时再深入其子项
+/// 返回配置项的路径(如trusted_profile.memory_limit)与错误
+fn find_invalid_key(
+    value: &serde_yaml::Value,
+    path: &mut Vec<String>,
+) -> Option<(String, serde_yaml::Error)> {
+    for (key, item) in value.as_mapping()?.iter() {
+        let key = match key.as_str() {
+            Some(v) => v,
+            None => continue,
+        };
+        path.push(key.to_string());
+        let document = path.iter().rev().fold(item.clone(), |acc, key| {
+            let mut mapping = serde_yaml::Mapping::new();
+            mapping.insert(serde_yaml::Value::String(key.clone()), acc);
+            serde_yaml::Value::Mapping(mapping)
+        });
+        if let Err(e) = serde_yaml::from_value::<JudgerConfig>(document) {
+            return find_invalid_key(item, path).or(Some((path.join("."), e)));
+        }
+        path.pop();
+    }
+    return None;
+}
+
 impl JudgerConfig {
-    pub fn suburl(&self, sub: &str) -> String {
-        let t = if sub.starts_with("/") {
-            sub.trim_start_matches("/").to_string()
-        } else {
-            sub.to_string()
+    /// 解析配置文件，出错时指出出错的配置项
+    pub fn parse(content: &str) -> ResultType<Self> {
+        let value =
+            serde_yaml::from_str::<serde_yaml::Value>(content).map_err(|e| anyhow!("{}", e))?;
+        return serde_yaml::from_value::<Self>(value.clone()).map_err(|e| {
+            match find_invalid_key(&value, &mut vec![]) {
+                Some((key, e)) => anyhow!("Invalid config `{}`: {}", key, e),
+                None => anyhow!("{}", e),
+            }
+        });
+    }
+    /// 消费的全部队列名
+    pub fn queue_names(&self) -> Vec<String> {
+        if self.queues.is_empty() {
+            return vec![DEFAULT_QUEUE.to_string()];
+        }
+        return self.queues.iter().map(|v| v.name.clone()).collect();
+    }
+    /// 拼接API地址，web_api_url可以带有路径(如https://host/oj/)，结尾的斜线可省略
+    pub fn suburl(&self, sub: &str) -> ResultType<String> {
+        let mut base = url::Url::parse(&self.web_api_url)
+            .map_err(|e| anyhow!("Invalid web_api_url: {}, {}", self.web_api_url, e))?;
+        if base.cannot_be_a_base() {
+            return Err(anyhow!("Invalid web_api_url: {}", self.web_api_url));
+        }
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        let path = [
+            self.api_prefix.trim_matches('/'),
+            sub.trim_start_matches('/'),
+        ]
+        .into_iter()
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<&str>>()
+        .join("/");
+        let suburl = base
+            .join(&path)
+            .map_err(|e| anyhow!("Invalid api url: {}, {}", path, e))?;
+        return Ok(suburl.to_string());
+    }
+    /// 解析sandbox_user，格式为uid或uid:gid，为空时返回None
+    pub fn sandbox_user(&self) -> ResultType<Option<(u32, u32)>> {
+        if self.sandbox_user.is_empty() {
+            return Ok(None);
+        }
+        let parse = |v: &str| {
+            v.parse::<u32>()
+                .map_err(|e| anyhow!("Invalid sandbox_user {}: {}", self.sandbox_user, e))
+        };
+        return match self.sandbox_user.split_once(':') {
+            Some((uid, gid)) => Ok(Some((parse(uid)?, parse(gid)?))),
+            None => {
+                let uid = parse(&self.sandbox_user)?;
+                Ok(Some((uid, uid)))
+            }
+        };
+    }
+    /// 评测机全局的磁盘IO限制
+    pub fn io_limit(&self) -> IoLimit {
+        return IoLimit {
+            device: self.io_throttle_device.clone(),
+            read_bps: self.io_read_bps,
+            write_bps: self.io_write_bps,
+            weight: self.io_weight,
         };
-        let suburl = url::Url::parse(&self.web_api_url)
-            .unwrap()
-            .join(&t)
-            .unwrap();
-        return suburl.to_string();
     }
 }
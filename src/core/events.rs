@@ -0,0 +1,125 @@
+use anyhow::anyhow;
+use log::{debug, error};
+use redis::aio::ConnectionManager;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use super::{config::JudgerConfig, misc::ResultType, progress::publish_progress, state::AppState};
+
+// Stream保留的大致条数
+const STREAM_MAX_LENGTH: usize = 10000;
+
+/// 将评测过程中的事件发布到Redis Stream或Pub/Sub频道
+/// 评测进度也通过该类型发布，此时channel为频道前缀，实际频道为前缀加提交ID
+pub struct EventPublisher {
+    client: redis::Client,
+    connection: Mutex<Option<ConnectionManager>>,
+    channel: String,
+    use_pubsub: bool,
+}
+
+impl EventPublisher {
+    /// event_redis_url为空时返回None
+    pub fn new(config: &JudgerConfig) -> ResultType<Option<Self>> {
+        if config.event_redis_url.is_empty() {
+            return Ok(None);
+        }
+        let use_pubsub = match config.event_channel_type.as_str() {
+            "stream" => false,
+            "pubsub" => true,
+            other => return Err(anyhow!("Unknown event channel type: {}", other)),
+        };
+        return Ok(Some(Self::open(
+            &config.event_redis_url,
+            &config.event_channel,
+            use_pubsub,
+        )?));
+    }
+    /// 发布评测进度的Pub/Sub频道，progress_redis_url为空时返回None
+    pub fn for_progress(config: &JudgerConfig) -> ResultType<Option<Self>> {
+        if config.progress_redis_url.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(Self::open(
+            &config.progress_redis_url,
+            &config.progress_channel_prefix,
+            true,
+        )?));
+    }
+    fn open(redis_url: &str, channel: &str, use_pubsub: bool) -> ResultType<Self> {
+        let client =
+            redis::Client::open(redis_url).map_err(|e| anyhow!("Invalid redis url: {}", e))?;
+        return Ok(Self {
+            client,
+            connection: Mutex::new(None),
+            channel: channel.to_string(),
+            use_pubsub,
+        });
+    }
+    async fn connection(&self) -> ResultType<ConnectionManager> {
+        let mut guard = self.connection.lock().await;
+        if let Some(v) = guard.as_ref() {
+            return Ok(v.clone());
+        }
+        let conn = ConnectionManager::new(self.client.clone())
+            .await
+            .map_err(|e| anyhow!("Failed to connect to redis: {}", e))?;
+        *guard = Some(conn.clone());
+        return Ok(conn);
+    }
+    pub async fn publish(&self, payload: &str) -> ResultType<()> {
+        return self.publish_to(&self.channel, payload).await;
+    }
+    /// 发布到channel加上suffix的频道
+    pub async fn publish_with_suffix(&self, suffix: &str, payload: &str) -> ResultType<()> {
+        return self
+            .publish_to(&format!("{}{}", self.channel, suffix), payload)
+            .await;
+    }
+    async fn publish_to(&self, channel: &str, payload: &str) -> ResultType<()> {
+        let mut conn = self.connection().await?;
+        let cmd = if self.use_pubsub {
+            let mut cmd = redis::cmd("PUBLISH");
+            cmd.arg(channel).arg(payload);
+            cmd
+        } else {
+            let mut cmd = redis::cmd("XADD");
+            cmd.arg(channel)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(STREAM_MAX_LENGTH)
+                .arg("*")
+                .arg("event")
+                .arg(payload);
+            cmd
+        };
+        cmd.query_async::<_, redis::Value>(&mut conn)
+            .await
+            .map_err(|e| anyhow!("Failed to publish event: {}", e))?;
+        return Ok(());
+    }
+}
+
+/// 发布一个评测事件，同时发布到该提交的进度频道，失败时只记录日志，不影响评测
+pub async fn publish_event(app: &AppState, submission_id: i64, event: &str, data: Value) {
+    if !app.report_status {
+        return;
+    }
+    let payload = json!({
+        "judger": app.config.judger_uuid,
+        "submission_id": submission_id,
+        "event": event,
+        "time": chrono::Local::now().timestamp_millis(),
+        "data": data
+    });
+    publish_progress(app, submission_id, &payload).await;
+    let publisher = match app.event_publisher.as_ref() {
+        Some(v) => v,
+        None => return,
+    };
+    let payload = payload.to_string();
+    debug!("Publishing event: {}", payload);
+    if let Err(e) = publisher.publish(&payload).await {
+        error!("Failed to publish event {}: {}", event, e);
+    }
+}
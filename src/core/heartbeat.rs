@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use log::{error, info};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+
+use super::{
+    api::Heartbeat,
+    misc::ResultType,
+    process_stats::collect_process_stats,
+    state::GLOBAL_APP_STATE,
+    webhook::{notify_webhook, EVENT_DISK_LOW},
+    workdir::work_dir_free_space,
+};
+
+/// 各个消费队列中等待的任务数，broker为各次心跳共用的连接，断开后自动重连
+async fn queue_depth(
+    broker: &mut Option<ConnectionManager>,
+    broker_url: &str,
+    queues: &[String],
+) -> ResultType<serde_json::Value> {
+    if broker.is_none() {
+        let client =
+            redis::Client::open(broker_url).map_err(|e| anyhow!("Invalid broker url: {}", e))?;
+        *broker = Some(
+            ConnectionManager::new(client)
+                .await
+                .map_err(|e| anyhow!("Failed to connect to broker: {}", e))?,
+        );
+    }
+    let conn = broker.as_mut().unwrap();
+    let mut ret = serde_json::Map::new();
+    for queue in queues.iter() {
+        let length = redis::cmd("LLEN")
+            .arg(queue)
+            .query_async::<_, i64>(conn)
+            .await
+            .map_err(|e| anyhow!("Failed to get length of queue {}: {}", queue, e))?;
+        ret.insert(queue.clone(), json!(length));
+    }
+    return Ok(serde_json::Value::Object(ret));
+}
+
+async fn docker_available() -> bool {
+    return match bollard::Docker::connect_with_socket_defaults() {
+        Ok(docker) => docker.ping().await.is_ok(),
+        Err(_) => false,
+    };
+}
+
+async fn send_heartbeat(broker: &mut Option<ConnectionManager>) -> ResultType<()> {
+    // 只在读取本地状态时持有全局状态的锁，网络请求前释放
+    let (mut heartbeat, api, broker_url, queues, check_docker) = {
+        let guard = GLOBAL_APP_STATE.read().await;
+        let app = guard.as_ref().ok_or(anyhow!("App state not initialized"))?;
+        let config = &app.config;
+        let free_space = work_dir_free_space(config).unwrap_or(-1);
+        if free_space >= 0 && free_space < config.work_dir_min_free_space * 1024 * 1024 {
+            notify_webhook(
+                app,
+                EVENT_DISK_LOW,
+                json!({
+                    "free_mb": free_space / 1024 / 1024,
+                    "required_mb": config.work_dir_min_free_space
+                }),
+            );
+        }
+        let lock_stats = app.file_dir_locks.stats();
+        let process_stats = tokio::task::block_in_place(|| collect_process_stats(app))
+            .ok()
+            .and_then(|v| serde_json::to_string(&v).ok())
+            .unwrap_or("null".to_string());
+        let heartbeat = Heartbeat {
+            version: app.version_string.clone(),
+            load: config.max_tasks_sametime - app.task_count_lock.available_permits(),
+            max_load: config.max_tasks_sametime,
+            queue_depth: String::new(),
+            docker_available: false,
+            disk_free: free_space / 1024 / 1024,
+            file_lock_stats: json!({
+                "active": lock_stats.active,
+                "acquired": lock_stats.acquired,
+                "contended": lock_stats.contended,
+            })
+            .to_string(),
+            process_stats,
+            time_scale: app.time_scale,
+        };
+        (
+            heartbeat,
+            app.api.clone(),
+            config.broker_url.clone(),
+            config.queue_names(),
+            config.sandbox_backend == "docker",
+        )
+    };
+    heartbeat.queue_depth = match queue_depth(broker, &broker_url, &queues).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to get queue depth: {}", e);
+            serde_json::Value::Null
+        }
+    }
+    .to_string();
+    heartbeat.docker_available = check_docker && docker_available().await;
+    api.send_heartbeat(&heartbeat).await?;
+    return Ok(());
+}
+
+/// 每隔interval秒向服务端发送一次心跳，interval为0时不发送
+pub fn spawn_heartbeat(interval: u64) {
+    if interval == 0 {
+        return;
+    }
+    info!("Sending heartbeat every {} seconds", interval);
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(Duration::from_secs(interval));
+        let mut broker = None;
+        loop {
+            timer.tick().await;
+            if let Err(e) = send_heartbeat(&mut broker).await {
+                error!("Failed to send heartbeat: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,37 @@
+use std::{path::Path, sync::Arc};
+
+use async_trait::async_trait;
+
+use super::{
+    misc::ResultType,
+    runner::{ExecuteOptions, ExecuteResult},
+};
+#[derive(Debug)]
+pub struct InteractResult {
+    // 用户程序的运行结果
+    pub user_result: ExecuteResult,
+    pub score: i64,
+    pub message: String,
+    // 两个程序都在运行但长时间没有数据流动，管道被评测机关闭
+    pub idle_limit_exceeded: bool,
+}
+#[async_trait]
+pub trait Interactor: Sync + Send {
+    /// 运行用户程序并与其交互
+    /// user_command应将标准输入输出重定向到INTERACT_USER_INPUT与INTERACT_USER_OUTPUT
+    async fn interact(
+        &self,
+        user_dir: &Path,
+        user_command: &Vec<String>,
+        // in bytes
+        memory_limit: i64,
+        // in microsecond
+        time_limit: i64,
+        options: &ExecuteOptions,
+        input_data: Arc<Vec<u8>>,
+        answer: Arc<Vec<u8>>,
+        full_score: i64,
+    ) -> ResultType<InteractResult>;
+}
+
+pub mod special;
@@ -0,0 +1,204 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::core::{
+    compare::special::{compile_problem_program_cached, SpjScoreScale},
+    config::JudgerConfig,
+    limits::{MemoryLimit, TimeLimit},
+    misc::ResultType,
+    model::LanguageConfig,
+    runner::{
+        execute_interactive, ExecuteOptions, Runner, INTERACTOR_INPUT, INTERACTOR_OUTPUT,
+        INTERACT_PIPE_DIR,
+    },
+    state::AppState,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::info;
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+const INTERACTOR_FILENAME: &str = "interactor";
+use super::{InteractResult, Interactor};
+
+/*
+    交互器可以为任何所支持的语言编写的程序，文件名格式应该为 interactor_语言ID.xxx,扩展名不限
+    交互器的标准输出将作为用户程序的标准输入，用户程序的标准输出将作为交互器的标准输入
+    交互器运行时间限制为用户程序时间限制加上SPJ时间限制
+    评测时交互器所在目录下将会有以下文件:
+    input: 测试点输入数据
+    answer: 测试点标准答案
+    交互器应该在退出前将结果输出到以下文件
+    score: 该测试点得分(与SPJ相同，默认为0~100的整数,自动折合;题目可以通过spj_score_scale指定其他范围)
+    message: 发送给用户的信息
+*/
+pub struct SpecialInteractor {
+    interactor_file: PathBuf,
+    language_config: LanguageConfig,
+    // 运行时使用的语言定义，jvm语言的堆大小适应容器的内存限制
+    run_language_config: LanguageConfig,
+    // 在用户程序时间限制之外额外给予交互器的时间
+    extra_time_limit: TimeLimit,
+    memory_limit: MemoryLimit,
+    docker_image: String,
+    runner: Arc<dyn Runner>,
+    working_dir: TempDir,
+    // 所有测试点共用同一个工作目录，并行评测的子任务需要依次使用
+    working_dir_lock: Mutex<()>,
+    // 空闲超时, in millisecond, 0为不检测
+    idle_limit: i64,
+    score_scale: SpjScoreScale,
+}
+#[async_trait]
+impl Interactor for SpecialInteractor {
+    async fn interact(
+        &self,
+        user_dir: &Path,
+        user_command: &Vec<String>,
+        memory_limit: i64,
+        time_limit: i64,
+        options: &ExecuteOptions,
+        input_data: Arc<Vec<u8>>,
+        answer: Arc<Vec<u8>>,
+        full_score: i64,
+    ) -> ResultType<InteractResult> {
+        let _guard = self.working_dir_lock.lock().await;
+        let working_path = self.working_dir.path();
+        for file in ["score", "message"] {
+            let path = working_path.join(file);
+            if path.exists() {
+                tokio::fs::remove_file(path)
+                    .await
+                    .map_err(|e| anyhow!("Failed to remove old {} file: {}", file, e))?;
+            }
+        }
+        tokio::fs::write(working_path.join("answer"), &*answer)
+            .await
+            .map_err(|e| anyhow!("Failed to write answer: {}", e))?;
+        tokio::fs::write(working_path.join("input"), &*input_data)
+            .await
+            .map_err(|e| anyhow!("Failed to write input: {}", e))?;
+        let interactor_cmdline = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            self.run_language_config.run_s(
+                &self.language_config.output(INTERACTOR_FILENAME),
+                &format!(
+                    "> {}/{} < {}/{}",
+                    INTERACT_PIPE_DIR, INTERACTOR_OUTPUT, INTERACT_PIPE_DIR, INTERACTOR_INPUT
+                ),
+            ),
+        ];
+        info!("Run interactor: {:?}", interactor_cmdline);
+        let (user_result, interactor_result, idle_limit_exceeded) = execute_interactive(
+            &*self.runner,
+            &self.docker_image,
+            user_dir
+                .to_str()
+                .ok_or(anyhow!("Invalid working directory"))?,
+            user_command,
+            memory_limit,
+            time_limit,
+            options,
+            working_path.to_str().unwrap_or(""),
+            &interactor_cmdline,
+            self.memory_limit.as_bytes(),
+            time_limit + self.extra_time_limit.as_micros(),
+            self.idle_limit,
+        )
+        .await?;
+        if idle_limit_exceeded {
+            // 管道被关闭后交互器的结果没有意义
+            return Ok(InteractResult {
+                user_result,
+                score: 0,
+                message: format!("超过 {} ms 没有数据交互", self.idle_limit),
+                idle_limit_exceeded,
+            });
+        }
+        info!("Interactor run result: {:#?}", interactor_result);
+        let message_file = working_path.join("message");
+        let message = if message_file.exists() {
+            tokio::fs::read_to_string(message_file)
+                .await
+                .map_err(|e| anyhow!("Failed to read message file: {}", e))?
+        } else {
+            "".to_string()
+        };
+        let score_file = working_path.join("score");
+        let score_str = if interactor_result.exit_code != 0 {
+            return Ok(InteractResult {
+                user_result,
+                score: 0,
+                message: format!(
+                    "Interactor exited: {}|{}",
+                    interactor_result.exit_code, message
+                ),
+                idle_limit_exceeded,
+            });
+        } else if !score_file.exists() {
+            return Ok(InteractResult {
+                user_result,
+                score: 0,
+                message: "Interactor exited with no score file".to_string(),
+                idle_limit_exceeded,
+            });
+        } else {
+            tokio::fs::read_to_string(score_file)
+                .await
+                .map_err(|e| anyhow!("Failed to read score: {}", e))?
+        };
+        return Ok(InteractResult {
+            user_result,
+            score: self.score_scale.convert(&score_str, full_score)?,
+            message,
+            idle_limit_exceeded,
+        });
+    }
+}
+impl SpecialInteractor {
+    /// 编译交互器，结果缓存在cache_root下
+    pub async fn compile(&self, app: &AppState, cache_root: &Path) -> ResultType<()> {
+        return compile_problem_program_cached(
+            app,
+            &*self.runner,
+            &self.docker_image,
+            cache_root,
+            self.working_dir.path(),
+            self.interactor_file.as_path(),
+            &self.language_config,
+            INTERACTOR_FILENAME,
+        )
+        .await;
+    }
+    pub fn try_new(
+        interactor_file: &Path,
+        language_config: &LanguageConfig,
+        extra_time_limit: TimeLimit,
+        docker_image: String,
+        runner: Arc<dyn Runner>,
+        working_dir: TempDir,
+        idle_limit: i64,
+        score_scale: SpjScoreScale,
+        config: &JudgerConfig,
+    ) -> ResultType<Self> {
+        let profile = &config.trusted_profile;
+        Ok(Self {
+            runner,
+            interactor_file: interactor_file.to_path_buf(),
+            language_config: language_config.clone(),
+            run_language_config: language_config
+                .with_container_limit(config, profile.interactor_memory_limit.as_bytes()),
+            // 交互器的时间为用户程序的时间限制加上额外时间，min_time_limit只作用于SPJ
+            extra_time_limit,
+            memory_limit: profile.interactor_memory_limit,
+            docker_image,
+            working_dir,
+            working_dir_lock: Mutex::new(()),
+            idle_limit,
+            score_scale,
+        })
+    }
+}
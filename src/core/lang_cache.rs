@@ -0,0 +1,73 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::model::LanguageConfig;
+
+struct CachedLanguageConfig {
+    config: LanguageConfig,
+    // 响应内容的sha256，过期后用于向服务端确认是否有变化
+    etag: String,
+    fetched_at: Instant,
+}
+
+/// 从服务端获取的语言定义的缓存，键为语言ID
+#[derive(Default)]
+pub struct LanguageConfigCache {
+    entries: Mutex<HashMap<String, CachedLanguageConfig>>,
+}
+
+impl LanguageConfigCache {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+    /// 未过期的缓存
+    pub fn get_fresh(&self, language_id: &str, ttl: Duration) -> Option<LanguageConfig> {
+        return self
+            .entries
+            .lock()
+            .unwrap()
+            .get(language_id)
+            .filter(|v| v.fetched_at.elapsed() < ttl)
+            .map(|v| v.config.clone());
+    }
+    /// 已经过期的缓存的etag
+    pub fn etag(&self, language_id: &str) -> Option<String> {
+        return self
+            .entries
+            .lock()
+            .unwrap()
+            .get(language_id)
+            .map(|v| v.etag.clone());
+    }
+    /// 服务端确认没有变化，重新开始计算有效期
+    pub fn revalidate(&self, language_id: &str) -> Option<LanguageConfig> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(language_id)?;
+        entry.fetched_at = Instant::now();
+        return Some(entry.config.clone());
+    }
+    pub fn store(&self, language_id: &str, config: LanguageConfig, etag: String) {
+        self.entries.lock().unwrap().insert(
+            language_id.to_string(),
+            CachedLanguageConfig {
+                config,
+                etag,
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+    /// 清除一种语言的缓存，返回是否存在
+    pub fn remove(&self, language_id: &str) -> bool {
+        return self.entries.lock().unwrap().remove(language_id).is_some();
+    }
+    /// 清空缓存，返回清除的条目数
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        return count;
+    }
+}
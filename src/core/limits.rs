@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+// 服务端下发的时间限制允许的范围, ms
+const MIN_TIME_LIMIT: i64 = 1;
+const MAX_TIME_LIMIT: i64 = 10 * 60 * 1000;
+// 服务端下发的内存限制允许的范围, MB
+const MIN_MEMORY_LIMIT: i64 = 1;
+const MAX_MEMORY_LIMIT: i64 = 64 * 1024;
+
+/// 时间限制，以毫秒为单位收发，内部以微秒保存
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "i64", into = "i64")]
+pub struct TimeLimit {
+    micros: i64,
+}
+impl TimeLimit {
+    pub const fn from_millis(millis: i64) -> Self {
+        return Self {
+            micros: millis * 1000,
+        };
+    }
+    pub const fn from_secs(secs: i64) -> Self {
+        return Self::from_millis(secs * 1000);
+    }
+    pub fn as_micros(&self) -> i64 {
+        return self.micros;
+    }
+    pub fn as_millis(&self) -> i64 {
+        return self.micros / 1000;
+    }
+    /// 按系数缩放，用于time_scale等
+    pub fn scale(&self, factor: f64) -> Self {
+        return Self {
+            micros: (self.micros as f64 * factor) as i64,
+        };
+    }
+}
+impl TryFrom<i64> for TimeLimit {
+    type Error = String;
+    fn try_from(millis: i64) -> Result<Self, Self::Error> {
+        if millis < MIN_TIME_LIMIT || millis > MAX_TIME_LIMIT {
+            return Err(format!(
+                "Time limit out of range: {} ms (expected {}~{})",
+                millis, MIN_TIME_LIMIT, MAX_TIME_LIMIT
+            ));
+        }
+        return Ok(Self::from_millis(millis));
+    }
+}
+impl From<TimeLimit> for i64 {
+    fn from(v: TimeLimit) -> Self {
+        return v.as_millis();
+    }
+}
+
+/// 内存限制，以MB为单位收发，内部以字节保存
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "i64", into = "i64")]
+pub struct MemoryLimit {
+    bytes: i64,
+}
+impl MemoryLimit {
+    pub const fn from_megabytes(megabytes: i64) -> Self {
+        return Self {
+            bytes: megabytes * 1024 * 1024,
+        };
+    }
+    pub fn as_bytes(&self) -> i64 {
+        return self.bytes;
+    }
+    pub fn as_megabytes(&self) -> i64 {
+        return self.bytes / 1024 / 1024;
+    }
+}
+impl TryFrom<i64> for MemoryLimit {
+    type Error = String;
+    fn try_from(megabytes: i64) -> Result<Self, Self::Error> {
+        if megabytes < MIN_MEMORY_LIMIT || megabytes > MAX_MEMORY_LIMIT {
+            return Err(format!(
+                "Memory limit out of range: {} MB (expected {}~{})",
+                megabytes, MIN_MEMORY_LIMIT, MAX_MEMORY_LIMIT
+            ));
+        }
+        return Ok(Self::from_megabytes(megabytes));
+    }
+}
+impl From<MemoryLimit> for i64 {
+    fn from(v: MemoryLimit) -> Self {
+        return v.as_megabytes();
+    }
+}
+
+// 编译用户程序时的内存限制
+pub const COMPILE_MEMORY_LIMIT: MemoryLimit = MemoryLimit::from_megabytes(2048);
+// 编译SPJ、交互器等题目程序时的限制
+pub const PROBLEM_PROGRAM_COMPILE_MEMORY_LIMIT: MemoryLimit = MemoryLimit::from_megabytes(1024);
+pub const PROBLEM_PROGRAM_COMPILE_TIME_LIMIT: TimeLimit = TimeLimit::from_secs(10);
+// 运行SPJ等题目程序时的内存限制
+pub const TRUSTED_PROGRAM_MEMORY_LIMIT: MemoryLimit = MemoryLimit::from_megabytes(8192);
+// 运行交互器时的内存限制，交互器与用户程序同时运行，限制更小
+pub const INTERACTOR_MEMORY_LIMIT: MemoryLimit = MemoryLimit::from_megabytes(1024);
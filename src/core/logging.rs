@@ -0,0 +1,43 @@
+use std::future::Future;
+
+use flexi_logger::{DeferredNow, Record, TS_DASHES_BLANK_COLONS_DOT_BLANK};
+use serde_json::json;
+
+/// 当前任务的上下文，JSON格式的日志中会带上这些字段
+#[derive(Debug, Clone, Default)]
+pub struct LogContext {
+    pub submission_id: Option<i64>,
+    pub run_id: Option<String>,
+}
+
+tokio::task_local! {
+    static LOG_CONTEXT: LogContext;
+}
+
+/// 在context下运行future，其中输出的日志都会带上context(tokio::spawn出的任务除外)
+pub async fn with_log_context<F: Future>(context: LogContext, future: F) -> F::Output {
+    return LOG_CONTEXT.scope(context, future).await;
+}
+
+/// 每行一个JSON对象，便于日志收集系统按提交筛选
+pub fn json_log_format(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), std::io::Error> {
+    let context = LOG_CONTEXT.try_with(|v| v.clone()).unwrap_or_default();
+    let mut line = json!({
+        "time": now.format(TS_DASHES_BLANK_COLONS_DOT_BLANK),
+        "level": record.level().to_string(),
+        "module": record.module_path().unwrap_or("<unnamed>"),
+        "line": record.line().unwrap_or(0),
+        "message": record.args().to_string(),
+    });
+    if let Some(v) = context.submission_id {
+        line["submission_id"] = json!(v);
+    }
+    if let Some(v) = context.run_id {
+        line["run_id"] = json!(v);
+    }
+    write!(w, "{}", line)
+}
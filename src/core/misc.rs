@@ -1 +1,32 @@
 pub type ResultType<T> = anyhow::Result<T>;
+
+/// 向服务端报告耗时操作(同步文件、解压等)的进度
+#[async_trait::async_trait]
+pub trait AsyncStatusUpdater: Sync + Send {
+    async fn update(&self, message: &str);
+}
+
+/// 由提交或题目数据本身导致的错误(如代码格式未知、SPJ编译失败)，重试不会改变结果
+#[derive(Debug)]
+pub struct InputError(pub String);
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+impl std::error::Error for InputError {}
+
+pub fn input_error(message: String) -> anyhow::Error {
+    return anyhow::Error::new(InputError(message));
+}
+
+/// 为错误附加说明，保留InputError的分类
+pub fn wrap_error(e: anyhow::Error, message: &str) -> anyhow::Error {
+    let text = format!("{}{}", message, e);
+    if e.is::<InputError>() {
+        return input_error(text);
+    }
+    return anyhow::anyhow!(text);
+}
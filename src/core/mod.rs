@@ -1,7 +1,26 @@
+pub mod api;
+pub mod archive;
+pub mod calibrate;
+pub mod cli;
 pub mod compare;
 pub mod config;
+pub mod events;
+pub mod heartbeat;
+pub mod interactor;
+pub mod lang_cache;
+pub mod limits;
+pub mod logging;
 pub mod misc;
 pub mod model;
+pub mod plugin;
+pub mod process_stats;
+pub mod progress;
 pub mod runner;
+pub mod scan;
 pub mod state;
+pub mod units;
+pub mod upload;
 pub mod util;
+pub mod watermark;
+pub mod webhook;
+pub mod workdir;
@@ -1,14 +1,28 @@
 use serde::{Deserialize, Serialize};
 
+use super::config::JudgerConfig;
+
+// 运行在虚拟机中的语言(如Java)的内存限制处理方式，见LanguageConfig::memory_limit_policy
+pub const MEMORY_LIMIT_POLICY_JVM: &str = "jvm";
+
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct LanguageConfig {
     pub source_file: String,
     pub output_file: String,
     pub compile: String,
     pub run: String,
+    // 内存限制的处理方式，为空时内存限制直接作为容器的限制
+    // jvm: 内存限制作为虚拟机的堆大小(编译与运行命令中的{memory_limit}，单位MB，如-Xmx{memory_limit}m)，容器的限制额外留出虚拟机本身占用的内存
+    #[serde(default)]
+    pub memory_limit_policy: String,
+    // 以下字段评测时不使用，配置文件中的语言定义可以省略
+    #[serde(default)]
     pub display: String,
+    #[serde(default)]
     pub version: String,
+    #[serde(default)]
     pub ace_mode: String,
+    #[serde(default)]
     pub hljs_mode: String,
 }
 
@@ -26,6 +40,50 @@ impl LanguageConfig {
             .replace("{output}", output)
             .replace("{extra}", extra);
     }
+    /// 将多个源文件编译为一个程序，template中的{sources}替换为以空格分隔的全部源文件
+    /// template为空时使用语言的编译命令，其中的{source}替换为全部源文件
+    pub fn compile_multi_s(
+        &self,
+        template: &str,
+        sources: &[String],
+        output: &str,
+        extra: &str,
+    ) -> String {
+        let joined = sources.join(" ");
+        let template = if template.is_empty() {
+            self.compile.replace("{source}", &joined)
+        } else {
+            template.to_string()
+        };
+        return template
+            .replace("{sources}", &joined)
+            .replace(
+                "{source}",
+                sources.first().map(|v| v.as_str()).unwrap_or(""),
+            )
+            .replace("{output}", output)
+            .replace("{extra}", extra);
+    }
+    pub fn is_jvm(&self) -> bool {
+        return self.memory_limit_policy == MEMORY_LIMIT_POLICY_JVM;
+    }
+    /// 将编译与运行命令中的{memory_limit}替换为给定的内存限制(字节)，单位为MB
+    pub fn with_memory_limit(&self, memory_limit: i64) -> Self {
+        let megabytes = (memory_limit / 1024 / 1024).max(1).to_string();
+        let mut ret = self.clone();
+        ret.compile = ret.compile.replace("{memory_limit}", &megabytes);
+        ret.run = ret.run.replace("{memory_limit}", &megabytes);
+        return ret;
+    }
+    /// 容器的内存限制固定时(如编译、运行题目程序)，以jvm语言在该限制下可用的堆大小替换{memory_limit}
+    pub fn with_container_limit(&self, config: &JudgerConfig, container_limit: i64) -> Self {
+        if !self.is_jvm() {
+            return self.with_memory_limit(container_limit);
+        }
+        let heap = (container_limit - config.vm_memory_overhead * 1024 * 1024) as f64
+            / config.vm_memory_overhead_factor.max(1.0);
+        return self.with_memory_limit(heap as i64);
+    }
     pub fn run_s(&self, program: &str, redirect: &str) -> String {
         return self
             .run
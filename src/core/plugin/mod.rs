@@ -0,0 +1,100 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{config::JudgerConfig, misc::ResultType, state::AppState};
+
+pub mod telemetry_log;
+
+/// 测试点评测完成时传给插件的信息
+#[derive(Debug)]
+pub struct TestcaseReport<'a> {
+    pub submission_id: i64,
+    pub subtask: &'a str,
+    pub index: usize,
+    pub status: &'a str,
+    pub score: i64,
+    // ms
+    pub time_cost: i64,
+    // bytes
+    pub memory_cost: i64,
+}
+
+/// 评测结束时传给插件的信息，同时作为post_judge脚本的评测摘要
+#[derive(Debug, Serialize)]
+pub struct JudgeSummary {
+    pub submission_id: i64,
+    pub problem_id: i64,
+    // 提交的总体结果: 评测失败时为judge_failed，被拒绝时为拒绝的原因，ICPC模式下为第一个未通过的测试点的状态，否则为accepted或第一个未通过的测试点的状态
+    pub status: String,
+    pub compile_error: bool,
+    pub score: i64,
+    // 各子任务的评测结果，被拒绝或编译错误时为空
+    pub judge_result: Value,
+}
+
+/// 评测流程中的扩展点，各方法默认什么也不做
+#[async_trait]
+pub trait JudgePlugin: Sync + Send {
+    fn name(&self) -> &str;
+    /// 编译用户程序前调用，返回Some时不再编译，以该信息判为编译错误
+    async fn pre_compile(
+        &self,
+        _submission_id: i64,
+        _language: &str,
+        _codes: &[&String],
+    ) -> ResultType<Option<String>> {
+        return Ok(None);
+    }
+    /// 每个测试点评测完成(包括跳过)后调用
+    async fn post_testcase(&self, _report: &TestcaseReport<'_>) {}
+    /// 评测结束后调用
+    async fn post_judge(&self, _summary: &JudgeSummary) {}
+}
+
+/// 按配置文件中的plugins列表创建内置插件
+pub fn create_plugins(config: &JudgerConfig) -> ResultType<Vec<Box<dyn JudgePlugin>>> {
+    let mut ret: Vec<Box<dyn JudgePlugin>> = vec![];
+    for name in config.plugins.iter() {
+        ret.push(match name.as_str() {
+            "telemetry_log" => Box::new(telemetry_log::TelemetryLogPlugin),
+            other => return Err(anyhow!("Unknown plugin: {}", other)),
+        });
+        info!("Plugin loaded: {}", name);
+    }
+    return Ok(ret);
+}
+
+/// 依次调用各插件的pre_compile，返回第一个拒绝编译的信息
+pub async fn run_pre_compile(
+    app: &AppState,
+    submission_id: i64,
+    language: &str,
+    codes: &[&String],
+) -> ResultType<Option<String>> {
+    for plugin in app.plugins.iter() {
+        if let Some(message) = plugin
+            .pre_compile(submission_id, language, codes)
+            .await
+            .map_err(|e| anyhow!("Plugin {} failed: {}", plugin.name(), e))?
+        {
+            info!("Submission rejected by plugin {}", plugin.name());
+            return Ok(Some(message));
+        }
+    }
+    return Ok(None);
+}
+
+pub async fn run_post_testcase(app: &AppState, report: &TestcaseReport<'_>) {
+    for plugin in app.plugins.iter() {
+        plugin.post_testcase(report).await;
+    }
+}
+
+pub async fn run_post_judge(app: &AppState, summary: &JudgeSummary) {
+    for plugin in app.plugins.iter() {
+        plugin.post_judge(summary).await;
+    }
+}
@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use log::info;
+
+use super::{JudgePlugin, JudgeSummary, TestcaseReport};
+
+/// 将测试点与评测结果以固定格式写入日志，供外部日志收集系统统计
+pub struct TelemetryLogPlugin;
+
+#[async_trait]
+impl JudgePlugin for TelemetryLogPlugin {
+    fn name(&self) -> &str {
+        return "telemetry_log";
+    }
+    async fn post_testcase(&self, report: &TestcaseReport<'_>) {
+        info!(
+            "[telemetry] testcase sid={} subtask={} index={} status={} score={} time={} memory={}",
+            report.submission_id,
+            report.subtask,
+            report.index,
+            report.status,
+            report.score,
+            report.time_cost,
+            report.memory_cost
+        );
+    }
+    async fn post_judge(&self, summary: &JudgeSummary) {
+        info!(
+            "[telemetry] judge sid={} compile_error={} score={}",
+            summary.submission_id, summary.compile_error, summary.score
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use log::{error, info, warn};
+use serde::Serialize;
+
+use super::{
+    config::JudgerConfig,
+    misc::ResultType,
+    state::{AppState, GLOBAL_APP_STATE},
+    workdir::work_dir_usage,
+};
+
+/// 评测机进程自身的资源占用，用于发现长时间运行后的泄漏
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessStats {
+    // in bytes
+    pub rss: i64,
+    pub open_fds: usize,
+    pub threads: usize,
+    // 正在运行的评测任务数
+    pub running_tasks: usize,
+    // 题目数据目录锁表中的锁数
+    pub file_locks: usize,
+    pub work_dirs: usize,
+    // 工作目录的总大小, in bytes
+    pub work_dir_size: u64,
+}
+
+/// 从/proc/self/status中读取一项，如VmRSS
+fn proc_status_field(status: &str, name: &str) -> Option<i64> {
+    return status
+        .lines()
+        .find(|v| v.starts_with(name) && v[name.len()..].starts_with(':'))
+        .and_then(|v| v[name.len() + 1..].split_whitespace().next())
+        .and_then(|v| v.parse::<i64>().ok());
+}
+
+pub fn collect_process_stats(app: &AppState) -> ResultType<ProcessStats> {
+    let status = std::fs::read_to_string("/proc/self/status")
+        .map_err(|e| anyhow!("Failed to read /proc/self/status: {}", e))?;
+    let open_fds = std::fs::read_dir("/proc/self/fd")
+        .map_err(|e| anyhow!("Failed to read /proc/self/fd: {}", e))?
+        .count();
+    let (work_dirs, work_dir_size) = work_dir_usage(&app.config)?;
+    return Ok(ProcessStats {
+        // in kB
+        rss: proc_status_field(&status, "VmRSS").unwrap_or(0) * 1024,
+        open_fds,
+        threads: proc_status_field(&status, "Threads").unwrap_or(0) as usize,
+        running_tasks: app.config.max_tasks_sametime - app.task_count_lock.available_permits(),
+        file_locks: app.file_dir_locks.stats().active,
+        work_dirs,
+        work_dir_size,
+    });
+}
+
+/// 超过配置的阈值时输出警告
+fn check_thresholds(stats: &ProcessStats, config: &JudgerConfig) {
+    if config.rss_warn_mb > 0 && stats.rss > config.rss_warn_mb * 1024 * 1024 {
+        warn!(
+            "Judger uses {} MB of memory, exceeding {} MB",
+            stats.rss / 1024 / 1024,
+            config.rss_warn_mb
+        );
+    }
+    if config.open_fds_warn > 0 && stats.open_fds > config.open_fds_warn {
+        warn!(
+            "Judger has {} open file descriptors, exceeding {}",
+            stats.open_fds, config.open_fds_warn
+        );
+    }
+    if config.work_dir_warn_mb > 0 && stats.work_dir_size > config.work_dir_warn_mb * 1024 * 1024 {
+        warn!(
+            "{} work dirs use {} MB, exceeding {} MB",
+            stats.work_dirs,
+            stats.work_dir_size / 1024 / 1024,
+            config.work_dir_warn_mb
+        );
+    }
+}
+
+/// 输出当前的资源占用，phase为所处的阶段，如某个提交评测开始
+pub fn log_process_stats(app: &AppState, phase: &str) {
+    // 统计工作目录大小需要遍历文件
+    let stats = tokio::task::block_in_place(|| collect_process_stats(app));
+    match stats {
+        Ok(stats) => {
+            info!("Process stats ({}): {:?}", phase, stats);
+            check_thresholds(&stats, &app.config);
+        }
+        Err(e) => error!("Failed to collect process stats: {}", e),
+    }
+}
+
+/// 每隔interval秒输出一次资源占用，interval为0时不输出
+pub fn spawn_process_stats_logger(interval: u64) {
+    if interval == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            timer.tick().await;
+            let guard = GLOBAL_APP_STATE.read().await;
+            if let Some(app) = guard.as_ref() {
+                log_process_stats(app, "periodic");
+            }
+        }
+    });
+}
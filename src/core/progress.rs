@@ -0,0 +1,24 @@
+use log::{debug, error};
+use serde_json::Value;
+
+use super::state::AppState;
+
+/// 发布一条评测进度(payload为完整的事件)，失败时只记录日志，不影响评测
+/// 进度发布到每个提交单独的Redis Pub/Sub频道，供前端通过websocket实时显示
+pub async fn publish_progress(app: &AppState, submission_id: i64, payload: &Value) {
+    if !app.report_status {
+        return;
+    }
+    let publisher = match app.progress_publisher.as_ref() {
+        Some(v) => v,
+        None => return,
+    };
+    let payload = payload.to_string();
+    debug!("Publishing progress of {}: {}", submission_id, payload);
+    if let Err(e) = publisher
+        .publish_with_suffix(&submission_id.to_string(), &payload)
+        .await
+    {
+        error!("Failed to publish progress of {}: {}", submission_id, e);
+    }
+}
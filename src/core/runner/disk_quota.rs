@@ -0,0 +1,97 @@
+use std::{
+    os::unix::fs::MetadataExt,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{error, warn};
+use tokio::task::JoinHandle;
+
+// 检查写入量的间隔
+const QUOTA_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 目录(包括子目录)中的文件实际占用的磁盘空间(字节)，不跟随符号链接，无法读取的文件忽略
+pub fn dir_disk_usage(path: &Path) -> u64 {
+    let entries = match std::fs::read_dir(path) {
+        Ok(v) => v,
+        Err(_) => return 0,
+    };
+    let mut ret = 0;
+    for entry in entries.flatten() {
+        let metadata = match std::fs::symlink_metadata(entry.path()) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        // st_blocks以512字节为单位，稀疏文件只计算实际占用的部分
+        ret += metadata.blocks() * 512;
+        if metadata.is_dir() {
+            ret += dir_disk_usage(&entry.path());
+        }
+    }
+    return ret;
+}
+
+/// 程序运行期间定时检查可写目录的写入量，超过限制时调用on_exceed结束程序
+/// 写入量为当前占用的空间减去开始时占用的空间
+pub struct WorkdirQuotaGuard {
+    stop: Arc<AtomicBool>,
+    exceeded: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl WorkdirQuotaGuard {
+    /// limit不大于0时返回None
+    pub fn spawn(
+        dirs: Vec<String>,
+        limit: i64,
+        on_exceed: impl FnOnce() + Send + 'static,
+    ) -> Option<Self> {
+        if limit <= 0 {
+            return None;
+        }
+        let usage = move || {
+            dirs.iter()
+                .map(|v| dir_disk_usage(Path::new(v)))
+                .sum::<u64>()
+        };
+        let baseline = usage();
+        let stop = Arc::new(AtomicBool::new(false));
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            let exceeded = exceeded.clone();
+            tokio::task::spawn_blocking(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    let written = usage().saturating_sub(baseline);
+                    if written > limit as u64 {
+                        warn!(
+                            "Working directory quota exceeded: {} > {} bytes",
+                            written, limit
+                        );
+                        exceeded.store(true, Ordering::SeqCst);
+                        on_exceed();
+                        return;
+                    }
+                    std::thread::sleep(QUOTA_CHECK_INTERVAL);
+                }
+            })
+        };
+        return Some(Self {
+            stop,
+            exceeded,
+            handle,
+        });
+    }
+    /// 停止检查，返回运行期间是否超过了限制
+    pub async fn finish(self) -> bool {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Err(e) = self.handle.await {
+            error!("Failed to join quota guard: {}", e);
+        }
+        return self.exceeded.load(Ordering::SeqCst);
+    }
+}
@@ -1,82 +1,62 @@
 use crate::core::{
     misc::ResultType,
-    runner::docker_watch::{watch_container, WatchResult},
+    runner::{
+        disk_quota::WorkdirQuotaGuard,
+        docker_session::DockerSession,
+        docker_watch::{kill_container_processes, watch_container, WatchResult},
+        ExecuteOptions, ExecuteResult, ExecutionEnv, Runner, Session,
+    },
 };
 use anyhow::anyhow;
+use async_trait::async_trait;
 use bollard::{
     container::{Config, LogOutput, LogsOptions},
     models::{
         ContainerStateStatusEnum, HostConfig, HostConfigCgroupnsModeEnum, Mount, MountTypeEnum,
-        ResourcesUlimits,
+        ResourcesUlimits, ThrottleDevice,
     },
 };
-use log::{debug, error, info};
-#[derive(Debug)]
-pub struct ExecuteResult {
-    pub exit_code: i32,
-    // in microsecond
-    pub time_cost: i64,
-    // in bytes
-    pub memory_cost: i64,
-    pub output: String,
-    pub output_truncated: bool,
+use futures_util::stream::StreamExt;
+use log::{debug, error, info, warn};
+use std::time::Duration;
+
+/// 收集容器输出的限制，避免容器持续输出时阻塞评测
+#[derive(Debug, Clone)]
+pub struct LogCollectLimit {
+    pub timeout: Duration,
+    pub max_bytes: usize,
 }
 
 pub async fn execute_in_docker(
     image_name: &str,
     mount_dir: &str,
     command: &Vec<String>,
+    env: &ExecutionEnv,
     // in bytes
     memory_limit: i64,
     // in microsecond
     time_limit: i64,
     // task_name: &str,
     max_output_length: usize,
+    options: &ExecuteOptions,
+    log_limit: &LogCollectLimit,
 ) -> ResultType<ExecuteResult> {
     let docker_client = bollard::Docker::connect_with_socket_defaults()
         .map_err(|e| anyhow!("Failed to initialize docker: {}", e))?;
+    env.chown_writable_mounts(mount_dir, &options.readwrite_mounts)
+        .await?;
+    let command = &env.wrap(command);
     let container = docker_client
         .create_container::<String, String>(
             None,
-            Config {
-                image: Some(image_name.to_string()),
-                cmd: Some(command.clone()),
-                tty: Some(true),
-                open_stdin: Some(false),
-                network_disabled: Some(true),
-                working_dir: Some("/temp".to_string()),
-                attach_stdout: Some(true),
-                attach_stderr: Some(true),
-                // volumes: Some(HashMap::from([("/temp".into(), HashMap::default())])),
-                host_config: Some(HostConfig {
-                    // binds: Some(vec![format!("{}:/temp:rw", mount_dir)]),
-                    cgroupns_mode: Some(HostConfigCgroupnsModeEnum::PRIVATE),
-                    privileged: Some(false),
-                    readonly_rootfs: Some(false),
-                    mounts: Some(vec![Mount {
-                        target: Some("/temp".to_string()),
-                        source: Some(mount_dir.to_string()),
-                        read_only: Some(false),
-                        typ: Some(MountTypeEnum::BIND),
-                        ..Default::default()
-                    }]),
-                    memory: Some(memory_limit),
-                    memory_swap: Some(memory_limit),
-                    oom_kill_disable: Some(false),
-                    // nano_cpus: Some((0.4 / 1e-9) as i64),
-                    network_mode: Some("none".to_string()),
-                    ulimits: Some(vec![ResourcesUlimits {
-                        name: Some("stack".to_string()),
-                        soft: Some(8277716992_i64),
-                        hard: Some(8277716992_i64),
-                    }]),
-                    cpu_period: Some(1000000),
-                    cpu_quota: Some(1000000),
-                    auto_remove: Some(false),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            },
+            container_config(
+                image_name,
+                mount_dir,
+                command,
+                memory_limit,
+                options,
+                env.docker_user(),
+            ),
         )
         .await
         .map_err(|e| anyhow!("Failed to create docker container: {}", e))?;
@@ -96,16 +76,37 @@ pub async fn execute_in_docker(
         .ok_or(anyhow!("Missing field: pid"))?;
     let long_id = attrs.id.ok_or(anyhow!("Failed to get container id!"))?;
     info!("Watcher started, pid = {}", pid);
+    let quota_guard = {
+        let long_id = long_id.clone();
+        let mut dirs = vec![mount_dir.to_string()];
+        dirs.extend(
+            options
+                .readwrite_mounts
+                .iter()
+                .map(|(source, _)| source.clone()),
+        );
+        WorkdirQuotaGuard::spawn(dirs, options.workdir_size_limit, move || {
+            if let Err(e) = kill_container_processes(&long_id, 0) {
+                error!("Failed to kill container: {}", e);
+            }
+        })
+    };
     // let handle =
     //     std::thread::spawn(move || unsafe { watch_container(pid as i32, time_limit, long_id) });
+    let detect_suspicious = options.detect_suspicious;
+    let timeline = options.timeline.clone();
     let watch_result = tokio::task::spawn_blocking(move || unsafe {
-        watch_container(pid as i32, time_limit, long_id)
+        watch_container(pid as i32, time_limit, long_id, detect_suspicious, timeline)
     })
     .await
     // .map_err(|e| anyhow!("Failed to join: {}", e))?
     .map_err(|e| anyhow!("Failed to run blocking task: {}", e))?
     .map_err(|e| anyhow!("Failed to watch the status: {}", e))?;
     info!("Watch result: {:#?}", watch_result);
+    let workdir_size_limit_exceeded = match quota_guard {
+        Some(v) => v.finish().await,
+        None => false,
+    };
     {
         let details = docker_client
             .inspect_container(container.id.as_str(), None)
@@ -125,33 +126,57 @@ pub async fn execute_in_docker(
             {
                 error!("Failed to kill container: {}", e);
             }
+            // 等待容器真正停止，之后的输出不会再增长
+            let mut wait = docker_client.wait_container::<&str>(container.id.as_str(), None);
+            if tokio::time::timeout(log_limit.timeout, wait.next())
+                .await
+                .is_err()
+            {
+                warn!("Container did not stop in time: {}", container.id);
+            }
         }
     }
-    use futures_util::stream::StreamExt;
-    let mut truncated = false;
+    let mut truncated;
+    let mut stderr = String::new();
     let output = {
         let mut out = String::new();
-        for line in docker_client
-            .logs::<&str>(
+        // 容器已经停止，不需要follow；仍然限制时间与字节数，保证及时返回
+        let collect = async {
+            let mut stream = docker_client.logs::<&str>(
                 container.id.as_str(),
                 Some(LogsOptions {
                     stderr: true,
                     stdout: true,
                     timestamps: false,
-                    follow: true,
+                    follow: false,
                     ..Default::default()
                 }),
-            )
-            .collect::<Vec<Result<LogOutput, bollard::errors::Error>>>()
-            .await
-            .into_iter()
-        {
-            out.push_str(line?.to_string().as_str());
-            if out.len() > max_output_length as usize {
-                out = String::from_iter(out.chars().take(max_output_length));
-                truncated = true;
-                break;
+            );
+            while let Some(line) = stream.next().await {
+                let line = line?;
+                if let LogOutput::StdErr { .. } = line {
+                    stderr.push_str(line.to_string().as_str());
+                }
+                out.push_str(line.to_string().as_str());
+                if out.len() > max_output_length.min(log_limit.max_bytes) {
+                    return ResultType::<bool>::Ok(true);
+                }
+            }
+            return ResultType::<bool>::Ok(false);
+        };
+        truncated = match tokio::time::timeout(log_limit.timeout, collect).await {
+            Ok(v) => v?,
+            Err(_) => {
+                warn!("Timed out collecting output of container: {}", container.id);
+                true
             }
+        };
+        if out.len() > max_output_length {
+            out = String::from_iter(out.chars().take(max_output_length));
+            truncated = true;
+        }
+        if stderr.len() > max_output_length {
+            stderr = String::from_iter(stderr.chars().take(max_output_length));
         }
         out
     };
@@ -168,7 +193,13 @@ pub async fn execute_in_docker(
     // }
     let WatchResult {
         time_result,
+        startup_overhead,
+        cpu_time_result,
         mut memory_result,
+        warnings,
+        timed_out,
+        timeline,
+        pids_limit_hit,
     } = watch_result;
     let is_oom_killed = attr
         .state
@@ -192,7 +223,160 @@ pub async fn execute_in_docker(
         exit_code: exit_code as i32,
         memory_cost: memory_result,
         time_cost: time_result,
+        startup_overhead,
+        cpu_time_cost: cpu_time_result,
         output,
         output_truncated: truncated,
+        stderr,
+        warnings,
+        killed_by_watchdog: timed_out,
+        oom_killed: is_oom_killed,
+        timeline,
+        pids_limit_exceeded: pids_limit_hit,
+        workdir_size_limit_exceeded,
     });
 }
+
+/// 创建容器所用的配置，mount_dir挂载到/temp
+pub fn container_config(
+    image_name: &str,
+    mount_dir: &str,
+    command: &Vec<String>,
+    // in bytes
+    memory_limit: i64,
+    options: &ExecuteOptions,
+    // 形如uid:gid，None时使用镜像的默认用户
+    user: Option<String>,
+) -> Config<String> {
+    let mut mounts = vec![Mount {
+        target: Some("/temp".to_string()),
+        source: Some(mount_dir.to_string()),
+        read_only: Some(false),
+        typ: Some(MountTypeEnum::BIND),
+        ..Default::default()
+    }];
+    for (source, target, read_only) in options
+        .readonly_mounts
+        .iter()
+        .map(|(s, t)| (s, t, true))
+        .chain(options.readwrite_mounts.iter().map(|(s, t)| (s, t, false)))
+    {
+        mounts.push(Mount {
+            target: Some(target.clone()),
+            source: Some(source.clone()),
+            read_only: Some(read_only),
+            typ: Some(MountTypeEnum::BIND),
+            ..Default::default()
+        });
+    }
+    let throttle = |rate: i64| {
+        if options.io_limit.device.is_empty() || rate <= 0 {
+            return None;
+        }
+        return Some(vec![ThrottleDevice {
+            path: Some(options.io_limit.device.clone()),
+            rate: Some(rate),
+        }]);
+    };
+    let mut ulimits = vec![ResourcesUlimits {
+        name: Some("stack".to_string()),
+        soft: Some(8277716992_i64),
+        hard: Some(8277716992_i64),
+    }];
+    if options.file_size_limit > 0 {
+        ulimits.push(ResourcesUlimits {
+            name: Some("fsize".to_string()),
+            soft: Some(options.file_size_limit),
+            hard: Some(options.file_size_limit),
+        });
+    }
+    return Config {
+        image: Some(image_name.to_string()),
+        cmd: Some(command.clone()),
+        user,
+        // 不使用tty，标准输出与标准错误分开传输
+        tty: Some(false),
+        open_stdin: Some(false),
+        network_disabled: Some(true),
+        working_dir: Some("/temp".to_string()),
+        attach_stdout: Some(true),
+        attach_stderr: Some(true),
+        // volumes: Some(HashMap::from([("/temp".into(), HashMap::default())])),
+        host_config: Some(HostConfig {
+            // binds: Some(vec![format!("{}:/temp:rw", mount_dir)]),
+            cgroupns_mode: Some(HostConfigCgroupnsModeEnum::PRIVATE),
+            privileged: Some(false),
+            readonly_rootfs: Some(false),
+            mounts: Some(mounts),
+            memory: Some(memory_limit),
+            memory_swap: Some(memory_limit),
+            oom_kill_disable: Some(false),
+            // nano_cpus: Some((0.4 / 1e-9) as i64),
+            network_mode: Some("none".to_string()),
+            ulimits: Some(ulimits),
+            cpu_period: Some(1000000),
+            cpu_quota: Some(1000000),
+            auto_remove: Some(false),
+            blkio_weight: if options.io_limit.weight > 0 {
+                Some(options.io_limit.weight)
+            } else {
+                None
+            },
+            blkio_device_read_bps: throttle(options.io_limit.read_bps),
+            blkio_device_write_bps: throttle(options.io_limit.write_bps),
+            security_opt: options.seccomp.as_ref().map(|v| vec![v.clone()]),
+            pids_limit: if options.pids_limit > 0 {
+                Some(options.pids_limit)
+            } else {
+                None
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+}
+
+pub struct DockerRunner {
+    pub reuse_container: bool,
+    pub env: ExecutionEnv,
+    pub log_limit: LogCollectLimit,
+}
+#[async_trait]
+impl Runner for DockerRunner {
+    async fn execute(
+        &self,
+        image_name: &str,
+        mount_dir: &str,
+        command: &Vec<String>,
+        memory_limit: i64,
+        time_limit: i64,
+        max_output_length: usize,
+        options: &ExecuteOptions,
+    ) -> ResultType<ExecuteResult> {
+        return execute_in_docker(
+            image_name,
+            mount_dir,
+            command,
+            &self.env,
+            memory_limit,
+            time_limit,
+            max_output_length,
+            options,
+            &self.log_limit,
+        )
+        .await;
+    }
+    async fn create_session(
+        &self,
+        image_name: &str,
+        mount_dir: &str,
+        options: &ExecuteOptions,
+    ) -> ResultType<Option<Box<dyn Session>>> {
+        if !self.reuse_container {
+            return Ok(None);
+        }
+        let session =
+            DockerSession::create(image_name, mount_dir, options, self.env.clone()).await?;
+        return Ok(Some(Box::new(session)));
+    }
+}
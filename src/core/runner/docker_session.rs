@@ -0,0 +1,356 @@
+use std::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::core::{
+    misc::ResultType,
+    runner::{
+        disk_quota::WorkdirQuotaGuard,
+        docker::container_config,
+        docker_watch::{
+            kill_container_processes, read_cpu_usage, read_oom_kill_count, reset_max_memory_usage,
+            watch_exec, WatchResult,
+        },
+        ExecuteOptions, ExecuteResult, ExecutionEnv, Session, TimelineOptions,
+    },
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bollard::{
+    container::{LogOutput, RemoveContainerOptions, UpdateContainerOptions},
+    exec::{CreateExecOptions, StartExecResults},
+    Docker,
+};
+use futures_util::{Stream, StreamExt};
+use log::{error, info};
+
+// 常驻进程，使容器在各次exec之间保持运行
+const IDLE_COMMAND: [&str; 2] = ["sleep", "infinity"];
+// 创建容器时的内存限制，每次exec前会重新设置
+const INITIAL_MEMORY_LIMIT: i64 = 2048 * 1024 * 1024;
+
+/// 整个提交共用一个容器，每次运行通过docker exec进行
+/// 每次运行前重新设置内存限制并清零内存峰值，运行后杀死容器内遗留的进程
+pub struct DockerSession {
+    docker_client: Docker,
+    container_id: String,
+    long_id: String,
+    // 容器常驻进程在宿主机上的pid
+    init_pid: i32,
+    detect_suspicious: bool,
+    timeline: Option<TimelineOptions>,
+    mount_dir: String,
+    readwrite_mounts: Vec<(String, String)>,
+    workdir_size_limit: i64,
+    env: ExecutionEnv,
+    closed: AtomicBool,
+}
+
+async fn collect_output(
+    mut stream: Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>,
+    max_output_length: usize,
+) -> (String, String, bool) {
+    let mut out = String::new();
+    let mut stderr = String::new();
+    let mut truncated = false;
+    while let Some(line) = stream.next().await {
+        // 截断后仍然读完输出，避免程序阻塞在写入上
+        if truncated {
+            continue;
+        }
+        match line {
+            Ok(v) => {
+                if let LogOutput::StdErr { .. } = v {
+                    stderr.push_str(v.to_string().as_str());
+                    if stderr.len() > max_output_length {
+                        stderr = String::from_iter(stderr.chars().take(max_output_length));
+                    }
+                }
+                out.push_str(v.to_string().as_str());
+                if out.len() > max_output_length {
+                    out = String::from_iter(out.chars().take(max_output_length));
+                    truncated = true;
+                }
+            }
+            Err(e) => {
+                error!("Failed to read exec output: {}", e);
+                break;
+            }
+        }
+    }
+    return (out, stderr, truncated);
+}
+
+impl DockerSession {
+    pub async fn create(
+        image_name: &str,
+        mount_dir: &str,
+        options: &ExecuteOptions,
+        env: ExecutionEnv,
+    ) -> ResultType<Self> {
+        let docker_client = Docker::connect_with_socket_defaults()
+            .map_err(|e| anyhow!("Failed to initialize docker: {}", e))?;
+        let command = IDLE_COMMAND
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>();
+        let container = docker_client
+            .create_container::<String, String>(
+                None,
+                container_config(
+                    image_name,
+                    mount_dir,
+                    &command,
+                    INITIAL_MEMORY_LIMIT,
+                    options,
+                    env.docker_user(),
+                ),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to create docker container: {}", e))?;
+        let mut session = Self {
+            docker_client,
+            container_id: container.id,
+            long_id: String::new(),
+            init_pid: 0,
+            detect_suspicious: options.detect_suspicious,
+            timeline: options.timeline.clone(),
+            mount_dir: mount_dir.to_string(),
+            readwrite_mounts: options.readwrite_mounts.clone(),
+            workdir_size_limit: options.workdir_size_limit,
+            env,
+            closed: AtomicBool::new(false),
+        };
+        // 之后的步骤失败时由Drop删除容器
+        session
+            .docker_client
+            .start_container::<&str>(&session.container_id, None)
+            .await
+            .map_err(|e| anyhow!("Failed to start container: {}", e))?;
+        let attrs = session
+            .docker_client
+            .inspect_container(&session.container_id, None)
+            .await
+            .map_err(|e| anyhow!("Failed to get contaier details: {}", e))?;
+        session.init_pid = attrs
+            .state
+            .ok_or(anyhow!("Missing field: 'state'"))?
+            .pid
+            .ok_or(anyhow!("Missing field: pid"))? as i32;
+        session.long_id = attrs.id.ok_or(anyhow!("Failed to get container id!"))?;
+        info!(
+            "Session container started: {}, init pid = {}",
+            session.long_id, session.init_pid
+        );
+        return Ok(session);
+    }
+}
+
+#[async_trait]
+impl Session for DockerSession {
+    async fn execute(
+        &self,
+        command: &Vec<String>,
+        memory_limit: i64,
+        time_limit: i64,
+        max_output_length: usize,
+    ) -> ResultType<ExecuteResult> {
+        self.docker_client
+            .update_container(
+                &self.container_id,
+                UpdateContainerOptions::<String> {
+                    memory: Some(memory_limit),
+                    memory_swap: Some(memory_limit),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to update memory limit: {}", e))?;
+        self.env
+            .chown_writable_mounts(&self.mount_dir, &self.readwrite_mounts)
+            .await?;
+        reset_max_memory_usage(&self.long_id)?;
+        let cpu_before = read_cpu_usage(&self.long_id)?;
+        let oom_before = read_oom_kill_count(&self.long_id)?;
+        let exec = self
+            .docker_client
+            .create_exec(
+                &self.container_id,
+                CreateExecOptions {
+                    cmd: Some(self.env.wrap(command)),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    tty: Some(false),
+                    working_dir: Some("/temp".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to create exec: {}", e))?;
+        info!("Running exec with command: {:?}", command);
+        let quota_guard = {
+            let long_id = self.long_id.clone();
+            let init_pid = self.init_pid;
+            let mut dirs = vec![self.mount_dir.clone()];
+            dirs.extend(
+                self.readwrite_mounts
+                    .iter()
+                    .map(|(source, _)| source.clone()),
+            );
+            WorkdirQuotaGuard::spawn(dirs, self.workdir_size_limit, move || {
+                if let Err(e) = kill_container_processes(&long_id, init_pid) {
+                    error!("Failed to kill processes in container: {}", e);
+                }
+            })
+        };
+        let output_stream = match self
+            .docker_client
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(|e| anyhow!("Failed to start exec: {}", e))?
+        {
+            StartExecResults::Attached { output, .. } => output,
+            StartExecResults::Detached => return Err(anyhow!("Exec started detached")),
+        };
+        let output_task = tokio::spawn(collect_output(output_stream, max_output_length));
+        let pid = loop {
+            let details = self
+                .docker_client
+                .inspect_exec(&exec.id)
+                .await
+                .map_err(|e| anyhow!("Failed to inspect exec: {}", e))?;
+            match details.pid {
+                Some(v) if v > 0 => break v as i32,
+                _ => {}
+            }
+            if details.running == Some(false) {
+                break 0;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        info!("Watcher started, pid = {}", pid);
+        let long_id = self.long_id.clone();
+        let init_pid = self.init_pid;
+        let detect_suspicious = self.detect_suspicious;
+        let timeline = self.timeline.clone();
+        let watch_result = tokio::task::spawn_blocking(move || {
+            let result = watch_exec(
+                pid,
+                init_pid,
+                time_limit,
+                &long_id,
+                detect_suspicious,
+                cpu_before,
+                timeline,
+            );
+            // 超时的进程与遗留的后台进程都不能留到下一次运行
+            kill_container_processes(&long_id, init_pid)?;
+            return result;
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to run blocking task: {}", e))?
+        .map_err(|e| anyhow!("Failed to watch the status: {}", e))?;
+        info!("Watch result: {:#?}", watch_result);
+        let workdir_size_limit_exceeded = match quota_guard {
+            Some(v) => v.finish().await,
+            None => false,
+        };
+        let (output, stderr, truncated) = output_task
+            .await
+            .map_err(|e| anyhow!("Failed to collect output: {}", e))?;
+        let exit_code = loop {
+            let details = self
+                .docker_client
+                .inspect_exec(&exec.id)
+                .await
+                .map_err(|e| anyhow!("Failed to inspect exec: {}", e))?;
+            if details.running != Some(true) {
+                break details.exit_code.unwrap_or(0);
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        let WatchResult {
+            time_result,
+            startup_overhead,
+            mut memory_result,
+            warnings,
+            timed_out,
+            timeline,
+            pids_limit_hit,
+            ..
+        } = watch_result;
+        let cpu_time_result = read_cpu_usage(&self.long_id)? - cpu_before;
+        let is_oom_killed = read_oom_kill_count(&self.long_id)? > oom_before;
+        info!("OOM Killed: {}", is_oom_killed);
+        if is_oom_killed {
+            memory_result = memory_limit;
+        } else if memory_result > memory_limit {
+            memory_result = 0;
+        }
+        return Ok(ExecuteResult {
+            exit_code: exit_code as i32,
+            memory_cost: memory_result,
+            time_cost: time_result,
+            startup_overhead,
+            cpu_time_cost: cpu_time_result,
+            output,
+            output_truncated: truncated,
+            stderr,
+            warnings,
+            killed_by_watchdog: timed_out,
+            oom_killed: is_oom_killed,
+            timeline,
+            pids_limit_exceeded: pids_limit_hit,
+            workdir_size_limit_exceeded,
+        });
+    }
+    async fn close(&self) -> ResultType<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        info!("Removing session container: {}", self.container_id);
+        self.docker_client
+            .remove_container(
+                &self.container_id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to remove container: {}", e))?;
+        return Ok(());
+    }
+}
+
+impl Drop for DockerSession {
+    // 评测出错提前返回时没有机会调用close，在这里补上
+    fn drop(&mut self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let docker_client = self.docker_client.clone();
+        let container_id = self.container_id.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(e) = docker_client
+                        .remove_container(
+                            &container_id,
+                            Some(RemoveContainerOptions {
+                                force: true,
+                                ..Default::default()
+                            }),
+                        )
+                        .await
+                    {
+                        error!("Failed to remove container {}: {}", container_id, e);
+                    }
+                });
+            }
+            Err(_) => error!("Container leaked: {}", self.container_id),
+        }
+    }
+}
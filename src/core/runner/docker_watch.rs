@@ -3,15 +3,33 @@ use std::{io::Write, ptr::null_mut};
 use libc::{gettid, usleep};
 use log::{error, info};
 
-use crate::core::misc::ResultType;
+use crate::core::{
+    misc::ResultType,
+    runner::{ResourceTimeline, TimelineOptions},
+};
 use anyhow::anyhow;
 #[derive(Debug)]
 pub struct WatchResult {
-    // time, microsecond
+    // time, microsecond，不含启动开销
     pub time_result: i64,
+    // 开始监视到容器内进程第一次消耗CPU之间的时间, microsecond
+    pub startup_overhead: i64,
+    // cpu time, microsecond
+    pub cpu_time_result: i64,
     // memory, bytes
     pub memory_result: i64,
+    // 检测到的可疑行为
+    pub warnings: Vec<String>,
+    // 是否因超时停止监视(随后容器会被强制结束)
+    pub timed_out: bool,
+    pub timeline: Option<ResourceTimeline>,
+    // 创建进程时达到了pids限制
+    pub pids_limit_hit: bool,
 }
+// 每隔多少次轮询检查一次进程列表
+const PROCESS_SAMPLE_INTERVAL: u64 = 500;
+// 每隔多少次轮询检查一次是否达到pids限制
+const PIDS_CHECK_INTERVAL: u64 = 64;
 #[inline]
 unsafe fn get_current_usec() -> i64 {
     use libc::{gettimeofday, timeval};
@@ -23,30 +41,224 @@ unsafe fn get_current_usec() -> i64 {
     return curr.tv_sec * 1_000_000 + curr.tv_usec;
 }
 
+#[inline]
+unsafe fn get_thread_cpu_usec() -> i64 {
+    use libc::{clock_gettime, timespec, CLOCK_THREAD_CPUTIME_ID};
+    let mut curr = timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    clock_gettime(CLOCK_THREAD_CPUTIME_ID, &mut curr as *mut timespec);
+    return curr.tv_sec * 1_000_000 + curr.tv_nsec / 1000;
+}
+
+/// 读取容器累计使用的CPU时间(微秒)，依次尝试cgroup v1的cpuacct.usage与v2的cpu.stat
+pub fn read_cpu_usage(container_long_id: &str) -> ResultType<i64> {
+    let v1_file = format!(
+        "/sys/fs/cgroup/cpuacct/docker/{}/cpuacct.usage",
+        container_long_id
+    );
+    if let Ok(content) = std::fs::read_to_string(&v1_file) {
+        let nanos = i64::from_str_radix(content.trim(), 10)
+            .map_err(|_| anyhow!("Failed to parse cpuacct.usage: {}", content))?;
+        return Ok(nanos / 1000);
+    }
+    let v2_file = format!(
+        "/sys/fs/cgroup/system.slice/docker-{}.scope/cpu.stat",
+        container_long_id
+    );
+    let content = std::fs::read_to_string(&v2_file)
+        .map_err(|e| anyhow!("Failed to read cpu usage of {}: {}", container_long_id, e))?;
+    for line in content.lines() {
+        if let Some(v) = line.strip_prefix("usage_usec ") {
+            return i64::from_str_radix(v.trim(), 10)
+                .map_err(|_| anyhow!("Failed to parse usage_usec: {}", v));
+        }
+    }
+    return Err(anyhow!("Missing usage_usec in cpu.stat"));
+}
+
+/// 容器因达到pids限制而创建进程失败的次数，依次尝试cgroup v1与v2
+pub fn read_pids_limit_hits(container_long_id: &str) -> Option<i64> {
+    for file in [
+        format!(
+            "/sys/fs/cgroup/pids/docker/{}/pids.events",
+            container_long_id
+        ),
+        format!(
+            "/sys/fs/cgroup/system.slice/docker-{}.scope/pids.events",
+            container_long_id
+        ),
+    ] {
+        if let Ok(content) = std::fs::read_to_string(&file) {
+            for line in content.lines() {
+                if let Some(v) = line.strip_prefix("max ") {
+                    return v.trim().parse::<i64>().ok();
+                }
+            }
+        }
+    }
+    return None;
+}
+
+/// 记录容器内进程第一次消耗CPU的时刻，此前的时间视为dockerd调度等启动开销，不计入运行时间
+struct StartupDetector {
+    container_long_id: String,
+    // 开始监视时容器已经使用的CPU时间
+    baseline: i64,
+    overhead: Option<i64>,
+}
+impl StartupDetector {
+    fn new(container_long_id: &str, baseline: i64) -> Self {
+        return Self {
+            container_long_id: container_long_id.to_string(),
+            baseline,
+            overhead: None,
+        };
+    }
+    /// elapsed为开始监视后经过的时间，own_cpu为监视线程自身计入容器的CPU时间
+    fn poll(&mut self, elapsed: i64, own_cpu: i64) {
+        if self.overhead.is_some() {
+            return;
+        }
+        if let Ok(v) = read_cpu_usage(&self.container_long_id) {
+            if v - own_cpu > self.baseline {
+                self.overhead = Some(elapsed);
+            }
+        }
+    }
+    fn overhead(&self) -> i64 {
+        return self.overhead.unwrap_or(0);
+    }
+}
+
+/// 按固定间隔采样容器的内存与CPU占用，采样数达到上限时丢弃一半的采样并加倍间隔
+pub struct TimelineSampler {
+    container_long_id: String,
+    // 微秒
+    interval: i64,
+    next_at: i64,
+    max_samples: usize,
+    cpu_baseline: i64,
+    timeline: ResourceTimeline,
+}
+impl TimelineSampler {
+    pub fn new(container_long_id: &str, options: &TimelineOptions, cpu_baseline: i64) -> Self {
+        return Self {
+            container_long_id: container_long_id.to_string(),
+            interval: options.interval as i64 * 1000,
+            next_at: 0,
+            max_samples: options.max_samples,
+            cpu_baseline,
+            timeline: ResourceTimeline {
+                interval: options.interval,
+                samples: vec![],
+            },
+        };
+    }
+    /// elapsed为开始监视后经过的时间，own_cpu为监视线程自身计入容器的CPU时间
+    fn poll(&mut self, elapsed: i64, own_cpu: i64) {
+        if elapsed < self.next_at {
+            return;
+        }
+        self.next_at = elapsed + self.interval;
+        let memory = std::fs::read_to_string(memory_cgroup_file(
+            &self.container_long_id,
+            "memory.usage_in_bytes",
+        ))
+        .ok()
+        .and_then(|v| v.trim().parse::<i64>().ok());
+        let cpu = read_cpu_usage(&self.container_long_id).ok();
+        let (memory, cpu) = match (memory, cpu) {
+            (Some(m), Some(c)) => (m, (c - self.cpu_baseline - own_cpu).max(0)),
+            // 容器已经退出
+            _ => return,
+        };
+        self.timeline
+            .samples
+            .push((elapsed / 1000, memory, cpu / 1000));
+        if self.timeline.samples.len() >= self.max_samples {
+            let mut index = 0;
+            self.timeline.samples.retain(|_| {
+                index += 1;
+                return index % 2 == 1;
+            });
+            self.interval *= 2;
+            self.timeline.interval *= 2;
+        }
+    }
+    fn finish(self) -> ResourceTimeline {
+        return self.timeline;
+    }
+}
+
+/// 检查cgroup内的进程: 主进程退出后仍在运行的进程，以及创建了POSIX定时器的进程
+/// ignored中的进程(例如复用容器的常驻进程)不参与检查
+fn inspect_processes(main_pid: i32, procs_file: &str, ignored: &[i32], warnings: &mut Vec<String>) {
+    let content = match std::fs::read_to_string(procs_file) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let myself = std::process::id() as i32;
+    let pids = content
+        .lines()
+        .filter_map(|v| v.trim().parse::<i32>().ok())
+        .filter(|v| *v != myself && !ignored.contains(v))
+        .collect::<Vec<i32>>();
+    let mut add_warning = |s: &str| {
+        if !warnings.iter().any(|v| v == s) {
+            warnings.push(s.to_string());
+        }
+    };
+    if !pids.is_empty() && !std::path::Path::new(&format!("/proc/{}", main_pid)).exists() {
+        add_warning("Background processes outlived the main process");
+    }
+    for pid in pids.iter() {
+        if let Ok(timers) = std::fs::read_to_string(format!("/proc/{}/timers", pid)) {
+            if !timers.trim().is_empty() {
+                add_warning("Program created POSIX timers");
+            }
+        }
+    }
+}
+
 // const FILE_FLAG: *const i8 = "r".as_ptr() as *const i8;
 // const FORMAT_STR: *const i8 = "%lld".as_ptr() as *const i8;
 pub unsafe fn watch_container(
-    _pid: i32,
+    pid: i32,
     time_limit: i64,
     container_long_id: String,
+    detect_suspicious: bool,
+    timeline: Option<TimelineOptions>,
 ) -> ResultType<WatchResult> {
     let tid = gettid();
     info!("Watcher tid: {}", tid);
     let main_group_file = "/sys/fs/cgroup/memory/tasks";
     let main_dir = format!("/sys/fs/cgroup/memory/docker/{}", container_long_id);
     let tasks_file = format!("/sys/fs/cgroup/memory/docker/{}/tasks", container_long_id);
+    let procs_file = format!(
+        "/sys/fs/cgroup/memory/docker/{}/cgroup.procs",
+        container_long_id
+    );
     let max_mem_usage_file = format!(
         "/sys/fs/cgroup/memory/docker/{}/memory.max_usage_in_bytes",
         container_long_id
     );
+    let cpuacct_dir = format!("/sys/fs/cgroup/cpuacct/docker/{}", container_long_id);
     // if let Err(e) =.
     match std::fs::File::options().append(true).open(&tasks_file) {
         Ok(mut f) => {
-            if let Err(e) = f.write(tid.to_string().as_bytes()) {
+            if let Err(e) = f.write_all(tid.to_string().as_bytes()) {
                 error!("Failed to write my tid: {}", e);
                 return Ok(WatchResult {
                     memory_result: 0,
                     time_result: 0,
+                    startup_overhead: 0,
+                    cpu_time_result: 0,
+                    warnings: vec![],
+                    timed_out: false,
+                    timeline: None,
+                    pids_limit_hit: false,
                 });
             }
         }
@@ -55,22 +267,62 @@ pub unsafe fn watch_container(
             return Ok(WatchResult {
                 memory_result: 0,
                 time_result: 0,
+                startup_overhead: 0,
+                cpu_time_result: 0,
+                warnings: vec![],
+                timed_out: false,
+                timeline: None,
+                pids_limit_hit: false,
             });
         }
     };
+    // 同样加入cpuacct组，防止容器退出后cgroup被删除而读不到CPU时间
+    // 本线程在组内消耗的CPU时间需要从结果中扣除
+    let joined_cpuacct = match std::fs::File::options()
+        .append(true)
+        .open(format!("{}/tasks", cpuacct_dir))
+    {
+        Ok(mut f) => f.write_all(tid.to_string().as_bytes()).is_ok(),
+        Err(_) => false,
+    };
+    let thread_cpu_begin = get_thread_cpu_usec();
     let begin = get_current_usec();
     let mut time_result: i64;
     let mut read_buf = Vec::<u8>::new();
     read_buf.reserve(128);
+    let mut warnings = Vec::<String>::new();
+    let mut iteration: u64 = 0;
+    let mut startup = StartupDetector::new(&container_long_id, 0);
+    let mut sampler = timeline
+        .as_ref()
+        .map(|v| TimelineSampler::new(&container_long_id, v, 0));
+    let mut pids_limit_hit = false;
     let should_cleanup = loop {
         time_result = get_current_usec() - begin;
-        if time_result >= time_limit {
+        let own_cpu = if joined_cpuacct {
+            get_thread_cpu_usec() - thread_cpu_begin
+        } else {
+            0
+        };
+        startup.poll(time_result, own_cpu);
+        if let Some(sampler) = sampler.as_mut() {
+            sampler.poll(time_result, own_cpu);
+        }
+        if time_result - startup.overhead() >= time_limit {
             break false;
         }
         let s = std::fs::read_to_string(&tasks_file).unwrap();
         if s.as_bytes().iter().filter(|v| **v == '\n' as u8).count() == 1 {
             break true;
         }
+        if detect_suspicious && iteration % PROCESS_SAMPLE_INTERVAL == 0 {
+            inspect_processes(pid, &procs_file, &[], &mut warnings);
+        }
+        // 容器退出后cgroup会被删除，需要在运行过程中检查
+        if !pids_limit_hit && iteration % PIDS_CHECK_INTERVAL == 0 {
+            pids_limit_hit = read_pids_limit_hits(&container_long_id).unwrap_or(0) > 0;
+        }
+        iteration += 1;
         // let mut fp = std::fs::File::open(&tasks_file)
         //     .map_err(|e| anyhow!("Fatal error: Can not open tasks file: {}", e))?;
         // fp.read_to_end(&mut read_buf)
@@ -95,16 +347,173 @@ pub unsafe fn watch_container(
         .to_string();
     let memory_usage = i64::from_str_radix(&usage_str, 10)
         .map_err(|_| anyhow!("Failed to parse: {}", usage_str))?;
+    let cpu_time_result = match read_cpu_usage(&container_long_id) {
+        Ok(v) if joined_cpuacct => (v - (get_thread_cpu_usec() - thread_cpu_begin)).max(0),
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to read cpu usage, using wall time: {}", e);
+            time_result
+        }
+    };
     std::fs::File::options()
         .append(true)
         .open(main_group_file)?
-        .write(tid.to_string().as_bytes())?;
+        .write_all(tid.to_string().as_bytes())?;
+    if joined_cpuacct {
+        std::fs::File::options()
+            .append(true)
+            .open("/sys/fs/cgroup/cpuacct/tasks")?
+            .write_all(tid.to_string().as_bytes())?;
+    }
     if should_cleanup {
         std::fs::remove_dir(&main_dir)
             .map_err(|e| anyhow!("Failed to cleanup cgroup dir: {}", e))?;
+        if joined_cpuacct {
+            if let Err(e) = std::fs::remove_dir(&cpuacct_dir) {
+                error!("Failed to cleanup cpuacct dir: {}", e);
+            }
+        }
     }
     return Ok(WatchResult {
-        time_result,
+        time_result: time_result - startup.overhead(),
+        startup_overhead: startup.overhead(),
+        cpu_time_result,
         memory_result: memory_usage,
+        warnings,
+        timed_out: !should_cleanup,
+        timeline: sampler.map(|v| v.finish()),
+        pids_limit_hit,
+    });
+}
+
+fn memory_cgroup_file(container_long_id: &str, name: &str) -> String {
+    return format!(
+        "/sys/fs/cgroup/memory/docker/{}/{}",
+        container_long_id, name
+    );
+}
+
+/// 读取容器的内存峰值
+pub fn read_max_memory_usage(container_long_id: &str) -> ResultType<i64> {
+    let usage_str = std::fs::read_to_string(memory_cgroup_file(
+        container_long_id,
+        "memory.max_usage_in_bytes",
+    ))?
+    .trim()
+    .to_string();
+    return i64::from_str_radix(&usage_str, 10)
+        .map_err(|_| anyhow!("Failed to parse: {}", usage_str));
+}
+
+/// 清零容器的内存峰值，使下一次运行单独计量
+pub fn reset_max_memory_usage(container_long_id: &str) -> ResultType<()> {
+    std::fs::write(
+        memory_cgroup_file(container_long_id, "memory.max_usage_in_bytes"),
+        "0",
+    )
+    .map_err(|e| anyhow!("Failed to reset max memory usage: {}", e))?;
+    return Ok(());
+}
+
+/// 容器内被OOM Killer杀死的进程数
+pub fn read_oom_kill_count(container_long_id: &str) -> ResultType<i64> {
+    let content =
+        std::fs::read_to_string(memory_cgroup_file(container_long_id, "memory.oom_control"))?;
+    for line in content.lines() {
+        if let Some(v) = line.strip_prefix("oom_kill ") {
+            return i64::from_str_radix(v.trim(), 10)
+                .map_err(|_| anyhow!("Failed to parse oom_kill: {}", v));
+        }
+    }
+    return Ok(0);
+}
+
+/// 杀死容器内除keep以外的全部进程，并等待它们退出
+pub fn kill_container_processes(container_long_id: &str, keep: i32) -> ResultType<()> {
+    let procs_file = memory_cgroup_file(container_long_id, "cgroup.procs");
+    let myself = std::process::id() as i32;
+    for _ in 0..1000 {
+        let pids = std::fs::read_to_string(&procs_file)?
+            .lines()
+            .filter_map(|v| v.trim().parse::<i32>().ok())
+            .filter(|v| *v != keep && *v != myself)
+            .collect::<Vec<i32>>();
+        if pids.is_empty() {
+            return Ok(());
+        }
+        for pid in pids.into_iter() {
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+        unsafe { usleep(1000) };
+    }
+    return Err(anyhow!("Failed to kill processes in container"));
+}
+
+/// 进程是否仍在运行(僵尸进程视为已退出)
+fn process_alive(pid: i32) -> bool {
+    let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    return match stat.rfind(')') {
+        Some(pos) => !stat[pos + 1..].trim_start().starts_with('Z'),
+        None => false,
+    };
+}
+
+/// 监视在复用容器中以exec方式运行的进程，init_pid为容器的常驻进程
+/// 调用前应先使用reset_max_memory_usage清零内存峰值，cpu_baseline为运行前容器已使用的CPU时间
+pub fn watch_exec(
+    pid: i32,
+    init_pid: i32,
+    time_limit: i64,
+    container_long_id: &str,
+    detect_suspicious: bool,
+    cpu_baseline: i64,
+    timeline: Option<TimelineOptions>,
+) -> ResultType<WatchResult> {
+    // 复用的容器中计数是累计的，只关心本次运行期间的增加
+    let pids_hits_before = read_pids_limit_hits(container_long_id).unwrap_or(0);
+    let procs_file = memory_cgroup_file(container_long_id, "cgroup.procs");
+    let begin = unsafe { get_current_usec() };
+    let mut time_result: i64;
+    let mut warnings = Vec::<String>::new();
+    let mut iteration: u64 = 0;
+    let mut startup = StartupDetector::new(container_long_id, cpu_baseline);
+    let mut sampler = timeline
+        .as_ref()
+        .map(|v| TimelineSampler::new(container_long_id, v, cpu_baseline));
+    let timed_out = loop {
+        time_result = unsafe { get_current_usec() } - begin;
+        startup.poll(time_result, 0);
+        if let Some(sampler) = sampler.as_mut() {
+            sampler.poll(time_result, 0);
+        }
+        if !process_alive(pid) {
+            break false;
+        }
+        if time_result - startup.overhead() >= time_limit {
+            break true;
+        }
+        if detect_suspicious && iteration % PROCESS_SAMPLE_INTERVAL == 0 {
+            inspect_processes(pid, &procs_file, &[init_pid], &mut warnings);
+        }
+        iteration += 1;
+        unsafe { usleep(150) };
+    };
+    // 主进程退出后再检查一次遗留的进程
+    if detect_suspicious {
+        inspect_processes(pid, &procs_file, &[init_pid], &mut warnings);
+    }
+    return Ok(WatchResult {
+        time_result: time_result - startup.overhead(),
+        startup_overhead: startup.overhead(),
+        // 由调用方根据运行前后的cpu用量计算
+        cpu_time_result: 0,
+        memory_result: read_max_memory_usage(container_long_id)?,
+        warnings,
+        timed_out,
+        timeline: sampler.map(|v| v.finish()),
+        pids_limit_hit: read_pids_limit_hits(container_long_id).unwrap_or(0) > pids_hits_before,
     });
 }
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use bollard::{errors::Error, image::CreateImageOptions, Docker};
+use futures_util::stream::StreamExt;
+use log::{error, info};
+
+use crate::core::{config::JudgerConfig, misc::ResultType};
+
+/// 评测需要的镜像: docker_image、sql_image、required_images与image_digests中的镜像
+pub fn required_images(config: &JudgerConfig) -> Vec<String> {
+    let mut ret = vec![config.docker_image.clone()];
+    if !config.sql_image.is_empty() {
+        ret.push(config.sql_image.clone());
+    }
+    ret.extend(config.required_images.iter().cloned());
+    ret.extend(config.image_digests.keys().cloned());
+    ret.sort();
+    ret.dedup();
+    return ret;
+}
+
+/// 镜像的id或任意一个repo digest与expected相同
+fn digest_matches(image: &bollard::models::Image, expected: &str) -> bool {
+    if image.id.eq_ignore_ascii_case(expected) {
+        return true;
+    }
+    return image
+        .repo_digests
+        .as_ref()
+        .map(|v| {
+            v.iter().any(|digest| match digest.split_once('@') {
+                Some((_, d)) => d.eq_ignore_ascii_case(expected),
+                None => false,
+            })
+        })
+        .unwrap_or(false);
+}
+
+/// 拉取镜像，每一层的状态变化时输出日志
+async fn pull_image(docker: &Docker, image: &str) -> ResultType<()> {
+    info!("Pulling image {}", image);
+    let mut stream = docker.create_image(
+        Some(CreateImageOptions {
+            from_image: image,
+            ..Default::default()
+        }),
+        None,
+        None,
+    );
+    // 层id -> 上次输出的状态
+    let mut layers = HashMap::<String, String>::new();
+    while let Some(item) = stream.next().await {
+        let item = item.map_err(|e| anyhow!("Failed to pull image {}: {}", image, e))?;
+        if let Some(e) = item.error {
+            return Err(anyhow!("Failed to pull image {}: {}", image, e));
+        }
+        let status = item.status.unwrap_or_default();
+        match item.id {
+            Some(id) => {
+                if layers.get(&id) != Some(&status) {
+                    info!("{}: {}: {}", image, id, status);
+                    layers.insert(id, status);
+                }
+            }
+            None => info!("{}: {}", image, status),
+        }
+    }
+    return Ok(());
+}
+
+/// 确保镜像存在于本地，不存在时(或force_pull为true时)拉取，并校验image_digests中配置的digest
+pub async fn ensure_image(
+    docker: &Docker,
+    config: &JudgerConfig,
+    image: &str,
+    force_pull: bool,
+) -> ResultType<()> {
+    let exists = match docker.inspect_image(image).await {
+        Ok(_) => true,
+        Err(Error::DockerResponseNotFoundError { .. }) => false,
+        Err(e) => return Err(anyhow!("Failed to inspect image {}: {}", image, e)),
+    };
+    if force_pull || !exists {
+        if !exists && !config.pull_missing_images {
+            return Err(anyhow!("Image {} does not exist", image));
+        }
+        pull_image(docker, image).await?;
+    }
+    if let Some(expected) = config.image_digests.get(image) {
+        let details = docker
+            .inspect_image(image)
+            .await
+            .map_err(|e| anyhow!("Failed to inspect image {}: {}", image, e))?;
+        if !digest_matches(&details, expected) {
+            return Err(anyhow!(
+                "Digest of image {} mismatch: expected {}, got {} {:?}",
+                image,
+                expected,
+                details.id,
+                details.repo_digests.unwrap_or_default()
+            ));
+        }
+        info!("Image {} verified: {}", image, expected);
+    }
+    return Ok(());
+}
+
+/// 检查images中的所有镜像，返回失败的镜像数，images为空时检查required_images
+/// 非docker后端不做任何事
+pub async fn ensure_images(
+    config: &JudgerConfig,
+    images: &[String],
+    force_pull: bool,
+) -> ResultType<usize> {
+    if config.sandbox_backend != "docker" {
+        return Ok(0);
+    }
+    let docker = Docker::connect_with_socket_defaults()
+        .map_err(|e| anyhow!("Failed to initialize docker: {}", e))?;
+    let images = if images.is_empty() {
+        required_images(config)
+    } else {
+        images.to_vec()
+    };
+    let mut failed = 0;
+    for image in images.iter() {
+        if let Err(e) = ensure_image(&docker, config, image, force_pull).await {
+            error!("{}", e);
+            failed += 1;
+        }
+    }
+    return Ok(failed);
+}
@@ -1,2 +1,565 @@
+use std::{
+    ffi::CString,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use self::relay::PipeRelay;
+
+use super::{config::JudgerConfig, misc::ResultType};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default)]
+pub struct ExecuteResult {
+    pub exit_code: i32,
+    // 墙上时间(不含容器启动开销), in microsecond
+    pub time_cost: i64,
+    // 容器启动到程序开始消耗CPU之间的时间, in microsecond
+    pub startup_overhead: i64,
+    // CPU时间(用户态+内核态), in microsecond
+    pub cpu_time_cost: i64,
+    // in bytes
+    pub memory_cost: i64,
+    // 标准输出与标准错误合并后的输出
+    pub output: String,
+    pub output_truncated: bool,
+    // 单独收集的标准错误，同样截断到max_output_length
+    pub stderr: String,
+    // 运行过程中检测到的可疑行为
+    pub warnings: Vec<String>,
+    // 因超时被评测机强制结束，此时的退出代码(如137)不代表程序运行错误
+    pub killed_by_watchdog: bool,
+    // 被OOM killer杀死
+    pub oom_killed: bool,
+    // 运行过程中的资源占用采样，未开启采样或后端不支持时为None
+    pub timeline: Option<ResourceTimeline>,
+    // 程序创建进程或线程时达到了pids_limit
+    pub pids_limit_exceeded: bool,
+    // 写入工作目录的数据超过了workdir_size_limit，程序被评测机结束
+    pub workdir_size_limit_exceeded: bool,
+}
+/// 运行过程中定时采样的资源占用，每个采样为(开始监视后的毫秒数, 内存字节数, CPU时间毫秒数)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceTimeline {
+    // 采样间隔(毫秒)，采样数达到上限后会丢弃一半的采样并加倍
+    pub interval: u64,
+    pub samples: Vec<(i64, i64, i64)>,
+}
+#[derive(Debug, Clone)]
+pub struct TimelineOptions {
+    // 毫秒
+    pub interval: u64,
+    pub max_samples: usize,
+}
+impl TimelineOptions {
+    /// resource_timeline_interval为0时返回None
+    pub fn from_config(config: &JudgerConfig) -> Option<Self> {
+        if config.resource_timeline_interval == 0 {
+            return None;
+        }
+        return Some(Self {
+            interval: config.resource_timeline_interval,
+            max_samples: config.resource_timeline_max_samples.max(2),
+        });
+    }
+}
+#[derive(Debug, Clone, Default)]
+pub struct ExecuteOptions {
+    // (宿主机路径, 容器内路径)，以只读方式挂载
+    pub readonly_mounts: Vec<(String, String)>,
+    // (宿主机路径, 容器内路径)，以读写方式挂载
+    pub readwrite_mounts: Vec<(String, String)>,
+    // 检测后台进程与定时器
+    pub detect_suspicious: bool,
+    // 磁盘IO限制(仅docker后端)
+    pub io_limit: IoLimit,
+    // 单个文件的大小上限(字节)，写入超出时程序收到SIGXFSZ，0为不限制
+    pub file_size_limit: i64,
+    // docker security_opt中的seccomp配置(seccomp=...)，None时使用docker的默认配置
+    pub seccomp: Option<String>,
+    // 运行时采样资源占用(仅docker后端)
+    pub timeline: Option<TimelineOptions>,
+    // 容器内进程与线程总数的上限，0为不限制(仅docker后端)
+    pub pids_limit: i64,
+    // 工作目录与可写挂载目录的写入量上限(字节)，超出时结束程序，0为不限制(仅docker后端)
+    pub workdir_size_limit: i64,
+}
+/// 沙箱中程序的运行环境，使结果不受镜像与宿主机环境的影响
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionEnv {
+    // 形如KEY=VALUE
+    pub vars: Vec<String>,
+    // 清除镜像中设置的环境变量，只保留vars
+    pub scrub: bool,
+    // 关闭地址空间随机化
+    pub disable_aslr: bool,
+    // 容器内运行程序的(uid, gid)，None时使用镜像的默认用户(仅docker后端)
+    pub user: Option<(u32, u32)>,
+}
+impl ExecutionEnv {
+    pub fn from_config(config: &JudgerConfig) -> Self {
+        return Self {
+            vars: config.sandbox_env.clone(),
+            scrub: config.scrub_sandbox_env,
+            disable_aslr: config.disable_aslr,
+            user: config.sandbox_user().ok().flatten(),
+        };
+    }
+    /// docker容器配置中的User
+    pub fn docker_user(&self) -> Option<String> {
+        return self.user.map(|(uid, gid)| format!("{}:{}", uid, gid));
+    }
+    /// 以非root用户运行时，将工作目录与可写挂载目录中的文件交给该用户
+    /// 评测机在两次运行之间写入的文件属于root，每次运行前都需要调用
+    pub async fn chown_writable_mounts(
+        &self,
+        mount_dir: &str,
+        readwrite_mounts: &[(String, String)],
+    ) -> ResultType<()> {
+        let (uid, gid) = match self.user {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let mut dirs = vec![mount_dir.to_string()];
+        dirs.extend(readwrite_mounts.iter().map(|(source, _)| source.clone()));
+        tokio::task::spawn_blocking(move || {
+            for dir in dirs.iter() {
+                chown_recursive(Path::new(dir), uid, gid)?;
+            }
+            return Ok(());
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to run blocking task: {}", e))?
+    }
+    /// 在command之前加上设置环境的命令，两者都通过exec运行command，不改变进程
+    pub fn wrap(&self, command: &Vec<String>) -> Vec<String> {
+        let mut ret = vec![];
+        if self.scrub || !self.vars.is_empty() {
+            ret.push("env".to_string());
+            if self.scrub {
+                ret.push("-i".to_string());
+            }
+            ret.extend(self.vars.iter().cloned());
+        }
+        if self.disable_aslr {
+            ret.extend([
+                "setarch".to_string(),
+                std::env::consts::ARCH.to_string(),
+                "-R".to_string(),
+            ]);
+        }
+        if ret.is_empty() {
+            return command.clone();
+        }
+        ret.extend(command.iter().cloned());
+        return ret;
+    }
+}
+/// 修改path及其下所有文件的所有者，不跟随符号链接
+fn chown_recursive(path: &Path, uid: u32, gid: u32) -> ResultType<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+        .map_err(|e| anyhow!("Failed to chown `{}`: {}", path.display(), e))?;
+    let metadata = std::fs::symlink_metadata(path)
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", path.display(), e))?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)
+            .map_err(|e| anyhow!("Failed to read `{}`: {}", path.display(), e))?
+        {
+            let entry = entry.map_err(|e| anyhow!("Failed to read `{}`: {}", path.display(), e))?;
+            chown_recursive(&entry.path(), uid, gid)?;
+        }
+    }
+    return Ok(());
+}
+#[derive(Debug, Clone, Default)]
+pub struct IoLimit {
+    // 限制读写速率的块设备，为空时不限制速率
+    pub device: String,
+    // 字节每秒，0为不限制
+    pub read_bps: i64,
+    pub write_bps: i64,
+    // blkio权重，0为不设置
+    pub weight: u16,
+}
+#[async_trait]
+pub trait Runner: Sync + Send {
+    /// 在沙箱中运行命令，mount_dir将作为工作目录/temp
+    async fn execute(
+        &self,
+        image_name: &str,
+        mount_dir: &str,
+        command: &Vec<String>,
+        // in bytes
+        memory_limit: i64,
+        // 墙上时间, in microsecond
+        time_limit: i64,
+        max_output_length: usize,
+        options: &ExecuteOptions,
+    ) -> ResultType<ExecuteResult>;
+    /// 创建在多次运行之间复用的沙箱，不支持复用的后端返回None
+    async fn create_session(
+        &self,
+        _image_name: &str,
+        _mount_dir: &str,
+        _options: &ExecuteOptions,
+    ) -> ResultType<Option<Box<dyn Session>>> {
+        return Ok(None);
+    }
+}
+
+/// 同一个提交内复用的沙箱，工作目录与挂载在创建时确定
+#[async_trait]
+pub trait Session: Sync + Send {
+    async fn execute(
+        &self,
+        command: &Vec<String>,
+        // in bytes
+        memory_limit: i64,
+        // 墙上时间, in microsecond
+        time_limit: i64,
+        max_output_length: usize,
+    ) -> ResultType<ExecuteResult>;
+    /// 释放沙箱占用的资源
+    async fn close(&self) -> ResultType<()>;
+}
+
+/// 每次运行都创建新沙箱的Session
+struct OneShotSession {
+    runner: Arc<dyn Runner>,
+    image_name: String,
+    mount_dir: String,
+    options: ExecuteOptions,
+}
+#[async_trait]
+impl Session for OneShotSession {
+    async fn execute(
+        &self,
+        command: &Vec<String>,
+        memory_limit: i64,
+        time_limit: i64,
+        max_output_length: usize,
+    ) -> ResultType<ExecuteResult> {
+        return self
+            .runner
+            .execute(
+                &self.image_name,
+                &self.mount_dir,
+                command,
+                memory_limit,
+                time_limit,
+                max_output_length,
+                &self.options,
+            )
+            .await;
+    }
+    async fn close(&self) -> ResultType<()> {
+        return Ok(());
+    }
+}
+
+/// 为一个提交打开Session，后端不支持复用时退化为每次运行创建新沙箱
+pub async fn open_session(
+    runner: &Arc<dyn Runner>,
+    image_name: &str,
+    mount_dir: &str,
+    options: &ExecuteOptions,
+) -> ResultType<Box<dyn Session>> {
+    if let Some(session) = runner
+        .create_session(image_name, mount_dir, options)
+        .await?
+    {
+        return Ok(session);
+    }
+    return Ok(Box::new(OneShotSession {
+        runner: runner.clone(),
+        image_name: image_name.to_string(),
+        mount_dir: mount_dir.to_string(),
+        options: options.clone(),
+    }));
+}
+
+pub fn create_runner(config: &JudgerConfig) -> ResultType<Arc<dyn Runner>> {
+    return match config.sandbox_backend.as_str() {
+        "docker" => Ok(Arc::new(docker::DockerRunner {
+            reuse_container: config.reuse_container,
+            env: ExecutionEnv::from_config(config),
+            log_limit: docker::LogCollectLimit {
+                timeout: Duration::from_millis(config.log_collect_timeout),
+                max_bytes: config.log_collect_max_bytes,
+            },
+        })),
+        "nsjail" => Ok(Arc::new(nsjail::NsjailRunner::new(config)?)),
+        other => Err(anyhow!("Unknown sandbox backend: {}", other)),
+    };
+}
+
+// 交互器 -> 用户程序
+pub const INTERACT_USER_INPUT: &str = "interact_in";
+// 用户程序 -> 交互器
+pub const INTERACT_USER_OUTPUT: &str = "interact_out";
+// 评测机 -> 交互器
+pub const INTERACTOR_INPUT: &str = "interactor_in";
+// 交互器 -> 评测机
+pub const INTERACTOR_OUTPUT: &str = "interactor_out";
+// 用户工作目录在交互器容器中的挂载点
+pub const INTERACT_PIPE_DIR: &str = "/pipe";
+
+fn make_fifo(path: &Path) -> ResultType<()> {
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| anyhow!("Failed to remove old fifo: {}", e))?;
+    }
+    let c_path = CString::new(path.to_str().ok_or(anyhow!("Invalid fifo path"))?)?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o666) } != 0 {
+        return Err(anyhow!(
+            "Failed to create fifo: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    return Ok(());
+}
+
+/// 在两个沙箱中分别运行用户程序与交互器，二者通过用户工作目录下的命名管道相连
+/// 用户程序应使用重定向 `< interact_in > interact_out`
+/// 交互器应使用重定向 `> /pipe/interactor_out < /pipe/interactor_in` (先打开写端，避免死锁)
+/// idle_limit不为0时由评测机转发数据，两个程序都在运行但超过idle_limit没有数据流动时关闭管道
+/// 返回 (用户程序运行结果, 交互器运行结果, 是否因空闲超时关闭了管道)
+pub async fn execute_interactive(
+    runner: &dyn Runner,
+    image_name: &str,
+    user_dir: &str,
+    user_command: &Vec<String>,
+    user_memory_limit: i64,
+    user_time_limit: i64,
+    user_options: &ExecuteOptions,
+    interactor_dir: &str,
+    interactor_command: &Vec<String>,
+    interactor_memory_limit: i64,
+    interactor_time_limit: i64,
+    // in millisecond, 0为不检测
+    idle_limit: i64,
+) -> ResultType<(ExecuteResult, ExecuteResult, bool)> {
+    let dir = Path::new(user_dir);
+    make_fifo(&dir.join(INTERACT_USER_INPUT))?;
+    make_fifo(&dir.join(INTERACT_USER_OUTPUT))?;
+    let user_exited = Arc::new(AtomicBool::new(false));
+    let interactor_exited = Arc::new(AtomicBool::new(false));
+    let stop = Arc::new(AtomicBool::new(false));
+    let relay = if idle_limit > 0 {
+        make_fifo(&dir.join(INTERACTOR_INPUT))?;
+        make_fifo(&dir.join(INTERACTOR_OUTPUT))?;
+        let relay = PipeRelay::new(
+            &dir.join(INTERACT_USER_INPUT),
+            &dir.join(INTERACT_USER_OUTPUT),
+            &dir.join(INTERACTOR_INPUT),
+            &dir.join(INTERACTOR_OUTPUT),
+            user_exited.clone(),
+            interactor_exited.clone(),
+            stop.clone(),
+            Duration::from_millis(idle_limit as u64),
+        )?;
+        Some(tokio::task::spawn_blocking(move || relay.run()))
+    } else {
+        // 不转发时两个程序直接使用同一对管道
+        for name in [INTERACTOR_INPUT, INTERACTOR_OUTPUT] {
+            if dir.join(name).exists() {
+                std::fs::remove_file(dir.join(name))
+                    .map_err(|e| anyhow!("Failed to remove old fifo: {}", e))?;
+            }
+        }
+        std::fs::hard_link(dir.join(INTERACT_USER_OUTPUT), dir.join(INTERACTOR_INPUT))
+            .map_err(|e| anyhow!("Failed to link fifo: {}", e))?;
+        std::fs::hard_link(dir.join(INTERACT_USER_INPUT), dir.join(INTERACTOR_OUTPUT))
+            .map_err(|e| anyhow!("Failed to link fifo: {}", e))?;
+        None
+    };
+    let interactor_options = ExecuteOptions {
+        readwrite_mounts: vec![(user_dir.to_string(), INTERACT_PIPE_DIR.to_string())],
+        ..Default::default()
+    };
+    let (user_result, interactor_result) = tokio::join!(
+        async {
+            let ret = runner
+                .execute(
+                    image_name,
+                    user_dir,
+                    user_command,
+                    user_memory_limit,
+                    user_time_limit,
+                    1000,
+                    user_options,
+                )
+                .await;
+            user_exited.store(true, Ordering::SeqCst);
+            ret
+        },
+        async {
+            let ret = runner
+                .execute(
+                    image_name,
+                    interactor_dir,
+                    interactor_command,
+                    interactor_memory_limit,
+                    interactor_time_limit,
+                    1024 * 1024,
+                    &interactor_options,
+                )
+                .await;
+            interactor_exited.store(true, Ordering::SeqCst);
+            ret
+        }
+    );
+    stop.store(true, Ordering::SeqCst);
+    let idle = match relay {
+        Some(handle) => handle
+            .await
+            .map_err(|e| anyhow!("Failed to join pipe relay: {}", e))??,
+        None => false,
+    };
+    return Ok((
+        user_result.map_err(|e| anyhow!("Failed to run user program: {}", e))?,
+        interactor_result.map_err(|e| anyhow!("Failed to run interactor: {}", e))?,
+        idle,
+    ));
+}
+
+// 通信题: 第一个程序 -> 第二个程序
+pub const COMM_FIRST_TO_SECOND: &str = "comm_1to2";
+// 通信题: 第二个程序 -> 第一个程序
+pub const COMM_SECOND_TO_FIRST: &str = "comm_2to1";
+
+/// 在两个沙箱中同时运行通信题的两个程序，二者通过工作目录下的命名管道相连
+/// 第一个程序应使用重定向 `> comm_1to2 < comm_2to1`
+/// 第二个程序应使用重定向 `< comm_1to2 > comm_2to1` (与第一个程序打开管道的顺序一致，避免死锁)
+/// 返回 (第一个程序运行结果, 第二个程序运行结果)
+pub async fn execute_paired(
+    runner: &dyn Runner,
+    image_name: &str,
+    working_dir: &str,
+    first_command: &Vec<String>,
+    second_command: &Vec<String>,
+    memory_limit: i64,
+    time_limit: i64,
+    options: &ExecuteOptions,
+) -> ResultType<(ExecuteResult, ExecuteResult)> {
+    make_fifo(&Path::new(working_dir).join(COMM_FIRST_TO_SECOND))?;
+    make_fifo(&Path::new(working_dir).join(COMM_SECOND_TO_FIRST))?;
+    let (first_result, second_result) = tokio::join!(
+        runner.execute(
+            image_name,
+            working_dir,
+            first_command,
+            memory_limit,
+            time_limit,
+            1000,
+            options,
+        ),
+        runner.execute(
+            image_name,
+            working_dir,
+            second_command,
+            memory_limit,
+            time_limit,
+            1000,
+            options,
+        )
+    );
+    return Ok((
+        first_result.map_err(|e| anyhow!("Failed to run the first program: {}", e))?,
+        second_result.map_err(|e| anyhow!("Failed to run the second program: {}", e))?,
+    ));
+}
+
+/// 合并通信题两个程序的运行结果，时间与内存取较大者，退出代码取第一个非零值
+pub fn merge_paired_results(first: ExecuteResult, second: ExecuteResult) -> ExecuteResult {
+    return ExecuteResult {
+        exit_code: if first.exit_code != 0 {
+            first.exit_code
+        } else {
+            second.exit_code
+        },
+        time_cost: first.time_cost.max(second.time_cost),
+        startup_overhead: first.startup_overhead.max(second.startup_overhead),
+        cpu_time_cost: first.cpu_time_cost.max(second.cpu_time_cost),
+        memory_cost: first.memory_cost.max(second.memory_cost),
+        output: first.output + &second.output,
+        output_truncated: first.output_truncated || second.output_truncated,
+        stderr: first.stderr + &second.stderr,
+        killed_by_watchdog: first.killed_by_watchdog || second.killed_by_watchdog,
+        oom_killed: first.oom_killed || second.oom_killed,
+        warnings: first
+            .warnings
+            .into_iter()
+            .chain(second.warnings.into_iter())
+            .collect(),
+        timeline: first.timeline.or(second.timeline),
+        pids_limit_exceeded: first.pids_limit_exceeded || second.pids_limit_exceeded,
+        workdir_size_limit_exceeded: first.workdir_size_limit_exceeded
+            || second.workdir_size_limit_exceeded,
+    };
+}
+
+pub mod disk_quota;
 pub mod docker;
+pub mod docker_session;
 pub mod docker_watch;
+pub mod images;
+pub mod nsjail;
+pub mod relay;
+pub mod seccomp;
+
+impl ExecuteResult {
+    /// 程序被信号终止时(退出代码为128+信号)返回信号名
+    pub fn signal_name(&self) -> Option<&'static str> {
+        if self.exit_code <= 128 {
+            return None;
+        }
+        return Some(match self.exit_code - 128 {
+            libc::SIGABRT => "SIGABRT",
+            libc::SIGBUS => "SIGBUS",
+            libc::SIGFPE => "SIGFPE",
+            libc::SIGILL => "SIGILL",
+            libc::SIGKILL => "SIGKILL",
+            libc::SIGSEGV => "SIGSEGV",
+            libc::SIGSYS => "SIGSYS",
+            libc::SIGTERM => "SIGTERM",
+            libc::SIGXFSZ => "SIGXFSZ",
+            _ => return None,
+        });
+    }
+    /// 标准错误的开头至多length个字符，用于附加在测试点信息中
+    pub fn stderr_excerpt(&self, length: usize) -> Option<String> {
+        let stderr = self.stderr.trim();
+        if length == 0 || stderr.is_empty() {
+            return None;
+        }
+        let mut excerpt = stderr.chars().take(length).collect::<String>();
+        if excerpt.len() < stderr.len() {
+            excerpt.push_str("...");
+        }
+        return Some(excerpt);
+    }
+    /// 常见终止信号的说明
+    pub fn signal_description(&self) -> Option<&'static str> {
+        if self.exit_code <= 128 {
+            return None;
+        }
+        return Some(match self.exit_code - 128 {
+            libc::SIGABRT => "Aborted",
+            libc::SIGBUS => "Bus error",
+            libc::SIGFPE => "Floating point exception",
+            libc::SIGILL => "Illegal instruction",
+            libc::SIGKILL => "Killed",
+            libc::SIGSEGV => "Segmentation fault",
+            libc::SIGSYS => "Bad system call",
+            libc::SIGTERM => "Terminated",
+            libc::SIGXFSZ => "File size limit exceeded",
+            _ => return None,
+        });
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+use crate::core::{
+    config::JudgerConfig,
+    misc::ResultType,
+    runner::{ExecuteOptions, ExecuteResult, ExecutionEnv, Runner},
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use libc::{c_int, kill, rusage, usleep, wait4, SIGKILL, WNOHANG};
+use log::{info, warn};
+
+/// 基于nsjail的轻量沙箱，省去了每次创建docker容器的开销
+/// 程序运行在nsjail_chroot指定的根目录下，忽略docker_image
+pub struct NsjailRunner {
+    nsjail_path: String,
+    chroot: String,
+    env: ExecutionEnv,
+    // nsjail自身创建命名空间、挂载等的开销(墙上时间, CPU时间), in microsecond
+    overhead: (i64, i64),
+}
+
+impl NsjailRunner {
+    pub fn new(config: &JudgerConfig) -> ResultType<Self> {
+        // 以宿主机的根目录作为沙箱根目录时，用户程序可以读取配置文件与测试数据
+        if config.nsjail_chroot.is_empty() {
+            return Err(anyhow!(
+                "nsjail_chroot must be set to a minimal root filesystem"
+            ));
+        }
+        let chroot = Path::new(&config.nsjail_chroot)
+            .canonicalize()
+            .map_err(|e| anyhow!("Invalid nsjail_chroot {}: {}", config.nsjail_chroot, e))?;
+        if chroot == Path::new("/") {
+            return Err(anyhow!("nsjail_chroot must not be the host root directory"));
+        }
+        let mut ret = Self {
+            nsjail_path: config.nsjail_path.clone(),
+            chroot: chroot.to_str().unwrap_or_default().to_string(),
+            env: ExecutionEnv::from_config(config),
+            overhead: (0, 0),
+        };
+        ret.overhead = match ret.measure_overhead() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to measure nsjail overhead: {}", e);
+                (0, 0)
+            }
+        };
+        info!(
+            "Nsjail overhead: {} us wall time, {} us cpu time",
+            ret.overhead.0, ret.overhead.1
+        );
+        return Ok(ret);
+    }
+    /// 运行一个空程序，测量nsjail自身的开销，从之后每次运行的耗时中扣除
+    fn measure_overhead(&self) -> ResultType<(i64, i64)> {
+        let mount_dir =
+            tempfile::tempdir().map_err(|e| anyhow!("Failed to create directory: {}", e))?;
+        let args = self.build_args(
+            mount_dir.path().to_str().unwrap_or_default(),
+            &vec!["true".to_string()],
+            256 * 1024 * 1024,
+            10_000_000,
+            &ExecuteOptions::default(),
+        );
+        let result = run_blocking(
+            self.nsjail_path.clone(),
+            args,
+            256 * 1024 * 1024,
+            10_000_000,
+            1024,
+            (0, 0),
+        )?;
+        if result.exit_code != 0 {
+            return Err(anyhow!(
+                "Exited with {}: {}",
+                result.exit_code,
+                result.output
+            ));
+        }
+        return Ok((result.time_cost, result.cpu_time_cost));
+    }
+    fn build_args(
+        &self,
+        mount_dir: &str,
+        command: &Vec<String>,
+        memory_limit: i64,
+        time_limit: i64,
+        options: &ExecuteOptions,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "--mode".to_string(),
+            "o".to_string(),
+            "--quiet".to_string(),
+            "--chroot".to_string(),
+            self.chroot.clone(),
+            "--cwd".to_string(),
+            "/temp".to_string(),
+            "--bindmount".to_string(),
+            format!("{}:/temp", mount_dir),
+            "--cgroup_mem_max".to_string(),
+            memory_limit.to_string(),
+            // 精确的时间限制由judger处理，这里只是兜底
+            "--time_limit".to_string(),
+            (time_limit / 1_000_000 + 2).to_string(),
+            "--rlimit_as".to_string(),
+            "inf".to_string(),
+            "--rlimit_fsize".to_string(),
+            // 单位为MB
+            if options.file_size_limit > 0 {
+                ((options.file_size_limit + (1 << 20) - 1) >> 20).to_string()
+            } else {
+                "inf".to_string()
+            },
+            "--rlimit_stack".to_string(),
+            "hard".to_string(),
+        ];
+        for (source, target) in options.readonly_mounts.iter() {
+            args.push("--bindmount_ro".to_string());
+            args.push(format!("{}:{}", source, target));
+        }
+        for (source, target) in options.readwrite_mounts.iter() {
+            args.push("--bindmount".to_string());
+            args.push(format!("{}:{}", source, target));
+        }
+        args.push("--".to_string());
+        args.extend(command.iter().cloned());
+        return args;
+    }
+}
+
+fn run_blocking(
+    nsjail_path: String,
+    args: Vec<String>,
+    memory_limit: i64,
+    time_limit: i64,
+    max_output_length: usize,
+    overhead: (i64, i64),
+) -> ResultType<ExecuteResult> {
+    let mut output_file =
+        tempfile::tempfile().map_err(|e| anyhow!("Failed to create output file: {}", e))?;
+    let mut stderr_file =
+        tempfile::tempfile().map_err(|e| anyhow!("Failed to create output file: {}", e))?;
+    let child = Command::new(&nsjail_path)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(output_file.try_clone()?))
+        .stderr(Stdio::from(stderr_file.try_clone()?))
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start nsjail: {}", e))?;
+    let pid = child.id() as i32;
+    let begin = Instant::now();
+    let mut status: c_int = 0;
+    let mut usage: rusage = unsafe { std::mem::zeroed() };
+    let mut killed_by_judger = false;
+    loop {
+        let ret = unsafe { wait4(pid, &mut status, WNOHANG, &mut usage) };
+        if ret == pid {
+            break;
+        }
+        if ret < 0 {
+            return Err(anyhow!(
+                "Failed to wait nsjail: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // nsjail自身的开销不计入程序的墙上时间
+        if begin.elapsed().as_micros() as i64 >= time_limit + overhead.0 {
+            unsafe {
+                kill(pid, SIGKILL);
+                wait4(pid, &mut status, 0, &mut usage);
+            }
+            killed_by_judger = true;
+            break;
+        }
+        unsafe { usleep(150) };
+    }
+    let time_cost = (begin.elapsed().as_micros() as i64 - overhead.0).max(0);
+    // rusage包含nsjail与其等待的沙箱内进程，扣除nsjail自身的CPU时间
+    let cpu_time_cost = (usage.ru_utime.tv_sec * 1_000_000
+        + usage.ru_utime.tv_usec
+        + usage.ru_stime.tv_sec * 1_000_000
+        + usage.ru_stime.tv_usec
+        - overhead.1)
+        .max(0);
+    let mut memory_cost = usage.ru_maxrss * 1024;
+    let mut oom_killed = false;
+    // (退出代码, 终止程序的信号)
+    let (exit_code, signal) = if libc::WIFEXITED(status) {
+        // 沙箱内的程序被信号终止时，nsjail以128+信号值退出
+        let code = libc::WEXITSTATUS(status);
+        (code, if code > 128 { Some(code - 128) } else { None })
+    } else if libc::WIFSIGNALED(status) {
+        (128 + libc::WTERMSIG(status), Some(libc::WTERMSIG(status)))
+    } else {
+        (0, None)
+    };
+    // 除了超时以外，只有cgroup的OOM killer会发送SIGKILL
+    if signal == Some(SIGKILL) && !killed_by_judger {
+        memory_cost = memory_limit;
+        oom_killed = true;
+    }
+    let mut raw_output = vec![];
+    output_file.seek(SeekFrom::Start(0))?;
+    output_file.read_to_end(&mut raw_output)?;
+    let mut raw_stderr = vec![];
+    stderr_file.seek(SeekFrom::Start(0))?;
+    stderr_file.read_to_end(&mut raw_stderr)?;
+    // 分开收集后无法保留交错顺序，标准错误接在标准输出之后
+    raw_output.extend_from_slice(&raw_stderr);
+    let mut output = String::from_utf8_lossy(&raw_output).to_string();
+    let mut truncated = false;
+    if output.len() > max_output_length {
+        output = String::from_iter(output.chars().take(max_output_length));
+        truncated = true;
+    }
+    let stderr = String::from_iter(
+        String::from_utf8_lossy(&raw_stderr)
+            .chars()
+            .take(max_output_length),
+    );
+    return Ok(ExecuteResult {
+        exit_code,
+        time_cost,
+        startup_overhead: overhead.0,
+        cpu_time_cost,
+        memory_cost,
+        output,
+        output_truncated: truncated,
+        stderr,
+        warnings: vec![],
+        killed_by_watchdog: killed_by_judger,
+        oom_killed,
+        timeline: None,
+        pids_limit_exceeded: false,
+        workdir_size_limit_exceeded: false,
+    });
+}
+
+#[async_trait]
+impl Runner for NsjailRunner {
+    async fn execute(
+        &self,
+        _image_name: &str,
+        mount_dir: &str,
+        command: &Vec<String>,
+        memory_limit: i64,
+        time_limit: i64,
+        max_output_length: usize,
+        options: &ExecuteOptions,
+    ) -> ResultType<ExecuteResult> {
+        let command = self.env.wrap(command);
+        let args = self.build_args(mount_dir, &command, memory_limit, time_limit, options);
+        info!("Running nsjail with command: {:?}", command);
+        let nsjail_path = self.nsjail_path.clone();
+        let overhead = self.overhead;
+        let result = tokio::task::spawn_blocking(move || {
+            run_blocking(
+                nsjail_path,
+                args,
+                memory_limit,
+                time_limit,
+                max_output_length,
+                overhead,
+            )
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to run blocking task: {}", e))??;
+        info!("Nsjail result: {:#?}", result);
+        return Ok(result);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::core::misc::ResultType;
+
+// 每个方向最多缓存的数据量，超过后暂停读取
+const RELAY_BUFFER_LIMIT: usize = 1024 * 1024;
+// poll的超时时间，用于定期检查程序是否已经退出
+const POLL_INTERVAL_MS: i32 = 10;
+
+/// 以非阻塞读写方式打开命名管道，评测机同时持有读端与写端，因此不会阻塞
+fn open_fifo(path: &Path) -> ResultType<File> {
+    return std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open fifo {}: {}", path.display(), e));
+}
+
+/// 一个方向的转发: src -> dst
+struct Direction {
+    src: File,
+    dst: File,
+    buf: Vec<u8>,
+    // 向src写入数据的程序已经退出
+    source_exited: Arc<AtomicBool>,
+}
+
+impl Direction {
+    /// 尽可能多地读取与写出，返回(是否有数据流动, 是否已经转发完毕)
+    /// 写数据的程序已经退出且数据已经全部转发时视为转发完毕，此时关闭写端使读数据的程序读到EOF
+    fn pump(&mut self) -> ResultType<(bool, bool)> {
+        // 必须在读取前检查，否则程序退出前写入的数据可能还未读取
+        let exited = self.source_exited.load(Ordering::SeqCst);
+        let mut progressed = false;
+        let mut chunk = [0u8; 64 * 1024];
+        while self.buf.len() < RELAY_BUFFER_LIMIT {
+            match self.src.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(anyhow!("Failed to read from pipe: {}", e)),
+            }
+        }
+        while !self.buf.is_empty() {
+            match self.dst.write(&self.buf) {
+                Ok(n) => {
+                    self.buf.drain(..n);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(anyhow!("Failed to write to pipe: {}", e)),
+            }
+        }
+        return Ok((progressed, exited && self.buf.is_empty()));
+    }
+}
+
+/// 交互题中在用户程序与交互器之间转发数据，两个方向都没有数据流动超过idle_limit时关闭管道
+pub struct PipeRelay {
+    directions: Vec<Direction>,
+    idle_limit: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+impl PipeRelay {
+    /// 转发 interactor_output -> user_input 与 user_output -> interactor_input
+    pub fn new(
+        user_input: &Path,
+        user_output: &Path,
+        interactor_input: &Path,
+        interactor_output: &Path,
+        user_exited: Arc<AtomicBool>,
+        interactor_exited: Arc<AtomicBool>,
+        stop: Arc<AtomicBool>,
+        idle_limit: Duration,
+    ) -> ResultType<Self> {
+        return Ok(Self {
+            directions: vec![
+                Direction {
+                    src: open_fifo(interactor_output)?,
+                    dst: open_fifo(user_input)?,
+                    buf: vec![],
+                    source_exited: interactor_exited,
+                },
+                Direction {
+                    src: open_fifo(user_output)?,
+                    dst: open_fifo(interactor_input)?,
+                    buf: vec![],
+                    source_exited: user_exited,
+                },
+            ],
+            idle_limit,
+            stop,
+        });
+    }
+    fn wait(&self) {
+        let mut fds = vec![];
+        for direction in self.directions.iter() {
+            fds.push(libc::pollfd {
+                fd: direction.src.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            });
+            if !direction.buf.is_empty() {
+                fds.push(libc::pollfd {
+                    fd: direction.dst.as_raw_fd(),
+                    events: libc::POLLOUT,
+                    revents: 0,
+                });
+            }
+        }
+        unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                POLL_INTERVAL_MS,
+            );
+        }
+    }
+    /// 阻塞运行直到stop被设置，返回是否因空闲超时关闭了管道
+    pub fn run(mut self) -> ResultType<bool> {
+        let mut last_activity = Instant::now();
+        while !self.stop.load(Ordering::SeqCst) {
+            let mut progressed = false;
+            let mut finished = vec![];
+            for direction in self.directions.iter_mut() {
+                let (v, done) = direction.pump()?;
+                progressed |= v;
+                finished.push(done);
+            }
+            // 丢弃Direction即关闭两端
+            let mut finished = finished.into_iter();
+            self.directions.retain(|_| !finished.next().unwrap());
+            if progressed {
+                last_activity = Instant::now();
+            }
+            // 有程序退出后不再检查空闲
+            if self
+                .directions
+                .iter()
+                .all(|v| !v.source_exited.load(Ordering::SeqCst) && v.buf.is_empty())
+                && self.directions.len() == 2
+                && last_activity.elapsed() >= self.idle_limit
+            {
+                info!(
+                    "No data transferred for {} ms, closing pipes",
+                    self.idle_limit.as_millis()
+                );
+                return Ok(true);
+            }
+            if self.directions.is_empty() {
+                return Ok(false);
+            }
+            self.wait();
+        }
+        return Ok(false);
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::anyhow;
+use serde_json::json;
+
+use crate::core::{config::JudgerConfig, misc::ResultType};
+
+// seccomp_profile为deny_list时默认禁止的系统调用；网络已由容器的网络设置禁用，不禁止socket以免影响部分语言的运行时
+pub const DEFAULT_DENY_SYSCALLS: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "mount",
+    "umount",
+    "umount2",
+    "pivot_root",
+    "chroot",
+    "unshare",
+    "setns",
+    "bpf",
+    "perf_event_open",
+    "userfaultfd",
+    "keyctl",
+    "add_key",
+    "request_key",
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "open_by_handle_at",
+    "name_to_handle_at",
+    "reboot",
+    "swapon",
+    "swapoff",
+    "acct",
+    "settimeofday",
+    "clock_settime",
+    "clock_adjtime",
+    "adjtimex",
+];
+
+/// 由禁止列表生成的seccomp配置，其他系统调用均允许，被禁止的调用返回EPERM
+/// 该配置会替代docker默认的允许列表，隔离程度弱于docker的默认配置
+pub fn deny_list_profile(syscalls: &[String]) -> String {
+    return json!({
+        "defaultAction": "SCMP_ACT_ALLOW",
+        "syscalls": [{
+            "names": syscalls,
+            "action": "SCMP_ACT_ERRNO",
+            "errnoRet": 1
+        }]
+    })
+    .to_string();
+}
+
+/// 解析language使用的seccomp配置，返回docker security_opt中的值，None表示使用docker的默认配置
+/// 配置值为docker、deny_list(内置的禁止列表，default为其旧名称)、unconfined、JSON字符串或JSON文件路径
+pub fn seccomp_security_opt(config: &JudgerConfig, language: &str) -> ResultType<Option<String>> {
+    let value = config
+        .language_seccomp_profiles
+        .get(language)
+        .unwrap_or(&config.seccomp_profile)
+        .trim();
+    let profile = match value {
+        "docker" => return Ok(None),
+        "unconfined" => return Ok(Some("seccomp=unconfined".to_string())),
+        "deny_list" | "default" => deny_list_profile(&config.seccomp_deny_syscalls),
+        v if v.starts_with('{') => v.to_string(),
+        path => std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read seccomp profile {}: {}", path, e))?,
+    };
+    serde_json::from_str::<serde_json::Value>(&profile)
+        .map_err(|e| anyhow!("Invalid seccomp profile for `{}`: {}", language, e))?;
+    return Ok(Some(format!("seccomp={}", profile)));
+}
+
+/// 启动时检查所有的seccomp配置
+pub fn check_seccomp_profiles(config: &JudgerConfig) -> ResultType<()> {
+    if config.sandbox_backend != "docker" {
+        return Ok(());
+    }
+    seccomp_security_opt(config, "")?;
+    for language in config.language_seccomp_profiles.keys() {
+        seccomp_security_opt(config, language)?;
+    }
+    return Ok(());
+}
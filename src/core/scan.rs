@@ -0,0 +1,106 @@
+use anyhow::anyhow;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::misc::ResultType;
+
+/// 编译前对源代码进行的静态检查规则
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ForbiddenRule {
+    // 正则表达式
+    pub pattern: String,
+    // reject: 拒绝评测; flag: 照常评测，在评测信息中提示
+    #[serde(default = "default_action")]
+    pub action: String,
+    // 仅对这些语言ID生效，为空时对所有语言生效
+    #[serde(default)]
+    pub languages: Vec<String>,
+    // 展示给用户的说明，为空时展示匹配到的内容
+    #[serde(default)]
+    pub message: String,
+}
+fn default_action() -> String {
+    return "reject".to_string();
+}
+
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    // 命中reject规则时的说明
+    pub rejected: Option<String>,
+    // 命中flag规则时的说明
+    pub flags: Vec<String>,
+}
+
+/// 使用rules检查language语言的各个源文件
+pub fn scan_code(
+    rules: &[ForbiddenRule],
+    language: &str,
+    codes: &[&String],
+) -> ResultType<ScanResult> {
+    let mut result = ScanResult::default();
+    for rule in rules.iter() {
+        if !rule.languages.is_empty() && !rule.languages.iter().any(|v| v == language) {
+            continue;
+        }
+        let regex = Regex::new(&rule.pattern)
+            .map_err(|e| anyhow!("Invalid forbidden rule {}: {}", rule.pattern, e))?;
+        let matched = match codes.iter().find_map(|v| regex.find(v)) {
+            Some(v) => v.as_str().to_string(),
+            None => continue,
+        };
+        let message = if rule.message.is_empty() {
+            format!("禁止使用: {}", matched)
+        } else {
+            rule.message.clone()
+        };
+        match rule.action.as_str() {
+            "reject" => {
+                result.rejected = Some(message);
+                return Ok(result);
+            }
+            "flag" => result.flags.push(message),
+            other => return Err(anyhow!("Unknown forbidden rule action: {}", other)),
+        }
+    }
+    return Ok(result);
+}
+
+// 控制字符(不含常见的空白字符)与无法解码的字符占比超过该值时认为是二进制文件
+const BINARY_CHAR_RATIO: f64 = 0.1;
+
+/// 是否明显不是文本(如可执行文件、压缩包)
+pub fn looks_binary(code: &str) -> bool {
+    if code.contains('\0') {
+        return true;
+    }
+    let total = code.chars().count();
+    if total == 0 {
+        return false;
+    }
+    let suspicious = code
+        .chars()
+        .filter(|v| {
+            (v.is_control() && !matches!(v, '\t' | '\n' | '\r' | '\x0b' | '\x0c'))
+                || *v == char::REPLACEMENT_CHARACTER
+        })
+        .count();
+    return suspicious as f64 / total as f64 > BINARY_CHAR_RATIO;
+}
+
+/// 检查各个源文件的长度(字节，0为不限制)与是否为文本，不通过时返回说明
+pub fn check_submission_text(codes: &[&String], max_length: usize) -> Option<String> {
+    for (i, code) in codes.iter().enumerate() {
+        if max_length > 0 && code.len() > max_length {
+            return Some(format!(
+                "第{}个程序的代码长度为{}字节，超过了上限{}字节",
+                i + 1,
+                code.len(),
+                max_length
+            ));
+        }
+        if looks_binary(code) {
+            return Some(format!("第{}个程序不是文本文件", i + 1));
+        }
+    }
+    return None;
+}
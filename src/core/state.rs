@@ -1,17 +1,113 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+};
 
-use tokio::sync::{Mutex, RwLock, Semaphore};
+use log::debug;
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock, Semaphore};
 
-use super::config::JudgerConfig;
+use super::{
+    api::Hj3ApiClient, config::JudgerConfig, events::EventPublisher,
+    lang_cache::LanguageConfigCache, plugin::JudgePlugin, runner::Runner, webhook::WebhookSender,
+};
 
 pub struct AppState {
     pub config: JudgerConfig,
-    pub file_dir_locks: tokio::sync::Mutex<HashMap<i64, Arc<Mutex<()>>>>,
+    // 题目数据目录的锁，键为题目ID
+    pub file_dir_locks: LockRegistry<i64>,
     pub testdata_dir: PathBuf,
     pub version_string: String,
     pub task_count_lock: Arc<Semaphore>,
+    // 为false时不向服务端汇报评测状态
+    pub report_status: bool,
+    pub runner: Arc<dyn Runner>,
+    pub event_publisher: Option<EventPublisher>,
+    // 发布到每个提交单独频道的评测进度，未配置时为None
+    pub progress_publisher: Option<EventPublisher>,
+    // 服务端未指定time_scale时使用，可能来自启动时的校准
+    pub time_scale: f64,
+    // 配置文件中启用的插件
+    pub plugins: Vec<Box<dyn JudgePlugin>>,
+    // 题目程序编译缓存目录的锁，键为缓存目录路径
+    pub compile_cache_locks: LockRegistry<String>,
+    // 服务端API
+    pub api: Hj3ApiClient,
+    // 未配置webhook时为None
+    pub webhooks: Option<WebhookSender>,
+    // 从服务端获取的语言定义
+    pub language_configs: LanguageConfigCache,
 }
 use lazy_static::lazy_static;
 lazy_static! {
     pub static ref GLOBAL_APP_STATE: RwLock<Option<AppState>> = RwLock::new(None);
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct LockStats {
+    // 当前有人持有或等待的锁的个数
+    pub active: usize,
+    // 累计加锁次数
+    pub acquired: u64,
+    // 累计需要等待其他任务释放的加锁次数
+    pub contended: u64,
+}
+
+/// 按键加锁，表中只保存弱引用，没有任务持有或等待的锁会被清理掉
+pub struct LockRegistry<K> {
+    locks: std::sync::Mutex<HashMap<K, Weak<Mutex<()>>>>,
+    acquired: AtomicU64,
+    contended: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone + std::fmt::Debug> Default for LockRegistry<K> {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl<K: Hash + Eq + Clone + std::fmt::Debug> LockRegistry<K> {
+    pub fn new() -> Self {
+        return Self {
+            locks: std::sync::Mutex::new(HashMap::new()),
+            acquired: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+        };
+    }
+    fn get_or_create(&self, key: &K) -> Arc<Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        if let Some(v) = locks.get(key).and_then(|v| v.upgrade()) {
+            return v;
+        }
+        // 插入新锁前顺便清理已经无人使用的锁，使表的大小不超过同时使用的锁的个数
+        locks.retain(|_, v| v.strong_count() > 0);
+        let v = Arc::new(Mutex::new(()));
+        locks.insert(key.clone(), Arc::downgrade(&v));
+        return v;
+    }
+    /// 获取key对应的锁，返回的guard被释放前其他任务无法获取同一个key的锁
+    pub async fn lock(&self, key: &K) -> OwnedMutexGuard<()> {
+        let lock = self.get_or_create(key);
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+        return match lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+                debug!("Waiting for lock: {:?}", key);
+                lock.lock_owned().await
+            }
+        };
+    }
+    pub fn stats(&self) -> LockStats {
+        let locks = self.locks.lock().unwrap();
+        return LockStats {
+            active: locks.values().filter(|v| v.strong_count() > 0).count(),
+            acquired: self.acquired.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+        };
+    }
+}
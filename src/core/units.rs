@@ -0,0 +1,319 @@
+use std::fmt::{self, Display};
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
+
+// 时间单位 -> 毫秒数
+const DURATION_UNITS: &[(&str, f64)] = &[
+    ("us", 0.001),
+    ("ms", 1.0),
+    ("s", 1000.0),
+    ("m", 60.0 * 1000.0),
+    ("min", 60.0 * 1000.0),
+    ("h", 3600.0 * 1000.0),
+    ("d", 86400.0 * 1000.0),
+];
+// 大小单位 -> 字节数，均以1024进位
+const SIZE_UNITS: &[(&str, f64)] = &[
+    ("b", 1.0),
+    ("k", 1024.0),
+    ("kb", 1024.0),
+    ("kib", 1024.0),
+    ("m", 1024.0 * 1024.0),
+    ("mb", 1024.0 * 1024.0),
+    ("mib", 1024.0 * 1024.0),
+    ("g", 1024.0 * 1024.0 * 1024.0),
+    ("gb", 1024.0 * 1024.0 * 1024.0),
+    ("gib", 1024.0 * 1024.0 * 1024.0),
+    ("t", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+    ("tb", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+    ("tib", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+];
+
+/// 配置项的单位: 可用的单位后缀，以及配置项本身的单位(不带后缀的数值按该单位解释)
+struct UnitKind {
+    name: &'static str,
+    units: &'static [(&'static str, f64)],
+    target: &'static str,
+}
+
+const MILLISECONDS: UnitKind = UnitKind {
+    name: "duration",
+    units: DURATION_UNITS,
+    target: "ms",
+};
+const SECONDS: UnitKind = UnitKind {
+    name: "duration",
+    units: DURATION_UNITS,
+    target: "s",
+};
+const BYTES: UnitKind = UnitKind {
+    name: "size",
+    units: SIZE_UNITS,
+    target: "b",
+};
+const MEGABYTES: UnitKind = UnitKind {
+    name: "size",
+    units: SIZE_UNITS,
+    target: "mb",
+};
+
+/// 配置文件中的原始值: 整数、小数或带单位的字符串(如"512MB"、"2.5s"、"1h")
+enum Quantity {
+    Int(i128),
+    Float(f64),
+    Text(String),
+}
+
+impl<'de> Deserialize<'de> for Quantity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct QuantityVisitor;
+        impl<'de> Visitor<'de> for QuantityVisitor {
+            type Value = Quantity;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                return f.write_str("a number or a string with unit such as \"512MB\" or \"2.5s\"");
+            }
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Quantity, E> {
+                return Ok(Quantity::Int(v as i128));
+            }
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Quantity, E> {
+                return Ok(Quantity::Int(v as i128));
+            }
+            fn visit_i128<E: de::Error>(self, v: i128) -> Result<Quantity, E> {
+                return Ok(Quantity::Int(v));
+            }
+            fn visit_u128<E: de::Error>(self, v: u128) -> Result<Quantity, E> {
+                return Ok(Quantity::Int(v.min(i128::MAX as u128) as i128));
+            }
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Quantity, E> {
+                return Ok(Quantity::Float(v));
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Quantity, E> {
+                return Ok(Quantity::Text(v.to_string()));
+            }
+        }
+        return deserializer.deserialize_any(QuantityVisitor);
+    }
+}
+
+fn unit_factor(kind: &UnitKind, unit: &str) -> Option<f64> {
+    return kind
+        .units
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|(_, factor)| *factor);
+}
+
+/// 换算为配置项本身的单位，结果必须是整数
+fn to_target_unit(kind: &UnitKind, quantity: &Quantity) -> Result<i64, String> {
+    let (text, value) = match quantity {
+        Quantity::Int(v) => {
+            return i64::try_from(*v).map_err(|_| format!("{} out of range: {}", kind.name, v))
+        }
+        Quantity::Float(v) => (v.to_string(), *v),
+        Quantity::Text(s) => {
+            let s = s.trim();
+            let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+            let (number, unit) = (s[..split].trim(), s[split..].to_ascii_lowercase());
+            let number = number
+                .parse::<f64>()
+                .map_err(|_| format!("invalid {} `{}`", kind.name, s))?;
+            let value = if unit.is_empty() {
+                number
+            } else {
+                let factor = unit_factor(kind, &unit).ok_or(format!(
+                    "invalid {} `{}`: unknown unit `{}` (expected one of {})",
+                    kind.name,
+                    s,
+                    &s[split..],
+                    kind.units
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                ))?;
+                number * factor / unit_factor(kind, kind.target).unwrap()
+            };
+            (s.to_string(), value)
+        }
+    };
+    if !value.is_finite() || value.abs() >= i64::MAX as f64 {
+        return Err(format!("{} out of range: `{}`", kind.name, text));
+    }
+    let rounded = value.round();
+    if (value - rounded).abs() > 1e-6 {
+        return Err(format!(
+            "{} `{}` is not a whole number of {}",
+            kind.name, text, kind.target
+        ));
+    }
+    return Ok(rounded as i64);
+}
+
+fn deserialize_unit<'de, D, T>(deserializer: D, kind: &UnitKind) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64>,
+    T::Error: Display,
+{
+    let quantity = Quantity::deserialize(deserializer)?;
+    let value = to_target_unit(kind, &quantity).map_err(de::Error::custom)?;
+    return T::try_from(value)
+        .map_err(|e| de::Error::custom(format!("invalid value {}: {}", value, e)));
+}
+
+/// 以毫秒为单位的时间，也可以写作"500ms"、"2.5s"、"1min"、"1h"等
+pub fn duration_ms<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64>,
+    T::Error: Display,
+{
+    return deserialize_unit(deserializer, &MILLISECONDS);
+}
+
+/// 可以不填的duration_ms
+pub fn option_duration_ms<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64>,
+    T::Error: Display,
+{
+    return match Option::<Quantity>::deserialize(deserializer)? {
+        Some(quantity) => {
+            let value = to_target_unit(&MILLISECONDS, &quantity).map_err(de::Error::custom)?;
+            T::try_from(value)
+                .map(Some)
+                .map_err(|e| de::Error::custom(format!("invalid value {}: {}", value, e)))
+        }
+        None => Ok(None),
+    };
+}
+
+/// 以秒为单位的时间，也可以写作"30s"、"10min"、"1h"、"7d"等
+pub fn duration_secs<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64>,
+    T::Error: Display,
+{
+    return deserialize_unit(deserializer, &SECONDS);
+}
+
+/// 以字节为单位的大小，也可以写作"64KB"、"512MB"、"1.5GB"等
+pub fn size_bytes<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64>,
+    T::Error: Display,
+{
+    return deserialize_unit(deserializer, &BYTES);
+}
+
+/// 以MB为单位的大小，也可以写作"512MB"、"2GB"等
+pub fn size_mb<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64>,
+    T::Error: Display,
+{
+    return deserialize_unit(deserializer, &MEGABYTES);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Limits {
+        #[serde(default, deserialize_with = "super::duration_ms")]
+        ms: i64,
+        #[serde(default, deserialize_with = "super::duration_secs")]
+        secs: u64,
+        #[serde(default, deserialize_with = "super::size_bytes")]
+        bytes: i64,
+        #[serde(default, deserialize_with = "super::size_mb")]
+        mb: u64,
+        #[serde(default, deserialize_with = "super::size_bytes")]
+        small: u32,
+    }
+
+    fn parse(yaml: &str) -> Result<Limits, serde_yaml::Error> {
+        return serde_yaml::from_str::<Limits>(yaml);
+    }
+
+    #[test]
+    fn plain_numbers_use_target_unit() {
+        let v = parse("{ms: 1500, secs: 30, bytes: 65536, mb: 512}").unwrap();
+        assert_eq!((v.ms, v.secs, v.bytes, v.mb), (1500, 30, 65536, 512));
+        // 不带单位的字符串同样按配置项本身的单位解释
+        assert_eq!(parse("{ms: '250'}").unwrap().ms, 250);
+    }
+
+    #[test]
+    fn units_are_case_insensitive() {
+        let v = parse("{ms: 2S, secs: 1H, bytes: 64Kb, mb: 2GiB}").unwrap();
+        assert_eq!((v.ms, v.secs, v.bytes, v.mb), (2000, 3600, 65536, 2048));
+        assert_eq!(parse("{bytes: ' 512 MB '}").unwrap().bytes, 512 << 20);
+    }
+
+    #[test]
+    fn fractional_values() {
+        let v = parse("{ms: 2.5s, secs: 1.5min, bytes: 1.5k, mb: 0.5GB}").unwrap();
+        assert_eq!((v.ms, v.secs, v.bytes, v.mb), (2500, 90, 1536, 512));
+        assert_eq!(parse("{ms: 2.0}").unwrap().ms, 2);
+        // 换算后不是整数时拒绝，而不是静默舍入
+        assert!(parse("{ms: 2.5}").is_err());
+        assert!(parse("{ms: 1.5us}").is_err());
+        assert!(parse("{mb: 0.5}").is_err());
+        assert!(parse("{secs: 1500ms}").is_err());
+        assert!(parse("{bytes: 0.3k}").is_err());
+    }
+
+    #[test]
+    fn m_depends_on_field_kind() {
+        // 时间中m为分钟，大小中m为MB
+        let v = parse("{ms: 1m, secs: 2m, bytes: 1m, mb: 3m}").unwrap();
+        assert_eq!((v.ms, v.secs, v.bytes, v.mb), (60000, 120, 1 << 20, 3));
+        assert_eq!(parse("{secs: 2min}").unwrap().secs, 120);
+        // min与mb等只属于其中一种
+        assert!(parse("{mb: 2min}").is_err());
+        assert!(parse("{secs: 2mb}").is_err());
+    }
+
+    #[test]
+    fn unknown_units() {
+        for yaml in [
+            "{ms: 5x}",
+            "{ms: 5 sec}",
+            "{secs: 1w}",
+            "{bytes: 5kbs}",
+            "{mb: 1pb}",
+            "{bytes: 1e}",
+            "{ms: s}",
+            "{ms: ''}",
+        ] {
+            assert!(parse(yaml).is_err(), "{}", yaml);
+        }
+        let message = parse("{mb: 1pb}").err().unwrap().to_string();
+        assert!(message.contains("unknown unit `pb`"), "{}", message);
+    }
+
+    #[test]
+    fn overflow() {
+        // 超出i64
+        assert!(parse("{bytes: 9999999tb}").is_err());
+        assert!(parse("{ms: 1e300}").is_err());
+        assert!(parse("{ms: 18446744073709551615}").is_err());
+        assert!(parse("{ms: .inf}").is_err());
+        assert!(parse("{ms: .nan}").is_err());
+        // 超出配置项的类型
+        assert!(parse("{small: 4GB}").is_err());
+        assert_eq!(parse("{small: 3GB}").unwrap().small, 3 << 30);
+        assert!(parse("{secs: -1}").is_err());
+        assert!(parse("{mb: -1GB}").is_err());
+        assert_eq!(parse("{ms: -1s}").unwrap().ms, -1000);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{future::Future, io::SeekFrom, path::Path, time::Duration};
+
+use super::{
+    api::{ApiResult, ArtifactUploadState},
+    misc::ResultType,
+    state::AppState,
+};
+use anyhow::anyhow;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const MAX_CHUNK_RETRY: u64 = 5;
+// 服务端连续多少次确认分块但已接收的字节数没有增加时放弃上传
+const MAX_STALLED_CHUNKS: u64 = 3;
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    return format!("{:x}", Sha256::digest(data));
+}
+
+/// 要上传的内容，文件按分块读取，不会整个读入内存
+enum UploadSource<'a> {
+    Memory(&'a [u8]),
+    // (路径, 大小)
+    File(&'a Path, usize),
+}
+impl<'a> UploadSource<'a> {
+    fn len(&self) -> usize {
+        return match self {
+            UploadSource::Memory(data) => data.len(),
+            UploadSource::File(_, size) => *size,
+        };
+    }
+    async fn read(&self, offset: usize, end: usize) -> ResultType<Vec<u8>> {
+        return match self {
+            UploadSource::Memory(data) => Ok(data[offset..end].to_vec()),
+            UploadSource::File(path, _) => {
+                let mut file = tokio::fs::File::open(path)
+                    .await
+                    .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+                file.seek(SeekFrom::Start(offset as u64))
+                    .await
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+                let mut buf = vec![0u8; end - offset];
+                file.read_exact(&mut buf)
+                    .await
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+                Ok(buf)
+            }
+        };
+    }
+}
+
+/// 分块上传评测产物，返回服务端的产物ID
+/// 服务端在结束时会校验整个文件的sha256
+pub async fn upload_artifact(
+    app: &AppState,
+    submission_id: i64,
+    name: &str,
+    data: &[u8],
+) -> ResultType<String> {
+    let checksum = sha256_hex(data);
+    return upload_chunked(app, name, &UploadSource::Memory(data), &checksum, || {
+        app.api
+            .begin_artifact_upload(submission_id, name, data.len(), &checksum)
+    })
+    .await;
+}
+
+/// 从磁盘分块上传题目文件(如生成的测试数据)，checksum为文件的sha256，之后的分块与结束请求与评测产物相同
+pub async fn upload_problem_file(
+    app: &AppState,
+    problem_id: i64,
+    name: &str,
+    path: &Path,
+    checksum: &str,
+) -> ResultType<()> {
+    let size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+        .len() as usize;
+    upload_chunked(app, name, &UploadSource::File(path, size), checksum, || {
+        app.api
+            .begin_problem_file_upload(problem_id, name, size, checksum)
+    })
+    .await?;
+    return Ok(());
+}
+
+/// begin用于开始或恢复上传，返回服务端已接收的字节数
+async fn upload_chunked<F, Fut>(
+    app: &AppState,
+    name: &str,
+    data: &UploadSource<'_>,
+    checksum: &str,
+    begin: F,
+) -> ResultType<String>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ApiResult<ArtifactUploadState>>,
+{
+    let mut state = begin()
+        .await
+        .map_err(|e| anyhow!("Failed to begin upload of {}: {}", name, e))?;
+    info!(
+        "Uploading artifact {} ({} bytes), server has {} bytes",
+        name,
+        data.len(),
+        state.received
+    );
+    let chunk_size = app.config.artifact_chunk_size.max(1);
+    let mut failures = 0;
+    // 服务端确认过的最大字节数与之后没有超过它的次数
+    let mut max_received = state.received;
+    let mut stalled = 0;
+    while state.received < data.len() {
+        let offset = state.received;
+        let end = (offset + chunk_size).min(data.len());
+        let chunk = data.read(offset, end).await?;
+        match app
+            .api
+            .upload_artifact_chunk(&state.upload_id, offset, &sha256_hex(&chunk), &chunk)
+            .await
+        {
+            Ok(received) => {
+                if received > max_received {
+                    max_received = received;
+                    stalled = 0;
+                } else {
+                    stalled += 1;
+                    if stalled >= MAX_STALLED_CHUNKS {
+                        return Err(anyhow!(
+                            "Failed to upload artifact {}: server made no progress at {} bytes",
+                            name,
+                            received
+                        ));
+                    }
+                    warn!(
+                        "Server made no progress on {} at {} bytes (offset {})",
+                        name, received, offset
+                    );
+                }
+                state.received = received;
+                failures = 0;
+            }
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_CHUNK_RETRY {
+                    return Err(anyhow!("Failed to upload artifact {}: {}", name, e));
+                }
+                warn!("Failed to upload chunk at {} of {}: {}", offset, name, e);
+                tokio::time::sleep(Duration::from_secs(failures)).await;
+                // 与服务端重新同步已接收的字节数
+                match begin().await {
+                    Ok(v) => state = v,
+                    Err(e) => warn!("Failed to resume upload of {}: {}", name, e),
+                }
+            }
+        }
+    }
+    let artifact_id = app
+        .api
+        .finish_artifact_upload(&state.upload_id, checksum)
+        .await
+        .map_err(|e| anyhow!("Failed to finish upload of {}: {}", name, e))?;
+    info!("Artifact uploaded: {} -> {}", name, artifact_id);
+    return Ok(artifact_id);
+}
@@ -1,39 +1,193 @@
-use super::{misc::ResultType, model::LanguageConfig, state::AppState};
+use std::{
+    future::Future,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    api::{ApiError, ApiResult},
+    config::JudgerConfig,
+    misc::ResultType,
+    model::LanguageConfig,
+    state::AppState,
+};
 use anyhow::anyhow;
-use serde::Deserialize;
-pub async fn get_language_config(
-    app: &AppState,
-    language_id: &str,
-    client: &reqwest::Client,
-) -> ResultType<LanguageConfig> {
-    let text_resp = client
-        .post(app.config.suburl("/api/judge/get_lang_config_as_json"))
-        .form(&[("lang_id", language_id), ("uuid", &app.config.judger_uuid)])
-        .send()
+use log::{info, warn};
+
+/// 是否是可能自行恢复的错误: 网络错误与HTTP 5xx
+fn is_transient(error: &ApiError) -> bool {
+    return match error {
+        ApiError::Network(_) => true,
+        ApiError::Server { code, .. } => (500..600).contains(code),
+        ApiError::Decode(_) => false,
+    };
+}
+
+/// 第attempt次重试(从0开始)前等待的时间，指数增长并带有随机抖动
+fn retry_delay(config: &JudgerConfig, attempt: u32) -> Duration {
+    let base = config
+        .api_retry_base_delay
+        .saturating_mul(1 << attempt.min(16))
+        .min(config.api_retry_max_delay);
+    // 在[base/2, base]中取值，避免多个评测机同时重试
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.subsec_nanos() as u64)
+        .unwrap_or(0);
+    let jitter = if base > 0 { nanos % (base / 2 + 1) } else { 0 };
+    return Duration::from_millis(base - jitter);
+}
+
+/// 调用服务端API，遇到暂时性的错误时按api_retry_attempts重试
+pub async fn with_retry<T, F, Fut>(config: &JudgerConfig, name: &str, mut call: F) -> ApiResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ApiResult<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Ok(v) => return Ok(v),
+            Err(e) if is_transient(&e) && attempt < config.api_retry_attempts => {
+                let delay = retry_delay(config, attempt);
+                warn!(
+                    "{} failed (attempt {}), retrying in {} ms: {}",
+                    name,
+                    attempt + 1,
+                    delay.as_millis(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 获取语言定义，配置文件中有该语言时直接使用
+/// 否则从服务端获取，缓存未过期时不请求服务端，过期后带上etag确认是否有变化
+pub async fn get_language_config(app: &AppState, language_id: &str) -> ResultType<LanguageConfig> {
+    if let Some(config) = app.config.languages.get(language_id) {
+        return Ok(config.clone());
+    }
+    let ttl = Duration::from_secs(app.config.language_config_ttl);
+    if let Some(config) = app.language_configs.get_fresh(language_id, ttl) {
+        return Ok(config);
+    }
+    let etag = app.language_configs.etag(language_id);
+    let fetched = with_retry(&app.config, "get_language_config", || {
+        app.api.fetch_language_config(language_id, etag.as_deref())
+    })
+    .await
+    .map_err(|e| anyhow!("Failed to get language config of {}: {}", language_id, e))?;
+    return match fetched {
+        Some((config, etag)) => {
+            app.language_configs
+                .store(language_id, config.clone(), etag);
+            Ok(config)
+        }
+        None => app.language_configs.revalidate(language_id).ok_or(anyhow!(
+            "Language config of {} not modified but not cached",
+            language_id
+        )),
+    };
+}
+/// 用单引号包裹参数，使其在sh -c中按原样传递
+pub fn shell_quote(s: &str) -> String {
+    return format!("'{}'", s.replace('\'', "'\\''"));
+}
+/// 目录下所有文件的总大小, bytes，无法读取的文件不计入
+pub fn dir_size(path: &Path) -> u64 {
+    let mut ret = 0;
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            match entry.metadata() {
+                Ok(v) if v.is_dir() => ret += dir_size(&entry.path()),
+                Ok(v) => ret += v.len(),
+                Err(_) => {}
+            }
+        }
+    }
+    return ret;
+}
+/// 将缓存项的修改时间更新为当前时间，作为最近一次使用的时间
+pub fn touch_cache_entry(path: &Path) {
+    let result = std::fs::File::open(path).and_then(|v| v.set_modified(SystemTime::now()));
+    if let Err(e) = result {
+        warn!("Failed to touch cache entry {}: {}", path.display(), e);
+    }
+}
+/// 删除题目程序编译缓存(cache_root)中超过problem_program_cache_ttl未使用的项，返回删除的项数
+/// 调用者不能持有任何缓存项的锁
+pub async fn evict_problem_program_cache(app: &AppState, cache_root: &Path) -> ResultType<usize> {
+    if app.config.problem_program_cache_ttl == 0 || !cache_root.exists() {
+        return Ok(0);
+    }
+    let ttl = Duration::from_secs(app.config.problem_program_cache_ttl);
+    let mut count = 0;
+    let mut reader = tokio::fs::read_dir(cache_root)
         .await
-        .map_err(|e| {
-            anyhow!(
-                "Failed to send request when getting language setting: {}",
-                e
-            )
-        })?
-        .text()
+        .map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?;
+    while let Some(entry) = reader
+        .next_entry()
+        .await
+        .map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?
+    {
+        // 跳过正在写入的临时目录
+        if entry.file_name().to_string_lossy().ends_with(".tmp") {
+            continue;
+        }
+        let path = entry.path();
+        let _guard = app
+            .compile_cache_locks
+            .lock(&path.to_str().unwrap_or("").to_string())
+            .await;
+        // 等待锁的过程中可能已被使用或删除
+        let expired = tokio::fs::metadata(&path)
+            .await
+            .and_then(|v| v.modified())
+            .map(|v| v.elapsed().map(|v| v > ttl).unwrap_or(false))
+            .unwrap_or(false);
+        if !expired {
+            continue;
+        }
+        tokio::fs::remove_dir_all(&path)
+            .await
+            .map_err(|e| anyhow!("Failed to remove `{}`: {}", path.display(), e))?;
+        count += 1;
+    }
+    if count > 0 {
+        info!(
+            "Evicted {} unused compile cache entries in {}",
+            count,
+            cache_root.display()
+        );
+    }
+    return Ok(count);
+}
+fn copy_dir_blocking(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            std::fs::create_dir(&target)?;
+            copy_dir_blocking(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            // 支持reflink的文件系统上std::fs::copy会共享数据块，写入时才复制
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    return Ok(());
+}
+/// 将from下的文件复制到已存在的目录to中
+pub async fn copy_dir(from: &Path, to: &Path) -> ResultType<()> {
+    let from = from.to_path_buf();
+    let to = to.to_path_buf();
+    tokio::task::spawn_blocking(move || copy_dir_blocking(&from, &to))
         .await
-        .map_err(|e| anyhow!("Failed to receive response: {}", e))?;
-    #[derive(Deserialize)]
-    struct Local {
-        pub code: i64,
-        pub message: Option<String>,
-        pub data: Option<LanguageConfig>,
-    }
-    let parsed = serde_json::from_str::<Local>(&text_resp)
-        .map_err(|e| anyhow!("Failed to deserialize language config: {}", e))?;
-    if parsed.code != 0 {
-        return Err(anyhow!(
-            "Invalid code {} when getting language config: {}",
-            parsed.code,
-            parsed.message.unwrap_or(String::from("<>"))
-        ));
-    }
-    return Ok(parsed.data.ok_or(anyhow!("Missing field!"))?);
+        .map_err(|e| anyhow!("Failed to run blocking task: {}", e))?
+        .map_err(|e| anyhow!("Failed to copy working directory: {}", e))?;
+    return Ok(());
 }
@@ -0,0 +1,241 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use chrono::TimeZone;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use super::{
+    config::JudgerConfig,
+    misc::ResultType,
+    webhook::{constant_time_eq, hmac_sha256_hex},
+};
+
+// 评测信息中水印行的前缀
+pub const WATERMARK_PREFIX: &str = "结果水印: ";
+// 水印格式的版本
+const WATERMARK_VERSION: &str = "HJ3-WM1";
+
+/// 评测结果的水印，由result_watermark_key对各字段签名，用于事后确认结果由授权的评测机给出且未被修改
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    pub submission_id: i64,
+    pub judger: String,
+    // 毫秒时间戳
+    pub timestamp: i64,
+    pub score: i64,
+    // 评测结果(按键排序的JSON)的sha256
+    pub result_digest: String,
+}
+
+/// 评测结果的摘要，先转换为serde_json::Value使键按字典序排列，与服务端存储时的字段顺序无关
+pub fn result_digest<T: Serialize>(judge_result: &T) -> ResultType<String> {
+    let value = serde_json::to_value(judge_result)
+        .map_err(|e| anyhow!("Failed to serialize judge result: {}", e))?;
+    return Ok(format!(
+        "{:x}",
+        Sha256::digest(value.to_string().as_bytes())
+    ));
+}
+
+impl Watermark {
+    pub fn new<T: Serialize>(
+        config: &JudgerConfig,
+        submission_id: i64,
+        score: i64,
+        judge_result: &T,
+    ) -> ResultType<Self> {
+        return Ok(Self {
+            submission_id,
+            judger: config.judger_uuid.clone(),
+            timestamp: chrono::Local::now().timestamp_millis(),
+            score,
+            result_digest: result_digest(judge_result)?,
+        });
+    }
+    fn payload(&self) -> String {
+        return format!(
+            "{}.{}.{}.{}.{}.{}",
+            WATERMARK_VERSION,
+            self.submission_id,
+            self.judger,
+            self.timestamp,
+            self.score,
+            self.result_digest
+        );
+    }
+    /// 水印文本: 各字段与签名以.分隔
+    pub fn sign(&self, key: &str) -> String {
+        let payload = self.payload();
+        let signature = hmac_sha256_hex(key.as_bytes(), payload.as_bytes());
+        return format!("{}.{}", payload, signature);
+    }
+    /// 附加在评测信息末尾的水印行
+    pub fn message_line(&self, key: &str) -> String {
+        return format!("{}{}", WATERMARK_PREFIX, self.sign(key));
+    }
+    /// 附加在事件等结构化数据中的水印
+    pub fn to_json(&self, key: &str) -> Value {
+        return json!({
+            "submission_id": self.submission_id,
+            "judger": self.judger,
+            "timestamp": self.timestamp,
+            "score": self.score,
+            "result_digest": self.result_digest,
+            "signature": self.sign(key),
+        });
+    }
+    /// 校验水印文本(或包含水印行的评测信息)，成功时返回其中的字段
+    pub fn verify(key: &str, text: &str) -> ResultType<Self> {
+        let text = text
+            .lines()
+            .find_map(|v| v.trim().strip_prefix(WATERMARK_PREFIX))
+            .unwrap_or(text)
+            .trim();
+        let parts = text.split('.').collect::<Vec<&str>>();
+        if parts.len() != 7 || parts[0] != WATERMARK_VERSION {
+            return Err(anyhow!("Malformed watermark: {}", text));
+        }
+        let parse = |name: &str, v: &str| {
+            v.parse::<i64>()
+                .map_err(|e| anyhow!("Invalid {} in watermark: {}", name, e))
+        };
+        let ret = Self {
+            submission_id: parse("submission id", parts[1])?,
+            judger: parts[2].to_string(),
+            timestamp: parse("timestamp", parts[3])?,
+            score: parse("score", parts[4])?,
+            result_digest: parts[5].to_string(),
+        };
+        let expected = hmac_sha256_hex(key.as_bytes(), ret.payload().as_bytes());
+        if !constant_time_eq(expected.as_bytes(), parts[6].as_bytes()) {
+            return Err(anyhow!("Watermark signature mismatch"));
+        }
+        return Ok(ret);
+    }
+}
+
+/// 校验input文件(评测信息或水印文本)中的水印并输出各字段，result不为None时同时校验评测结果文件的摘要
+pub async fn verify_watermark_file(
+    config: &JudgerConfig,
+    input: &Path,
+    result: Option<&Path>,
+) -> ResultType<()> {
+    if config.result_watermark_key.is_empty() {
+        return Err(anyhow!("result_watermark_key is not configured"));
+    }
+    let text = tokio::fs::read_to_string(input)
+        .await
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", input.display(), e))?;
+    let watermark = Watermark::verify(&config.result_watermark_key, &text)?;
+    // 签名正确的水印也可能由配置错误的评测机给出，时间戳不一定合法
+    let time = chrono::Local
+        .timestamp_millis_opt(watermark.timestamp)
+        .single()
+        .ok_or(anyhow!(
+            "Invalid timestamp in watermark: {}",
+            watermark.timestamp
+        ))?;
+    println!("Submission: {}", watermark.submission_id);
+    println!("Judger: {}", watermark.judger);
+    println!("Time: {}", time.format("%F %X"));
+    println!("Score: {}", watermark.score);
+    if let Some(path) = result {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow!("Failed to read `{}`: {}", path.display(), e))?;
+        let judge_result = serde_json::from_str::<Value>(&content)
+            .map_err(|e| anyhow!("Failed to parse judge result: {}", e))?;
+        if result_digest(&judge_result)? != watermark.result_digest {
+            return Err(anyhow!("Judge result does not match the watermark"));
+        }
+        println!("Judge result matches");
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{result_digest, Watermark, WATERMARK_PREFIX};
+    use serde_json::json;
+
+    const KEY: &str = "watermark-key";
+
+    fn watermark() -> Watermark {
+        return Watermark {
+            submission_id: 1234,
+            judger: "0f7c2d9e-judger".to_string(),
+            timestamp: 1_700_000_000_000,
+            score: 85,
+            result_digest: result_digest(&json!({ "default": { "score": 85 } })).unwrap(),
+        };
+    }
+
+    /// 将签名后水印的第index个字段替换为value
+    fn tamper(index: usize, value: &str) -> String {
+        let signed = watermark().sign(KEY);
+        let mut parts = signed.split('.').collect::<Vec<&str>>();
+        parts[index] = value;
+        return parts.join(".");
+    }
+
+    #[test]
+    fn sign_verify_round_trip() {
+        let origin = watermark();
+        let verified = Watermark::verify(KEY, &origin.sign(KEY)).unwrap();
+        assert_eq!(verified.submission_id, origin.submission_id);
+        assert_eq!(verified.judger, origin.judger);
+        assert_eq!(verified.timestamp, origin.timestamp);
+        assert_eq!(verified.score, origin.score);
+        assert_eq!(verified.result_digest, origin.result_digest);
+    }
+
+    #[test]
+    fn verify_message_line() {
+        let message = format!(
+            "评测结束于: 2024-01-01 00:00:00\n  {}  \n编译时间占用: 1 ms",
+            watermark().message_line(KEY)
+        );
+        assert_eq!(Watermark::verify(KEY, &message).unwrap().score, 85);
+        assert!(watermark().message_line(KEY).starts_with(WATERMARK_PREFIX));
+    }
+
+    #[test]
+    fn tampered_fields_are_rejected() {
+        assert!(Watermark::verify(KEY, &tamper(1, "1235")).is_err());
+        assert!(Watermark::verify(KEY, &tamper(2, "other-judger")).is_err());
+        assert!(Watermark::verify(KEY, &tamper(3, "1700000000001")).is_err());
+        assert!(Watermark::verify(KEY, &tamper(4, "100")).is_err());
+        assert!(Watermark::verify(KEY, &tamper(5, &"0".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let signed = watermark().sign(KEY);
+        assert!(Watermark::verify("another-key", &signed).is_err());
+        assert!(Watermark::verify("", &signed).is_err());
+        assert_ne!(watermark().sign("another-key"), signed);
+    }
+
+    #[test]
+    fn malformed_watermarks_are_rejected() {
+        let signed = watermark().sign(KEY);
+        assert!(Watermark::verify(KEY, "").is_err());
+        assert!(Watermark::verify(KEY, &tamper(0, "HJ3-WM0")).is_err());
+        assert!(Watermark::verify(KEY, &tamper(1, "abc")).is_err());
+        assert!(Watermark::verify(KEY, &format!("{}.extra", signed)).is_err());
+        assert!(Watermark::verify(KEY, signed.rsplit_once('.').unwrap().0).is_err());
+    }
+
+    #[test]
+    fn digest_ignores_key_order() {
+        let a = serde_json::from_str::<serde_json::Value>(r#"{"a": 1, "b": [1, 2]}"#).unwrap();
+        let b = serde_json::from_str::<serde_json::Value>(r#"{"b": [1, 2], "a": 1}"#).unwrap();
+        assert_eq!(result_digest(&a).unwrap(), result_digest(&b).unwrap());
+        assert_ne!(
+            result_digest(&a).unwrap(),
+            result_digest(&json!({"a": 1, "b": [2, 1]})).unwrap()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use super::{
+    api::{ApiRequest, Hj3ApiClient},
+    config::JudgerConfig,
+    state::AppState,
+};
+
+pub const EVENT_TASK_FAILED: &str = "task_failed";
+pub const EVENT_DEAD_LETTER: &str = "dead_letter";
+pub const EVENT_QUOTA_EXHAUSTED: &str = "quota_exhausted";
+pub const EVENT_DISK_LOW: &str = "disk_low";
+pub const EVENT_SELF_TEST_FAILED: &str = "self_test_failed";
+
+const SIGNATURE_HEADER: &str = "X-HJ3-Signature";
+// 重试间隔的上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// HMAC-SHA256，返回十六进制字符串
+pub fn hmac_sha256_hex(key: &[u8], data: &[u8]) -> String {
+    const BLOCK_SIZE: usize = 64;
+    let mut key_block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        key_block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(key_block.iter().map(|v| v ^ 0x36).collect::<Vec<u8>>());
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(key_block.iter().map(|v| v ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(inner.finalize());
+    return format!("{:x}", outer.finalize());
+}
+
+/// 比较签名，耗时只与长度有关，不会因提前返回泄露相同前缀的长度
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    return a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0;
+}
+
+/// 向webhook发送评测机告警，通知放入有界队列由后台任务逐个发送，失败时重试
+pub struct WebhookSender {
+    queue: mpsc::Sender<String>,
+    min_interval: Duration,
+    // 事件类型 -> (上次发送的时间, 之后被忽略的次数)
+    last_sent: Mutex<HashMap<String, (Instant, u64)>>,
+    version_string: String,
+    host: String,
+}
+
+impl WebhookSender {
+    /// webhook_urls为空时返回None
+    pub fn spawn(config: &JudgerConfig, api: &Hj3ApiClient, version_string: &str) -> Option<Self> {
+        if config.webhook_urls.is_empty() {
+            return None;
+        }
+        let (tx, mut rx) = mpsc::channel::<String>(config.webhook_queue_size.max(1));
+        let urls = config.webhook_urls.clone();
+        let secret = config.webhook_secret.clone();
+        let max_retries = config.webhook_max_retries;
+        let api = api.clone();
+        info!("Sending webhook notifications to {} urls", urls.len());
+        tokio::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                for url in urls.iter() {
+                    deliver(&api, url, &secret, &payload, max_retries).await;
+                }
+            }
+        });
+        return Some(Self {
+            queue: tx,
+            min_interval: Duration::from_secs(config.webhook_min_interval),
+            last_sent: Mutex::new(HashMap::new()),
+            version_string: version_string.to_string(),
+            host: std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default(),
+        });
+    }
+    /// 将通知放入发送队列，不等待发送完成
+    pub fn notify(&self, event: &str, data: Value) {
+        let suppressed = {
+            let mut last_sent = self.last_sent.lock().unwrap();
+            match last_sent.get_mut(event) {
+                Some((time, count)) if time.elapsed() < self.min_interval => {
+                    *count += 1;
+                    debug!("Webhook event {} suppressed", event);
+                    return;
+                }
+                Some((time, count)) => {
+                    let suppressed = *count;
+                    *time = Instant::now();
+                    *count = 0;
+                    suppressed
+                }
+                None => {
+                    last_sent.insert(event.to_string(), (Instant::now(), 0));
+                    0
+                }
+            }
+        };
+        let payload = json!({
+            "judger": self.version_string,
+            "host": self.host,
+            "event": event,
+            "time": chrono::Local::now().timestamp_millis(),
+            "suppressed": suppressed,
+            "data": data
+        })
+        .to_string();
+        if let Err(e) = self.queue.try_send(payload) {
+            warn!("Webhook notification {} dropped: {}", event, e);
+        }
+    }
+}
+
+async fn deliver(api: &Hj3ApiClient, url: &str, secret: &str, payload: &str, max_retries: u32) {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 0..=max_retries {
+        let mut request = ApiRequest::external(url)
+            .header("Content-Type", "application/json")
+            .body(payload.as_bytes().to_vec());
+        if !secret.is_empty() {
+            request = request.header(
+                SIGNATURE_HEADER,
+                format!(
+                    "sha256={}",
+                    hmac_sha256_hex(secret.as_bytes(), payload.as_bytes())
+                ),
+            );
+        }
+        match api.send_external(request).await {
+            Ok(_) => return,
+            Err(e) => {
+                warn!(
+                    "Failed to send webhook to {} (attempt {}): {}",
+                    url,
+                    attempt + 1,
+                    e
+                );
+            }
+        }
+        if attempt < max_retries {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+    error!("Giving up sending webhook to {}", url);
+}
+
+/// 发送一个告警通知，未配置webhook时不做任何事
+pub fn notify_webhook(app: &AppState, event: &str, data: Value) {
+    if !app.report_status {
+        return;
+    }
+    if let Some(sender) = app.webhooks.as_ref() {
+        sender.notify(event, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, hmac_sha256_hex};
+
+    // RFC 4231 中HMAC-SHA-256的测试向量(第5组为截断输出，不适用)
+    #[test]
+    fn hmac_sha256_rfc4231() {
+        let cases: Vec<(Vec<u8>, Vec<u8>, &str)> = vec![
+            (
+                vec![0x0b; 20],
+                b"Hi There".to_vec(),
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?".to_vec(),
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                vec![0xaa; 20],
+                vec![0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                (1..=25).collect(),
+                vec![0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            (
+                vec![0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                vec![0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.".to_vec(),
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (i, (key, data, expected)) in cases.iter().enumerate() {
+            assert_eq!(
+                hmac_sha256_hex(key, data),
+                *expected,
+                "test case {}",
+                if i < 4 { i + 1 } else { i + 2 }
+            );
+        }
+    }
+
+    #[test]
+    fn constant_time_eq_compares_content() {
+        assert!(constant_time_eq(b"abcd", b"abcd"));
+        assert!(!constant_time_eq(b"abcd", b"abce"));
+        assert!(!constant_time_eq(b"abcd", b"abc"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
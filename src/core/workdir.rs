@@ -0,0 +1,115 @@
+use std::{ffi::CString, path::PathBuf};
+
+use anyhow::anyhow;
+use log::{error, info};
+use tempfile::TempDir;
+
+use super::{config::JudgerConfig, misc::ResultType, util::dir_size};
+
+// 在work_dir_root下创建的工作目录的前缀，启动时据此清理上次崩溃遗留的目录
+const WORK_DIR_PREFIX: &str = "hj3-work-";
+
+fn work_dir_root(config: &JudgerConfig) -> PathBuf {
+    if config.work_dir_root.is_empty() {
+        return std::env::temp_dir();
+    }
+    // 工作目录会被挂载进容器，docker只接受绝对路径
+    return std::env::current_dir()
+        .unwrap_or_default()
+        .join(&config.work_dir_root);
+}
+
+/// 目录所在文件系统的可用空间, bytes
+fn available_space(path: &PathBuf) -> ResultType<i64> {
+    let c_path = CString::new(path.to_str().ok_or(anyhow!("Invalid path"))?)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(anyhow!(
+            "Failed to stat {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    return Ok(stat.f_bavail as i64 * stat.f_frsize as i64);
+}
+
+/// 工作目录所在文件系统的可用空间, bytes
+pub fn work_dir_free_space(config: &JudgerConfig) -> ResultType<i64> {
+    return available_space(&work_dir_root(config));
+}
+
+/// 当前存在的工作目录个数与总大小(bytes)
+pub fn work_dir_usage(config: &JudgerConfig) -> ResultType<(usize, u64)> {
+    let root = work_dir_root(config);
+    let mut count = 0;
+    let mut size = 0;
+    for entry in std::fs::read_dir(&root)
+        .map_err(|e| anyhow!("Failed to read work dir root: {}", e))?
+        .flatten()
+    {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(WORK_DIR_PREFIX)
+        {
+            count += 1;
+            size += dir_size(&entry.path());
+        }
+    }
+    return Ok((count, size));
+}
+
+/// 创建工作目录，可用空间不足work_dir_min_free_space时返回错误
+pub fn create_work_dir(config: &JudgerConfig) -> ResultType<TempDir> {
+    let root = work_dir_root(config);
+    let available = available_space(&root)?;
+    if available < config.work_dir_min_free_space * 1024 * 1024 {
+        return Err(anyhow!(
+            "Not enough free space in {}: {} MB available, {} MB required",
+            root.display(),
+            available / 1024 / 1024,
+            config.work_dir_min_free_space
+        ));
+    }
+    return tempfile::Builder::new()
+        .prefix(WORK_DIR_PREFIX)
+        .tempdir_in(&root)
+        .map_err(|e| anyhow!("Failed to create directory in {}: {}", root.display(), e));
+}
+
+/// 删除work_dir_root下上次运行遗留的工作目录，work_dir_root为空时不做任何事
+pub fn cleanup_stale_work_dirs(config: &JudgerConfig) -> ResultType<()> {
+    if config.work_dir_root.is_empty() {
+        return Ok(());
+    }
+    let root = work_dir_root(config);
+    if !root.exists() {
+        std::fs::create_dir_all(&root)
+            .map_err(|e| anyhow!("Failed to create work dir root: {}", e))?;
+        return Ok(());
+    }
+    let mut count = 0;
+    for entry in
+        std::fs::read_dir(&root).map_err(|e| anyhow!("Failed to read work dir root: {}", e))?
+    {
+        let entry = entry.map_err(|e| anyhow!("Failed to read work dir root: {}", e))?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(WORK_DIR_PREFIX)
+        {
+            continue;
+        }
+        if let Err(e) = std::fs::remove_dir_all(entry.path()) {
+            error!(
+                "Failed to remove stale work dir {}: {}",
+                entry.path().display(),
+                e
+            );
+        } else {
+            count += 1;
+        }
+    }
+    info!("Removed {} stale work dirs in {}", count, root.display());
+    return Ok(());
+}
@@ -1,21 +1,59 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc};
 
-use crate::{
+use anyhow::anyhow;
+use celery::{
+    broker::{RedisBroker, RedisBrokerBuilder},
+    Celery, CeleryBuilder,
+};
+use config::Config;
+use flexi_logger::{DeferredNow, Record, TS_DASHES_BLANK_COLONS_DOT_BLANK};
+use hellojudge3_judger::{
+    bench::run_benchmarks,
     core::{
-        config::JudgerConfig,
+        api::Hj3ApiClient,
+        calibrate::{calibrate_time_scale, run_calibration_benchmark},
+        cli::{parse_command, Command},
+        compare::isolated::run_compare_helper,
+        config::{JudgerConfig, DEFAULT_QUEUE},
+        events::EventPublisher,
+        heartbeat::spawn_heartbeat,
+        lang_cache::LanguageConfigCache,
+        logging::{json_log_format, with_log_context, LogContext},
         misc::ResultType,
-        state::{AppState, GLOBAL_APP_STATE},
+        plugin::create_plugins,
+        process_stats::spawn_process_stats_logger,
+        runner::{create_runner, images::ensure_images, seccomp::check_seccomp_profiles},
+        state::{AppState, LockRegistry, GLOBAL_APP_STATE},
+        watermark::verify_watermark_file,
+        webhook::{WebhookSender, EVENT_SELF_TEST_FAILED},
+        workdir::cleanup_stale_work_dirs,
+    },
+    task::{
+        admin::{
+            broadcast::spawn_admin_listener, clear_cache_handler, pull_images_handler,
+            purge_cache_handler,
+        },
+        local::{
+            batch::rejudge_batch_handler,
+            cancel::cancel_judge_handler,
+            compile::{cleanup_expired_compile_cache, spawn_compile_cache_cleanup},
+            custom_input::run_custom_input_handler,
+            executor::handle,
+            generate::generate_testdata_handler,
+            janitor::spawn_testdata_janitor,
+            journal::read_journal,
+            local_judge_task_handler,
+            requeue::set_requeue_sender,
+            sync_state::migrate_legacy_sync_state,
+            util::spawn_status_replay,
+            validate::validate_problem_handler,
+        },
+        online_ide::online_ide_handler,
+        stress::stress_test_handler,
     },
-    task::{local::local_judge_task_handler, online_ide::online_ide_handler},
 };
-use anyhow::anyhow;
-use celery::{broker::RedisBrokerBuilder, CeleryBuilder};
-use config::Config;
-use flexi_logger::{DeferredNow, Record, TS_DASHES_BLANK_COLONS_DOT_BLANK};
-use log::info;
+use log::{error, info};
 use tokio::sync::Semaphore;
-pub mod core;
-pub mod task;
 pub fn my_log_format(
     w: &mut dyn std::io::Write,
     now: &mut DeferredNow,
@@ -34,6 +72,10 @@ pub fn my_log_format(
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> ResultType<()> {
+    let command = parse_command(&std::env::args().collect::<Vec<String>>())?;
+    if let Command::CompareHelper { method, full_score } = &command {
+        return run_compare_helper(method, *full_score);
+    }
     if !std::path::Path::new("config.yaml").exists() {
         tokio::fs::write(
             "config.yaml",
@@ -47,21 +89,53 @@ async fn main() -> ResultType<()> {
     let builder = Config::builder()
         .add_source(Config::try_from(&JudgerConfig::default())?)
         .add_source(Config::try_from(
-            &serde_yaml::from_str::<JudgerConfig>(
+            &JudgerConfig::parse(
                 &tokio::fs::read_to_string("config.yaml")
                     .await
                     .map_err(|e| anyhow!("Failed to read configure file: {}", e))?,
             )
             .map_err(|e| anyhow!("Failed to deserialize configure file: {}", e))?,
         )?);
-    let config: JudgerConfig = builder.build()?.try_deserialize()?;
+    let mut config: JudgerConfig = builder.build()?.try_deserialize()?;
+    if config.compare_helper_path.is_empty() {
+        config.compare_helper_path = std::env::current_exe()
+            .map_err(|e| anyhow!("Failed to get judger executable: {}", e))?
+            .to_string_lossy()
+            .to_string();
+    }
+    config
+        .suburl("")
+        .map_err(|e| anyhow!("Invalid web api config: {}", e))?;
+    if !["files", "archive"].contains(&config.testdata_sync_mode.as_str()) {
+        return Err(anyhow!(
+            "Unknown testdata sync mode: {}",
+            config.testdata_sync_mode
+        ));
+    }
+    config.sandbox_user()?;
+    if config.shadow_mode && config.journal_dir.is_empty() && config.shadow_endpoint.is_empty() {
+        return Err(anyhow!(
+            "shadow_mode requires journal_dir or shadow_endpoint to record results"
+        ));
+    }
     if config.prefetch_count < 2 {
         return Err(anyhow!("prefetch_count must be greater than 1"));
     }
-    use flexi_logger::{Duplicate, FileSpec, Logger};
+    if let Some(queue) = config.queues.iter().find(|v| v.weight < 2) {
+        return Err(anyhow!(
+            "Weight of queue {} must be greater than 1",
+            queue.name
+        ));
+    }
+    use flexi_logger::{Duplicate, FileSpec, FormatFunction, Logger};
+    let log_format: FormatFunction = match config.log_format.as_str() {
+        "text" => my_log_format,
+        "json" => json_log_format,
+        other => return Err(anyhow!("Unknown log format: {}", other)),
+    };
     Logger::try_with_str(&config.logging_level)
         .map_err(|_| anyhow!("Invalid loggine level: {}", config.logging_level))?
-        .format(my_log_format)
+        .format(log_format)
         .log_to_file(FileSpec::default().directory("logs").basename("hj3-judger"))
         .duplicate_to_stdout(Duplicate::All)
         .start()
@@ -73,21 +147,176 @@ async fn main() -> ResultType<()> {
     if !data_dir.exists() {
         std::fs::create_dir(&data_dir).expect("Failed to create data dir");
     }
+    if let Command::MigrateSyncState = command {
+        let summary = migrate_legacy_sync_state(&data_dir).await?;
+        println!(
+            "Scanned {} problems, migrated {} files, skipped {} lock files",
+            summary.problems, summary.migrated, summary.skipped
+        );
+        return Ok(());
+    }
+    if let Command::VerifyWatermark { input, result } = &command {
+        return verify_watermark_file(
+            &config,
+            std::path::Path::new(input),
+            result.as_ref().map(std::path::Path::new),
+        )
+        .await;
+    }
+    cleanup_stale_work_dirs(&config)?;
+    cleanup_expired_compile_cache(&config)?;
     let task_count = config.max_tasks_sametime.clone();
+    check_seccomp_profiles(&config)?;
+    let runner = create_runner(&config)?;
+    // 提前准备好镜像，避免评测时才发现镜像不存在
+    let failed_images = ensure_images(&config, &[], false).await?;
+    if failed_images > 0 {
+        return Err(anyhow!("{} docker images are not available", failed_images));
+    }
+    let event_publisher = EventPublisher::new(&config)?;
+    let progress_publisher = EventPublisher::for_progress(&config)?;
+    let plugins = create_plugins(&config)?;
+    let api = Hj3ApiClient::new(&config)?;
+    if let Command::Bench { rounds, only } = &command {
+        return run_benchmarks(&config, &*runner, *rounds, only).await;
+    }
+    if let Command::Calibrate = command {
+        let best = run_calibration_benchmark(&config, &*runner).await?;
+        println!("benchmark time: {} ms", best / 1000);
+        if config.calibration_reference_time > 0 {
+            println!(
+                "time_scale: {:.4}",
+                best as f64 / (config.calibration_reference_time * 1000) as f64
+            );
+        }
+        return Ok(());
+    }
+    let version_string = format!("HelloJudge3-Judger {}", env!("CARGO_PKG_VERSION"),);
+    let webhooks = match command {
+        Command::Serve => WebhookSender::spawn(&config, &api, &version_string),
+        _ => None,
+    };
+    let time_scale = if config.calibrate_time_scale {
+        match calibrate_time_scale(&config, &*runner).await {
+            Ok(v) => {
+                info!("Using calibrated time_scale: {:.4}", v);
+                v
+            }
+            Err(e) => {
+                error!("Calibration failed, using time_scale from config: {}", e);
+                if let Some(sender) = webhooks.as_ref() {
+                    sender.notify(
+                        EVENT_SELF_TEST_FAILED,
+                        serde_json::json!({ "check": "calibration", "message": e.to_string() }),
+                    );
+                }
+                info!("Using time_scale from config: {}", config.time_scale);
+                config.time_scale
+            }
+        }
+    } else {
+        info!("Using time_scale from config: {}", config.time_scale);
+        config.time_scale
+    };
+    let shadow_mode = config.shadow_mode;
+    if shadow_mode {
+        info!("Running in shadow mode, results will not be reported");
+    }
     let app_state = AppState {
         config,
-        file_dir_locks: tokio::sync::Mutex::new(HashMap::default()),
+        file_dir_locks: LockRegistry::new(),
         testdata_dir: data_dir,
-        version_string: format!("HelloJudge3-Judger {}", env!("CARGO_PKG_VERSION"),),
+        version_string,
         task_count_lock: Arc::new(Semaphore::new(task_count)),
+        report_status: match command {
+            // 影子模式下评测结果只记录在本地或发送到shadow_endpoint
+            Command::Serve => !shadow_mode,
+            Command::Replay { report, .. } => report,
+            Command::Calibrate
+            | Command::MigrateSyncState
+            | Command::Bench { .. }
+            | Command::VerifyWatermark { .. }
+            | Command::CompareHelper { .. } => false,
+        },
+        runner,
+        event_publisher,
+        progress_publisher,
+        time_scale,
+        plugins,
+        compile_cache_locks: LockRegistry::new(),
+        api,
+        webhooks,
+        language_configs: LanguageConfigCache::new(),
     };
     *GLOBAL_APP_STATE.write().await = Some(app_state);
     let guard = GLOBAL_APP_STATE.read().await;
     let app_state = guard.as_ref().unwrap();
+    if let Command::Replay { submission_id, .. } = command {
+        info!("Replaying submission {}", submission_id);
+        let entry = read_journal(app_state, submission_id).await?;
+        info!("Task received at {}", entry.received_at);
+        with_log_context(
+            LogContext {
+                submission_id: Some(submission_id),
+                ..Default::default()
+            },
+            handle(entry.submission_data, entry.extra_config, app_state),
+        )
+        .await?;
+        info!("Replay finished");
+        return Ok(());
+    }
+    // (队列名, prefetch_count)
+    let queues = if app_state.config.queues.is_empty() {
+        vec![(DEFAULT_QUEUE.to_string(), app_state.config.prefetch_count)]
+    } else {
+        app_state
+            .config
+            .queues
+            .iter()
+            .map(|v| (v.name.clone(), v.weight))
+            .collect()
+    };
+    let mut consumers = vec![];
+    for (queue, prefetch_count) in queues.into_iter() {
+        let celery_app = build_celery_app(&app_state.config, &queue, prefetch_count).await?;
+        if consumers.is_empty() {
+            set_requeue_sender(celery_app.clone()).await;
+        }
+        info!(
+            "Consuming queue {}, prefetch_count = {}",
+            queue, prefetch_count
+        );
+        consumers.push(async move {
+            return celery_app
+                .consume()
+                .await
+                .map_err(|e| anyhow!("Failed to consume queue {}: {}", queue, e));
+        });
+    }
+    spawn_heartbeat(app_state.config.heartbeat_interval);
+    spawn_process_stats_logger(app_state.config.process_stats_interval);
+    spawn_testdata_janitor(app_state.config.data_dir_max_bytes);
+    spawn_status_replay();
+    spawn_admin_listener(&app_state.config);
+    spawn_compile_cache_cleanup(&app_state.config);
+    info!("{}", app_state.version_string);
+    info!("Started!");
+    futures_util::future::try_join_all(consumers).await?;
+    return Ok(());
+}
+
+/// 创建消费queue的celery应用并注册全部任务，每个队列使用单独的连接以分别限制prefetch_count
+async fn build_celery_app(
+    config: &JudgerConfig,
+    queue: &str,
+    prefetch_count: u16,
+) -> ResultType<Arc<Celery<RedisBroker>>> {
     let celery_app = Arc::new(
-        CeleryBuilder::<RedisBrokerBuilder>::new("hj3-judger", &app_state.config.broker_url)
+        CeleryBuilder::<RedisBrokerBuilder>::new("hj3-judger", &config.broker_url)
+            .default_queue(queue)
             .task_retry_for_unexpected(false)
-            .prefetch_count(app_state.config.prefetch_count)
+            .prefetch_count(prefetch_count)
             .acks_late(true)
             .build()
             .await?,
@@ -96,12 +325,45 @@ async fn main() -> ResultType<()> {
         .register_task::<local_judge_task_handler>()
         .await
         .expect("Failed to register local judge handler");
+    celery_app
+        .register_task::<cancel_judge_handler>()
+        .await
+        .expect("Failed to register cancel handler");
+    celery_app
+        .register_task::<run_custom_input_handler>()
+        .await
+        .expect("Failed to register custom input handler");
+    celery_app
+        .register_task::<validate_problem_handler>()
+        .await
+        .expect("Failed to register problem validation handler");
+    celery_app
+        .register_task::<generate_testdata_handler>()
+        .await
+        .expect("Failed to register testdata generation handler");
+    celery_app
+        .register_task::<rejudge_batch_handler>()
+        .await
+        .expect("Failed to register rejudge batch handler");
     celery_app
         .register_task::<online_ide_handler>()
         .await
         .expect("Failed to register online ide handler");
-    info!("{}", app_state.version_string);
-    info!("Started!");
-    celery_app.consume().await.unwrap();
-    return Ok(());
+    celery_app
+        .register_task::<stress_test_handler>()
+        .await
+        .expect("Failed to register stress test handler");
+    celery_app
+        .register_task::<clear_cache_handler>()
+        .await
+        .expect("Failed to register clear cache handler");
+    celery_app
+        .register_task::<purge_cache_handler>()
+        .await
+        .expect("Failed to register purge cache handler");
+    celery_app
+        .register_task::<pull_images_handler>()
+        .await
+        .expect("Failed to register pull images handler");
+    return Ok(celery_app);
 }
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{config::JudgerConfig, misc::ResultType, state::GLOBAL_APP_STATE};
+
+use super::{clear_language_cache, purge::purge_scopes};
+
+// 订阅断开后重新连接的间隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 需要在所有评测机上执行的管理命令
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminCommand {
+    ClearCache { languages: Vec<String> },
+    PurgeCache { scopes: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdminMessage {
+    // 发出命令的评测机，该评测机已经执行过命令
+    sender: String,
+    #[serde(flatten)]
+    command: AdminCommand,
+}
+
+/// 通过broker上的admin_broadcast_channel将命令发送给其他评测机
+/// celery任务只会被一个评测机取出，取出任务的评测机在本地执行后转发给其余评测机
+pub async fn broadcast(config: &JudgerConfig, command: AdminCommand) -> ResultType<()> {
+    if config.admin_broadcast_channel.is_empty() {
+        return Ok(());
+    }
+    let payload = serde_json::to_string(&AdminMessage {
+        sender: config.judger_uuid.clone(),
+        command,
+    })?;
+    let client = redis::Client::open(config.broker_url.as_str())
+        .map_err(|e| anyhow!("Invalid broker url: {}", e))?;
+    let mut conn = client
+        .get_async_connection()
+        .await
+        .map_err(|e| anyhow!("Failed to connect to broker: {}", e))?;
+    let receivers = redis::cmd("PUBLISH")
+        .arg(&config.admin_broadcast_channel)
+        .arg(&payload)
+        .query_async::<_, i64>(&mut conn)
+        .await
+        .map_err(|e| anyhow!("Failed to broadcast admin command: {}", e))?;
+    info!("Admin command broadcast to {} judgers", receivers);
+    return Ok(());
+}
+
+async fn execute(message: AdminMessage) -> ResultType<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().ok_or(anyhow!("App state not initialized"))?;
+    if message.sender == app.config.judger_uuid {
+        return Ok(());
+    }
+    info!(
+        "Received admin command from {}: {:?}",
+        message.sender, message.command
+    );
+    match message.command {
+        AdminCommand::ClearCache { languages } => clear_language_cache(app, &languages),
+        AdminCommand::PurgeCache { scopes } => {
+            purge_scopes(app, &scopes).await?;
+        }
+    }
+    return Ok(());
+}
+
+async fn listen(broker_url: &str, channel: &str) -> ResultType<()> {
+    let client =
+        redis::Client::open(broker_url).map_err(|e| anyhow!("Invalid broker url: {}", e))?;
+    let mut pubsub = client
+        .get_async_connection()
+        .await
+        .map_err(|e| anyhow!("Failed to connect to broker: {}", e))?
+        .into_pubsub();
+    pubsub
+        .subscribe(channel)
+        .await
+        .map_err(|e| anyhow!("Failed to subscribe {}: {}", channel, e))?;
+    info!("Listening admin commands on {}", channel);
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let payload = match msg.get_payload::<String>() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Invalid admin command: {}", e);
+                continue;
+            }
+        };
+        match serde_json::from_str::<AdminMessage>(&payload) {
+            Ok(message) => {
+                if let Err(e) = execute(message).await {
+                    error!("Failed to execute admin command: {}", e);
+                }
+            }
+            Err(e) => error!("Invalid admin command {}: {}", payload, e),
+        }
+    }
+    return Err(anyhow!("Subscription closed"));
+}
+
+/// 订阅其他评测机转发的管理命令，channel为空时不订阅
+pub fn spawn_admin_listener(config: &JudgerConfig) {
+    if config.admin_broadcast_channel.is_empty() {
+        return;
+    }
+    let broker_url = config.broker_url.clone();
+    let channel = config.admin_broadcast_channel.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&broker_url, &channel).await {
+                error!("Admin command listener stopped, reconnecting: {}", e);
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    });
+}
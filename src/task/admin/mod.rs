@@ -0,0 +1,62 @@
+use celery::{prelude::TaskError, task::TaskResult};
+use log::{error, info};
+
+use crate::core::{
+    runner::images::ensure_images,
+    state::{AppState, GLOBAL_APP_STATE},
+};
+
+pub mod broadcast;
+pub mod purge;
+use broadcast::{broadcast, AdminCommand};
+pub use purge::purge_cache_handler;
+
+/// languages为空时清空全部语言定义，否则只清除指定的语言
+fn clear_language_cache(app: &AppState, languages: &[String]) {
+    if languages.is_empty() {
+        let count = app.language_configs.clear();
+        info!("Cleared {} cached language configs", count);
+    } else {
+        for language in languages.iter() {
+            if app.language_configs.remove(language) {
+                info!("Cleared cached language config: {}", language);
+            }
+        }
+    }
+}
+
+/// 清空评测机的缓存，languages为空时清空全部语言定义，否则只清除指定的语言
+/// 服务端修改语言定义后可以发送该任务，使其立即生效；本地清空后通过admin_broadcast_channel转发给其他评测机
+#[celery::task(name = "judgers.admin.clear_cache")]
+pub async fn clear_cache_handler(languages: Vec<String>) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().ok_or(TaskError::UnexpectedError(
+        "App state not initialized".to_string(),
+    ))?;
+    clear_language_cache(app, &languages);
+    if let Err(e) = broadcast(&app.config, AdminCommand::ClearCache { languages }).await {
+        error!("Failed to broadcast clear_cache: {}", e);
+    }
+    return Ok(());
+}
+
+/// 重新拉取镜像并校验digest，images为空时拉取评测需要的全部镜像
+/// 服务端更新镜像后可以发送该任务
+#[celery::task(name = "judgers.admin.pull_images")]
+pub async fn pull_images_handler(images: Vec<String>) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().ok_or(TaskError::UnexpectedError(
+        "App state not initialized".to_string(),
+    ))?;
+    let failed = ensure_images(&app.config, &images, true)
+        .await
+        .map_err(|e| TaskError::UnexpectedError(e.to_string()))?;
+    if failed > 0 {
+        return Err(TaskError::UnexpectedError(format!(
+            "Failed to pull {} images",
+            failed
+        )));
+    }
+    info!("Images pulled");
+    return Ok(());
+}
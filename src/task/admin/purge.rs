@@ -0,0 +1,125 @@
+use anyhow::anyhow;
+use celery::{prelude::TaskError, task::TaskResult};
+use log::{error, info, warn};
+
+use super::broadcast::{broadcast, AdminCommand};
+use crate::{
+    core::{
+        misc::ResultType,
+        state::{AppState, GLOBAL_APP_STATE},
+        util::dir_size,
+    },
+    task::local::{
+        compile::purge_compile_cache,
+        janitor::{remove_unused_problem, scan_problem_dirs},
+        util::clear_problem_compile_cache,
+    },
+};
+
+/// 可以清除的缓存
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PurgeScope {
+    // 一个题目的数据目录(包括其中编译好的SPJ等)
+    Problem(i64),
+    // 全部题目数据
+    Testdata,
+    // 用户程序的编译缓存
+    CompileCache,
+    // 各题目中编译好的SPJ、交互器与grader
+    SpjCache,
+    // 语言定义
+    LanguageCache,
+}
+
+impl PurgeScope {
+    /// problem:<题目ID>、testdata、compile_cache、spj_cache或language_cache
+    pub fn parse(value: &str) -> ResultType<Self> {
+        if let Some(id) = value.strip_prefix("problem:") {
+            return id
+                .parse::<i64>()
+                .map(PurgeScope::Problem)
+                .map_err(|e| anyhow!("Invalid problem id `{}`: {}", id, e));
+        }
+        return match value {
+            "testdata" => Ok(PurgeScope::Testdata),
+            "compile_cache" => Ok(PurgeScope::CompileCache),
+            "spj_cache" => Ok(PurgeScope::SpjCache),
+            "language_cache" => Ok(PurgeScope::LanguageCache),
+            other => Err(anyhow!("Unknown cache scope: {}", other)),
+        };
+    }
+}
+
+/// 删除题目数据，正在使用的题目跳过，返回释放的字节数
+async fn purge_problem(app: &AppState, problem_id: i64) -> ResultType<u64> {
+    let path = app.testdata_dir.join(problem_id.to_string());
+    let size = dir_size(&path);
+    if !remove_unused_problem(app, problem_id, &path).await? {
+        warn!("Testdata of problem {} is in use, skipped", problem_id);
+        return Ok(0);
+    }
+    return Ok(size);
+}
+
+async fn purge(app: &AppState, scope: PurgeScope) -> ResultType<u64> {
+    return match scope {
+        PurgeScope::Problem(problem_id) => purge_problem(app, problem_id).await,
+        PurgeScope::Testdata | PurgeScope::SpjCache => {
+            let testdata_dir = app.testdata_dir.clone();
+            let problems = tokio::task::spawn_blocking(move || scan_problem_dirs(&testdata_dir))
+                .await
+                .map_err(|e| anyhow!("Failed to scan testdata dir: {}", e))??;
+            let mut freed = 0;
+            for (problem_id, path, _, _) in problems.into_iter() {
+                freed += if scope == PurgeScope::Testdata {
+                    purge_problem(app, problem_id).await?
+                } else {
+                    clear_problem_compile_cache(app, &path).await?
+                };
+            }
+            Ok(freed)
+        }
+        PurgeScope::CompileCache => purge_compile_cache(&app.config),
+        PurgeScope::LanguageCache => {
+            let count = app.language_configs.clear();
+            info!("Cleared {} cached language configs", count);
+            Ok(0)
+        }
+    };
+}
+
+/// 在本地清除指定范围的缓存，返回释放的字节数；任何一个范围无法识别时不清除任何缓存
+pub async fn purge_scopes(app: &AppState, scopes: &[String]) -> ResultType<u64> {
+    let scopes = scopes
+        .iter()
+        .map(|v| PurgeScope::parse(v))
+        .collect::<ResultType<Vec<PurgeScope>>>()?;
+    let mut total = 0;
+    for scope in scopes.into_iter() {
+        let freed = purge(app, scope)
+            .await
+            .map_err(|e| anyhow!("{:?}: {}", scope, e))?;
+        info!("Purged {:?}, {} bytes freed", scope, freed);
+        total += freed;
+    }
+    info!("Cache purged, {} bytes freed in total", total);
+    return Ok(total);
+}
+
+/// 清除指定范围的缓存，用于修复损坏的缓存，返回本评测机释放的字节数
+/// 题目数据在持有题目目录的锁时删除，正在评测的题目跳过；SPJ等逐项持有编译缓存的锁
+/// 本地清除后通过admin_broadcast_channel转发给其他评测机
+#[celery::task(name = "judgers.admin.purge_cache")]
+pub async fn purge_cache_handler(scopes: Vec<String>) -> TaskResult<u64> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().ok_or(TaskError::UnexpectedError(
+        "App state not initialized".to_string(),
+    ))?;
+    let total = purge_scopes(app, &scopes)
+        .await
+        .map_err(|e| TaskError::UnexpectedError(e.to_string()))?;
+    if let Err(e) = broadcast(&app.config, AdminCommand::PurgeCache { scopes }).await {
+        error!("Failed to broadcast purge_cache: {}", e);
+    }
+    return Ok(total);
+}
@@ -0,0 +1,157 @@
+use serde::Deserialize;
+
+use crate::core::api::{ApiBody, ApiRequest, ApiResult, Hj3ApiClient};
+
+use super::model::ProblemInfo;
+
+#[derive(Deserialize)]
+pub struct ProblemFile {
+    pub name: String,
+    pub size: i64,
+    pub last_modified_time: f64,
+    // 文件内容的sha256，旧版服务端不提供
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DataManifest {
+    // 数据包版本，数据变化时改变
+    pub version: String,
+    pub sha256: Option<String>,
+}
+
+/// 评测状态更新
+pub struct JudgeStatusUpdate<'a> {
+    pub submission_id: i64,
+    // JSON
+    pub judge_result: &'a str,
+    pub message: &'a str,
+    pub extra_status: Option<&'a str>,
+    // judge_result只包含部分子任务，服务端将其合并到已有的结果中
+    pub partial: bool,
+    // 各阶段的时间(JSON)
+    pub timings: Option<&'a str>,
+    // judge_result的编码，为空时为普通的JSON，columnar时子任务的测试点逐列编码
+    pub encoding: &'a str,
+}
+
+/// 批量重测的进度
+pub struct RejudgeBatchProgress<'a> {
+    pub batch_id: &'a str,
+    pub total: usize,
+    pub finished: usize,
+    pub failed: usize,
+}
+
+/// 自定义输入运行、题目数据检查等不属于提交的任务的状态
+pub struct RunStatusUpdate<'a> {
+    pub run_id: &'a str,
+    // running或done
+    pub status: &'a str,
+    pub message: &'a str,
+    // 运行结果(JSON)，运行结束前为空
+    pub result: &'a str,
+}
+
+impl Hj3ApiClient {
+    pub async fn update_judge_status(&self, update: &JudgeStatusUpdate<'_>) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("judge/update")
+                    .param("submission_id", update.submission_id)
+                    .param("judge_result", update.judge_result)
+                    .param("message", update.message)
+                    .param("extra_status", update.extra_status.unwrap_or(""))
+                    .param("partial", update.partial)
+                    .param("timings", update.timings.unwrap_or(""))
+                    .param("encoding", update.encoding),
+            )
+            .await;
+    }
+    pub async fn update_rejudge_batch(&self, progress: &RejudgeBatchProgress<'_>) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("judge/rejudge_batch_update")
+                    .param("batch_id", progress.batch_id)
+                    .param("total", progress.total)
+                    .param("finished", progress.finished)
+                    .param("failed", progress.failed),
+            )
+            .await;
+    }
+    pub async fn update_custom_input(&self, update: &RunStatusUpdate<'_>) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("judge/update_custom_input")
+                    .param("run_id", update.run_id)
+                    .param("status", update.status)
+                    .param("message", update.message)
+                    .param("result", update.result),
+            )
+            .await;
+    }
+    pub async fn update_problem_validation(&self, update: &RunStatusUpdate<'_>) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("judge/update_problem_validation")
+                    .param("run_id", update.run_id)
+                    .param("status", update.status)
+                    .param("message", update.message)
+                    .param("result", update.result),
+            )
+            .await;
+    }
+    pub async fn update_testdata_generation(&self, update: &RunStatusUpdate<'_>) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("judge/update_testdata_generation")
+                    .param("run_id", update.run_id)
+                    .param("status", update.status)
+                    .param("message", update.message)
+                    .param("result", update.result),
+            )
+            .await;
+    }
+    pub async fn get_problem_info(&self, problem_id: i64) -> ApiResult<ProblemInfo> {
+        return self
+            .call(ApiRequest::new("judge/get_problem_info").param("problem_id", problem_id))
+            .await;
+    }
+    pub async fn get_problem_files(&self, problem_id: i64) -> ApiResult<Vec<ProblemFile>> {
+        return self
+            .call(
+                ApiRequest::new("judge/get_file_list")
+                    .param("problem_id", problem_id)
+                    .param("with_checksum", true),
+            )
+            .await;
+    }
+    /// 从offset开始下载题目文件，服务端不支持Range时返回200与整个文件
+    pub async fn open_problem_file(
+        &self,
+        problem_id: i64,
+        filename: &str,
+        offset: u64,
+    ) -> ApiResult<Box<dyn ApiBody>> {
+        return self
+            .open(
+                ApiRequest::new("judge/download_file")
+                    .param("problem_id", problem_id)
+                    .param("filename", filename)
+                    .range_start(offset),
+            )
+            .await;
+    }
+    pub async fn get_data_manifest(&self, problem_id: i64) -> ApiResult<DataManifest> {
+        return self
+            .call(ApiRequest::new("judge/get_data_manifest").param("problem_id", problem_id))
+            .await;
+    }
+    /// 打开数据包的下载，由调用者逐块读取
+    pub async fn open_data_archive(&self, problem_id: i64) -> ApiResult<Box<dyn ApiBody>> {
+        return self
+            .open(ApiRequest::new("judge/download_data_archive").param("problem_id", problem_id))
+            .await;
+    }
+}
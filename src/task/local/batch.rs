@@ -0,0 +1,62 @@
+use celery::task::TaskResult;
+use futures_util::StreamExt;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::core::state::{AppState, GLOBAL_APP_STATE};
+
+use super::{
+    executor::judge_submission, model::ExtraJudgeConfig, timing::now_millis,
+    util::update_batch_progress,
+};
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct RejudgeItem {
+    pub submission_data: Value,
+    pub extra_config: ExtraJudgeConfig,
+}
+
+/// 评测一个提交，与普通评测任务共享task_count_lock
+async fn judge_item(app: &AppState, item: RejudgeItem) -> bool {
+    let received_at = now_millis();
+    let _semaphore_guard = app.task_count_lock.acquire().await.unwrap();
+    return judge_submission(app, item.submission_data, item.extra_config, received_at)
+        .await
+        .is_ok();
+}
+
+/// 批量重测，同时最多评测concurrency个提交，避免大量单独的评测任务挤占正常提交
+#[celery::task(name = "judgers.local.rejudge_batch")]
+pub async fn rejudge_batch_handler(
+    batch_id: String,
+    items: Vec<RejudgeItem>,
+    concurrency: usize,
+) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().unwrap();
+    let total = items.len();
+    info!(
+        "Rejudge batch {}: {} submissions, concurrency = {}",
+        batch_id, total, concurrency
+    );
+    update_batch_progress(app, &batch_id, total, 0, 0).await;
+    let mut results = futures_util::stream::iter(items.into_iter().map(|v| judge_item(app, v)))
+        .buffer_unordered(concurrency.max(1));
+    let mut finished = 0;
+    let mut failed = 0;
+    while let Some(ok) = results.next().await {
+        finished += 1;
+        if !ok {
+            failed += 1;
+        }
+        update_batch_progress(app, &batch_id, total, finished, failed).await;
+    }
+    if failed != 0 {
+        error!(
+            "Rejudge batch {}: {} of {} submissions failed",
+            batch_id, failed, total
+        );
+    }
+    return Ok(());
+}
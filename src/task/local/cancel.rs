@@ -0,0 +1,139 @@
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::anyhow;
+use celery::{prelude::TaskError, task::TaskResult};
+use lazy_static::lazy_static;
+use log::{error, info};
+use redis::aio::ConnectionManager;
+use tokio::sync::Mutex;
+
+use crate::core::{
+    misc::ResultType,
+    runner::Session,
+    state::{AppState, GLOBAL_APP_STATE},
+};
+
+// 取消标记的有效期，评测结束时会提前删除
+const CANCEL_KEY_TTL: u64 = 3600;
+
+lazy_static! {
+    // 本评测机评测时检查到已被取消的提交
+    static ref CANCELLED: std::sync::Mutex<HashSet<i64>> = std::sync::Mutex::new(HashSet::new());
+    // 连接broker，用于读写取消标记
+    static ref BROKER_CONNECTION: Mutex<Option<ConnectionManager>> = Mutex::new(None);
+}
+
+async fn broker_connection(app: &AppState) -> ResultType<ConnectionManager> {
+    let mut guard = BROKER_CONNECTION.lock().await;
+    if let Some(v) = guard.as_ref() {
+        return Ok(v.clone());
+    }
+    let client = redis::Client::open(app.config.broker_url.as_str())
+        .map_err(|e| anyhow!("Invalid broker url: {}", e))?;
+    let conn = ConnectionManager::new(client)
+        .await
+        .map_err(|e| anyhow!("Failed to connect to broker: {}", e))?;
+    *guard = Some(conn.clone());
+    return Ok(conn);
+}
+
+fn cancel_key(app: &AppState, sid: i64) -> String {
+    return format!("{}{}", app.config.cancel_key_prefix, sid);
+}
+
+/// 标记提交为已取消，正在评测该提交的评测机(可能不是本机)会在检查时停止评测
+/// 只写入broker中的标记，本机的记录只由评测该提交的流程维护，以免影响之后在本机上的重新评测
+pub async fn request_cancel(app: &AppState, sid: i64) -> ResultType<()> {
+    let mut conn = broker_connection(app).await?;
+    redis::cmd("SET")
+        .arg(cancel_key(app, sid))
+        .arg(1)
+        .arg("EX")
+        .arg(CANCEL_KEY_TTL)
+        .query_async::<_, ()>(&mut conn)
+        .await
+        .map_err(|e| anyhow!("Failed to set cancel flag: {}", e))?;
+    return Ok(());
+}
+
+/// 提交是否已被取消，读取broker失败时视为未取消
+pub async fn is_cancelled(app: &AppState, sid: i64) -> bool {
+    if app.config.cancel_poll_interval == 0 {
+        return false;
+    }
+    if CANCELLED.lock().unwrap().contains(&sid) {
+        return true;
+    }
+    let result = async {
+        let mut conn = broker_connection(app).await?;
+        return redis::cmd("EXISTS")
+            .arg(cancel_key(app, sid))
+            .query_async::<_, bool>(&mut conn)
+            .await
+            .map_err(|e| anyhow!("Failed to read cancel flag: {}", e));
+    }
+    .await;
+    return match result {
+        Ok(v) => {
+            if v {
+                CANCELLED.lock().unwrap().insert(sid);
+            }
+            v
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    };
+}
+
+/// 本次评测中是否检查到提交已被取消，被取消的评测结果不完整
+pub fn was_cancelled(sid: i64) -> bool {
+    return CANCELLED.lock().unwrap().contains(&sid);
+}
+
+/// 评测结束后清除取消标记，之后的重新评测不受影响
+pub async fn clear_cancel(app: &AppState, sid: i64) {
+    if !CANCELLED.lock().unwrap().remove(&sid) {
+        return;
+    }
+    let result = async {
+        let mut conn = broker_connection(app).await?;
+        return redis::cmd("DEL")
+            .arg(cancel_key(app, sid))
+            .query_async::<_, ()>(&mut conn)
+            .await
+            .map_err(|e| anyhow!("Failed to clear cancel flag: {}", e));
+    }
+    .await;
+    if let Err(e) = result {
+        error!("{}", e);
+    }
+}
+
+/// 每隔cancel_poll_interval检查一次，提交被取消时关闭session(删除容器)，使正在运行的程序立即结束
+/// 只在被取消后返回，应与评测测试点的future一同select
+pub async fn close_on_cancel(app: &AppState, sid: i64, session: &dyn Session) {
+    if app.config.cancel_poll_interval == 0 {
+        return std::future::pending().await;
+    }
+    let interval = Duration::from_millis(app.config.cancel_poll_interval);
+    while !is_cancelled(app, sid).await {
+        tokio::time::sleep(interval).await;
+    }
+    info!("Submission {} cancelled, closing sandbox session", sid);
+    if let Err(e) = session.close().await {
+        error!("Failed to close sandbox session: {}", e);
+    }
+}
+
+/// 取消正在评测(或排队中)的提交: 正在运行的测试点被终止，其余测试点标记为cancelled，并汇报已有的结果
+#[celery::task(name = "judgers.local.cancel")]
+pub async fn cancel_judge_handler(submission_id: i64) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().unwrap();
+    info!("Cancelling submission {}", submission_id);
+    return request_cancel(app, submission_id)
+        .await
+        .map_err(|e| TaskError::UnexpectedError(e.to_string()));
+}
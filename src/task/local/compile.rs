@@ -1,28 +1,563 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     core::{
+        config::JudgerConfig,
+        events::publish_event,
+        limits::COMPILE_MEMORY_LIMIT,
         misc::ResultType,
         model::LanguageConfig,
-        runner::docker::{execute_in_docker, ExecuteResult},
+        runner::{ExecuteResult, Session},
         state::AppState,
+        upload::sha256_hex,
+        util::{dir_size, evict_problem_program_cache, touch_cache_entry},
+    },
+    task::local::{
+        model::SubmissionJudgeResult,
+        program_filename,
+        util::{update_status, COMPILE_CACHE_DIR},
     },
-    task::local::{model::SubmissionJudgeResult, util::update_status, DEFAULT_PROGRAM_FILENAME},
 };
 
 use super::model::{ExtraJudgeConfig, ProblemInfo, SubmissionInfo};
 use anyhow::anyhow;
 use log::{error, info};
+use serde_json::json;
+// 定期清理过期编译缓存的间隔
+const COMPILE_CACHE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// 相对路径 -> (大小, 修改时间)
+type FileSnapshot = HashMap<PathBuf, (u64, Option<SystemTime>)>;
 pub struct CompileResult {
     pub execute_result: ExecuteResult,
     pub compile_error: bool,
 }
+/// 函数实现题中参与编译的源文件: 用户程序与provides中扩展名相同的文件
+fn grader_sources(
+    lang_config: &LanguageConfig,
+    user_source: &str,
+    problem_data: &ProblemInfo,
+) -> Vec<String> {
+    let extension = Path::new(&lang_config.source_file)
+        .extension()
+        .map(|v| v.to_os_string());
+    let mut ret = vec![user_source.to_string()];
+    ret.extend(
+        problem_data
+            .provides
+            .iter()
+            .filter(|v| extension.is_some() && Path::new(v).extension() == extension.as_deref())
+            .cloned(),
+    );
+    return ret;
+}
+/// 将grader源文件编译为目标文件，按题目数据与语言缓存在cache_root/grader-<sha256>下
+/// 键由grader与provides中全部文件的内容、编译命令、语言与运行环境决定，返回目标文件名
+async fn precompile_graders(
+    app: &AppState,
+    session: &dyn Session,
+    working_dir: &Path,
+    language: &str,
+    docker_image: &str,
+    graders: &[String],
+    problem_data: &ProblemInfo,
+    cache_root: &Path,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<Vec<String>> {
+    let objects = graders
+        .iter()
+        .map(|v| {
+            Path::new(v)
+                .with_extension("o")
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect::<Vec<String>>();
+    let mut key_data = vec![];
+    for part in [
+        language,
+        problem_data.grader_object_command.as_str(),
+        app.config.sandbox_backend.as_str(),
+        docker_image,
+    ] {
+        key_data.extend_from_slice(part.as_bytes());
+        key_data.push(0);
+    }
+    // 头文件等可能被grader包含，同样计入
+    for file in problem_data.provides.iter() {
+        let data = tokio::fs::read(working_dir.join(file))
+            .await
+            .map_err(|e| anyhow!("Failed to read provided file: {}, {}", file, e))?;
+        key_data.extend_from_slice(file.as_bytes());
+        key_data.push(0);
+        key_data.extend_from_slice(&data);
+        key_data.push(0);
+    }
+    let key = sha256_hex(&key_data);
+    let cache_dir = cache_root.join(format!("grader-{}", key));
+    let guard = app
+        .compile_cache_locks
+        .lock(&cache_dir.to_str().unwrap_or("").to_string())
+        .await;
+    if cache_dir.exists() {
+        info!("Using cached grader objects: {}", key);
+        touch_cache_entry(&cache_dir);
+        for object in objects.iter() {
+            tokio::fs::copy(cache_dir.join(object), working_dir.join(object))
+                .await
+                .map_err(|e| anyhow!("Failed to load cached grader object {}: {}", object, e))?;
+        }
+        return Ok(objects);
+    }
+    for (source, object) in graders.iter().zip(objects.iter()) {
+        let cmdline = problem_data
+            .grader_object_command
+            .replace("{source}", source)
+            .replace("{output}", object)
+            .split_ascii_whitespace()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>();
+        info!("Compiling grader: {:?}", cmdline);
+        let result = session
+            .execute(
+                &cmdline,
+                COMPILE_MEMORY_LIMIT.as_bytes(),
+                extra_config.compile_time_limit.as_micros(),
+                extra_config.compile_result_length_limit as usize,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to compile grader {}: {}", source, e))?;
+        if result.exit_code != 0 || !working_dir.join(object).is_file() {
+            return Err(anyhow!(
+                "Failed to compile grader {} (exit code = {}):\n{}",
+                source,
+                result.exit_code,
+                result.output
+            ));
+        }
+    }
+    // 先复制到临时目录再改名，避免其他任务读到不完整的缓存；缓存失败不影响评测
+    let store = async {
+        let temp_dir = cache_root.join(format!("grader-{}.tmp", key));
+        if temp_dir.exists() {
+            tokio::fs::remove_dir_all(&temp_dir).await?;
+        }
+        tokio::fs::create_dir_all(&temp_dir).await?;
+        for object in objects.iter() {
+            tokio::fs::copy(working_dir.join(object), temp_dir.join(object)).await?;
+        }
+        tokio::fs::rename(&temp_dir, &cache_dir).await?;
+        return ResultType::<()>::Ok(());
+    };
+    if let Err(e) = store.await {
+        error!("Failed to cache grader objects: {}", e);
+    }
+    drop(guard);
+    if let Err(e) = evict_problem_program_cache(app, cache_root).await {
+        error!("Failed to evict compile cache: {}", e);
+    }
+    return Ok(objects);
+}
+/// 编译缓存的键: 语言、编译命令、运行环境、源代码以及provides中的文件(可能被#include)
+async fn compile_cache_key(
+    config: &JudgerConfig,
+    docker_image: &str,
+    language: &str,
+    compile_cmdline: &Vec<String>,
+    code: &str,
+    working_dir: &Path,
+    problem_data: &ProblemInfo,
+) -> ResultType<String> {
+    let mut key_data = code.as_bytes().to_vec();
+    for part in [
+        language,
+        compile_cmdline.join(" ").as_str(),
+        config.sandbox_backend.as_str(),
+        docker_image,
+    ] {
+        key_data.push(0);
+        key_data.extend_from_slice(part.as_bytes());
+    }
+    for file in problem_data.provides.iter() {
+        let data = tokio::fs::read(working_dir.join(file))
+            .await
+            .map_err(|e| anyhow!("Failed to read provided file: {}, {}", file, e))?;
+        key_data.push(0);
+        key_data.extend_from_slice(file.as_bytes());
+        key_data.push(0);
+        key_data.extend_from_slice(&data);
+    }
+    return Ok(sha256_hex(&key_data));
+}
+/// dir下全部文件(包括子目录中的)的相对路径、大小与修改时间
+fn snapshot_files(dir: &Path) -> FileSnapshot {
+    fn walk(root: &Path, dir: &Path, ret: &mut FileSnapshot) {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                match entry.metadata() {
+                    Ok(v) if v.is_dir() => walk(root, &path, ret),
+                    Ok(v) if v.is_file() => {
+                        if let Ok(relative) = path.strip_prefix(root) {
+                            ret.insert(relative.to_path_buf(), (v.len(), v.modified().ok()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    let mut ret = FileSnapshot::new();
+    walk(dir, dir, &mut ret);
+    return ret;
+}
+/// 编译后新出现或被修改的文件，即编译产物(如Java的Main.class与Main$1.class)
+fn compile_outputs(before: &FileSnapshot, working_dir: &Path, source: &str) -> Vec<PathBuf> {
+    return snapshot_files(working_dir)
+        .into_iter()
+        .filter(|(path, meta)| before.get(path) != Some(meta) && path != Path::new(source))
+        .map(|(path, _)| path)
+        .collect();
+}
+/// 将from下的files(相对路径)复制到to下的相同位置
+async fn copy_files(from: &Path, to: &Path, files: &[PathBuf]) -> std::io::Result<()> {
+    for file in files.iter() {
+        let target = to.join(file);
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(from.join(file), &target).await?;
+    }
+    return Ok(());
+}
+/// 将缓存的全部编译产物复制到working_dir，缓存不存在或已过期时返回false
+async fn load_compile_cache(config: &JudgerConfig, key: &str, working_dir: &Path) -> bool {
+    let cached = Path::new(&config.compile_cache_dir).join(key);
+    let modified = match tokio::fs::metadata(&cached).await {
+        // 旧版本的缓存为单个文件，视为未命中
+        Ok(v) if v.is_dir() => match v.modified() {
+            Ok(v) => v,
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    if modified
+        .elapsed()
+        .map(|v| v.as_secs() > config.compile_cache_ttl)
+        .unwrap_or(true)
+    {
+        return false;
+    }
+    let files = snapshot_files(&cached)
+        .into_keys()
+        .collect::<Vec<PathBuf>>();
+    if let Err(e) = copy_files(&cached, working_dir, &files).await {
+        error!("Failed to load compile cache {}: {}", key, e);
+        return false;
+    }
+    return true;
+}
+/// 缓存全部编译产物，先复制到临时目录再改名，避免其他任务读到不完整的缓存
+async fn store_compile_cache(
+    config: &JudgerConfig,
+    key: &str,
+    working_dir: &Path,
+    outputs: &[PathBuf],
+    sid: i64,
+) -> ResultType<()> {
+    let root = Path::new(&config.compile_cache_dir);
+    let temp_dir = root.join(format!("{}.{}.tmp", key, sid));
+    tokio::fs::create_dir_all(&temp_dir)
+        .await
+        .map_err(|e| anyhow!("Failed to create compile cache dir: {}", e))?;
+    let stored = async {
+        copy_files(working_dir, &temp_dir, outputs)
+            .await
+            .map_err(|e| anyhow!("Failed to copy compile result: {}", e))?;
+        if root.join(key).exists() {
+            // 其他任务已经写入了相同的缓存
+            return Err(anyhow!("Compile cache {} already exists", key));
+        }
+        tokio::fs::rename(&temp_dir, root.join(key))
+            .await
+            .map_err(|e| anyhow!("Failed to rename compile cache: {}", e))?;
+        return ResultType::<()>::Ok(());
+    }
+    .await;
+    if stored.is_err() {
+        tokio::fs::remove_dir_all(&temp_dir).await.ok();
+    }
+    return stored;
+}
+/// 删除一个缓存项，旧版本的缓存为单个文件
+fn remove_cache_entry(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        return std::fs::remove_dir_all(path);
+    }
+    return std::fs::remove_file(path);
+}
+/// 定期删除过期的编译缓存，compile_cache_dir为空时不做任何事
+pub fn spawn_compile_cache_cleanup(config: &JudgerConfig) {
+    if config.compile_cache_dir.is_empty() {
+        return;
+    }
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(COMPILE_CACHE_CLEANUP_INTERVAL);
+        // 启动时已经清理过一次
+        timer.tick().await;
+        loop {
+            timer.tick().await;
+            let config = config.clone();
+            let result =
+                tokio::task::spawn_blocking(move || cleanup_expired_compile_cache(&config))
+                    .await
+                    .map_err(|e| anyhow!("Failed to run blocking task: {}", e))
+                    .and_then(|v| v);
+            if let Err(e) = result {
+                error!("Failed to clean up compile cache: {}", e);
+            }
+        }
+    });
+}
+/// 删除过期的编译缓存，compile_cache_dir为空时不做任何事
+pub fn cleanup_expired_compile_cache(config: &JudgerConfig) -> ResultType<()> {
+    let root = Path::new(&config.compile_cache_dir);
+    if config.compile_cache_dir.is_empty() || !root.exists() {
+        return Ok(());
+    }
+    let mut count = 0;
+    for entry in
+        std::fs::read_dir(root).map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?
+    {
+        let entry = entry.map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?;
+        // 临时目录可能正在写入，超过清理间隔仍未改名的才视为崩溃时遗留的
+        let max_age = if entry.file_name().to_string_lossy().ends_with(".tmp") {
+            COMPILE_CACHE_CLEANUP_INTERVAL
+        } else {
+            Duration::from_secs(config.compile_cache_ttl)
+        };
+        let expired = entry
+            .metadata()
+            .and_then(|v| v.modified())
+            .map(|v| v.elapsed().map(|v| v > max_age).unwrap_or(true))
+            .unwrap_or(true);
+        if !expired {
+            continue;
+        }
+        if let Err(e) = remove_cache_entry(&entry.path()) {
+            error!(
+                "Failed to remove compile cache {}: {}",
+                entry.path().display(),
+                e
+            );
+        } else {
+            count += 1;
+        }
+    }
+    info!("Removed {} expired compile cache entries", count);
+    return Ok(());
+}
+/// 删除全部编译缓存，返回释放的字节数；正在写入的临时目录保留
+pub fn purge_compile_cache(config: &JudgerConfig) -> ResultType<u64> {
+    let root = Path::new(&config.compile_cache_dir);
+    if config.compile_cache_dir.is_empty() || !root.exists() {
+        return Ok(0);
+    }
+    let mut freed = 0;
+    for entry in
+        std::fs::read_dir(root).map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?
+    {
+        let entry = entry.map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?;
+        if entry.file_name().to_string_lossy().ends_with(".tmp") {
+            continue;
+        }
+        let size = dir_size(&entry.path()) + entry.metadata().map(|v| v.len()).unwrap_or(0);
+        // 缓存先写入临时目录再改名，删除不会影响正在读取的任务
+        remove_cache_entry(&entry.path()).map_err(|e| {
+            anyhow!(
+                "Failed to remove compile cache {}: {}",
+                entry.path().display(),
+                e
+            )
+        })?;
+        freed += size;
+    }
+    return Ok(freed);
+}
+/// 在working_dir下编译提交的全部程序，不汇报状态，返回最后编译的程序的下标与编译结果
+/// 任意一个程序编译失败时停止，此时返回的即为失败的程序
+pub async fn compile_submission(
+    app: &AppState,
+    session: &dyn Session,
+    working_dir: &Path,
+    sid: i64,
+    sub_info: &SubmissionInfo,
+    lang_config: &LanguageConfig,
+    // 编译所在的镜像，用于区分编译缓存
+    docker_image: &str,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<(usize, ExecuteResult)> {
+    // jvm语言的编译器同样运行在虚拟机中，堆大小需为虚拟机本身留出内存
+    let lang_config =
+        &lang_config.with_container_limit(&app.config, COMPILE_MEMORY_LIMIT.as_bytes());
+    for file in problem_data.provides.iter() {
+        tokio::fs::copy(this_problem_path.join(file), working_dir.join(file))
+            .await
+            .map_err(|e| anyhow!("Failed to copy compile-time provided file: {}, {}", file, e))?;
+    }
+    // 预先编译的grader目标文件，代替grader源文件参与编译
+    let grader_objects =
+        if problem_data.compile_with_provides && !problem_data.grader_object_command.is_empty() {
+            let graders = grader_sources(lang_config, "", problem_data)
+                .into_iter()
+                .skip(1)
+                .collect::<Vec<String>>();
+            if graders.is_empty() {
+                None
+            } else {
+                Some(
+                    precompile_graders(
+                        app,
+                        session,
+                        working_dir,
+                        &sub_info.language,
+                        docker_image,
+                        &graders,
+                        problem_data,
+                        &this_problem_path.join(COMPILE_CACHE_DIR),
+                        extra_config,
+                    )
+                    .await?,
+                )
+            }
+        } else {
+            None
+        };
+    let program_codes = sub_info.program_codes();
+    let mut execute_result = None;
+    // 多文件提交(通信题)依次编译每个程序，任意一个失败即为编译错误
+    for (index, code) in program_codes.iter().enumerate() {
+        let program_name = program_filename(index);
+        let app_source_file_name = lang_config.source(&program_name);
+        let app_output_file_name = lang_config.output(&program_name);
+        tokio::fs::write(working_dir.join(&app_source_file_name), code)
+            .await
+            .map_err(|e| anyhow!("Failed to write code: {}", e))?;
+        let compile_cmdline = if problem_data.compile_with_provides {
+            let sources = match grader_objects.as_ref() {
+                Some(objects) => std::iter::once(app_source_file_name.clone())
+                    .chain(objects.iter().cloned())
+                    .collect(),
+                None => grader_sources(lang_config, &app_source_file_name, problem_data),
+            };
+            lang_config.compile_multi_s(
+                &problem_data.grader_compile_command,
+                &sources,
+                &app_output_file_name,
+                &extra_config.extra_compile_parameter,
+            )
+        } else {
+            lang_config.compile_s(
+                &app_source_file_name,
+                &app_output_file_name,
+                &extra_config.extra_compile_parameter,
+            )
+        };
+        let compile_cmdline = compile_cmdline
+            .split_ascii_whitespace()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>();
+        // 解释型语言的编译产物即为源代码，无需缓存
+        let cache_key = if app.config.compile_cache_dir.is_empty()
+            || app_output_file_name == app_source_file_name
+        {
+            None
+        } else {
+            Some(
+                compile_cache_key(
+                    &app.config,
+                    docker_image,
+                    &sub_info.language,
+                    &compile_cmdline,
+                    code,
+                    working_dir,
+                    problem_data,
+                )
+                .await?,
+            )
+        };
+        let output_path = working_dir.join(&app_output_file_name);
+        if let Some(key) = cache_key.as_ref() {
+            if load_compile_cache(&app.config, key, working_dir).await {
+                info!("Using cached compile result: {}", key);
+                execute_result = Some((
+                    index,
+                    ExecuteResult {
+                        exit_code: 0,
+                        time_cost: 0,
+                        startup_overhead: 0,
+                        cpu_time_cost: 0,
+                        memory_cost: 0,
+                        output: "命中编译缓存".to_string(),
+                        output_truncated: false,
+                        stderr: String::new(),
+                        warnings: vec![],
+                        killed_by_watchdog: false,
+                        oom_killed: false,
+                        timeline: None,
+                        pids_limit_exceeded: false,
+                        workdir_size_limit_exceeded: false,
+                    },
+                ));
+                continue;
+            }
+        }
+        // 编译前的文件，用于找出全部编译产物
+        let before = cache_key.as_ref().map(|_| snapshot_files(working_dir));
+        info!("Compiling user program: {:?}", compile_cmdline);
+        let result = session
+            .execute(
+                &compile_cmdline,
+                COMPILE_MEMORY_LIMIT.as_bytes(),
+                extra_config.compile_time_limit.as_micros(),
+                extra_config.compile_result_length_limit as usize,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to compile your program: {}", e))?;
+        info!("Compile result:\n{:#?}", result);
+        let failed = result.exit_code != 0;
+        if let (Some(key), Some(before)) = (cache_key.as_ref(), before.as_ref()) {
+            if !failed && output_path.is_file() {
+                let outputs = compile_outputs(before, working_dir, &app_source_file_name);
+                if let Err(e) =
+                    store_compile_cache(&app.config, key, working_dir, &outputs, sid).await
+                {
+                    error!("Failed to store compile cache: {}", e);
+                }
+            }
+        }
+        execute_result = Some((index, result));
+        if failed {
+            break;
+        }
+    }
+    return execute_result.ok_or(anyhow!("No program to compile"));
+}
 pub async fn compile_program(
     app: &AppState,
+    session: &dyn Session,
     working_dir: &Path,
     sid: i64,
     sub_info: &SubmissionInfo,
     lang_config: &LanguageConfig,
+    // 编译所在的镜像，用于区分编译缓存
+    docker_image: &str,
     problem_data: &ProblemInfo,
     this_problem_path: &Path,
     extra_config: &ExtraJudgeConfig,
@@ -36,43 +571,38 @@ pub async fn compile_program(
         sid,
     )
     .await;
-    let app_source_file_name = lang_config.source(DEFAULT_PROGRAM_FILENAME);
-    let app_output_file_name = lang_config.output(DEFAULT_PROGRAM_FILENAME);
-    tokio::fs::write(working_dir.join(&app_source_file_name), &sub_info.code)
-        .await
-        .map_err(|e| anyhow!("Failed to write code: {}", e))?;
-    for file in problem_data.provides.iter() {
-        tokio::fs::copy(this_problem_path.join(file), working_dir.join(file))
-            .await
-            .map_err(|e| anyhow!("Failed to copy compile-time provided file: {}, {}", file, e))?;
-    }
-    let compile_cmdline = lang_config
-        .compile_s(
-            &app_source_file_name,
-            &app_output_file_name,
-            &extra_config.extra_compile_parameter,
-        )
-        .split_ascii_whitespace()
-        .map(|v| v.to_string())
-        .collect::<Vec<String>>();
-    info!("Compiling user program: {:?}", compile_cmdline);
-    let execute_result = execute_in_docker(
-        &app.config.docker_image,
-        working_dir.to_str().ok_or(anyhow!("?"))?,
-        &compile_cmdline,
-        2048 * 1024 * 1024,
-        extra_config.compile_time_limit * 1000,
-        extra_config.compile_result_length_limit as usize,
+    publish_event(
+        app,
+        sid,
+        "compiling",
+        json!({ "language": sub_info.language }),
     )
-    .await
-    .map_err(|e| anyhow!("Failed to compile your program: {}", e))?;
-    info!("Compile result:\n{:#?}", execute_result);
+    .await;
+    let (index, execute_result) = compile_submission(
+        app,
+        session,
+        working_dir,
+        sid,
+        sub_info,
+        lang_config,
+        docker_image,
+        problem_data,
+        this_problem_path,
+        extra_config,
+    )
+    .await?;
+    let program_codes = sub_info.program_codes();
     if execute_result.exit_code != 0 {
         update_status(
             app,
             &SubmissionJudgeResult::default(),
             &format!(
-                "{}{}\nTime usage: {} ms\nMemory usage: {} bytes\nExit code: {}",
+                "{}{}{}\nTime usage: {} ms\nMemory usage: {} bytes\nExit code: {}",
+                if program_codes.len() > 1 {
+                    format!("Program {}:\n", index + 1)
+                } else {
+                    "".to_string()
+                },
                 execute_result.output,
                 if execute_result.output_truncated {
                     "[Truncated]"
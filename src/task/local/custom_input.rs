@@ -0,0 +1,322 @@
+use anyhow::anyhow;
+use celery::{prelude::TaskError, task::TaskResult};
+use log::{error, info};
+use serde_json::{json, Value};
+use tokio::io::AsyncReadExt;
+
+use crate::core::{
+    logging::{with_log_context, LogContext},
+    misc::{AsyncStatusUpdater, ResultType},
+    runner::{open_session, seccomp::seccomp_security_opt, ExecuteOptions, ExecuteResult},
+    state::{AppState, GLOBAL_APP_STATE},
+    util::{get_language_config, with_retry},
+    workdir::create_work_dir,
+};
+
+use super::{
+    api::RunStatusUpdate,
+    compile::compile_submission,
+    executor::parse_submission,
+    model::ExtraJudgeConfig,
+    program_filename,
+    sql::SQL_PROBLEM_TYPE,
+    util::{
+        container_memory_limit, io_limit, language_runtime, memory_limit_exceeded, pids_limit,
+        resolve_problem_data, resource_mounts, sync_problem_files, workdir_size_limit,
+    },
+};
+
+// 返回给服务端的标准输出长度上限(字节)，超出部分截断
+const CUSTOM_INPUT_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+pub async fn update_custom_input_status(
+    app: &AppState,
+    run_id: &str,
+    status: &str,
+    message: &str,
+    result: Option<&Value>,
+) {
+    let result = result.map(|v| v.to_string()).unwrap_or_default();
+    if !app.report_status {
+        info!(
+            "Custom input run {}: {} {} {}",
+            run_id, status, message, result
+        );
+        return;
+    }
+    let update = RunStatusUpdate {
+        run_id,
+        status,
+        message,
+        result: &result,
+    };
+    if let Err(e) = with_retry(&app.config, "update_custom_input", || {
+        app.api.update_custom_input(&update)
+    })
+    .await
+    {
+        error!("Failed to report custom input run status: {}", e);
+    }
+}
+
+struct RunStatusUpdater<'a> {
+    pub run_id: &'a str,
+}
+#[async_trait::async_trait]
+impl<'a> AsyncStatusUpdater for RunStatusUpdater<'a> {
+    async fn update(&self, message: &str) {
+        let guard = GLOBAL_APP_STATE.read().await;
+        let app_state_guard = guard.as_ref().unwrap();
+        update_custom_input_status(app_state_guard, self.run_id, "running", message, None).await;
+    }
+}
+
+/// 编译已有的提交，并以给定的输入数据在题目的限制下运行(用于hack)，运行结果汇报给服务端
+/// subtask为使用其时间与内存限制的子任务，为空时使用第一个子任务
+#[celery::task(name = "judgers.local.run_custom_input")]
+pub async fn run_custom_input_handler(
+    run_id: String,
+    submission_data: Value,
+    input: String,
+    subtask: String,
+    extra_config: ExtraJudgeConfig,
+) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app_state_guard = guard.as_ref().unwrap();
+    let _semaphore_guard = app_state_guard.task_count_lock.acquire().await.unwrap();
+    let context = LogContext {
+        run_id: Some(run_id.clone()),
+        ..Default::default()
+    };
+    if let Err(e) = with_log_context(
+        context,
+        handle(
+            app_state_guard,
+            &run_id,
+            submission_data,
+            &input,
+            &subtask,
+            &extra_config,
+        ),
+    )
+    .await
+    {
+        let err_str = e.to_string();
+        update_custom_input_status(app_state_guard, &run_id, "done", &err_str, None).await;
+        return Err(TaskError::UnexpectedError(err_str));
+    }
+    return Ok(());
+}
+
+/// 运行结果的状态: ok、time_limit_exceed、memory_limit_exceed或runtime_error
+pub fn run_status(
+    run_result: &ExecuteResult,
+    memory_limit: i64,
+    time_limit: i64,
+    wall_time_limit: i64,
+) -> &'static str {
+    if run_result.oom_killed || run_result.memory_cost >= memory_limit {
+        return "memory_limit_exceed";
+    }
+    if run_result.cpu_time_cost >= time_limit
+        || run_result.killed_by_watchdog
+        || run_result.time_cost >= wall_time_limit
+    {
+        return "time_limit_exceed";
+    }
+    if run_result.workdir_size_limit_exceeded || run_result.exit_code == 128 + libc::SIGXFSZ {
+        return "output_size_limit_exceed";
+    }
+    if run_result.exit_code != 0 {
+        return "runtime_error";
+    }
+    return "ok";
+}
+
+async fn handle(
+    app: &AppState,
+    run_id: &str,
+    submission_data: Value,
+    input: &str,
+    subtask: &str,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<()> {
+    let sub_info = parse_submission(submission_data, extra_config)?;
+    info!(
+        "Received custom input run {} of submission {}",
+        run_id, sub_info.id
+    );
+    if !sub_info.extra_codes.is_empty() {
+        return Err(anyhow!(
+            "Custom input is not supported for multi-program submissions"
+        ));
+    }
+    let problem_data =
+        resolve_problem_data(app, sub_info.problem_id, extra_config.problem_info.as_ref()).await?;
+    // 交互题、通信题与SQL题的运行需要题目提供的其他程序或数据
+    if ["interactive", "communication", SQL_PROBLEM_TYPE]
+        .contains(&problem_data.problem_type.as_str())
+        || extra_config.submit_answer
+    {
+        return Err(anyhow!(
+            "Custom input is not supported for {} problems",
+            problem_data.problem_type
+        ));
+    }
+    let limits = if subtask.is_empty() {
+        problem_data.subtasks.first()
+    } else {
+        problem_data.subtasks.iter().find(|v| v.name == subtask)
+    }
+    .ok_or(anyhow!("Subtask not found: {}", subtask))?;
+    let this_problem_path = app.testdata_dir.join(problem_data.id.to_string());
+    // 只需要编译时使用的provides
+    if extra_config.auto_sync_files && !problem_data.provides.is_empty() {
+        sync_problem_files(
+            problem_data.id,
+            Some(&problem_data.provides),
+            &RunStatusUpdater { run_id },
+            app,
+        )
+        .await
+        .map_err(|e| anyhow!("Error occurred when syncing problem files:\n{}", e))?;
+    }
+    let working_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+    let working_dir_path = working_dir.path();
+    let lang_config = get_language_config(app, &sub_info.language)
+        .await
+        .map_err(|e| anyhow!("Failed to download language definition: {}", e))?;
+    let (user_image, lang_config) =
+        language_runtime(&app.config, &problem_data, &sub_info.language, lang_config);
+    let seccomp = seccomp_security_opt(&app.config, &sub_info.language)?;
+    let options = ExecuteOptions {
+        readonly_mounts: resource_mounts(&problem_data, this_problem_path.as_path())?,
+        io_limit: io_limit(&app.config, &problem_data),
+        pids_limit: pids_limit(&app.config, &problem_data),
+        workdir_size_limit: workdir_size_limit(&app.config, extra_config),
+        file_size_limit: extra_config.output_file_size_limit,
+        detect_suspicious: app.config.detect_suspicious_processes,
+        seccomp,
+        ..Default::default()
+    };
+    let session = open_session(
+        &app.runner,
+        &user_image,
+        working_dir_path.to_str().ok_or(anyhow!("?"))?,
+        &options,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to create sandbox session: {}", e))?;
+    let result = async {
+        update_custom_input_status(app, run_id, "running", "Compiling..", None).await;
+        let (_, compile_result) = compile_submission(
+            app,
+            session.as_ref(),
+            working_dir_path,
+            sub_info.id,
+            &sub_info,
+            &lang_config,
+            &user_image,
+            &problem_data,
+            this_problem_path.as_path(),
+            extra_config,
+        )
+        .await?;
+        if compile_result.exit_code != 0 {
+            return Ok(json!({
+                "status": "compile_error",
+                "message": compile_result.output,
+            }));
+        }
+        let file_io = problem_data.using_file_io == 1;
+        let (input_file, output_file) = if file_io {
+            (
+                problem_data.input_file_name.as_str(),
+                problem_data.output_file_name.as_str(),
+            )
+        } else {
+            ("in", "out")
+        };
+        tokio::fs::write(working_dir_path.join(input_file), input)
+            .await
+            .map_err(|e| anyhow!("Failed to write input data: {}", e))?;
+        let lang_config = lang_config.with_memory_limit(limits.memory_limit.as_bytes());
+        let container_limit =
+            container_memory_limit(&app.config, &lang_config, limits.memory_limit.as_bytes());
+        let time_scale = extra_config.time_scale.unwrap_or(app.time_scale);
+        let time_limit = limits.time_limit.scale(time_scale);
+        let wall_time_limit = time_limit.scale(app.config.wall_time_limit_factor);
+        let run_cmdline = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            lang_config.run_s(
+                &lang_config.output(&program_filename(0)),
+                &(if file_io {
+                    "".to_string()
+                } else {
+                    format!("< {} > {}", input_file, output_file)
+                }),
+            ),
+        ];
+        info!("Run with: {:?}", run_cmdline);
+        update_custom_input_status(app, run_id, "running", "Running..", None).await;
+        let run_result = session
+            .execute(
+                &run_cmdline,
+                container_limit,
+                wall_time_limit.as_micros(),
+                1000,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run: {}", e))?;
+        info!("Run result:\n{:#?}", run_result);
+        let (output, output_truncated) =
+            match tokio::fs::File::open(working_dir_path.join(output_file)).await {
+                Ok(mut file) => {
+                    let mut buf = vec![];
+                    (&mut file)
+                        .take(CUSTOM_INPUT_OUTPUT_LIMIT as u64 + 1)
+                        .read_to_end(&mut buf)
+                        .await
+                        .map_err(|e| anyhow!("Failed to read output: {}", e))?;
+                    let truncated = buf.len() > CUSTOM_INPUT_OUTPUT_LIMIT;
+                    buf.truncate(CUSTOM_INPUT_OUTPUT_LIMIT);
+                    (String::from_utf8_lossy(&buf).to_string(), truncated)
+                }
+                Err(_) => (String::new(), false),
+            };
+        let status = if memory_limit_exceeded(
+            &app.config,
+            &lang_config,
+            &run_result,
+            limits.memory_limit.as_bytes(),
+        ) {
+            "memory_limit_exceed"
+        } else {
+            run_status(
+                &run_result,
+                container_limit,
+                time_limit.as_micros(),
+                wall_time_limit.as_micros(),
+            )
+        };
+        return ResultType::<Value>::Ok(json!({
+            "status": status,
+            "exit_code": run_result.exit_code,
+            "time_cost": (run_result.cpu_time_cost as f64 / 1000.0).ceil() as i64,
+            "memory_cost": run_result.memory_cost,
+            "output": output,
+            "output_truncated": output_truncated,
+            "stderr": run_result.stderr,
+        }));
+    }
+    .await;
+    if let Err(e) = session.close().await {
+        error!("Failed to close sandbox session: {}", e);
+    }
+    let result = result?;
+    update_custom_input_status(app, run_id, "done", "运行完成", Some(&result)).await;
+    info!("Custom input run done: {}", run_id);
+    return Ok(());
+}
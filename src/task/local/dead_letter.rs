@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use log::{error, info, warn};
+use serde_json::{json, Value};
+
+use crate::core::{
+    misc::ResultType,
+    state::AppState,
+    webhook::{notify_webhook, EVENT_DEAD_LETTER},
+};
+
+use super::{model::ExtraJudgeConfig, util::update_status};
+
+// 死信队列最多保留的记录数
+const DEAD_LETTER_MAX_LENGTH: isize = 10000;
+
+/// 评测失败的原因
+pub struct JudgeFailure {
+    pub message: String,
+    // 评测机或基础设施(docker、网络、同步、磁盘)的问题，重试可能成功；由提交或题目数据导致的错误不重试
+    pub retryable: bool,
+}
+
+/// 一次评测失败后的处理方式
+pub enum FailureAction {
+    // 在指定秒数后重新评测
+    Retry(u32),
+    // 已达到最大尝试次数，不再重试
+    GiveUp,
+}
+
+/// 将失败记录写入broker上的死信队列(Redis list)，最新的记录在表头
+async fn push_dead_letter(app: &AppState, record: &Value) -> ResultType<()> {
+    let client = redis::Client::open(app.config.broker_url.as_str())
+        .map_err(|e| anyhow!("Invalid broker url: {}", e))?;
+    let mut conn = client
+        .get_async_connection()
+        .await
+        .map_err(|e| anyhow!("Failed to connect to redis: {}", e))?;
+    redis::pipe()
+        .cmd("LPUSH")
+        .arg(&app.config.dead_letter_queue)
+        .arg(record.to_string())
+        .ignore()
+        .cmd("LTRIM")
+        .arg(&app.config.dead_letter_queue)
+        .arg(0)
+        .arg(DEAD_LETTER_MAX_LENGTH - 1)
+        .ignore()
+        .query_async::<_, ()>(&mut conn)
+        .await
+        .map_err(|e| anyhow!("Failed to push dead letter: {}", e))?;
+    return Ok(());
+}
+
+/// 第attempt次(从1开始)评测失败后调用，未达到judge_max_attempts时返回重试，否则写入死信队列并报告最终状态
+/// 不可重试的错误已在评测结束时报告，直接放弃
+pub async fn handle_judge_failure(
+    app: &AppState,
+    attempt: u32,
+    submission_data: &Value,
+    extra_config: &ExtraJudgeConfig,
+    failure: &JudgeFailure,
+) -> FailureAction {
+    let sid = submission_data.pointer("/id").and_then(|v| v.as_i64());
+    let message = failure.message.as_str();
+    if !failure.retryable {
+        info!(
+            "Judging {:?} failed due to the submission or problem data, not retrying: {}",
+            sid, message
+        );
+        return FailureAction::GiveUp;
+    }
+    if attempt < app.config.judge_max_attempts {
+        let delay = app.config.judge_retry_delay;
+        warn!(
+            "Judging {:?} failed (attempt {}/{}), retrying in {}s: {}",
+            sid, attempt, app.config.judge_max_attempts, delay, message
+        );
+        if let Some(sid) = sid {
+            update_status(
+                app,
+                &BTreeMap::new(),
+                &format!(
+                    "{}\n评测失败，将在{}秒后重试 ({}/{})",
+                    message, delay, attempt, app.config.judge_max_attempts
+                ),
+                None,
+                sid,
+            )
+            .await;
+        }
+        return FailureAction::Retry(delay);
+    }
+    error!(
+        "Judging {:?} failed after {} attempts: {}",
+        sid, attempt, message
+    );
+    // 影子评测不影响线上，不写入死信队列
+    if !app.report_status {
+        return FailureAction::GiveUp;
+    }
+    let record = json!({
+        "submission_id": sid,
+        "attempts": attempt,
+        "error": message,
+        "judger": app.version_string,
+        "time": chrono::Local::now().timestamp_millis(),
+        "submission_data": submission_data,
+        "extra_config": extra_config,
+    });
+    if !app.config.dead_letter_queue.is_empty() {
+        match push_dead_letter(app, &record).await {
+            Ok(_) => info!(
+                "Submission {:?} pushed to dead letter queue {}",
+                sid, app.config.dead_letter_queue
+            ),
+            Err(e) => error!("{}", e),
+        }
+    }
+    notify_webhook(
+        app,
+        EVENT_DEAD_LETTER,
+        json!({ "submission_id": sid, "attempts": attempt, "message": message }),
+    );
+    if let Some(sid) = sid {
+        update_status(
+            app,
+            &BTreeMap::new(),
+            &format!("{}\n评测失败，已通知管理员", message),
+            None,
+            sid,
+        )
+        .await;
+    }
+    return FailureAction::GiveUp;
+}
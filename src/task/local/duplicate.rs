@@ -0,0 +1,112 @@
+use std::{collections::HashMap, path::Path, time::Instant};
+
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+
+use crate::core::{model::LanguageConfig, state::AppState, upload::sha256_hex};
+
+use super::{
+    model::{ExtraJudgeConfig, ProblemInfo, SubmissionInfo, SubmissionJudgeResult},
+    sync_state::SyncManifest,
+};
+
+#[derive(Clone)]
+pub struct RecentVerdict {
+    pub submission_id: i64,
+    pub finished_at: Instant,
+    pub judge_result: SubmissionJudgeResult,
+    pub message: String,
+    // ICPC模式下的提交结果
+    pub extra_status: Option<String>,
+}
+
+lazy_static! {
+    // 最近评测完成的提交，键为duplicate_key
+    static ref RECENT_VERDICTS: Mutex<HashMap<String, RecentVerdict>> =
+        Mutex::new(HashMap::new());
+}
+
+/// 同一用户、同一比赛与题目、相同的评测设置(编译参数、禁止规则等)、题目数据与运行环境下的相同代码具有相同的键
+pub async fn duplicate_key(
+    sub_info: &SubmissionInfo,
+    extra_config: &ExtraJudgeConfig,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    image: &str,
+    lang_config: &LanguageConfig,
+) -> String {
+    // 重新入队的次数等不影响评测结果的字段不计入
+    let mut judge_config = serde_json::to_value(extra_config).unwrap_or_default();
+    if let Some(v) = judge_config.as_object_mut() {
+        v.remove("requeue_count");
+        v.remove("excluded_judgers");
+    }
+    // 题目数据更新后同步记录随之改变
+    let manifest = SyncManifest::load(this_problem_path).await;
+    let mut key_data = vec![];
+    for part in [
+        sub_info.uid.to_string(),
+        sub_info.problem_id.to_string(),
+        sub_info.contest_id.to_string(),
+        format!("{:?}", sub_info.virtual_contest_id),
+        sub_info.language.clone(),
+        format!("{:?}", sub_info.selected_compile_parameters),
+        judge_config.to_string(),
+        serde_json::to_string(problem_data).unwrap_or_default(),
+        serde_json::to_string(&manifest).unwrap_or_default(),
+        image.to_string(),
+        serde_json::to_string(lang_config).unwrap_or_default(),
+    ] {
+        key_data.extend_from_slice(part.as_bytes());
+        key_data.push(0);
+    }
+    for code in sub_info.program_codes() {
+        key_data.extend_from_slice(code.as_bytes());
+        key_data.push(0);
+    }
+    return sha256_hex(&key_data);
+}
+
+/// duplicate_submission_window内其他提交的评测结果，重测同一个提交时不会命中
+pub async fn find_recent_verdict(
+    app: &AppState,
+    key: &str,
+    submission_id: i64,
+) -> Option<RecentVerdict> {
+    let window = app.config.duplicate_submission_window;
+    if window == 0 {
+        return None;
+    }
+    let verdicts = RECENT_VERDICTS.lock().await;
+    return verdicts
+        .get(key)
+        .filter(|v| v.submission_id != submission_id && v.finished_at.elapsed().as_secs() < window)
+        .cloned();
+}
+
+/// 记录评测结果，同时清理过期的记录
+pub async fn record_verdict(
+    app: &AppState,
+    key: String,
+    submission_id: i64,
+    judge_result: &SubmissionJudgeResult,
+    message: &str,
+    extra_status: Option<&str>,
+) {
+    let window = app.config.duplicate_submission_window;
+    if window == 0 {
+        return;
+    }
+    let mut verdicts = RECENT_VERDICTS.lock().await;
+    verdicts.retain(|_, v| v.finished_at.elapsed().as_secs() < window);
+    verdicts.insert(
+        key,
+        RecentVerdict {
+            submission_id,
+            finished_at: Instant::now(),
+            judge_result: judge_result.clone(),
+            message: message.to_string(),
+            extra_status: extra_status.map(|v| v.to_string()),
+        },
+    );
+}
@@ -1,140 +1,465 @@
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
-    sync::Arc,
+    path::Path,
 };
 
-use async_zip::read::mem::ZipFileReader;
-use celery::{prelude::TaskError, task::TaskResult};
+use celery::{
+    prelude::TaskError,
+    task::{Task, TaskResult},
+};
+use futures_util::StreamExt;
 use lazy_static::lazy_static;
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use regex::Regex;
-use serde_json::Value;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
 
 use crate::{
     core::{
-        compare::{simple::SimpleLineComparator, special::SpecialJudgeComparator, Comparator},
-        misc::ResultType,
+        archive::extract_to_memory,
+        compare::{
+            builtin_comparator,
+            special::{SpecialJudgeComparator, SpjScoreScale},
+            Comparator,
+        },
+        events::publish_event,
+        interactor::{special::SpecialInteractor, Interactor},
+        logging::{with_log_context, LogContext},
+        misc::{input_error, wrap_error, AsyncStatusUpdater, InputError, ResultType},
+        plugin::{run_post_judge, run_pre_compile, JudgeSummary},
+        process_stats::log_process_stats,
+        runner::{open_session, seccomp::seccomp_security_opt, ExecuteOptions},
+        scan::{check_submission_text, scan_code, ForbiddenRule, ScanResult},
         state::{AppState, GLOBAL_APP_STATE},
         util::get_language_config,
+        watermark::Watermark,
+        webhook::{notify_webhook, EVENT_TASK_FAILED},
+        workdir::create_work_dir,
     },
     task::local::{
+        cancel::{clear_cancel, was_cancelled},
         compile::compile_program,
-        model::{SubmissionInfo, SubmissionSubtaskResult, SubmissionTestcaseResult},
-        submit_answer::handle_submit_answer,
-        traditional::handle_traditional,
-        util::{get_problem_data, sync_problem_files},
+        dead_letter::{handle_judge_failure, FailureAction, JudgeFailure},
+        duplicate::{duplicate_key, find_recent_verdict, record_verdict, RecentVerdict},
+        janitor::use_problem,
+        journal::write_journal,
+        model::{
+            ProblemInfo, ProblemSubtask, SubmissionInfo, SubmissionSubtaskResult,
+            SubmissionTestcaseResult,
+        },
+        notebook::extract_notebook_code,
+        post_judge::run_post_judge_script,
+        report::{forget_reported, mark_dirty},
+        requeue::{check_image_capability, check_task_capability, requeue_task, CapabilityError},
+        shadow::finish_shadow_judge,
+        subtask::{judge_subtask, publish_testcase_event},
+        timing::{forget_timing, now_millis, queue_wait, record_timing, start_timing},
+        traditional::{check_testcase_outputs, TestcaseContext},
+        util::{
+            icpc_mode, io_limit, language_runtime, pids_limit, resolve_problem_data,
+            resource_mounts, runtime_image, sync_problem_files, COMPILE_CACHE_DIR,
+        },
     },
 };
 
 use super::{
     compile::CompileResult,
+    message::{build_status_message, MessageSection},
     model::{ExtraJudgeConfig, SubmissionJudgeResult},
-    util::{update_status, AsyncStatusUpdater},
+    submit_answer::{load_answer_scoring, AnswerScoring, ANSWER_SCORING_FILE},
+    util::update_status,
 };
 use anyhow::anyhow;
-#[celery::task(name = "judgers.local.run")]
+#[celery::task(name = "judgers.local.run", bind = true)]
 pub async fn local_judge_task_handler(
+    task: &Self,
     submission_data: Value,
     extra_config: ExtraJudgeConfig,
 ) -> TaskResult<()> {
     let guard = GLOBAL_APP_STATE.read().await;
     let app_state_guard = guard.as_ref().unwrap();
+    let received_at = now_millis();
     let _semaphore_guard = app_state_guard.task_count_lock.acquire().await.unwrap();
-    let sid = submission_data.pointer("/id").unwrap().as_i64().unwrap();
-    if let Err(e) = handle(submission_data, extra_config, app_state_guard).await {
-        let err_str = format!("{}", e,);
-        update_status(app_state_guard, &BTreeMap::new(), &err_str, None, sid).await;
-        return Err(TaskError::UnexpectedError(err_str.clone()));
+    let err = match judge_submission(
+        app_state_guard,
+        submission_data.clone(),
+        extra_config.clone(),
+        received_at,
+    )
+    .await
+    {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    let attempt = task.request().retries + 1;
+    return match handle_judge_failure(
+        app_state_guard,
+        attempt,
+        &submission_data,
+        &extra_config,
+        &err,
+    )
+    .await
+    {
+        FailureAction::Retry(delay) => task.retry_with_countdown(delay),
+        FailureAction::GiveUp => Err(TaskError::UnexpectedError(err.message)),
+    };
+}
+/// 评测一个提交，出错时向服务端报告错误信息并返回
+/// received_at为收到任务的时间，用于计算等待评测名额的时间
+pub async fn judge_submission(
+    app: &AppState,
+    submission_data: Value,
+    extra_config: ExtraJudgeConfig,
+    received_at: i64,
+) -> Result<(), JudgeFailure> {
+    let sid = submission_data
+        .pointer("/id")
+        .and_then(|v| v.as_i64())
+        .ok_or(JudgeFailure {
+            message: "Missing submission id".to_string(),
+            retryable: false,
+        })?;
+    if let Err(e) = write_journal(app, sid, &submission_data, &extra_config).await {
+        error!("Failed to journal submission {}: {}", sid, e);
     }
-    return Ok(());
+    let context = LogContext {
+        submission_id: Some(sid),
+        ..Default::default()
+    };
+    if app.config.log_phase_process_stats {
+        log_process_stats(app, &format!("submission {} started", sid));
+    }
+    start_timing(sid, received_at);
+    if let Some(wait) = queue_wait(sid) {
+        info!("Submission {} waited {} ms for a judging slot", sid, wait);
+    }
+    let result = with_log_context(
+        context,
+        handle(submission_data.clone(), extra_config.clone(), app),
+    )
+    .await;
+    forget_reported(sid).await;
+    clear_cancel(app, sid).await;
+    if app.config.shadow_mode {
+        finish_shadow_judge(app, sid).await;
+    }
+    if app.config.log_phase_process_stats {
+        log_process_stats(app, &format!("submission {} finished", sid));
+    }
+    let outcome = match result {
+        Ok(_) => Ok(()),
+        Err(e) => report_failure(app, sid, &submission_data, &extra_config, e).await,
+    };
+    // 出错时汇报的状态中同样带有评测耗时，之后才清除
+    forget_timing(sid);
+    return outcome;
+}
+
+/// 评测出错时转交其他评测机或向服务端报告错误信息
+async fn report_failure(
+    app: &AppState,
+    sid: i64,
+    submission_data: &Value,
+    extra_config: &ExtraJudgeConfig,
+    e: anyhow::Error,
+) -> Result<(), JudgeFailure> {
+    if let Some(reason) = e.downcast_ref::<CapabilityError>() {
+        match requeue_task(app, submission_data, extra_config).await {
+            Ok(true) => {
+                update_status(
+                    app,
+                    &BTreeMap::new(),
+                    &format!("{}\n已转交其他评测机", reason),
+                    None,
+                    sid,
+                )
+                .await;
+                publish_event(
+                    app,
+                    sid,
+                    "requeued",
+                    json!({ "message": reason.to_string(), "queue_wait": queue_wait(sid) }),
+                )
+                .await;
+                return Ok(());
+            }
+            Ok(false) => {}
+            Err(e) => error!("{}", e),
+        }
+    }
+    let err_str = format!("{}", e,);
+    update_status(app, &BTreeMap::new(), &err_str, None, sid).await;
+    publish_event(
+        app,
+        sid,
+        "failed",
+        json!({ "message": err_str, "queue_wait": queue_wait(sid) }),
+    )
+    .await;
+    notify_webhook(
+        app,
+        EVENT_TASK_FAILED,
+        json!({ "submission_id": sid, "message": err_str }),
+    );
+    // 评测失败同样是提交的最终结果，插件需要看到每一个结束的提交
+    run_post_judge(
+        app,
+        &JudgeSummary {
+            submission_id: sid,
+            problem_id: submission_data
+                .pointer("/problem_id")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(0),
+            status: "judge_failed".to_string(),
+            compile_error: false,
+            score: 0,
+            judge_result: json!({}),
+        },
+    )
+    .await;
+    return Err(JudgeFailure {
+        message: err_str,
+        retryable: !e.is::<InputError>(),
+    });
+}
+/// 题目使用的比较器: SPJ(编译后缓存在题目数据目录下)或内置的比较器
+pub async fn build_comparator(
+    app: &AppState,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<Box<dyn Comparator>> {
+    let comparator: Box<dyn Comparator> = if &problem_data.spj_filename != "" {
+        let spj_filename = &problem_data.spj_filename;
+        info!("SPJ filename: {}", spj_filename);
+        let spj_file = this_problem_path.join(spj_filename);
+        lazy_static! {
+            static ref SPJ_FILENAME_REGEX: Regex = Regex::new(r#"spj_(.+)\..*"#).unwrap();
+        };
+        let spj_name_match = SPJ_FILENAME_REGEX
+            .captures(spj_filename)
+            .ok_or(input_error(format!(
+                "Invalid spj filename: {}",
+                spj_filename
+            )))?;
+        let lang = spj_name_match
+            .get(1)
+            .ok_or(anyhow!("Failed to match spjfilename!"))?
+            .as_str();
+        info!("SPJ language: {}", lang);
+        let lang_config = get_language_config(app, lang)
+            .await
+            .map_err(|e| anyhow!("Failed to get spj language definition: {}", e))?;
+        let spj = SpecialJudgeComparator::try_new(
+            spj_file.as_path(),
+            &lang_config,
+            extra_config.spj_execute_time_limit,
+            app.config.docker_image.clone(),
+            app.runner.clone(),
+            create_work_dir(&app.config)
+                .map_err(|e| anyhow!("Failed to create spj working directory: {}", e))?,
+            app.config.spj_feedback_size_limit,
+            &app.config,
+            SpjScoreScale::parse(&problem_data.spj_score_scale)?,
+        )
+        .map_err(|e| anyhow!("Failed to create spj comprator: {}", e))?;
+        spj.compile(app, &this_problem_path.join(COMPILE_CACHE_DIR))
+            .await
+            .map_err(|e| wrap_error(e, "Error occurred when compiling special judge program:\n"))?;
+        Box::new(spj)
+    } else {
+        builtin_comparator(
+            &app.config,
+            &problem_data.comparator,
+            problem_data.case_insensitive,
+        )?
+    };
+    return Ok(comparator);
 }
 pub enum IntermediateValue {
-    SubmitAnswer(HashMap<String, Vec<u8>>),
+    // 用户提交的答案文件与题目的评分文件
+    SubmitAnswer(HashMap<String, Vec<u8>>, Option<AnswerScoring>),
     Traditional(CompileResult),
 }
 impl IntermediateValue {
     pub fn traditional(self) -> Option<CompileResult> {
         match self {
-            IntermediateValue::SubmitAnswer(_) => None,
+            IntermediateValue::SubmitAnswer(..) => None,
             IntermediateValue::Traditional(v) => Some(v),
         }
     }
     pub fn submit_answer(&self) -> Option<&HashMap<String, Vec<u8>>> {
         match self {
-            IntermediateValue::SubmitAnswer(v) => Some(v),
+            IntermediateValue::SubmitAnswer(v, _) => Some(v),
             IntermediateValue::Traditional(_) => None,
         }
     }
+    pub fn answer_scoring(&self) -> Option<&AnswerScoring> {
+        match self {
+            IntermediateValue::SubmitAnswer(_, v) => v.as_ref(),
+            IntermediateValue::Traditional(_) => None,
+        }
+    }
+}
+/// 解析任务中的提交信息，并按code_format取出其中的代码
+pub fn parse_submission(
+    submission_info: Value,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<SubmissionInfo> {
+    let mut sub_info = serde_json::from_value::<SubmissionInfo>(submission_info)
+        .map_err(|e| input_error(format!("Failed to deserialize submission info: {}", e)))?;
+    match extra_config.code_format.as_str() {
+        "" => {}
+        "ipynb" => {
+            sub_info.code =
+                extract_notebook_code(&sub_info.code).map_err(|e| input_error(e.to_string()))?;
+            for code in sub_info.extra_codes.iter_mut() {
+                *code = extract_notebook_code(code).map_err(|e| input_error(e.to_string()))?;
+            }
+        }
+        other => return Err(input_error(format!("Unknown code format: {}", other))),
+    }
+    return Ok(sub_info);
 }
-async fn handle(
+pub async fn handle(
     submission_info: Value,
     extra_config: ExtraJudgeConfig,
     app: &AppState,
 ) -> ResultType<()> {
     debug!("Raw task:\n{:#?}", submission_info);
-    let sub_info = serde_json::from_value::<SubmissionInfo>(submission_info)
-        .map_err(|e| anyhow!("Failed to deserialize submission info: {}", e))?;
+    let sub_info = parse_submission(submission_info, &extra_config)?;
     info!("Received judge task:\n{:#?}", sub_info);
-    let http_client = reqwest::Client::new();
-    let problem_data = get_problem_data(&http_client, app, &sub_info).await?;
+    let sid = sub_info.id.clone();
+    check_task_capability(app, &sub_info.language, &extra_config)?;
+    publish_event(
+        app,
+        sid,
+        "received",
+        json!({ "problem_id": sub_info.problem_id, "language": sub_info.language }),
+    )
+    .await;
+    let problem_data =
+        resolve_problem_data(app, sub_info.problem_id, extra_config.problem_info.as_ref()).await?;
     debug!("Problem info:\n{:#?}", problem_data);
+    check_testcase_outputs(&problem_data)
+        .map_err(|e| input_error(format!("Invalid problem data: {}", e)))?;
     let this_problem_path = app.testdata_dir.join(problem_data.id.to_string());
-    let sid = sub_info.id.clone();
+    let _problem_guard = use_problem(problem_data.id);
+    // 在同步题目数据与编译SPJ之前检查镜像，无法评测时尽早转交
+    check_image_capability(
+        app,
+        &runtime_image(&app.config, &problem_data, &sub_info.language),
+    )
+    .await?;
     if extra_config.auto_sync_files {
+        publish_event(
+            app,
+            sid,
+            "syncing",
+            json!({ "problem_id": problem_data.id }),
+        )
+        .await;
+        record_timing(sid, |v| v.sync_started_at = Some(now_millis()));
         sync_problem_files(
             problem_data.id.clone(),
+            None,
             &MyUpdater {
                 judge_result: &sub_info.judge_result,
                 submission_id: sub_info.id.clone(),
             },
-            &http_client,
             app,
         )
         .await
         .map_err(|e| anyhow!("Error occurred when syncing problem files:\n{}", e))?;
+        record_timing(sid, |v| v.sync_ended_at = Some(now_millis()));
     }
-    if extra_config.submit_answer && problem_data.spj_filename.is_empty() {
-        return Err(anyhow!(
-            "Special judge must be used when using submit-answer problems!"
-        ));
+    // 提交答案题需要使用SPJ，或者提供评分文件
+    let answer_scoring = if extra_config.submit_answer {
+        load_answer_scoring(this_problem_path.as_path(), &problem_data)
+            .await
+            .map_err(|e| input_error(e.to_string()))?
+    } else {
+        None
+    };
+    if extra_config.submit_answer
+        && problem_data.spj_filename.is_empty()
+        && answer_scoring.is_none()
+    {
+        return Err(input_error(format!(
+            "Special judge or {} must be used when using submit-answer problems!",
+            ANSWER_SCORING_FILE
+        )));
     }
-    let comparator: Box<dyn Comparator> = if &problem_data.spj_filename != "" {
-        let spj_filename = &problem_data.spj_filename;
-        info!("SPJ filename: {}", spj_filename);
-        let spj_file = this_problem_path.join(spj_filename);
+    let comparator =
+        build_comparator(app, &problem_data, &this_problem_path, &extra_config).await?;
+    let interactor: Option<Box<dyn Interactor>> = if problem_data.problem_type == "interactive" {
+        if extra_config.submit_answer {
+            return Err(input_error(
+                "Interactive problems can not be submit-answer problems!".to_string(),
+            ));
+        }
+        let interactor_filename = &problem_data.interactor_filename;
+        info!("Interactor filename: {}", interactor_filename);
         lazy_static! {
-            static ref SPJ_FILENAME_REGEX: Regex = Regex::new(r#"spj_(.+)\..*"#).unwrap();
+            static ref INTERACTOR_FILENAME_REGEX: Regex =
+                Regex::new(r#"interactor_(.+)\..*"#).unwrap();
         };
-        let spj_name_match = SPJ_FILENAME_REGEX
-            .captures(spj_filename)
-            .ok_or(anyhow!("Invalid spj filename: {}", spj_filename))?;
-        let lang = spj_name_match
+        let lang = INTERACTOR_FILENAME_REGEX
+            .captures(interactor_filename)
+            .ok_or(input_error(format!(
+                "Invalid interactor filename: {}",
+                interactor_filename
+            )))?
             .get(1)
-            .ok_or(anyhow!("Failed to match spjfilename!"))?
+            .ok_or(anyhow!("Failed to match interactor filename!"))?
             .as_str();
-        info!("SPJ language: {}", lang);
-        let lang_config = get_language_config(app, lang, &http_client)
+        info!("Interactor language: {}", lang);
+        let lang_config = get_language_config(app, lang)
             .await
-            .map_err(|e| anyhow!("Failed to get spj language definition: {}", e))?;
-        let spj = SpecialJudgeComparator::try_new(
-            spj_file.as_path(),
+            .map_err(|e| anyhow!("Failed to get interactor language definition: {}", e))?;
+        let interactor = SpecialInteractor::try_new(
+            this_problem_path.join(interactor_filename).as_path(),
             &lang_config,
-            extra_config.spj_execute_time_limit * 1000,
+            extra_config.spj_execute_time_limit,
             app.config.docker_image.clone(),
+            app.runner.clone(),
+            create_work_dir(&app.config)
+                .map_err(|e| anyhow!("Failed to create interactor working directory: {}", e))?,
+            app.config.interactive_idle_limit,
+            SpjScoreScale::parse(&problem_data.spj_score_scale)?,
+            &app.config,
         )
-        .map_err(|e| anyhow!("Failed to create spj comprator: {}", e))?;
-        spj.compile().await.map_err(|e| {
-            anyhow!(
-                "Error occurred when compiling special judge program:\n{}",
-                e
-            )
-        })?;
-        Box::new(spj)
+        .map_err(|e| anyhow!("Failed to create interactor: {}", e))?;
+        interactor
+            .compile(app, &this_problem_path.join(COMPILE_CACHE_DIR))
+            .await
+            .map_err(|e| wrap_error(e, "Error occurred when compiling interactor:\n"))?;
+        Some(Box::new(interactor))
     } else {
-        Box::new(SimpleLineComparator {})
+        None
     };
-    let working_dir =
-        tempfile::tempdir().map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+    let program_count = sub_info.program_codes().len();
+    if problem_data.problem_type == "communication" {
+        if extra_config.submit_answer {
+            return Err(input_error(
+                "Communication problems can not be submit-answer problems!".to_string(),
+            ));
+        }
+        if program_count != 2 {
+            return Err(input_error(format!(
+                "Communication problems require exactly 2 programs, but got {}",
+                program_count
+            )));
+        }
+    } else if program_count != 1 {
+        return Err(input_error(format!(
+            "Only communication problems accept multiple programs, but got {}",
+            program_count
+        )));
+    }
+    let working_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
     // let s = PathBuf::from("/test");
     let working_dir_path = working_dir.path();
     info!(
@@ -149,24 +474,163 @@ async fn handle(
         sid,
     )
     .await;
-    let lang_config = get_language_config(app, &sub_info.language, &http_client)
+    let lang_config = get_language_config(app, &sub_info.language)
         .await
         .map_err(|e| anyhow!("Failed to download language definition: {}", e))?;
     info!("Language definition:\n{:#?}", lang_config);
+    let (user_image, lang_config) =
+        language_runtime(&app.config, &problem_data, &sub_info.language, lang_config);
+    let verdict_key = duplicate_key(
+        &sub_info,
+        &extra_config,
+        &problem_data,
+        &this_problem_path,
+        &user_image,
+        &lang_config,
+    )
+    .await;
+    let seccomp = seccomp_security_opt(&app.config, &sub_info.language)?;
+    // 提交答案题不运行用户程序，不需要沙箱
+    let session = if !extra_config.submit_answer {
+        let options = ExecuteOptions {
+            readonly_mounts: resource_mounts(&problem_data, this_problem_path.as_path())?,
+            io_limit: io_limit(&app.config, &problem_data),
+            pids_limit: pids_limit(&app.config, &problem_data),
+            detect_suspicious: app.config.detect_suspicious_processes,
+            seccomp: seccomp.clone(),
+            ..Default::default()
+        };
+        Some(
+            open_session(
+                &app.runner,
+                &user_image,
+                working_dir_path.to_str().ok_or(anyhow!("?"))?,
+                &options,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to create sandbox session: {}", e))?,
+        )
+    } else {
+        None
+    };
+    // 命中flag规则时的提示，附加在最终的评测信息中
+    let mut scan_flags = vec![];
     let intermediate_value = if !extra_config.submit_answer {
+        let compile_started_at = now_millis();
+        record_timing(sid, |v| v.compile_started_at = Some(compile_started_at));
+        let rules = app
+            .config
+            .forbidden_rules
+            .iter()
+            .chain(extra_config.forbidden_rules.iter())
+            .cloned()
+            .collect::<Vec<ForbiddenRule>>();
+        let max_code_length = if problem_data.max_code_length > 0 {
+            problem_data.max_code_length
+        } else {
+            app.config.max_code_length
+        };
+        // 超长或二进制的提交不再进行正则检查
+        let invalid = check_submission_text(&sub_info.program_codes(), max_code_length);
+        let scan_result = if invalid.is_none() {
+            scan_code(&rules, &sub_info.language, &sub_info.program_codes())?
+        } else {
+            ScanResult::default()
+        };
+        scan_flags = scan_result.flags;
+        // (提交状态, 信息)
+        let rejection = if let Some(message) = invalid {
+            Some(("invalid_submission", message))
+        } else if let Some(message) = scan_result.rejected {
+            Some(("forbidden_content", message))
+        } else if let Some(message) =
+            run_pre_compile(app, sid, &sub_info.language, &sub_info.program_codes()).await?
+        {
+            Some(("compile_error", message))
+        } else {
+            None
+        };
+        if let Some((status, message)) = rejection {
+            if let Err(e) = session.as_ref().unwrap().close().await {
+                error!("Failed to close sandbox session: {}", e);
+            }
+            update_status(
+                app,
+                &SubmissionJudgeResult::default(),
+                &message,
+                Some(status),
+                sid,
+            )
+            .await;
+            publish_event(
+                app,
+                sid,
+                "finished",
+                json!({
+                    "compile_error": status == "compile_error",
+                    "status": status,
+                    "queue_wait": queue_wait(sid)
+                }),
+            )
+            .await;
+            let summary = JudgeSummary {
+                submission_id: sid,
+                problem_id: problem_data.id,
+                status: status.to_string(),
+                compile_error: status == "compile_error",
+                score: 0,
+                judge_result: json!({}),
+            };
+            run_post_judge(app, &summary).await;
+            run_post_judge_script(app, &problem_data, &this_problem_path, &summary).await;
+            return Ok(());
+        }
+        // 通过代码检查后才沿用相同提交的结果，比赛的禁止规则等总是生效
+        if let Some(previous) = find_recent_verdict(app, &verdict_key, sid).await {
+            if let Err(e) = session.as_ref().unwrap().close().await {
+                error!("Failed to close sandbox session: {}", e);
+            }
+            return reuse_verdict(app, sid, &problem_data, &this_problem_path, previous).await;
+        }
         let compile_ret = compile_program(
             app,
+            session.as_deref().unwrap(),
             working_dir_path,
             sid,
             &sub_info,
             &lang_config,
+            &user_image,
             &problem_data,
             this_problem_path.as_path(),
             &extra_config,
             &sub_info.judge_result,
         )
         .await?;
+        record_timing(sid, |v| {
+            v.compile_time = Some(now_millis() - compile_started_at)
+        });
+        // 各子任务在编译目录的副本中使用自己的沙箱
+        if let Err(e) = session.as_ref().unwrap().close().await {
+            error!("Failed to close sandbox session: {}", e);
+        }
         if compile_ret.compile_error {
+            publish_event(
+                app,
+                sid,
+                "finished",
+                json!({ "compile_error": true, "queue_wait": queue_wait(sid) }),
+            )
+            .await;
+            let summary = JudgeSummary {
+                submission_id: sid,
+                problem_id: problem_data.id,
+                status: "compile_error".to_string(),
+                compile_error: true,
+                score: 0,
+                judge_result: json!({}),
+            };
+            run_post_judge(app, &summary).await;
+            run_post_judge_script(app, &problem_data, &this_problem_path, &summary).await;
             return Ok(());
         }
         IntermediateValue::Traditional(compile_ret)
@@ -177,42 +641,31 @@ async fn handle(
                 required_files.insert(testcase.output.clone());
             }
         }
-        let b64dec = Arc::new(
-            base64::decode(
-                extra_config
-                    .answer_data
-                    .as_ref()
-                    .ok_or(anyhow!("Missing answer data!"))?,
-            )
-            .map_err(|e| anyhow!("Failed to decode answer data: {}", e))?,
-        );
-        let mut zip = ZipFileReader::new(&b64dec)
-            .await
-            .map_err(|e| anyhow!("Failed to read zip file: {}", e))?;
-        let mut answer_files = HashMap::<String, Vec<u8>>::default();
-        for t in required_files.iter() {
-            let entry = zip.entry(t.as_str()).map(|v| v.0);
-            let to_insert = if let Some(v) = entry {
-                let things = zip
-                    .entry_reader(v)
-                    .await
-                    .map_err(|e| anyhow!("Failed to read file: {}, {}", t, e))?;
-                things
-                    .read_to_end_crc()
-                    .await
-                    .map_err(|e| anyhow!("Failed to decompress file: {}, {}", t, e))?
-            } else {
-                vec![]
-            };
-            answer_files.insert(t.clone(), to_insert);
-        }
+        let b64dec = base64::decode(
+            extra_config
+                .answer_data
+                .as_ref()
+                .ok_or(anyhow!("Missing answer data!"))?,
+        )
+        .map_err(|e| anyhow!("Failed to decode answer data: {}", e))?;
+        // 支持zip与tar.zst格式
+        let mut answer_files = extract_to_memory(
+            &b64dec,
+            &|name| required_files.contains(name),
+            app.config.answer_max_extracted_bytes,
+            None,
+        )
+        .await?;
         info!(
-            "Files in user zip: {:?}",
+            "Files in user archive: {:?}",
             answer_files.keys().collect::<Vec<&String>>()
         );
-        IntermediateValue::SubmitAnswer(answer_files)
+        for t in required_files.iter() {
+            answer_files.entry(t.clone()).or_default();
+        }
+        IntermediateValue::SubmitAnswer(answer_files, answer_scoring)
     };
-    let time_scale = extra_config.time_scale.unwrap_or(1.02);
+    let time_scale = extra_config.time_scale.unwrap_or(app.time_scale);
     let mut judge_result = sub_info.judge_result.clone();
     // 先上传一遍全新的测试点
     problem_data.subtasks.iter().for_each(|v| {
@@ -233,112 +686,295 @@ async fn handle(
                         score: 0,
                         status: "waiting".to_string(),
                         time_cost: 0,
+                        feedback: vec![],
+                        timeline: None,
+                        judge_time: None,
                     })
                     .collect(),
+                started_at: None,
+                judge_time: None,
             },
         );
     });
+    record_timing(sid, |v| v.judge_started_at = Some(now_millis()));
     update_status(app, &judge_result, "", None, sid).await;
-    for subtask in problem_data.subtasks.iter() {
-        info!("Judging subtask: {:?}", subtask);
-        // let mut subtask_result = judge_result.get_mut(&subtask.name).unwrap();
-
-        let mut will_skip = false;
-        for (i, testcase) in subtask.testcases.iter().enumerate() {
-            judge_result.get_mut(&subtask.name).unwrap().testcases[i].status =
-                "judging".to_string();
-            update_status(
-                app,
-                &judge_result.clone(),
-                &format!("评测: 子任务 {}, 测试点 {}", subtask.name, i + 1),
-                None,
+    let mut subtasks = problem_data
+        .subtasks
+        .iter()
+        .collect::<Vec<&ProblemSubtask>>();
+    // 样例子任务最先单独评测，题目中没有该子任务时按没有样例评测
+    let samples_subtask = if let Some(samples) = extra_config.samples_subtask.as_ref() {
+        match subtasks.iter().position(|v| &v.name == samples) {
+            Some(pos) => Some(subtasks.remove(pos)),
+            None => {
+                warn!("Samples subtask not found: {}", samples);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let judge_result = Mutex::new(judge_result);
+    let testcase_ctx = TestcaseContext {
+        app,
+        problem_data: &problem_data,
+        this_problem_path: this_problem_path.as_path(),
+        compile_dir: working_dir_path,
+        time_scale,
+        lang_config: &lang_config,
+        docker_image: &user_image,
+        seccomp: seccomp.as_deref(),
+        comparator: &*comparator,
+        interactor: interactor.as_deref(),
+        extra_config: &extra_config,
+    };
+    let icpc = icpc_mode(&problem_data, &extra_config);
+    let mut stages = vec![];
+    if let Some(samples) = samples_subtask {
+        stages.push(vec![samples]);
+    }
+    // ICPC模式下子任务按顺序逐个评测，以便在第一个未通过的测试点处停止
+    if icpc {
+        stages.extend(subtasks.into_iter().map(|v| vec![v]));
+    } else {
+        stages.push(subtasks);
+    }
+    // 之后的子任务全部跳过的原因
+    let mut skip_message = None;
+    // ICPC模式下第一个未通过的测试点的状态，作为提交的结果
+    let mut icpc_verdict = None;
+    for stage in stages.into_iter() {
+        if let Some(message) = skip_message {
+            let mut guard = judge_result.lock().await;
+            for subtask in stage.iter() {
+                let subtask_result = guard.get_mut(&subtask.name).unwrap();
+                for testcase_result in subtask_result.testcases.iter_mut() {
+                    testcase_result.score = 0;
+                    testcase_result.update("skipped", message);
+                }
+                subtask_result.score = 0;
+                subtask_result.status = "unaccepted".to_string();
+                for (i, testcase_result) in subtask_result.testcases.iter().enumerate() {
+                    publish_testcase_event(app, sid, &subtask.name, i, testcase_result).await;
+                }
+            }
+            mark_dirty(sid, stage.iter().map(|v| v.name.clone())).await;
+            continue;
+        }
+        let mut pending = vec![];
+        for subtask in stage.iter() {
+            pending.push(judge_subtask(
+                &testcase_ctx,
                 sid,
-            )
+                subtask,
+                &intermediate_value,
+                &judge_result,
+            ));
+        }
+        // 同一阶段的子任务并行评测，结果按名称合并，与完成顺序无关
+        let results = futures_util::stream::iter(pending)
+            .buffered(app.config.parallel_subtasks.max(1))
+            .collect::<Vec<ResultType<bool>>>()
             .await;
-            if will_skip {
-                let mut ret_ref = &mut judge_result.get_mut(&subtask.name).unwrap().testcases[i];
-                ret_ref.score = 0;
-                ret_ref.status = "skipped".to_string();
-                ret_ref.message = "跳过".to_string();
+        for (subtask, result) in stage.iter().zip(results.into_iter()) {
+            if result? {
                 continue;
             }
-            if extra_config.submit_answer {
-                let testcase_result =
-                    &mut judge_result.get_mut(&subtask.name).unwrap().testcases[i];
-                handle_submit_answer(
-                    testcase_result,
-                    testcase,
-                    this_problem_path.as_path(),
-                    &intermediate_value,
-                    &*comparator,
-                )
-                .await?;
-            } else {
-                handle_traditional(
-                    &problem_data,
-                    this_problem_path.as_path(),
-                    working_dir_path,
-                    testcase,
-                    subtask,
-                    time_scale,
-                    &lang_config,
-                    app,
-                    &*comparator,
-                    &extra_config,
-                    i,
-                    &mut will_skip,
-                    &mut judge_result,
-                )
-                .await?;
+            if extra_config.samples_subtask.as_ref() == Some(&subtask.name) {
+                info!("Samples subtask failed, skipping the rest");
+                skip_message = Some("样例未通过，跳过");
+            } else if icpc {
+                info!(
+                    "Subtask {} failed in ICPC mode, skipping the rest",
+                    subtask.name
+                );
+                skip_message = Some("之前的测试点未通过，跳过");
             }
-        } //subtask
-        let mut subtask_result = judge_result.get_mut(&subtask.name).unwrap();
-        if subtask.method == "min" {
-            if subtask_result
-                .testcases
-                .iter()
-                .all(|v| v.status == "accepted")
-            {
-                subtask_result.score = subtask.score;
-            } else {
-                subtask_result.score = 0;
+            if icpc && icpc_verdict.is_none() {
+                icpc_verdict = judge_result
+                    .lock()
+                    .await
+                    .get(&subtask.name)
+                    .and_then(|v| {
+                        v.testcases
+                            .iter()
+                            .find(|t| t.status != "accepted" && t.status != "skipped")
+                    })
+                    .map(|v| v.status.clone());
             }
-        } else if subtask.method == "sum" {
-            subtask_result.score = subtask_result.testcases.iter().map(|v| v.score).sum();
         }
-        subtask_result.status = (if subtask_result.score == subtask.score {
-            "accepted"
-        } else {
-            "unaccepted"
-        })
-        .to_string();
     }
+    record_timing(sid, |v| v.finished_at = Some(now_millis()));
+    let judge_result = judge_result.into_inner();
     info!("Judge result: {:?}", judge_result);
+    let total_score = judge_result.values().map(|v| v.score).sum::<i64>();
+    let watermark = result_watermark(app, sid, total_score, &judge_result)?;
+    // 水印行不计入沿用给相同提交的信息，沿用时重新生成
+    let watermark_line = watermark
+        .as_ref()
+        .map(|v| format!("\n{}", v.message_line(&app.config.result_watermark_key)))
+        .unwrap_or_default();
+    publish_event(
+        app,
+        sid,
+        "finished",
+        json!({
+            "compile_error": false,
+            "score": total_score,
+            "queue_wait": queue_wait(sid),
+            "subtasks": judge_result
+                .iter()
+                .map(|(k, v)| (k.clone(), json!({ "score": v.score, "status": v.status })))
+                .collect::<serde_json::Map<String, Value>>(),
+            "watermark": watermark.map(|v| v.to_json(&app.config.result_watermark_key))
+        }),
+    )
+    .await;
+    let summary = JudgeSummary {
+        submission_id: sid,
+        problem_id: problem_data.id,
+        status: overall_status(&judge_result, icpc_verdict.as_deref()),
+        compile_error: false,
+        score: total_score,
+        judge_result: serde_json::to_value(&judge_result)?,
+    };
+    run_post_judge(app, &summary).await;
     if !extra_config.submit_answer {
         let compile_result = intermediate_value.traditional().unwrap().execute_result;
-        update_status(
-            app,
-            &judge_result,
-            &format!(
-                "{}\n评测结束于: {}\n{}\n编译时间占用: {} ms\n编译内存占用: {} MB\n退出代码: {}",
+        let sections = [
+            MessageSection::fixed(format!(
+                "{}\n评测结束于: {}\n",
                 app.version_string,
                 chrono::Local::now().format("%F %X").to_string(),
-                compile_result.output,
+            )),
+            MessageSection::truncatable(
+                scan_flags
+                    .iter()
+                    .map(|v| format!("代码检查提示: {}\n", v))
+                    .collect::<String>(),
+            ),
+            MessageSection::truncatable(compile_result.output),
+            MessageSection::fixed(format!(
+                "\n编译时间占用: {} ms\n编译内存占用: {} MB\n退出代码: {}",
                 compile_result.time_cost / 1000,
                 compile_result.memory_cost / 1024 / 1024,
                 compile_result.exit_code
-            ),
-            None,
+            )),
+            MessageSection::fixed(watermark_line.clone()),
+        ];
+        let message = build_status_message(app, sid, &sections).await;
+        let reusable = message.strip_suffix(&watermark_line).unwrap_or(&message);
+        // 被取消的评测结果不完整，不能沿用给相同的提交
+        if !was_cancelled(sid) {
+            record_verdict(
+                app,
+                verdict_key,
+                sid,
+                &judge_result,
+                reusable,
+                icpc_verdict.as_deref(),
+            )
+            .await;
+        }
+        update_status(app, &judge_result, &message, icpc_verdict.as_deref(), sid).await;
+    } else {
+        update_status(
+            app,
+            &judge_result,
+            watermark_line.trim_start(),
+            icpc_verdict.as_deref(),
             sid,
         )
         .await;
-    } else {
-        update_status(app, &judge_result, "", None, sid).await;
     }
+    // 结果已经汇报，脚本的运行不影响评测结果
+    run_post_judge_script(app, &problem_data, &this_problem_path, &summary).await;
     info!("Judge task finished");
     return Ok(());
 }
 
+/// 提交的总体结果: ICPC模式下为第一个未通过的测试点的状态，否则全部子任务通过时为accepted，
+/// 未通过时为第一个未通过(且未跳过)的测试点的状态
+fn overall_status(judge_result: &SubmissionJudgeResult, icpc_verdict: Option<&str>) -> String {
+    if let Some(v) = icpc_verdict {
+        return v.to_string();
+    }
+    if judge_result.values().all(|v| v.status == "accepted") {
+        return "accepted".to_string();
+    }
+    return judge_result
+        .values()
+        .flat_map(|v| v.testcases.iter())
+        .find(|v| v.status != "accepted" && v.status != "skipped")
+        .map(|v| v.status.clone())
+        .unwrap_or("unaccepted".to_string());
+}
+
+/// 沿用近期相同提交的评测结果
+async fn reuse_verdict(
+    app: &AppState,
+    sid: i64,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    previous: RecentVerdict,
+) -> ResultType<()> {
+    info!(
+        "Submission {} is identical to {}, reusing its verdict",
+        sid, previous.submission_id
+    );
+    let total_score = previous.judge_result.values().map(|v| v.score).sum::<i64>();
+    let watermark = result_watermark(app, sid, total_score, &previous.judge_result)?;
+    let mut message = format!(
+        "与近期的提交 #{} 代码相同，沿用其评测结果\n{}",
+        previous.submission_id, previous.message
+    );
+    if let Some(v) = &watermark {
+        message.push_str(&format!(
+            "\n{}",
+            v.message_line(&app.config.result_watermark_key)
+        ));
+    }
+    let extra_status = previous.extra_status.as_deref();
+    update_status(app, &previous.judge_result, &message, extra_status, sid).await;
+    publish_event(
+        app,
+        sid,
+        "finished",
+        json!({
+            "compile_error": false,
+            "score": total_score,
+            "duplicate_of": previous.submission_id,
+            "queue_wait": queue_wait(sid),
+            "watermark": watermark.map(|v| v.to_json(&app.config.result_watermark_key))
+        }),
+    )
+    .await;
+    let summary = JudgeSummary {
+        submission_id: sid,
+        problem_id: problem_data.id,
+        status: overall_status(&previous.judge_result, extra_status),
+        compile_error: false,
+        score: total_score,
+        judge_result: serde_json::to_value(&previous.judge_result)?,
+    };
+    run_post_judge(app, &summary).await;
+    run_post_judge_script(app, problem_data, this_problem_path, &summary).await;
+    return Ok(());
+}
+
+/// result_watermark_key不为空时为最终结果生成水印
+fn result_watermark(
+    app: &AppState,
+    sid: i64,
+    score: i64,
+    judge_result: &SubmissionJudgeResult,
+) -> ResultType<Option<Watermark>> {
+    if app.config.result_watermark_key.is_empty() {
+        return Ok(None);
+    }
+    return Ok(Some(Watermark::new(&app.config, sid, score, judge_result)?));
+}
+
 struct MyUpdater<'a> {
     pub judge_result: &'a SubmissionJudgeResult,
     pub submission_id: i64,
@@ -0,0 +1,388 @@
+use std::{collections::HashMap, path::Path, time::UNIX_EPOCH};
+
+use anyhow::anyhow;
+use celery::{prelude::TaskError, task::TaskResult};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+use crate::core::{
+    compare::special::compile_problem_program_cached,
+    limits::TimeLimit,
+    logging::{with_log_context, LogContext},
+    misc::{AsyncStatusUpdater, ResultType},
+    model::LanguageConfig,
+    runner::ExecuteOptions,
+    state::{AppState, GLOBAL_APP_STATE},
+    upload::upload_problem_file,
+    util::{get_language_config, shell_quote, with_retry},
+    workdir::create_work_dir,
+};
+
+use super::{
+    api::RunStatusUpdate,
+    janitor::use_problem,
+    sync_state::{SyncManifest, SyncedFile, SYNC_MANIFEST_FILE},
+    util::{check_problem_file_name, file_sha256, sync_problem_files, COMPILE_CACHE_DIR},
+};
+
+// 题目数据中描述如何生成测试数据的文件
+pub const GENERATE_RECIPE_FILE: &str = "generate.json";
+// 生成器单次运行的默认时间限制
+const DEFAULT_GENERATOR_TIME_LIMIT: TimeLimit = TimeLimit::from_secs(10);
+const GENERATOR_OUTPUT: &str = "out";
+
+/// generate.json的内容
+#[derive(Deserialize, Debug)]
+pub struct GenerateRecipe {
+    pub generators: Vec<GeneratorProgram>,
+    pub tests: Vec<GenerateStep>,
+    // 生成器单次运行的时间限制(ms)
+    #[serde(default)]
+    pub time_limit: Option<TimeLimit>,
+}
+#[derive(Deserialize, Debug)]
+pub struct GeneratorProgram {
+    pub name: String,
+    // 题目文件中的源代码
+    pub source: String,
+    // 语言ID
+    pub language: String,
+}
+/// 运行一次生成器: 以seed与args为命令行参数，标准输出写入题目数据目录下的output
+#[derive(Deserialize, Debug)]
+pub struct GenerateStep {
+    pub generator: String,
+    pub seed: i64,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub output: String,
+}
+
+/// 生成器的源代码必须是题目数据目录下的文件，名称用作编译结果的文件名，只能是单个文件名
+fn check_generator(generator: &GeneratorProgram) -> ResultType<()> {
+    check_problem_file_name(&generator.source)?;
+    if generator.name.is_empty() || generator.name.contains('/') || generator.name.starts_with('.')
+    {
+        return Err(anyhow!("Illegal generator name: {}", generator.name));
+    }
+    return Ok(());
+}
+
+/// 生成的文件只能位于题目数据目录下，且不能覆盖生成配置、同步记录与编译缓存等
+fn check_output_name(name: &str) -> ResultType<()> {
+    if name.is_empty()
+        || name.contains('/')
+        || name.starts_with('.')
+        || [GENERATE_RECIPE_FILE, SYNC_MANIFEST_FILE, COMPILE_CACHE_DIR].contains(&name)
+    {
+        return Err(anyhow!("Illegal output file name: {}", name));
+    }
+    return Ok(());
+}
+
+pub async fn update_generation_status(
+    app: &AppState,
+    run_id: &str,
+    status: &str,
+    message: &str,
+    result: Option<&Value>,
+) {
+    let result = result.map(|v| v.to_string()).unwrap_or_default();
+    if !app.report_status {
+        info!(
+            "Testdata generation {}: {} {} {}",
+            run_id, status, message, result
+        );
+        return;
+    }
+    let update = RunStatusUpdate {
+        run_id,
+        status,
+        message,
+        result: &result,
+    };
+    if let Err(e) = with_retry(&app.config, "update_testdata_generation", || {
+        app.api.update_testdata_generation(&update)
+    })
+    .await
+    {
+        error!("Failed to report testdata generation status: {}", e);
+    }
+}
+
+struct GenerationUpdater<'a> {
+    pub run_id: &'a str,
+}
+#[async_trait::async_trait]
+impl<'a> AsyncStatusUpdater for GenerationUpdater<'a> {
+    async fn update(&self, message: &str) {
+        let guard = GLOBAL_APP_STATE.read().await;
+        let app_state_guard = guard.as_ref().unwrap();
+        update_generation_status(app_state_guard, self.run_id, "running", message, None).await;
+    }
+}
+
+/// 按题目的generate.json在沙箱中运行生成器，生成(或重新生成)测试数据并写入题目数据目录
+/// 生成的文件记入同步记录，与服务端内容相同的文件之后不会再通过HTTP下载
+/// upload为true时将生成的文件上传到服务端，同一个题目只需要一个评测机上传
+#[celery::task(name = "judgers.local.generate_testdata")]
+pub async fn generate_testdata_handler(
+    run_id: String,
+    problem_id: i64,
+    only: Vec<String>,
+    upload: bool,
+) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app_state_guard = guard.as_ref().unwrap();
+    let _semaphore_guard = app_state_guard.task_count_lock.acquire().await.unwrap();
+    let context = LogContext {
+        run_id: Some(run_id.clone()),
+        ..Default::default()
+    };
+    if let Err(e) = with_log_context(
+        context,
+        handle(app_state_guard, &run_id, problem_id, &only, upload),
+    )
+    .await
+    {
+        let err_str = e.to_string();
+        update_generation_status(app_state_guard, &run_id, "done", &err_str, None).await;
+        return Err(TaskError::UnexpectedError(err_str));
+    }
+    return Ok(());
+}
+
+/// 编译全部生成器，每个生成器使用单独的工作目录
+async fn compile_generators(
+    app: &AppState,
+    recipe: &GenerateRecipe,
+    data_path: &Path,
+) -> ResultType<HashMap<String, (TempDir, LanguageConfig)>> {
+    let mut ret = HashMap::new();
+    for generator in recipe.generators.iter() {
+        let lang_config = get_language_config(app, &generator.language)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Failed to get language definition of {}: {}",
+                    generator.name,
+                    e
+                )
+            })?;
+        let working_dir = create_work_dir(&app.config)
+            .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+        compile_problem_program_cached(
+            app,
+            &*app.runner,
+            &app.config.docker_image,
+            &data_path.join(COMPILE_CACHE_DIR),
+            working_dir.path(),
+            &data_path.join(&generator.source),
+            &lang_config,
+            &generator.name,
+        )
+        .await
+        .map_err(|e| anyhow!("Error occurred when compiling {}:\n{}", generator.name, e))?;
+        ret.insert(generator.name.clone(), (working_dir, lang_config));
+    }
+    return Ok(ret);
+}
+
+async fn handle(
+    app: &AppState,
+    run_id: &str,
+    problem_id: i64,
+    only: &[String],
+    upload: bool,
+) -> ResultType<()> {
+    info!(
+        "Received testdata generation {} of problem {}",
+        run_id, problem_id
+    );
+    let data_path = app.testdata_dir.join(problem_id.to_string());
+    let _problem_guard = use_problem(problem_id);
+    let updater = GenerationUpdater { run_id };
+    // 先取得生成配置，再只同步生成器的源代码
+    sync_problem_files(
+        problem_id,
+        Some(&[GENERATE_RECIPE_FILE.to_string()]),
+        &updater,
+        app,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to sync {}: {}", GENERATE_RECIPE_FILE, e))?;
+    let content = tokio::fs::read_to_string(data_path.join(GENERATE_RECIPE_FILE))
+        .await
+        .map_err(|e| anyhow!("Failed to read {}: {}", GENERATE_RECIPE_FILE, e))?;
+    let recipe = serde_json::from_str::<GenerateRecipe>(&content)
+        .map_err(|e| anyhow!("Failed to parse {}: {}", GENERATE_RECIPE_FILE, e))?;
+    for generator in recipe.generators.iter() {
+        check_generator(generator)?;
+    }
+    let steps = recipe
+        .tests
+        .iter()
+        .filter(|v| only.is_empty() || only.contains(&v.output))
+        .collect::<Vec<&GenerateStep>>();
+    for step in steps.iter() {
+        check_output_name(&step.output)?;
+    }
+    let sources = recipe
+        .generators
+        .iter()
+        .map(|v| v.source.clone())
+        .collect::<Vec<String>>();
+    sync_problem_files(problem_id, Some(&sources), &updater, app)
+        .await
+        .map_err(|e| anyhow!("Failed to sync generators: {}", e))?;
+    update_generation_status(app, run_id, "running", "Compiling generators..", None).await;
+    let generators = compile_generators(app, &recipe, &data_path).await?;
+    let time_limit = recipe.time_limit.unwrap_or(DEFAULT_GENERATOR_TIME_LIMIT);
+    let wall_time_limit = time_limit.scale(app.config.wall_time_limit_factor);
+    let options = ExecuteOptions {
+        file_size_limit: app.config.generator_output_limit,
+        workdir_size_limit: app.config.generator_output_limit,
+        pids_limit: app.config.pids_limit,
+        ..Default::default()
+    };
+    // 全部生成完成后再写入题目数据目录，避免评测读到一部分新一部分旧的数据
+    let output_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+    for (i, step) in steps.iter().enumerate() {
+        update_generation_status(
+            app,
+            run_id,
+            "running",
+            &format!("Generating {} ({}/{})..", step.output, i + 1, steps.len()),
+            None,
+        )
+        .await;
+        let (working_dir, lang_config) = generators
+            .get(&step.generator)
+            .ok_or(anyhow!("Unknown generator: {}", step.generator))?;
+        let arguments = std::iter::once(step.seed.to_string())
+            .chain(step.args.iter().cloned())
+            .map(|v| shell_quote(&v))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let lang_config = lang_config.with_container_limit(
+            &app.config,
+            app.config.trusted_profile.memory_limit.as_bytes(),
+        );
+        let cmdline = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            lang_config.run_s(
+                &lang_config.output(&step.generator),
+                &format!("{} > {}", arguments, GENERATOR_OUTPUT),
+            ),
+        ];
+        info!("Run generator: {:?}", cmdline);
+        let result = app
+            .runner
+            .execute(
+                &app.config.docker_image,
+                working_dir.path().to_str().unwrap_or(""),
+                &cmdline,
+                app.config.trusted_profile.memory_limit.as_bytes(),
+                wall_time_limit.as_micros(),
+                1024,
+                &options,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run generator {}: {}", step.generator, e))?;
+        let error = if result.killed_by_watchdog || result.cpu_time_cost >= time_limit.as_micros() {
+            Some(format!(
+                "time limit exceeded ({} ms)",
+                time_limit.as_millis()
+            ))
+        } else if result.workdir_size_limit_exceeded || result.exit_code == 128 + libc::SIGXFSZ {
+            Some(format!(
+                "output exceeds {} bytes",
+                app.config.generator_output_limit
+            ))
+        } else if result.exit_code != 0 {
+            Some(format!("exit code = {}", result.exit_code))
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            return Err(anyhow!(
+                "Generator {} failed when generating {} ({}):\n{}",
+                step.generator,
+                step.output,
+                error,
+                result.output
+            ));
+        }
+        tokio::fs::rename(
+            working_dir.path().join(GENERATOR_OUTPUT),
+            output_dir.path().join(&step.output),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to move generated {}: {}", step.output, e))?;
+    }
+    let mut files = vec![];
+    {
+        let _guard = app.file_dir_locks.lock(&problem_id).await;
+        let mut manifest = SyncManifest::load(&data_path).await;
+        for step in steps.iter() {
+            let generated = output_dir.path().join(&step.output);
+            let sha256 = file_sha256(&generated).await?;
+            // 工作目录与题目数据可能不在同一个文件系统中，先复制到临时文件再改名
+            let part_file = data_path.join(format!(".{}.part", step.output));
+            let size = tokio::fs::copy(&generated, &part_file)
+                .await
+                .map_err(|e| anyhow!("Failed to write `{}`: {}", part_file.display(), e))?;
+            tokio::fs::rename(&part_file, data_path.join(&step.output))
+                .await
+                .map_err(|e| anyhow!("Failed to save `{}`: {}", step.output, e))?;
+            manifest.files.insert(
+                step.output.clone(),
+                SyncedFile {
+                    synced_at: std::time::SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_err(|e| anyhow!("Failed to get timestamp: {}", e))?
+                        .as_secs() as f64,
+                    size,
+                    sha256: sha256.clone(),
+                },
+            );
+            files.push(json!({ "name": step.output, "size": size, "sha256": sha256 }));
+        }
+        manifest.save(&data_path).await?;
+    }
+    if upload {
+        for (i, (step, file)) in steps.iter().zip(files.iter()).enumerate() {
+            update_generation_status(
+                app,
+                run_id,
+                "running",
+                &format!("Uploading {} ({}/{})..", step.output, i + 1, steps.len()),
+                None,
+            )
+            .await;
+            // 上传时不持有题目目录的锁，从生成结果的副本读取
+            upload_problem_file(
+                app,
+                problem_id,
+                &step.output,
+                &output_dir.path().join(&step.output),
+                file["sha256"].as_str().unwrap_or(""),
+            )
+            .await?;
+        }
+    }
+    update_generation_status(
+        app,
+        run_id,
+        "done",
+        &format!("已生成 {} 个文件", files.len()),
+        Some(&json!({ "files": files, "uploaded": upload })),
+    )
+    .await;
+    info!("Testdata generation done: {}", run_id);
+    return Ok(());
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde_json::json;
+
+use crate::core::{
+    misc::ResultType,
+    state::{AppState, GLOBAL_APP_STATE},
+    util::dir_size,
+    webhook::{notify_webhook, EVENT_QUOTA_EXHAUSTED},
+};
+
+// 检查题目数据目录大小的间隔
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct ProblemUsage {
+    // 正在使用该题目数据的任务数
+    in_use: usize,
+    last_used: Option<SystemTime>,
+}
+
+lazy_static! {
+    // 键为题目ID
+    static ref PROBLEM_USAGE: Mutex<HashMap<i64, ProblemUsage>> = Mutex::new(HashMap::new());
+}
+
+/// 持有期间题目数据不会被清理
+pub struct ProblemUseGuard {
+    problem_id: i64,
+}
+
+impl Drop for ProblemUseGuard {
+    fn drop(&mut self) {
+        let mut usage = PROBLEM_USAGE.lock().unwrap();
+        let entry = usage.entry(self.problem_id).or_default();
+        entry.in_use = entry.in_use.saturating_sub(1);
+        entry.last_used = Some(SystemTime::now());
+    }
+}
+
+/// 标记题目数据正在使用，应在同步题目文件之前调用，并在使用完数据之后释放
+pub fn use_problem(problem_id: i64) -> ProblemUseGuard {
+    let mut usage = PROBLEM_USAGE.lock().unwrap();
+    let entry = usage.entry(problem_id).or_default();
+    entry.in_use += 1;
+    entry.last_used = Some(SystemTime::now());
+    return ProblemUseGuard { problem_id };
+}
+
+/// 删除未在使用的题目的数据目录，题目正在使用时返回false
+/// 持有锁时不会有任务在同步该题目，同步之前任务一定已经标记了使用
+pub async fn remove_unused_problem(
+    app: &AppState,
+    problem_id: i64,
+    path: &Path,
+) -> ResultType<bool> {
+    let _lock = app.file_dir_locks.lock(&problem_id).await;
+    if PROBLEM_USAGE
+        .lock()
+        .unwrap()
+        .get(&problem_id)
+        .map(|v| v.in_use > 0)
+        .unwrap_or(false)
+    {
+        return Ok(false);
+    }
+    if path.exists() {
+        tokio::fs::remove_dir_all(path)
+            .await
+            .map_err(|e| anyhow!("Failed to remove {}: {}", path.display(), e))?;
+    }
+    PROBLEM_USAGE.lock().unwrap().remove(&problem_id);
+    return Ok(true);
+}
+
+/// 题目数据目录下的各个题目: (题目ID, 路径, 大小, 修改时间)
+pub fn scan_problem_dirs(testdata_dir: &Path) -> ResultType<Vec<(i64, PathBuf, u64, SystemTime)>> {
+    let mut ret = vec![];
+    for entry in std::fs::read_dir(testdata_dir)
+        .map_err(|e| anyhow!("Failed to read testdata dir: {}", e))?
+        .flatten()
+    {
+        // 跳过正在解压的数据包等临时目录
+        let problem_id = match entry
+            .file_name()
+            .to_str()
+            .and_then(|v| v.parse::<i64>().ok())
+        {
+            Some(v) => v,
+            None => continue,
+        };
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let modified = entry
+            .metadata()
+            .and_then(|v| v.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        ret.push((problem_id, path.clone(), dir_size(&path), modified));
+    }
+    return Ok(ret);
+}
+
+/// 题目数据超过max_bytes时，按最近使用时间从旧到新删除未在使用的题目
+async fn evict_testdata(max_bytes: u64) -> ResultType<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().ok_or(anyhow!("App state not initialized"))?;
+    let testdata_dir = app.testdata_dir.clone();
+    let mut problems = tokio::task::spawn_blocking(move || scan_problem_dirs(&testdata_dir))
+        .await
+        .map_err(|e| anyhow!("Failed to scan testdata dir: {}", e))??;
+    let mut total = problems.iter().map(|v| v.2).sum::<u64>();
+    if total <= max_bytes {
+        return Ok(());
+    }
+    info!(
+        "Testdata uses {} bytes, exceeding limit {} bytes",
+        total, max_bytes
+    );
+    {
+        // 本次运行期间没有用过的题目按目录修改时间排序
+        let usage = PROBLEM_USAGE.lock().unwrap();
+        problems.sort_by_key(|(id, _, _, modified)| {
+            usage.get(id).and_then(|v| v.last_used).unwrap_or(*modified)
+        });
+    }
+    for (problem_id, path, size, _) in problems.into_iter() {
+        if total <= max_bytes {
+            break;
+        }
+        if !remove_unused_problem(app, problem_id, &path).await? {
+            continue;
+        }
+        info!("Evicted testdata of problem {}, {} bytes", problem_id, size);
+        total -= size;
+    }
+    if total > max_bytes {
+        warn!(
+            "Testdata still uses {} bytes after eviction, all remaining problems are in use",
+            total
+        );
+        notify_webhook(
+            app,
+            EVENT_QUOTA_EXHAUSTED,
+            json!({ "used_bytes": total, "max_bytes": max_bytes }),
+        );
+    }
+    return Ok(());
+}
+
+/// 定期检查题目数据目录的大小，max_bytes为0时不检查
+pub fn spawn_testdata_janitor(max_bytes: u64) {
+    if max_bytes == 0 {
+        return;
+    }
+    info!("Testdata quota: {} bytes", max_bytes);
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(JANITOR_INTERVAL);
+        loop {
+            timer.tick().await;
+            if let Err(e) = evict_testdata(max_bytes).await {
+                error!("Failed to evict testdata: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,83 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::core::{misc::ResultType, state::AppState};
+
+use super::model::ExtraJudgeConfig;
+
+/// 评测任务的原始参数，用于在本地重现评测
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub submission_id: i64,
+    pub received_at: String,
+    pub submission_data: Value,
+    pub extra_config: ExtraJudgeConfig,
+    // 评测过程中附加的信息(如post_judge脚本的输出)，名称 -> 内容
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attachments: BTreeMap<String, Value>,
+}
+
+fn journal_file(app: &AppState, submission_id: i64) -> PathBuf {
+    return PathBuf::from(&app.config.journal_dir).join(format!("{}.json", submission_id));
+}
+
+pub async fn write_journal(
+    app: &AppState,
+    submission_id: i64,
+    submission_data: &Value,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<()> {
+    if app.config.journal_dir.is_empty() {
+        return Ok(());
+    }
+    let entry = JournalEntry {
+        submission_id,
+        received_at: chrono::Local::now().to_rfc3339(),
+        submission_data: submission_data.clone(),
+        extra_config: extra_config.clone(),
+        attachments: BTreeMap::new(),
+    };
+    tokio::fs::create_dir_all(&app.config.journal_dir)
+        .await
+        .map_err(|e| anyhow!("Failed to create journal dir: {}", e))?;
+    tokio::fs::write(
+        journal_file(app, submission_id),
+        serde_json::to_string_pretty(&entry)?,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to write journal: {}", e))?;
+    return Ok(());
+}
+
+pub async fn read_journal(app: &AppState, submission_id: i64) -> ResultType<JournalEntry> {
+    let text = tokio::fs::read_to_string(journal_file(app, submission_id))
+        .await
+        .map_err(|e| anyhow!("Failed to read journal of {}: {}", submission_id, e))?;
+    let entry = serde_json::from_str::<JournalEntry>(&text)
+        .map_err(|e| anyhow!("Failed to deserialize journal: {}", e))?;
+    return Ok(entry);
+}
+
+/// 在提交的journal中附加信息，同名的信息会被覆盖；journal_dir为空时忽略
+pub async fn attach_to_journal(
+    app: &AppState,
+    submission_id: i64,
+    name: &str,
+    value: Value,
+) -> ResultType<()> {
+    if app.config.journal_dir.is_empty() {
+        return Ok(());
+    }
+    let mut entry = read_journal(app, submission_id).await?;
+    entry.attachments.insert(name.to_string(), value);
+    tokio::fs::write(
+        journal_file(app, submission_id),
+        serde_json::to_string_pretty(&entry)?,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to write journal: {}", e))?;
+    return Ok(());
+}
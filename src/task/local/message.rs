@@ -0,0 +1,112 @@
+use log::{error, info};
+
+use crate::core::{state::AppState, upload::upload_artifact};
+
+const TRUNCATED_MARK: &str = "...[已截断]";
+// 完整信息的产物名
+const FULL_MESSAGE_ARTIFACT: &str = "message.txt";
+
+/// 状态信息的一段，超出长度时只截断truncatable的段
+pub struct MessageSection {
+    pub text: String,
+    // 编译输出等可以截断的段，版本、时间等短小且重要的段不截断
+    pub truncatable: bool,
+}
+
+impl MessageSection {
+    pub fn fixed(text: impl Into<String>) -> Self {
+        return Self {
+            text: text.into(),
+            truncatable: false,
+        };
+    }
+    pub fn truncatable(text: impl Into<String>) -> Self {
+        return Self {
+            text: text.into(),
+            truncatable: true,
+        };
+    }
+}
+
+/// 保留开头不超过max_len字节的内容
+pub fn truncate_text(text: &str, max_len: usize) -> String {
+    if text.len() <= max_len {
+        return text.to_string();
+    }
+    let mut end = max_len.saturating_sub(TRUNCATED_MARK.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    return format!("{}{}", &text[..end], TRUNCATED_MARK);
+}
+
+/// 将各段拼接为不超过budget字节的信息，可截断的段按各自的长度比例分配剩余的长度
+/// 返回拼接结果与是否发生了截断
+pub fn budget_message(sections: &[MessageSection], budget: usize) -> (String, bool) {
+    let total = sections.iter().map(|v| v.text.len()).sum::<usize>();
+    if total <= budget {
+        return (
+            sections.iter().map(|v| v.text.as_str()).collect::<String>(),
+            false,
+        );
+    }
+    let fixed = sections
+        .iter()
+        .filter(|v| !v.truncatable)
+        .map(|v| v.text.len())
+        .sum::<usize>();
+    let truncatable = total - fixed;
+    let remaining = budget.saturating_sub(fixed);
+    let mut ret = String::new();
+    for section in sections.iter() {
+        if section.truncatable {
+            let share = (remaining as u128 * section.text.len() as u128
+                / truncatable.max(1) as u128) as usize;
+            ret.push_str(&truncate_text(&section.text, share));
+        } else {
+            ret.push_str(&section.text);
+        }
+    }
+    // 不可截断的段本身超出预算时整体截断
+    return (truncate_text(&ret, budget), true);
+}
+
+/// 拼接最终的评测信息，超出status_message_limit时截断，并在开启时将完整信息作为产物上传
+pub async fn build_status_message(
+    app: &AppState,
+    submission_id: i64,
+    sections: &[MessageSection],
+) -> String {
+    let limit = app.config.status_message_limit;
+    if limit == 0 {
+        return sections.iter().map(|v| v.text.as_str()).collect::<String>();
+    }
+    let (message, truncated) = budget_message(sections, limit);
+    if !truncated || !app.config.upload_full_message || !app.report_status {
+        return message;
+    }
+    let full = sections.iter().map(|v| v.text.as_str()).collect::<String>();
+    info!(
+        "Status message of {} truncated: {} -> {} bytes",
+        submission_id,
+        full.len(),
+        message.len()
+    );
+    match upload_artifact(app, submission_id, FULL_MESSAGE_ARTIFACT, full.as_bytes()).await {
+        Ok(artifact_id) => {
+            let reference = format!("\n完整信息见评测产物: {}", artifact_id);
+            let (message, _) = budget_message(
+                &[
+                    MessageSection::truncatable(message),
+                    MessageSection::fixed(reference),
+                ],
+                limit,
+            );
+            return message;
+        }
+        Err(e) => {
+            error!("Failed to upload full status message: {}", e);
+            return message;
+        }
+    }
+}
@@ -1,9 +1,38 @@
+pub mod api;
+pub mod batch;
+pub mod cancel;
 pub mod compile;
+pub mod custom_input;
+pub mod dead_letter;
+pub mod duplicate;
 pub mod executor;
+pub mod generate;
+pub mod janitor;
+pub mod journal;
+pub mod message;
 pub mod model;
+pub mod notebook;
+pub mod post_judge;
+pub mod report;
+pub mod requeue;
+pub mod shadow;
+pub mod sql;
 pub mod submit_answer;
+pub mod subtask;
+pub mod sync_state;
+pub mod timing;
 pub mod traditional;
 pub mod util;
+pub mod validate;
 pub use executor::local_judge_task_handler;
 
 pub const DEFAULT_PROGRAM_FILENAME: &str = "user-app";
+pub const RESOURCE_MOUNT_DIR: &str = "/resources";
+
+/// 多文件提交中第index个程序(从0开始)的文件名，第一个程序使用DEFAULT_PROGRAM_FILENAME
+pub fn program_filename(index: usize) -> String {
+    if index == 0 {
+        return DEFAULT_PROGRAM_FILENAME.to_string();
+    }
+    return format!("{}-{}", DEFAULT_PROGRAM_FILENAME, index + 1);
+}
@@ -2,14 +2,19 @@ use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::core::{
+    compare::FeedbackFile,
+    limits::{MemoryLimit, TimeLimit},
+    runner::ResourceTimeline,
+    scan::ForbiddenRule,
+};
+
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct ExtraJudgeConfig {
-    //ms
-    pub compile_time_limit: i64,
+    pub compile_time_limit: TimeLimit,
     //chars
     pub compile_result_length_limit: i64,
-    //ms
-    pub spj_execute_time_limit: i64,
+    pub spj_execute_time_limit: TimeLimit,
     pub extra_compile_parameter: String,
     pub auto_sync_files: bool,
     // bytes
@@ -18,6 +23,29 @@ pub struct ExtraJudgeConfig {
     // in base64
     pub answer_data: Option<String>,
     pub time_scale: Option<f64>,
+    // 样例子任务名，该子任务未通过时跳过其余所有子任务
+    pub samples_subtask: Option<String>,
+    // 该提交额外使用的代码检查规则(如比赛设置)，与评测机配置的规则一同生效
+    #[serde(default)]
+    pub forbidden_rules: Vec<ForbiddenRule>,
+    // 提交代码的格式: 为空时为源代码，"ipynb"为Jupyter笔记本，评测前按顺序提取代码单元格
+    #[serde(default)]
+    pub code_format: String,
+    // 服务端随任务下发的题目信息，存在时不再请求get_problem_info
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub problem_info: Option<serde_json::Value>,
+    // 用户程序向工作目录写入数据的总量上限(字节)，0为使用评测机的配置
+    #[serde(default)]
+    pub workdir_size_limit: i64,
+    // 任务被无法评测的评测机转交的次数
+    #[serde(default)]
+    pub requeue_count: u32,
+    // 转交过该任务的评测机uuid，这些评测机再次收到任务时直接转交
+    #[serde(default)]
+    pub excluded_judgers: Vec<String>,
+    // 覆盖题目的icpc_mode(如比赛设置)
+    #[serde(default)]
+    pub icpc_mode: Option<bool>,
 }
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct SubmissionInfo {
@@ -40,6 +68,17 @@ pub struct SubmissionInfo {
     pub uid: i64,
     pub virtual_contest_id: Option<i64>,
     pub judge_result: SubmissionJudgeResult,
+    // 多文件提交(通信题)中其余程序的源代码，依次为第2、3...个程序
+    #[serde(default)]
+    pub extra_codes: Vec<String>,
+}
+impl SubmissionInfo {
+    /// 提交的所有程序的源代码，第一个为code
+    pub fn program_codes(&self) -> Vec<&String> {
+        let mut ret = vec![&self.code];
+        ret.extend(self.extra_codes.iter());
+        return ret;
+    }
 }
 
 pub type SubmissionJudgeResult = BTreeMap<String, SubmissionSubtaskResult>;
@@ -53,6 +92,15 @@ pub struct SubmissionTestcaseResult {
     pub score: i64,
     pub status: String,
     pub time_cost: i64,
+    // SPJ输出的附加文件
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub feedback: Vec<FeedbackFile>,
+    // 运行过程中的内存与CPU占用采样
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeline: Option<ResourceTimeline>,
+    // 评测该测试点(运行与比较)的墙上时间(毫秒)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub judge_time: Option<i64>,
 }
 impl SubmissionTestcaseResult {
     pub fn update(&mut self, status: &str, message: &str) {
@@ -68,6 +116,11 @@ pub struct SubmissionSubtaskResult {
     pub score: i64,
     pub status: String,
     pub testcases: Vec<SubmissionTestcaseResult>,
+    // 子任务开始评测的时间(unix时间戳，毫秒)与评测的墙上时间(毫秒)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub judge_time: Option<i64>,
 }
 
 #[derive(Deserialize, Debug, Clone, Serialize)]
@@ -83,6 +136,77 @@ pub struct ProblemInfo {
     pub spj_filename: String,
     pub using_file_io: i8,
     pub subtasks: Vec<ProblemSubtask>,
+    // 运行时以只读方式挂载到 RESOURCE_MOUNT_DIR 下的文件，不会被复制进工作目录
+    #[serde(default)]
+    pub resource_files: Vec<String>,
+    // 交互题的交互器文件名，格式为 interactor_语言ID.xxx
+    #[serde(default)]
+    pub interactor_filename: String,
+    // 为true时非零退出代码不再判为运行错误，而是交给比较器/SPJ作为答案的一部分
+    #[serde(default)]
+    pub exit_code_as_answer: bool,
+    // 覆盖评测机配置的磁盘读写速率上限(字节每秒)，0为不限制
+    #[serde(default)]
+    pub io_read_bps: Option<i64>,
+    #[serde(default)]
+    pub io_write_bps: Option<i64>,
+    // 覆盖评测机配置的进程数上限，0为不限制
+    #[serde(default)]
+    pub pids_limit: Option<i64>,
+    // 函数实现题: provides中与用户程序扩展名相同的文件(如grader.cpp)与用户程序一起编译，头文件等其余文件仅复制
+    #[serde(default)]
+    pub compile_with_provides: bool,
+    // 函数实现题的编译命令，支持{sources} {source} {output} {extra}，为空时使用语言的编译命令
+    #[serde(default)]
+    pub grader_compile_command: String,
+    // 函数实现题中将每个grader源文件预先编译为目标文件(xxx.o)的命令，支持{source} {output}
+    // 不为空时grader按题目数据与语言只编译一次并缓存，评测时以目标文件代替grader源文件参与编译
+    #[serde(default)]
+    pub grader_object_command: String,
+    // 未使用SPJ时的比较方式: "line"(默认)逐行比较，"token"忽略空白逐个单词比较
+    #[serde(default)]
+    pub comparator: String,
+    // 单词比较时忽略大小写
+    #[serde(default)]
+    pub case_insensitive: bool,
+    // 同时支持标准输入输出与文件读写: 输入数据同时提供为标准输入与input_file_name，
+    // 存在output_file_name时以其为输出，否则使用标准输出，忽略using_file_io
+    #[serde(default)]
+    pub dual_io: bool,
+    // 对环境污染敏感(如用户程序会写入缓存文件)的题目: 每个测试点都使用新的容器与编译目录副本，
+    // 即使开启了reuse_container，以速度换取隔离
+    #[serde(default)]
+    pub isolate_testcases: bool,
+    // 每个程序的代码长度上限(字节)，为0时使用评测机配置的max_code_length
+    #[serde(default)]
+    pub max_code_length: usize,
+    // 语言ID -> 该语言使用的镜像与命令，用于需要特殊运行环境的题目(如在数据库镜像中评测SQL)
+    #[serde(default)]
+    pub language_runtimes: BTreeMap<String, LanguageRuntime>,
+    // SQL题每个测试点在执行输入数据前执行的建表脚本，为空时只执行输入数据
+    #[serde(default)]
+    pub sql_schema: String,
+    // SPJ与交互器输出分数的范围: "percent"(默认，0~100的整数)、"full_score"(直接为测试点得分)或正数N(0~N，可以为小数)
+    #[serde(default)]
+    pub spj_score_scale: String,
+    // ICPC模式: 按顺序评测，在第一个未通过的测试点处停止，其后的测试点全部跳过
+    // 提交的结果为该测试点的状态，子任务全部通过才得分，不使用子任务的计分方式
+    #[serde(default)]
+    pub icpc_mode: bool,
+    // 评测结束后在沙箱中运行的脚本(题目数据中的文件名)，用于清理或汇总，评测摘要只读挂载在/judge/summary.json
+    #[serde(default)]
+    pub post_judge: String,
+}
+/// 题目为某种语言指定的运行环境，为空的字段使用评测机配置与语言定义
+#[derive(Deserialize, Debug, Clone, Serialize, Default)]
+pub struct LanguageRuntime {
+    #[serde(default)]
+    pub image: String,
+    // 替换语言定义中的编译命令与运行命令
+    #[serde(default)]
+    pub compile: String,
+    #[serde(default)]
+    pub run: String,
 }
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct ProblemFile {
@@ -94,11 +218,33 @@ pub struct ProblemTestcase {
     pub full_score: i64,
     pub input: String,
     pub output: String,
+    // 需要输出多个文件的测试点，非空时忽略output
+    #[serde(default)]
+    pub outputs: Vec<TestcaseOutputFile>,
+    // 期望的退出代码，仅在题目的exit_code_as_answer为true时生效
+    #[serde(default)]
+    pub expected_exit_code: Option<i32>,
+    // 该测试点单独的时间/内存限制，未指定时使用子任务的限制
+    #[serde(default)]
+    pub time_limit: Option<TimeLimit>,
+    #[serde(default)]
+    pub memory_limit: Option<MemoryLimit>,
+}
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct TestcaseOutputFile {
+    // 用户程序产生的文件
+    pub name: String,
+    // 标准答案文件
+    pub answer: String,
+    pub score: i64,
+    // 为空时使用题目的比较器，"line"为逐行比较，"token"为逐个单词比较
+    #[serde(default)]
+    pub comparator: String,
 }
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct ProblemSubtask {
-    pub time_limit: i64,
-    pub memory_limit: i64,
+    pub time_limit: TimeLimit,
+    pub memory_limit: MemoryLimit,
     pub method: String,
     pub name: String,
     pub score: i64,
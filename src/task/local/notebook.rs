@@ -0,0 +1,65 @@
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::core::misc::ResultType;
+
+// 只包含代码、去掉开头魔法命令后仍可作为Python运行的单元格魔法命令
+const TRANSPARENT_CELL_MAGICS: &[&str] = &["%%time", "%%timeit", "%%capture"];
+
+/// 单元格的source可以是字符串或字符串数组
+fn cell_source(cell: &Value) -> ResultType<String> {
+    return match cell.get("source") {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Array(lines)) => lines
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(|s| s.to_string())
+                    .ok_or(anyhow!("Invalid notebook cell source"))
+            })
+            .collect::<ResultType<Vec<String>>>()
+            .map(|v| v.concat()),
+        None => Ok("".to_string()),
+        _ => Err(anyhow!("Invalid notebook cell source")),
+    };
+}
+
+/// 将.ipynb格式的提交按顺序拼接为一个Python脚本
+/// 跳过非代码单元格，去掉魔法命令(%xxx)与shell命令(!xxx)，其他单元格魔法(如%%bash)所在的单元格整个跳过
+pub fn extract_notebook_code(notebook: &str) -> ResultType<String> {
+    let parsed = serde_json::from_str::<Value>(notebook)
+        .map_err(|e| anyhow!("Failed to parse notebook: {}", e))?;
+    let cells = parsed
+        .get("cells")
+        .and_then(|v| v.as_array())
+        .ok_or(anyhow!("Invalid notebook: missing field 'cells'"))?;
+    let mut ret = String::new();
+    for (index, cell) in cells.iter().enumerate() {
+        if cell.get("cell_type").and_then(|v| v.as_str()) != Some("code") {
+            continue;
+        }
+        let source = cell_source(cell)?;
+        let first_line = source.lines().next().unwrap_or("").trim();
+        if first_line.starts_with("%%")
+            && !TRANSPARENT_CELL_MAGICS
+                .iter()
+                .any(|v| first_line.split_whitespace().next() == Some(*v))
+        {
+            continue;
+        }
+        ret.push_str(&format!("# In[{}]:\n", index + 1));
+        for line in source.lines() {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with('%') || trimmed.starts_with('!') {
+                // 保留缩进替换为pass，避免代码块变为空
+                ret.push_str(&line[..line.len() - trimmed.len()]);
+                ret.push_str("pass\n");
+                continue;
+            }
+            ret.push_str(line);
+            ret.push('\n');
+        }
+        ret.push('\n');
+    }
+    return Ok(ret);
+}
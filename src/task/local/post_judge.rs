@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use log::{error, info};
+use serde_json::{json, Value};
+
+use crate::core::{
+    misc::ResultType, plugin::JudgeSummary, runner::ExecuteOptions, state::AppState,
+    workdir::create_work_dir,
+};
+
+use super::{
+    journal::attach_to_journal,
+    model::ProblemInfo,
+    util::{io_limit, pids_limit, problem_file_path, resource_mounts},
+};
+
+// 评测摘要在沙箱中的挂载位置
+pub const POST_JUDGE_MOUNT_DIR: &str = "/judge";
+const POST_JUDGE_SUMMARY_FILE: &str = "summary.json";
+// 收集的脚本输出长度上限(字节)
+const POST_JUDGE_OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// 评测结束后在沙箱中运行题目提供的post_judge脚本，评测摘要以只读方式挂载在/judge/summary.json
+/// 脚本在新建的空工作目录中运行，看不到评测时的工作目录(评测的工作目录由评测机自行删除)
+/// 脚本的输出与退出代码附在journal中；脚本出错不影响评测结果
+pub async fn run_post_judge_script(
+    app: &AppState,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    summary: &JudgeSummary,
+) {
+    let sid = summary.submission_id;
+    if problem_data.post_judge.is_empty() || app.config.post_judge_time_limit == 0 {
+        return;
+    }
+    let output = match execute(app, problem_data, this_problem_path, summary).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to run post judge script: {}", e);
+            json!({ "error": e.to_string() })
+        }
+    };
+    if let Err(e) = attach_to_journal(app, sid, "post_judge", output).await {
+        error!("Failed to attach post judge output to journal: {}", e);
+    }
+}
+
+async fn execute(
+    app: &AppState,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    summary: &JudgeSummary,
+) -> ResultType<Value> {
+    let script = &problem_data.post_judge;
+    if script.contains('/') || script.starts_with('.') {
+        return Err(anyhow!("Illegal post judge script name: {}", script));
+    }
+    let working_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+    tokio::fs::copy(
+        problem_file_path(this_problem_path, script)?,
+        working_dir.path().join(script),
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to copy post judge script {}: {}", script, e))?;
+    let summary_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create summary directory: {}", e))?;
+    tokio::fs::write(
+        summary_dir.path().join(POST_JUDGE_SUMMARY_FILE),
+        serde_json::to_string_pretty(summary)?,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to write judge summary: {}", e))?;
+    // docker只接受绝对路径
+    let summary_path = summary_dir
+        .path()
+        .canonicalize()
+        .map_err(|e| anyhow!("Failed to resolve summary directory: {}", e))?;
+    let mut readonly_mounts = resource_mounts(problem_data, this_problem_path)?;
+    readonly_mounts.push((
+        summary_path
+            .to_str()
+            .ok_or(anyhow!("Invalid summary directory"))?
+            .to_string(),
+        POST_JUDGE_MOUNT_DIR.to_string(),
+    ));
+    let cmdline = vec!["sh".to_string(), script.clone()];
+    info!("Run post judge script: {:?}", cmdline);
+    let result = app
+        .runner
+        .execute(
+            &app.config.docker_image,
+            working_dir.path().to_str().unwrap_or(""),
+            &cmdline,
+            app.config.trusted_profile.memory_limit.as_bytes(),
+            app.config.post_judge_time_limit * 1000,
+            POST_JUDGE_OUTPUT_LIMIT,
+            &ExecuteOptions {
+                readonly_mounts,
+                io_limit: io_limit(&app.config, problem_data),
+                pids_limit: pids_limit(&app.config, problem_data),
+                file_size_limit: app.config.post_judge_disk_limit,
+                workdir_size_limit: app.config.post_judge_disk_limit,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to run post judge script: {}", e))?;
+    info!(
+        "Post judge script exited with {}:\n{}",
+        result.exit_code, result.output
+    );
+    return Ok(json!({
+        "exit_code": result.exit_code,
+        "output": result.output,
+        "output_truncated": result.output_truncated,
+        "stderr": result.stderr,
+        "time_cost": result.time_cost / 1000,
+        "killed_by_watchdog": result.killed_by_watchdog,
+    }));
+}
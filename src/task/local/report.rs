@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use lazy_static::lazy_static;
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+
+use crate::core::misc::ResultType;
+
+use super::model::{SubmissionJudgeResult, SubmissionSubtaskResult};
+
+// 逐列编码的评测结果
+pub const REPORT_ENCODING_COLUMNAR: &str = "columnar";
+
+lazy_static! {
+    // 分块报告时每个提交已经报告过的子任务，键为提交ID
+    static ref REPORTED_SUBTASKS: Mutex<HashMap<i64, HashSet<String>>> =
+        Mutex::new(HashMap::new());
+    // 上次报告之后被修改过的子任务，由修改评测结果的地方标记，键为提交ID
+    static ref DIRTY_SUBTASKS: Mutex<HashMap<i64, HashSet<String>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// 一个需要报告的子任务
+pub struct SubtaskChunk {
+    pub name: String,
+    // 只包含这一个子任务的judge_result
+    pub judge_result: String,
+}
+
+/// 测试点总数
+pub fn testcase_count(judge_result: &SubmissionJudgeResult) -> usize {
+    return judge_result.values().map(|v| v.testcases.len()).sum();
+}
+
+/// 标记子任务的结果已被修改，下次分块报告时发送
+pub async fn mark_dirty(submission_id: i64, names: impl IntoIterator<Item = String>) {
+    DIRTY_SUBTASKS
+        .lock()
+        .await
+        .entry(submission_id)
+        .or_default()
+        .extend(names);
+}
+
+/// 子任务的测试点按字段逐列编码: testcases为字段名 -> 各测试点该字段的值，缺少的字段为null
+/// 大量测试点时省去了每个测试点重复的字段名
+fn encode_columnar(subtask: &SubmissionSubtaskResult) -> ResultType<Value> {
+    let mut value = serde_json::to_value(subtask)?;
+    let count = subtask.testcases.len();
+    let mut columns = BTreeMap::<String, Vec<Value>>::new();
+    for (i, testcase) in subtask.testcases.iter().enumerate() {
+        if let Value::Object(fields) = serde_json::to_value(testcase)? {
+            for (key, field) in fields.into_iter() {
+                columns
+                    .entry(key)
+                    .or_insert_with(|| vec![Value::Null; count])[i] = field;
+            }
+        }
+    }
+    value["testcases"] = Value::Object(
+        columns
+            .into_iter()
+            .map(|(k, v)| (k, Value::Array(v)))
+            .collect::<Map<String, Value>>(),
+    );
+    value["testcase_count"] = Value::from(count);
+    return Ok(value);
+}
+
+/// 只返回从未报告过或被标记为已修改的子任务，取出的修改标记在报告失败时应通过mark_dirty恢复
+pub async fn changed_subtasks(
+    submission_id: i64,
+    judge_result: &SubmissionJudgeResult,
+    encoding: &str,
+) -> ResultType<Vec<SubtaskChunk>> {
+    let reported = REPORTED_SUBTASKS.lock().await;
+    let reported = reported.get(&submission_id);
+    let dirty = DIRTY_SUBTASKS
+        .lock()
+        .await
+        .remove(&submission_id)
+        .unwrap_or_default();
+    let mut ret = vec![];
+    for (name, subtask) in judge_result.iter() {
+        if reported.map(|v| v.contains(name)).unwrap_or(false) && !dirty.contains(name) {
+            continue;
+        }
+        let encoded = if encoding == REPORT_ENCODING_COLUMNAR {
+            encode_columnar(subtask)?
+        } else {
+            serde_json::to_value(subtask)?
+        };
+        let mut single = Map::new();
+        single.insert(name.clone(), encoded);
+        ret.push(SubtaskChunk {
+            name: name.clone(),
+            judge_result: Value::Object(single).to_string(),
+        });
+    }
+    return Ok(ret);
+}
+
+/// 服务端接收成功后记录该子任务已报告
+pub async fn mark_reported(submission_id: i64, chunk: &SubtaskChunk) {
+    REPORTED_SUBTASKS
+        .lock()
+        .await
+        .entry(submission_id)
+        .or_default()
+        .insert(chunk.name.clone());
+}
+
+/// 提交评测结束后清除记录
+pub async fn forget_reported(submission_id: i64) {
+    REPORTED_SUBTASKS.lock().await.remove(&submission_id);
+    DIRTY_SUBTASKS.lock().await.remove(&submission_id);
+}
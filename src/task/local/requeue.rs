@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use bollard::Docker;
+use celery::{broker::RedisBroker, Celery};
+use lazy_static::lazy_static;
+use log::info;
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::core::{
+    misc::ResultType,
+    runner::images::{ensure_image, required_images},
+    state::AppState,
+    workdir::work_dir_free_space,
+};
+
+use super::{executor::local_judge_task_handler, model::ExtraJudgeConfig};
+
+// 转交的任务延迟投递的秒数，避免所有评测机都无法评测时任务被反复快速转交
+const REQUEUE_COUNTDOWN: u32 = 5;
+
+lazy_static! {
+    // 用于重新发布任务的celery应用，只在作为worker运行时设置
+    static ref REQUEUE_SENDER: RwLock<Option<Arc<Celery<RedisBroker>>>> = RwLock::new(None);
+}
+
+pub async fn set_requeue_sender(app: Arc<Celery<RedisBroker>>) {
+    *REQUEUE_SENDER.write().await = Some(app);
+}
+
+/// 本评测机无法评测该任务(缺少语言、镜像或磁盘空间)，任务应转交给其他评测机
+#[derive(Debug)]
+pub struct CapabilityError(pub String);
+
+impl std::fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+impl std::error::Error for CapabilityError {}
+
+fn capability_error(message: String) -> anyhow::Error {
+    return anyhow::Error::new(CapabilityError(message));
+}
+
+/// 收到任务时的检查: 曾被本机拒绝的任务(未达到转交次数上限时)、语言与工作目录的可用空间
+pub fn check_task_capability(
+    app: &AppState,
+    language: &str,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<()> {
+    if extra_config.requeue_count < app.config.max_requeue
+        && extra_config
+            .excluded_judgers
+            .contains(&app.config.judger_uuid)
+    {
+        return Err(capability_error(
+            "Task was already declined by this judger".to_string(),
+        ));
+    }
+    let languages = &app.config.supported_languages;
+    if !languages.is_empty() && !languages.iter().any(|v| v == language) {
+        return Err(capability_error(format!(
+            "Language {} is not supported by this judger",
+            language
+        )));
+    }
+    if work_dir_free_space(&app.config)? < app.config.work_dir_min_free_space * 1024 * 1024 {
+        return Err(capability_error(format!(
+            "Less than {} MB free space in working directory",
+            app.config.work_dir_min_free_space
+        )));
+    }
+    return Ok(());
+}
+
+/// 检查用户程序使用的镜像是否可用
+/// 只有required_images中的镜像会按pull_missing_images拉取，题目指定的其他镜像只检查本地是否存在，避免评测时从仓库拉取
+pub async fn check_image_capability(app: &AppState, image: &str) -> ResultType<()> {
+    if app.config.sandbox_backend != "docker" {
+        return Ok(());
+    }
+    let docker = Docker::connect_with_socket_defaults()
+        .map_err(|e| anyhow!("Failed to initialize docker: {}", e))?;
+    if required_images(&app.config).iter().any(|v| v == image) {
+        return ensure_image(&docker, &app.config, image, false)
+            .await
+            .map_err(|e| capability_error(e.to_string()));
+    }
+    return match docker.inspect_image(image).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(capability_error(format!(
+            "Image {} is not available on this judger: {}",
+            image, e
+        ))),
+    };
+}
+
+/// 将任务重新发布到requeue_queue，并将本机加入排除列表，返回是否已转交
+/// 达到max_requeue次或不向服务端汇报状态时不转交
+pub async fn requeue_task(
+    app: &AppState,
+    submission_data: &Value,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<bool> {
+    if !app.report_status || extra_config.requeue_count >= app.config.max_requeue {
+        return Ok(false);
+    }
+    let guard = REQUEUE_SENDER.read().await;
+    let sender = match guard.as_ref() {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    let mut extra_config = extra_config.clone();
+    extra_config.requeue_count += 1;
+    if !extra_config
+        .excluded_judgers
+        .contains(&app.config.judger_uuid)
+    {
+        extra_config
+            .excluded_judgers
+            .push(app.config.judger_uuid.clone());
+    }
+    let requeue_count = extra_config.requeue_count;
+    let mut signature = local_judge_task_handler::new(submission_data.clone(), extra_config)
+        .with_countdown(REQUEUE_COUNTDOWN);
+    if !app.config.requeue_queue.is_empty() {
+        signature = signature.with_queue(&app.config.requeue_queue);
+    }
+    sender
+        .send_task(signature)
+        .await
+        .map_err(|e| anyhow!("Failed to requeue task: {}", e))?;
+    info!(
+        "Task requeued ({}/{})",
+        requeue_count, app.config.max_requeue
+    );
+    return Ok(true);
+}
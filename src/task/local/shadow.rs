@@ -0,0 +1,89 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::Serialize;
+
+use crate::core::{api::ApiRequest, misc::ResultType, state::AppState};
+
+use super::model::SubmissionJudgeResult;
+
+/// 影子评测的结果，与发送给服务端的最后一次状态相同
+#[derive(Serialize, Clone)]
+pub struct ShadowResult {
+    pub submission_id: i64,
+    pub judger: String,
+    pub judged_at: String,
+    pub judge_result: SubmissionJudgeResult,
+    pub message: String,
+    pub extra_status: Option<String>,
+}
+
+lazy_static! {
+    // 正在评测的提交最近一次的状态，键为提交ID
+    static ref SHADOW_STATUS: Mutex<HashMap<i64, ShadowResult>> = Mutex::new(HashMap::new());
+}
+
+/// 影子模式下记录本应发送给服务端的状态
+pub fn record_shadow_status(
+    app: &AppState,
+    judge_result: &SubmissionJudgeResult,
+    message: &str,
+    extra_status: Option<&str>,
+    submission_id: i64,
+) {
+    SHADOW_STATUS.lock().unwrap().insert(
+        submission_id,
+        ShadowResult {
+            submission_id,
+            judger: app.version_string.clone(),
+            judged_at: chrono::Local::now().to_rfc3339(),
+            judge_result: judge_result.clone(),
+            message: message.to_string(),
+            extra_status: extra_status.map(|v| v.to_string()),
+        },
+    );
+}
+
+fn shadow_file(app: &AppState, submission_id: i64) -> PathBuf {
+    return PathBuf::from(&app.config.journal_dir).join(format!("{}.shadow.json", submission_id));
+}
+
+async fn save_shadow_result(app: &AppState, result: &ShadowResult) -> ResultType<()> {
+    let data = serde_json::to_vec_pretty(result)?;
+    if !app.config.journal_dir.is_empty() {
+        tokio::fs::create_dir_all(&app.config.journal_dir)
+            .await
+            .map_err(|e| anyhow!("Failed to create journal dir: {}", e))?;
+        tokio::fs::write(shadow_file(app, result.submission_id), &data)
+            .await
+            .map_err(|e| anyhow!("Failed to write shadow result: {}", e))?;
+    }
+    if !app.config.shadow_endpoint.is_empty() {
+        app.api
+            .send_external(
+                ApiRequest::external(&app.config.shadow_endpoint)
+                    .header("Content-Type", "application/json")
+                    .body(data),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to send shadow result: {}", e))?;
+    }
+    return Ok(());
+}
+
+/// 提交评测结束，保存并发送其最终结果
+pub async fn finish_shadow_judge(app: &AppState, submission_id: i64) {
+    let result = match SHADOW_STATUS.lock().unwrap().remove(&submission_id) {
+        Some(v) => v,
+        None => return,
+    };
+    info!(
+        "Shadow result of {}: {:?} {}",
+        submission_id, result.extra_status, result.message
+    );
+    if let Err(e) = save_shadow_result(app, &result).await {
+        error!("Failed to save shadow result of {}: {}", submission_id, e);
+    }
+}
@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::core::{config::JudgerConfig, misc::ResultType, runner::Session, state::AppState};
+
+use super::{
+    model::{ExtraJudgeConfig, ProblemInfo},
+    util::problem_file_path,
+};
+
+pub const SQL_PROBLEM_TYPE: &str = "sql";
+// 工作目录下的数据库文件
+pub const SQL_DATABASE_FILE: &str = "judge.db";
+
+/// 使用数据库执行script的命令
+pub fn sql_cmdline(config: &JudgerConfig, script: &str) -> String {
+    return config
+        .sql_command
+        .replace("{database}", SQL_DATABASE_FILE)
+        .replace("{script}", script);
+}
+
+/// 删除上一个测试点的数据库，再依次执行题目的建表脚本与测试点的输入数据建立新的数据库
+pub async fn reset_database(
+    app: &AppState,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    working_dir_path: &Path,
+    session: &dyn Session,
+    extra_config: &ExtraJudgeConfig,
+    input_file: &str,
+) -> ResultType<()> {
+    let database = working_dir_path.join(SQL_DATABASE_FILE);
+    if database.exists() {
+        tokio::fs::remove_file(&database)
+            .await
+            .map_err(|e| anyhow!("Failed to remove database: {}", e))?;
+    }
+    let mut scripts = vec![];
+    if !problem_data.sql_schema.is_empty() {
+        tokio::fs::copy(
+            problem_file_path(this_problem_path, &problem_data.sql_schema)?,
+            working_dir_path.join(&problem_data.sql_schema),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to copy sql schema: {}", e))?;
+        scripts.push(sql_cmdline(&app.config, &problem_data.sql_schema));
+    }
+    scripts.push(sql_cmdline(&app.config, input_file));
+    let profile = &app.config.trusted_profile;
+    let time_limit = profile.run_time_limit(extra_config.spj_execute_time_limit);
+    let command = vec!["sh".to_string(), "-c".to_string(), scripts.join(" && ")];
+    info!("Seeding database: {:?}", command);
+    let result = session
+        .execute(
+            &command,
+            profile.memory_limit.as_bytes(),
+            time_limit.as_micros(),
+            1000,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to seed database: {}", e))?;
+    if result.exit_code != 0 || result.killed_by_watchdog {
+        return Err(anyhow!(
+            "Failed to seed database, exit code {}:\n{}",
+            result.exit_code,
+            result.output
+        ));
+    }
+    return Ok(());
+}
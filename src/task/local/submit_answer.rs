@@ -1,14 +1,92 @@
-use std::{path::Path, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use super::{
     executor::IntermediateValue,
-    model::{ProblemTestcase, SubmissionTestcaseResult},
+    model::{ProblemInfo, ProblemTestcase, SubmissionTestcaseResult},
+    util::{check_problem_file_name, problem_file_path},
 };
 use crate::core::{
     compare::{Comparator, CompareResult},
     misc::ResultType,
 };
 use anyhow::anyhow;
+use serde::Deserialize;
+
+// 提交答案题的评分文件，放在题目数据目录下
+pub const ANSWER_SCORING_FILE: &str = "scoring.json";
+
+/// 一个答案文件的评分方式
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AnswerScoringEntry {
+    // 该答案文件的分值，不填时使用测试点的分值
+    #[serde(default)]
+    pub weight: Option<i64>,
+    // 除测试点输出文件外也被接受的答案文件，为题目数据目录下的文件名
+    #[serde(default)]
+    pub answers: Vec<String>,
+}
+
+/// 键为答案文件名(即测试点的输出文件名)
+pub type AnswerScoring = HashMap<String, AnswerScoringEntry>;
+
+/// 读取题目的评分文件，不存在时返回None
+/// 键必须是某个测试点的输出文件名，被接受的答案必须位于题目数据目录下
+/// 按sum计分的子任务中，各测试点的分值(weight或测试点分值)之和必须等于子任务分值
+pub async fn load_answer_scoring(
+    this_problem_path: &Path,
+    problem_data: &ProblemInfo,
+) -> ResultType<Option<AnswerScoring>> {
+    let path = this_problem_path.join(ANSWER_SCORING_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| anyhow!("Failed to read {}: {}", ANSWER_SCORING_FILE, e))?;
+    let scoring = serde_json::from_str::<AnswerScoring>(&text)
+        .map_err(|e| anyhow!("Invalid {}: {}", ANSWER_SCORING_FILE, e))?;
+    for (name, entry) in scoring.iter() {
+        if !problem_data
+            .subtasks
+            .iter()
+            .flat_map(|v| v.testcases.iter())
+            .any(|v| &v.output == name)
+        {
+            return Err(anyhow!(
+                "Unknown answer file in {}: {}",
+                ANSWER_SCORING_FILE,
+                name
+            ));
+        }
+        if entry.weight.map(|v| v < 0).unwrap_or(false) {
+            return Err(anyhow!("Negative weight of answer file: {}", name));
+        }
+        for answer in entry.answers.iter() {
+            check_problem_file_name(answer)?;
+        }
+    }
+    for subtask in problem_data.subtasks.iter().filter(|v| v.method == "sum") {
+        let total: i64 = subtask
+            .testcases
+            .iter()
+            .map(|v| {
+                scoring
+                    .get(&v.output)
+                    .and_then(|e| e.weight)
+                    .unwrap_or(v.full_score)
+            })
+            .sum();
+        if total != subtask.score {
+            return Err(anyhow!(
+                "Weights in subtask {} sum to {}, but the subtask score is {}",
+                subtask.name,
+                total,
+                subtask.score
+            ));
+        }
+    }
+    return Ok(Some(scoring));
+}
 
 pub async fn handle_submit_answer(
     testcase_result: &mut SubmissionTestcaseResult,
@@ -22,49 +100,84 @@ pub async fn handle_submit_answer(
     testcase_result.message = String::new();
     let input_file_name = &testcase.input;
     let output_file_name = &testcase.output;
-    let input_data = tokio::fs::read(this_problem_path.join(input_file_name))
-        .await
-        .map_err(|e| anyhow!("Failed to read input file: {}", e))?;
-    let output_data = tokio::fs::read(this_problem_path.join(output_file_name))
-        .await
-        .map_err(|e| anyhow!("Failed to read output file: {}", e))?;
+    let entry = intermediate_value
+        .answer_scoring()
+        .and_then(|v| v.get(output_file_name))
+        .cloned()
+        .unwrap_or_default();
+    let full_score = entry.weight.unwrap_or(testcase.full_score);
+    testcase_result.full_score = full_score;
+    let input_data = Arc::new(
+        tokio::fs::read(this_problem_path.join(input_file_name))
+            .await
+            .map_err(|e| anyhow!("Failed to read input file: {}", e))?,
+    );
     let files = intermediate_value.submit_answer().unwrap();
-    let user_answer = files.get(output_file_name);
-    if let Some(v) = user_answer {
-        match comparator
+    let user_answer = match files.get(output_file_name) {
+        Some(v) => Arc::new(v.clone()),
+        None => {
+            testcase_result.status = "wrong_answer".to_string();
+            testcase_result.score = 0;
+            testcase_result
+                .message
+                .push_str(&format!("Missing file: {}", output_file_name));
+            return Ok(());
+        }
+    };
+    // 依次与每个被接受的答案比较，取最高分
+    let mut best: Option<CompareResult> = None;
+    for answer in std::iter::once(output_file_name).chain(entry.answers.iter()) {
+        let answer_data = tokio::fs::read(problem_file_path(this_problem_path, answer)?)
+            .await
+            .map_err(|e| anyhow!("Failed to read answer file {}: {}", answer, e))?;
+        let result = match comparator
             .compare(
-                Arc::new(v.clone()),
-                Arc::new(output_data),
-                Arc::new(input_data),
-                testcase.full_score,
+                user_answer.clone(),
+                Arc::new(answer_data),
+                input_data.clone(),
+                full_score,
+                None,
             )
             .await
         {
-            Ok(CompareResult { message, score }) => {
-                testcase_result.score = score;
-                if score < testcase.full_score {
-                    testcase_result.status = "wrong_answer".to_string();
-                } else if score == testcase.full_score {
-                    testcase_result.status = "accepted".to_string();
-                } else {
-                    testcase_result.score = 0;
-                    testcase_result.status = "judge_failed".to_string();
-                    testcase_result.message = format!("Invalid score: {}", score);
-                }
-                testcase_result.message.push_str(&message);
-            }
+            Ok(v) => v,
             Err(e) => {
                 testcase_result.status = "judge_failed".to_string();
                 testcase_result.score = 0;
                 testcase_result.message.push_str(&e.to_string());
+                return Ok(());
             }
+        };
+        if result.score > full_score {
+            testcase_result.score = 0;
+            testcase_result.status = "judge_failed".to_string();
+            testcase_result.message = format!("Invalid score: {}", result.score);
+            return Ok(());
+        }
+        if best
+            .as_ref()
+            .map(|v| result.score > v.score)
+            .unwrap_or(true)
+        {
+            best = Some(result);
+        }
+        if best.as_ref().unwrap().score == full_score {
+            break;
         }
-    } else {
-        testcase_result.status = "wrong_answer".to_string();
-        testcase_result.score = 0;
-        testcase_result
-            .message
-            .push_str(&format!("Missing file: {}", output_file_name));
     }
+    let CompareResult {
+        message,
+        score,
+        feedback,
+    } = best.unwrap();
+    testcase_result.feedback = feedback;
+    testcase_result.score = score;
+    testcase_result.status = (if score == full_score {
+        "accepted"
+    } else {
+        "wrong_answer"
+    })
+    .to_string();
+    testcase_result.message.push_str(&message);
     return Ok(());
 }
@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use log::{error, info};
+use serde_json::json;
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+
+use crate::core::{
+    events::publish_event,
+    misc::ResultType,
+    plugin::{run_post_testcase, TestcaseReport},
+    runner::{open_session, ExecuteOptions, Session, TimelineOptions},
+    state::AppState,
+    util::copy_dir,
+    workdir::create_work_dir,
+};
+
+use super::{
+    cancel::{close_on_cancel, is_cancelled},
+    executor::IntermediateValue,
+    model::{ProblemSubtask, SubmissionJudgeResult, SubmissionTestcaseResult},
+    report::mark_dirty,
+    submit_answer::handle_submit_answer,
+    timing::now_millis,
+    traditional::{handle_traditional, TestcaseContext},
+    util::{icpc_mode, io_limit, pids_limit, resource_mounts, update_status, workdir_size_limit},
+};
+
+/// 测试点评测完成(包括跳过)时发布事件并调用插件
+pub async fn publish_testcase_event(
+    app: &AppState,
+    sid: i64,
+    subtask_name: &str,
+    index: usize,
+    testcase_result: &SubmissionTestcaseResult,
+) {
+    run_post_testcase(
+        app,
+        &TestcaseReport {
+            submission_id: sid,
+            subtask: subtask_name,
+            index,
+            status: &testcase_result.status,
+            score: testcase_result.score,
+            time_cost: testcase_result.time_cost,
+            memory_cost: testcase_result.memory_cost,
+        },
+    )
+    .await;
+    publish_event(
+        app,
+        sid,
+        "testcase_done",
+        json!({
+            "subtask": subtask_name,
+            "testcase": index,
+            "status": testcase_result.status,
+            "score": testcase_result.score,
+            "time_cost": testcase_result.time_cost,
+            "memory_cost": testcase_result.memory_cost
+        }),
+    )
+    .await;
+}
+
+/// 提交被取消后，正在运行与尚未评测的测试点
+fn mark_cancelled(testcase_result: &mut SubmissionTestcaseResult) {
+    testcase_result.score = 0;
+    testcase_result.status = "cancelled".to_string();
+    testcase_result.message = "评测已取消".to_string();
+}
+
+/// 将一个子任务的结果合并进总的评测结果，并上传合并后的结果
+async fn merge_and_report(
+    app: &AppState,
+    sid: i64,
+    judge_result: &Mutex<SubmissionJudgeResult>,
+    local_result: &SubmissionJudgeResult,
+    message: &str,
+) {
+    let snapshot = {
+        let mut guard = judge_result.lock().await;
+        for (name, result) in local_result.iter() {
+            guard.insert(name.clone(), result.clone());
+        }
+        guard.clone()
+    };
+    mark_dirty(sid, local_result.keys().cloned()).await;
+    update_status(app, &snapshot, message, None, sid).await;
+}
+
+/// 在编译目录的一个新副本上打开沙箱
+async fn open_subtask_sandbox(
+    ctx: &TestcaseContext<'_>,
+    subtask: &ProblemSubtask,
+) -> ResultType<(Box<dyn Session>, TempDir)> {
+    let TestcaseContext {
+        app,
+        problem_data,
+        this_problem_path,
+        compile_dir,
+        docker_image,
+        seccomp,
+        extra_config,
+        ..
+    } = *ctx;
+    let subtask_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create subtask working directory: {}", e))?;
+    copy_dir(compile_dir, subtask_dir.path()).await?;
+    info!(
+        "Subtask {} working at: {}",
+        subtask.name,
+        subtask_dir.path().to_str().unwrap_or("")
+    );
+    let options = ExecuteOptions {
+        readonly_mounts: resource_mounts(problem_data, this_problem_path)?,
+        io_limit: io_limit(&app.config, problem_data),
+        pids_limit: pids_limit(&app.config, problem_data),
+        workdir_size_limit: workdir_size_limit(&app.config, extra_config),
+        detect_suspicious: app.config.detect_suspicious_processes,
+        // 输出无限循环的程序在写满磁盘前就会被终止
+        file_size_limit: extra_config.output_file_size_limit,
+        seccomp: seccomp.map(|v| v.to_string()),
+        timeline: TimelineOptions::from_config(&app.config),
+        ..Default::default()
+    };
+    let session = open_session(
+        &app.runner,
+        docker_image,
+        subtask_dir.path().to_str().ok_or(anyhow!("?"))?,
+        &options,
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to create sandbox session: {}", e))?;
+    return Ok((session, subtask_dir));
+}
+
+/// 评测一个子任务，返回该子任务是否通过
+/// 每个子任务在编译目录的副本中运行，互不影响，因此不同子任务可以并行评测
+pub async fn judge_subtask(
+    ctx: &TestcaseContext<'_>,
+    sid: i64,
+    subtask: &ProblemSubtask,
+    intermediate_value: &IntermediateValue,
+    judge_result: &Mutex<SubmissionJudgeResult>,
+) -> ResultType<bool> {
+    let TestcaseContext {
+        app,
+        problem_data,
+        this_problem_path,
+        comparator,
+        extra_config,
+        ..
+    } = *ctx;
+    info!("Judging subtask: {:?}", subtask);
+    let started_at = now_millis();
+    let mut local_result = BTreeMap::from([(
+        subtask.name.clone(),
+        judge_result
+            .lock()
+            .await
+            .get(&subtask.name)
+            .ok_or(anyhow!("Missing subtask result: {}", subtask.name))?
+            .clone(),
+    )]);
+    local_result.get_mut(&subtask.name).unwrap().started_at = Some(started_at);
+    // 提交答案题不运行用户程序，不需要工作目录与沙箱
+    let mut sandbox = if !extra_config.submit_answer {
+        Some(open_subtask_sandbox(ctx, subtask).await?)
+    } else {
+        None
+    };
+    let mut will_skip = false;
+    let mut cancelled = false;
+    let icpc = icpc_mode(problem_data, extra_config);
+    for (i, testcase) in subtask.testcases.iter().enumerate() {
+        if !cancelled && is_cancelled(app, sid).await {
+            cancelled = true;
+        }
+        if cancelled {
+            let ret_ref = &mut local_result.get_mut(&subtask.name).unwrap().testcases[i];
+            mark_cancelled(ret_ref);
+            publish_testcase_event(app, sid, &subtask.name, i, ret_ref).await;
+            continue;
+        }
+        local_result.get_mut(&subtask.name).unwrap().testcases[i].status = "judging".to_string();
+        merge_and_report(
+            app,
+            sid,
+            judge_result,
+            &local_result,
+            &format!("评测: 子任务 {}, 测试点 {}", subtask.name, i + 1),
+        )
+        .await;
+        if will_skip {
+            let ret_ref = &mut local_result.get_mut(&subtask.name).unwrap().testcases[i];
+            ret_ref.score = 0;
+            ret_ref.status = "skipped".to_string();
+            ret_ref.message = "跳过".to_string();
+            publish_testcase_event(app, sid, &subtask.name, i, ret_ref).await;
+            continue;
+        }
+        let testcase_started_at = now_millis();
+        // 对环境污染敏感的题目，每个测试点都在新的容器与编译目录副本中运行
+        if problem_data.isolate_testcases && i > 0 {
+            if let Some((session, _)) = sandbox.take() {
+                if let Err(e) = session.close().await {
+                    error!("Failed to close sandbox session: {}", e);
+                }
+                sandbox = Some(open_subtask_sandbox(ctx, subtask).await?);
+            }
+        }
+        if let Some((session, subtask_dir)) = sandbox.as_ref() {
+            let ret = {
+                let run = handle_traditional(
+                    ctx,
+                    subtask_dir.path(),
+                    &**session,
+                    subtask,
+                    testcase,
+                    &mut local_result.get_mut(&subtask.name).unwrap().testcases[i],
+                    &mut will_skip,
+                );
+                tokio::pin!(run);
+                tokio::select! {
+                    v = &mut run => v,
+                    _ = close_on_cancel(app, sid, &**session) => {
+                        // 容器已被删除，等待测试点以出错或被杀死的结果结束
+                        cancelled = true;
+                        run.await
+                    }
+                }
+            };
+            if cancelled {
+                mark_cancelled(&mut local_result.get_mut(&subtask.name).unwrap().testcases[i]);
+            } else {
+                ret?;
+            }
+        } else {
+            let testcase_result = &mut local_result.get_mut(&subtask.name).unwrap().testcases[i];
+            handle_submit_answer(
+                testcase_result,
+                testcase,
+                this_problem_path,
+                intermediate_value,
+                comparator,
+            )
+            .await?;
+        }
+        let testcase_result = &mut local_result.get_mut(&subtask.name).unwrap().testcases[i];
+        testcase_result.judge_time = Some(now_millis() - testcase_started_at);
+        // ICPC模式下第一个未通过的测试点之后全部跳过
+        if icpc && testcase_result.status != "accepted" {
+            will_skip = true;
+        }
+        publish_testcase_event(app, sid, &subtask.name, i, testcase_result).await;
+    }
+    if let Some((session, _)) = sandbox {
+        if let Err(e) = session.close().await {
+            error!("Failed to close sandbox session: {}", e);
+        }
+    }
+    let subtask_result = local_result.get_mut(&subtask.name).unwrap();
+    subtask_result.judge_time = Some(now_millis() - started_at);
+    // ICPC模式不按子任务的计分方式计分，全部通过才得分
+    if subtask.method == "min" || icpc {
+        if subtask_result
+            .testcases
+            .iter()
+            .all(|v| v.status == "accepted")
+        {
+            subtask_result.score = subtask.score;
+        } else {
+            subtask_result.score = 0;
+        }
+    } else if subtask.method == "sum" {
+        subtask_result.score = subtask_result.testcases.iter().map(|v| v.score).sum();
+    }
+    subtask_result.status = (if subtask_result.score == subtask.score {
+        "accepted"
+    } else {
+        "unaccepted"
+    })
+    .to_string();
+    let accepted = subtask_result.status == "accepted";
+    let names = local_result.keys().cloned().collect::<Vec<String>>();
+    {
+        let mut guard = judge_result.lock().await;
+        for (name, result) in local_result.into_iter() {
+            guard.insert(name, result);
+        }
+    }
+    // 合并之后再标记，避免其他子任务的报告取走标记却发送了合并之前的结果
+    mark_dirty(sid, names).await;
+    return Ok(accepted);
+}
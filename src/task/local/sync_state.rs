@@ -0,0 +1,165 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::core::misc::ResultType;
+
+use super::util::file_sha256;
+
+// 逐个文件同步时记录同步状态的文件，位于题目数据目录下
+pub const SYNC_MANIFEST_FILE: &str = ".sync_manifest.json";
+// 旧版本为每个文件单独记录同步时间的文件后缀
+pub const LEGACY_LOCK_SUFFIX: &str = ".lock";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncedFile {
+    // 同步完成时的时间戳(秒)
+    pub synced_at: f64,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// 一个题目数据目录下已同步的文件
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct SyncManifest {
+    pub files: BTreeMap<String, SyncedFile>,
+}
+
+impl SyncManifest {
+    /// 读取题目数据目录下的同步记录，不存在或无法解析时返回空记录
+    pub async fn load(data_path: &Path) -> Self {
+        let path = data_path.join(SYNC_MANIFEST_FILE);
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(v) => v,
+            Err(_) => return Self::default(),
+        };
+        return match serde_json::from_str(&content) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Ignoring broken sync manifest {}: {}", path.display(), e);
+                Self::default()
+            }
+        };
+    }
+    /// 先写入临时文件再替换，避免中断时留下写了一半的记录
+    pub async fn save(&self, data_path: &Path) -> ResultType<()> {
+        let path = data_path.join(SYNC_MANIFEST_FILE);
+        let temp_path = data_path.join(format!("{}.tmp", SYNC_MANIFEST_FILE));
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize sync manifest: {}", e))?;
+        tokio::fs::write(&temp_path, content)
+            .await
+            .map_err(|e| anyhow!("Failed to write `{}`: {}", temp_path.display(), e))?;
+        tokio::fs::rename(&temp_path, &path)
+            .await
+            .map_err(|e| anyhow!("Failed to write `{}`: {}", path.display(), e))?;
+        return Ok(());
+    }
+}
+
+/// 读取旧版本的lock文件中记录的同步时间
+pub async fn read_legacy_lock(data_path: &Path, name: &str) -> Option<f64> {
+    let lock_file = data_path.join(format!("{}{}", name, LEGACY_LOCK_SUFFIX));
+    return tokio::fs::read_to_string(&lock_file)
+        .await
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok());
+}
+
+/// 删除旧版本的lock文件，文件不存在时不报错
+pub async fn remove_legacy_lock(data_path: &Path, name: &str) -> ResultType<()> {
+    let lock_file = data_path.join(format!("{}{}", name, LEGACY_LOCK_SUFFIX));
+    if let Err(e) = tokio::fs::remove_file(&lock_file).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(anyhow!("Failed to remove `{}`: {}", lock_file.display(), e));
+        }
+    }
+    return Ok(());
+}
+
+#[derive(Default, Debug)]
+pub struct MigrateSummary {
+    pub problems: usize,
+    pub migrated: usize,
+    // 数据文件缺失或lock文件无法解析，保留原样
+    pub skipped: usize,
+}
+
+/// 将一个题目数据目录下的lock文件转换为同步记录，记录写入成功后才删除lock文件
+async fn migrate_problem_dir(data_path: &Path, summary: &mut MigrateSummary) -> ResultType<()> {
+    let mut manifest = SyncManifest::load(data_path).await;
+    let mut migrated = vec![];
+    let mut entries = tokio::fs::read_dir(data_path)
+        .await
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", data_path.display(), e))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", data_path.display(), e))?
+    {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let name = match file_name.strip_suffix(LEGACY_LOCK_SUFFIX) {
+            Some(v) if !v.is_empty() => v.to_string(),
+            _ => continue,
+        };
+        let data_file = data_path.join(&name);
+        let synced_at = read_legacy_lock(data_path, &name).await;
+        let (synced_at, metadata) = match (synced_at, tokio::fs::metadata(&data_file).await) {
+            (Some(t), Ok(m)) if m.is_file() => (t, m),
+            _ => {
+                warn!("Skipping `{}`: no usable data file", entry.path().display());
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        if !manifest.files.contains_key(&name) {
+            manifest.files.insert(
+                name.clone(),
+                SyncedFile {
+                    synced_at,
+                    size: metadata.len(),
+                    sha256: file_sha256(&data_file).await?,
+                },
+            );
+        }
+        migrated.push(name);
+    }
+    if migrated.is_empty() {
+        return Ok(());
+    }
+    manifest.save(data_path).await?;
+    for name in migrated.iter() {
+        remove_legacy_lock(data_path, name).await?;
+    }
+    summary.migrated += migrated.len();
+    return Ok(());
+}
+
+/// 扫描数据目录下所有的题目，将旧版本的lock文件转换为同步记录
+pub async fn migrate_legacy_sync_state(data_dir: &Path) -> ResultType<MigrateSummary> {
+    let mut summary = MigrateSummary::default();
+    let mut entries = tokio::fs::read_dir(data_dir)
+        .await
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", data_dir.display(), e))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", data_dir.display(), e))?
+    {
+        // 题目数据目录以题目ID命名，跳过解压中的临时目录等
+        let is_problem_dir = entry
+            .file_name()
+            .to_str()
+            .map(|v| v.parse::<i64>().is_ok())
+            .unwrap_or(false);
+        if !is_problem_dir || !entry.path().is_dir() {
+            continue;
+        }
+        info!("Migrating sync state of {}", entry.path().display());
+        migrate_problem_dir(&entry.path(), &mut summary).await?;
+        summary.problems += 1;
+    }
+    return Ok(summary);
+}
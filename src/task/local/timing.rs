@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+lazy_static! {
+    // 正在评测的提交各阶段的时间，键为提交ID
+    static ref JUDGE_TIMINGS: std::sync::Mutex<HashMap<i64, JudgeTimings>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// 评测各阶段的时间，时间点为unix时间戳(毫秒)，随评测状态一同发送，用于分析评测耗时
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct JudgeTimings {
+    // 收到评测任务的时间，与取得评测名额(task_count_lock)开始评测的时间
+    pub received_at: i64,
+    pub started_at: i64,
+    // 等待评测名额的时间(毫秒)
+    pub queue_wait: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_started_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_ended_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_started_at: Option<i64>,
+    // 代码检查与编译的用时(毫秒)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub judge_started_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+}
+
+/// 当前的unix时间戳(毫秒)
+pub fn now_millis() -> i64 {
+    return chrono::Utc::now().timestamp_millis();
+}
+
+/// 开始记录一个提交的评测时间，received_at为收到任务(等待评测名额之前)的时间
+pub fn start_timing(submission_id: i64, received_at: i64) {
+    let started_at = now_millis();
+    JUDGE_TIMINGS.lock().unwrap().insert(
+        submission_id,
+        JudgeTimings {
+            received_at,
+            started_at,
+            queue_wait: (started_at - received_at).max(0),
+            ..Default::default()
+        },
+    );
+}
+
+/// 修改一个提交的评测时间，未开始记录时忽略
+pub fn record_timing(submission_id: i64, f: impl FnOnce(&mut JudgeTimings)) {
+    if let Some(v) = JUDGE_TIMINGS.lock().unwrap().get_mut(&submission_id) {
+        f(v);
+    }
+}
+
+/// 等待评测名额的时间(毫秒)
+pub fn queue_wait(submission_id: i64) -> Option<i64> {
+    return JUDGE_TIMINGS
+        .lock()
+        .unwrap()
+        .get(&submission_id)
+        .map(|v| v.queue_wait);
+}
+
+/// 已记录的评测时间(JSON)
+pub fn timings_json(submission_id: i64) -> Option<String> {
+    return JUDGE_TIMINGS
+        .lock()
+        .unwrap()
+        .get(&submission_id)
+        .and_then(|v| serde_json::to_string(v).ok());
+}
+
+/// 提交评测结束后清除记录
+pub fn forget_timing(submission_id: i64) {
+    JUDGE_TIMINGS.lock().unwrap().remove(&submission_id);
+}
@@ -5,131 +5,582 @@ use tokio::io::AsyncReadExt;
 
 use crate::{
     core::{
-        compare::{Comparator, CompareResult},
+        compare::{builtin_comparator, Comparator, CompareResult},
+        interactor::{InteractResult, Interactor},
         misc::ResultType,
         model::LanguageConfig,
-        runner::docker::execute_in_docker,
+        runner::{
+            execute_paired, merge_paired_results, ExecuteOptions, ExecuteResult, Session,
+            TimelineOptions, COMM_FIRST_TO_SECOND, COMM_SECOND_TO_FIRST, INTERACT_USER_INPUT,
+            INTERACT_USER_OUTPUT,
+        },
         state::AppState,
     },
-    task::local::DEFAULT_PROGRAM_FILENAME,
+    task::local::{program_filename, DEFAULT_PROGRAM_FILENAME},
 };
 
-use super::model::{
-    ExtraJudgeConfig, ProblemInfo, ProblemSubtask, ProblemTestcase, SubmissionJudgeResult,
+use super::{
+    model::{
+        ExtraJudgeConfig, ProblemInfo, ProblemSubtask, ProblemTestcase, SubmissionTestcaseResult,
+    },
+    sql::{reset_database, sql_cmdline, SQL_PROBLEM_TYPE},
+    util::{
+        check_problem_file_name, check_work_file_name, container_memory_limit, io_limit,
+        memory_limit_exceeded, pids_limit, resource_mounts, workdir_size_limit,
+    },
 };
 use anyhow::anyhow;
+
+/// 检查需要输出多个文件的测试点: 文件名合法且不重复，各文件分数之和等于测试点分数
+pub fn check_testcase_outputs(problem_data: &ProblemInfo) -> ResultType<()> {
+    for subtask in problem_data.subtasks.iter() {
+        for (i, testcase) in subtask.testcases.iter().enumerate() {
+            if testcase.outputs.is_empty() {
+                continue;
+            }
+            let mut names = vec![];
+            for output in testcase.outputs.iter() {
+                check_work_file_name(&output.name)?;
+                check_problem_file_name(&output.answer)?;
+                if names.contains(&output.name.as_str()) {
+                    return Err(anyhow!(
+                        "Duplicate output file {} in testcase #{} of subtask {}",
+                        output.name,
+                        i + 1,
+                        subtask.name
+                    ));
+                }
+                names.push(output.name.as_str());
+            }
+            let total = testcase.outputs.iter().map(|v| v.score).sum::<i64>();
+            if total != testcase.full_score {
+                return Err(anyhow!(
+                    "Scores of output files in testcase #{} of subtask {} sum to {}, expected {}",
+                    i + 1,
+                    subtask.name,
+                    total,
+                    testcase.full_score
+                ));
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// 根据运行结果得出的测试点结果，Finished表示程序正常结束，需要比较输出
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RunVerdict {
+    MemoryLimitExceeded,
+    IdlenessLimitExceeded,
+    WorkdirSizeLimitExceeded,
+    PidsLimitExceeded,
+    // CPU时间超限
+    TimeLimitExceeded,
+    // 墙上时间超限(被评测机强制结束或运行时间超过墙上时间限制)
+    WallTimeLimitExceeded,
+    // 写入的文件超过了沙箱的文件大小限制(SIGXFSZ)
+    FileSizeLimitExceeded,
+    RuntimeError,
+    Finished,
+}
+
+/// 按优先级判断运行结果，时间单位均为微秒
+/// 被评测机因超时杀死的程序退出代码形如信号终止(137)，必须先于退出代码判断
+/// 自行退出的程序只有CPU时间超限才判为超时，否则按退出代码判断
+pub fn run_verdict(
+    run_result: &ExecuteResult,
+    memory_limit_exceeded: bool,
+    idle_limit_exceeded: bool,
+    time_limit: i64,
+    wall_time_limit: i64,
+    exit_code_as_answer: bool,
+) -> RunVerdict {
+    if memory_limit_exceeded {
+        return RunVerdict::MemoryLimitExceeded;
+    }
+    // 管道被关闭后程序通常会因读到EOF而出错，必须先于其他判断
+    if idle_limit_exceeded {
+        return RunVerdict::IdlenessLimitExceeded;
+    }
+    if run_result.workdir_size_limit_exceeded {
+        return RunVerdict::WorkdirSizeLimitExceeded;
+    }
+    // fork炸弹等程序创建进程失败后通常会超时或出错，此时给出更明确的原因
+    if run_result.pids_limit_exceeded
+        && (run_result.exit_code != 0
+            || run_result.killed_by_watchdog
+            || run_result.cpu_time_cost >= time_limit)
+    {
+        return RunVerdict::PidsLimitExceeded;
+    }
+    if run_result.cpu_time_cost >= time_limit {
+        return RunVerdict::TimeLimitExceeded;
+    }
+    if run_result.killed_by_watchdog {
+        return RunVerdict::WallTimeLimitExceeded;
+    }
+    if run_result.exit_code == 128 + libc::SIGXFSZ {
+        return RunVerdict::FileSizeLimitExceeded;
+    }
+    if run_result.exit_code != 0 && !exit_code_as_answer {
+        return RunVerdict::RuntimeError;
+    }
+    if run_result.time_cost >= wall_time_limit {
+        return RunVerdict::WallTimeLimitExceeded;
+    }
+    return RunVerdict::Finished;
+}
+
+/// 同一提交的所有子任务与测试点共用的评测参数
+pub struct TestcaseContext<'a> {
+    pub app: &'a AppState,
+    pub problem_data: &'a ProblemInfo,
+    pub this_problem_path: &'a Path,
+    // 编译目录，每个子任务在其副本中运行
+    pub compile_dir: &'a Path,
+    pub time_scale: f64,
+    pub lang_config: &'a LanguageConfig,
+    // 用户程序使用的镜像
+    pub docker_image: &'a str,
+    // 用户程序容器的seccomp配置
+    pub seccomp: Option<&'a str>,
+    pub comparator: &'a dyn Comparator,
+    pub interactor: Option<&'a dyn Interactor>,
+    pub extra_config: &'a ExtraJudgeConfig,
+}
+
 #[inline]
 pub async fn handle_traditional(
-    problem_data: &ProblemInfo,
-    this_problem_path: &Path,
+    ctx: &TestcaseContext<'_>,
     working_dir_path: &Path,
-    testcase: &ProblemTestcase,
+    session: &dyn Session,
     subtask: &ProblemSubtask,
-    time_scale: f64,
-    lang_config: &LanguageConfig,
-    app: &AppState,
-    comparator: &dyn Comparator,
-    extra_config: &ExtraJudgeConfig,
-    i: usize,
+    testcase: &ProblemTestcase,
+    testcase_result: &mut SubmissionTestcaseResult,
     will_skip: &mut bool,
-    judge_result: &mut SubmissionJudgeResult,
 ) -> ResultType<()> {
-    let input_file = if problem_data.using_file_io == 1 {
+    let TestcaseContext {
+        app,
+        problem_data,
+        this_problem_path,
+        time_scale,
+        lang_config,
+        docker_image,
+        seccomp,
+        comparator,
+        interactor,
+        extra_config,
+        ..
+    } = *ctx;
+    // 通信题的两个程序通过标准输入输出相连，测试数据只能通过文件读写
+    let communication = problem_data.problem_type == "communication";
+    // SQL题在每个测试点重新建立的数据库上执行用户脚本
+    let sql = problem_data.problem_type == SQL_PROBLEM_TYPE;
+    // 题目的文件读写声明不可靠时，标准输入输出与文件读写都可以使用
+    let dual_io = problem_data.dual_io
+        && interactor.is_none()
+        && !communication
+        && !sql
+        && testcase.outputs.is_empty();
+    let file_io = problem_data.using_file_io == 1 && !dual_io && !sql;
+    let input_file = if file_io {
         problem_data.input_file_name.as_str()
     } else {
         "in"
     };
-    let output_file = if problem_data.using_file_io == 1 {
+    let output_file = if file_io {
         problem_data.output_file_name.as_str()
     } else {
         "out"
     };
     info!("Input file: {}, output file: {}", input_file, output_file);
-    tokio::fs::copy(
-        this_problem_path.join(&testcase.input),
-        working_dir_path.join(input_file),
-    )
-    .await
-    .map_err(|e| anyhow!("Failed to copy input file: {}", e))?;
-    let scaled_time = (subtask.time_limit as f64 * time_scale) as i64;
-    let execute_cmdline = lang_config.run_s(
-        &lang_config.output(DEFAULT_PROGRAM_FILENAME),
-        &(if problem_data.using_file_io == 1 {
-            "".to_string()
-        } else {
-            format!("< {} > {}", input_file, output_file)
-        }),
-    );
+    // (用户输出文件, 标准答案文件, 分数, 比较器)
+    let mut outputs = if testcase.outputs.is_empty() {
+        vec![(
+            output_file.to_string(),
+            testcase.output.clone(),
+            testcase.full_score,
+            "".to_string(),
+        )]
+    } else {
+        testcase
+            .outputs
+            .iter()
+            .map(|v| {
+                (
+                    v.name.clone(),
+                    v.answer.clone(),
+                    v.score,
+                    v.comparator.clone(),
+                )
+            })
+            .collect::<Vec<(String, String, i64, String)>>()
+    };
+    // 清理上一个测试点留下的输出文件
+    let mut stale_files = outputs.iter().map(|v| v.0.as_str()).collect::<Vec<&str>>();
+    if dual_io && !problem_data.output_file_name.is_empty() {
+        stale_files.push(problem_data.output_file_name.as_str());
+    }
+    for user_file in stale_files.into_iter() {
+        let path = working_dir_path.join(user_file);
+        if path.exists() {
+            tokio::fs::remove_file(&path)
+                .await
+                .map_err(|e| anyhow!("Failed to remove stale output {}: {}", user_file, e))?;
+        }
+    }
+    // 交互题的输入数据只交给交互器
+    if interactor.is_none() {
+        tokio::fs::copy(
+            this_problem_path.join(&testcase.input),
+            working_dir_path.join(input_file),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to copy input file: {}", e))?;
+        if dual_io && !problem_data.input_file_name.is_empty() {
+            tokio::fs::copy(
+                this_problem_path.join(&testcase.input),
+                working_dir_path.join(&problem_data.input_file_name),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to copy input file: {}", e))?;
+        }
+    }
+    if sql {
+        reset_database(
+            app,
+            problem_data,
+            this_problem_path,
+            working_dir_path,
+            session,
+            extra_config,
+            input_file,
+        )
+        .await?;
+    }
+    // 测试点单独指定的限制优先于子任务的限制
+    let time_limit = testcase.time_limit.unwrap_or(subtask.time_limit);
+    let memory_limit = testcase.memory_limit.unwrap_or(subtask.memory_limit);
+    // jvm语言以内存限制作为堆大小，容器额外留出虚拟机本身占用的内存
+    let lang_config = &lang_config.with_memory_limit(memory_limit.as_bytes());
+    let container_limit = container_memory_limit(&app.config, lang_config, memory_limit.as_bytes());
+    let scaled_time = time_limit.scale(time_scale);
+    // 睡眠或阻塞的程序不消耗CPU时间，用墙上时间兜底
+    let wall_time_limit = scaled_time.scale(app.config.wall_time_limit_factor);
+    let execute_cmdline = if sql {
+        format!(
+            "{} > {}",
+            sql_cmdline(&app.config, &lang_config.output(DEFAULT_PROGRAM_FILENAME)),
+            output_file
+        )
+    } else {
+        lang_config.run_s(
+            &lang_config.output(DEFAULT_PROGRAM_FILENAME),
+            &(if interactor.is_some() {
+                format!("< {} > {}", INTERACT_USER_INPUT, INTERACT_USER_OUTPUT)
+            } else if communication {
+                format!("> {} < {}", COMM_FIRST_TO_SECOND, COMM_SECOND_TO_FIRST)
+            } else if file_io {
+                "".to_string()
+            } else {
+                format!("< {} > {}", input_file, output_file)
+            }),
+        )
+    };
     info!("Run command line: {}", execute_cmdline);
-    let run_result = execute_in_docker(
-        &app.config.docker_image,
-        working_dir_path.to_str().unwrap(),
-        &vec!["sh".to_string(), "-c".to_string(), execute_cmdline],
-        subtask.memory_limit * 1024 * 1024,
-        scaled_time * 1000,
-        1000,
-    )
-    .await
-    .map_err(|e| anyhow!("Fatal error: {}", e))?;
+    let user_command = vec!["sh".to_string(), "-c".to_string(), execute_cmdline];
+    // 交互题空闲超时的信息
+    let mut idle_message = None;
+    let (run_result, interact_result) = if let Some(interactor) = interactor {
+        let input_data = tokio::fs::read(this_problem_path.join(&testcase.input))
+            .await
+            .map_err(|e| anyhow!("Failed to read input data: {}, {}", testcase.input, e))?;
+        let answer_data = tokio::fs::read(this_problem_path.join(&testcase.output))
+            .await
+            .map_err(|e| anyhow!("Failed to read answer data: {}, {}", testcase.output, e))?;
+        // 交互题的用户程序需要与交互器同时运行，不使用session
+        let options = ExecuteOptions {
+            readonly_mounts: resource_mounts(problem_data, this_problem_path)?,
+            io_limit: io_limit(&app.config, problem_data),
+            pids_limit: pids_limit(&app.config, problem_data),
+            workdir_size_limit: workdir_size_limit(&app.config, extra_config),
+            detect_suspicious: app.config.detect_suspicious_processes,
+            file_size_limit: extra_config.output_file_size_limit,
+            seccomp: seccomp.map(|v| v.to_string()),
+            timeline: TimelineOptions::from_config(&app.config),
+            ..Default::default()
+        };
+        let InteractResult {
+            user_result,
+            score,
+            message,
+            idle_limit_exceeded,
+        } = interactor
+            .interact(
+                working_dir_path,
+                &user_command,
+                container_limit,
+                wall_time_limit.as_micros(),
+                &options,
+                Arc::new(input_data),
+                Arc::new(answer_data),
+                testcase.full_score,
+            )
+            .await
+            .map_err(|e| anyhow!("Fatal error: {}", e))?;
+        if idle_limit_exceeded {
+            idle_message = Some(message.clone());
+        }
+        (
+            user_result,
+            Some(CompareResult {
+                score,
+                message,
+                feedback: vec![],
+            }),
+        )
+    } else if communication {
+        let second_command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            lang_config.run_s(
+                &lang_config.output(&program_filename(1)),
+                &format!("< {} > {}", COMM_FIRST_TO_SECOND, COMM_SECOND_TO_FIRST),
+            ),
+        ];
+        info!("Second program command line: {:?}", second_command);
+        // 两个程序需要同时运行，不使用session
+        let options = ExecuteOptions {
+            readonly_mounts: resource_mounts(problem_data, this_problem_path)?,
+            io_limit: io_limit(&app.config, problem_data),
+            pids_limit: pids_limit(&app.config, problem_data),
+            workdir_size_limit: workdir_size_limit(&app.config, extra_config),
+            detect_suspicious: app.config.detect_suspicious_processes,
+            file_size_limit: extra_config.output_file_size_limit,
+            seccomp: seccomp.map(|v| v.to_string()),
+            timeline: TimelineOptions::from_config(&app.config),
+            ..Default::default()
+        };
+        let (first_result, second_result) = execute_paired(
+            &*app.runner,
+            docker_image,
+            working_dir_path.to_str().ok_or(anyhow!("?"))?,
+            &user_command,
+            &second_command,
+            container_limit,
+            wall_time_limit.as_micros(),
+            &options,
+        )
+        .await
+        .map_err(|e| anyhow!("Fatal error: {}", e))?;
+        (merge_paired_results(first_result, second_result), None)
+    } else {
+        let run_result = session
+            .execute(
+                &user_command,
+                container_limit,
+                wall_time_limit.as_micros(),
+                1000,
+            )
+            .await
+            .map_err(|e| anyhow!("Fatal error: {}", e))?;
+        (run_result, None)
+    };
     info!("Run result:\n{:#?}", run_result);
+    // 程序写了声明的输出文件时以其为输出，否则使用标准输出
+    if dual_io
+        && !problem_data.output_file_name.is_empty()
+        && working_dir_path
+            .join(&problem_data.output_file_name)
+            .exists()
+    {
+        info!("Using output file: {}", problem_data.output_file_name);
+        outputs[0].0 = problem_data.output_file_name.clone();
+    }
     {
-        let mut testcase_result = &mut judge_result.get_mut(&subtask.name).unwrap().testcases[i];
         testcase_result.memory_cost = run_result.memory_cost;
-        testcase_result.time_cost = (run_result.time_cost as f64 / 1000.0).ceil() as i64;
-        if run_result.memory_cost / 1024 / 1024 >= subtask.memory_limit {
+        testcase_result.timeline = run_result.timeline.clone();
+        testcase_result.time_cost = (run_result.cpu_time_cost as f64 / 1000.0).ceil() as i64;
+        let verdict = run_verdict(
+            &run_result,
+            memory_limit_exceeded(
+                &app.config,
+                lang_config,
+                &run_result,
+                memory_limit.as_bytes(),
+            ),
+            idle_message.is_some(),
+            scaled_time.as_micros(),
+            wall_time_limit.as_micros(),
+            problem_data.exit_code_as_answer,
+        );
+        if verdict == RunVerdict::MemoryLimitExceeded {
             testcase_result.update_status("memory_limit_exceed");
-        } else if run_result.time_cost >= scaled_time * 1000 {
+        } else if verdict == RunVerdict::IdlenessLimitExceeded {
+            testcase_result.update(
+                "idleness_limit_exceeded",
+                idle_message.as_deref().unwrap_or(""),
+            );
+        } else if verdict == RunVerdict::WorkdirSizeLimitExceeded {
+            testcase_result.update(
+                "output_size_limit_exceed",
+                &format!(
+                    "写入工作目录的数据超过限制 ({} 字节)",
+                    workdir_size_limit(&app.config, extra_config)
+                ),
+            );
+        } else if verdict == RunVerdict::PidsLimitExceeded {
+            testcase_result.update(
+                "runtime_error",
+                &format!(
+                    "创建的进程或线程数超过限制 ({})",
+                    pids_limit(&app.config, problem_data)
+                ),
+            );
+        } else if verdict == RunVerdict::TimeLimitExceeded {
             testcase_result.update_status("time_limit_exceed");
-        } else if run_result.exit_code != 0 {
+        } else if verdict == RunVerdict::WallTimeLimitExceeded {
+            testcase_result.update(
+                "time_limit_exceed",
+                &format!("墙上时间超过 {} ms", wall_time_limit.as_millis()),
+            );
+        } else if verdict == RunVerdict::FileSizeLimitExceeded {
+            testcase_result.update(
+                "output_size_limit_exceed",
+                &format!("退出代码: {} (SIGXFSZ: 输出文件过大)", run_result.exit_code),
+            );
+        } else if verdict == RunVerdict::RuntimeError {
             testcase_result.update(
                 "runtime_error",
-                &format!("退出代码: {}", run_result.exit_code),
+                &match (run_result.signal_name(), run_result.signal_description()) {
+                    (Some(signal), Some(description)) => format!(
+                        "{} (退出代码: {}, {})",
+                        description, run_result.exit_code, signal
+                    ),
+                    _ => format!("退出代码: {}", run_result.exit_code),
+                },
             );
         } else {
-            let user_out = match tokio::fs::File::open(working_dir_path.join(output_file)).await {
-                Ok(mut f) => match f.metadata().await {
-                    Ok(d) => {
-                        if d.len() > extra_config.output_file_size_limit as u64 {
-                            testcase_result.update("output_size_limit_exceed", "输出文件过大");
+            let full_score = testcase.full_score;
+            let exit_code = if problem_data.exit_code_as_answer {
+                Some(run_result.exit_code)
+            } else {
+                None
+            };
+            let exit_code_mismatch = match (testcase.expected_exit_code, exit_code) {
+                (Some(expected), Some(actual)) if expected != actual => Some(format!(
+                    "期望退出代码: {}, 实际退出代码: {}",
+                    expected, actual
+                )),
+                _ => None,
+            };
+            let CompareResult {
+                score,
+                message,
+                feedback,
+            } = if let Some(v) = interact_result {
+                v
+            } else if let Some(message) = exit_code_mismatch {
+                CompareResult {
+                    score: 0,
+                    message,
+                    feedback: vec![],
+                }
+            } else {
+                let input_data = Arc::new(
+                    tokio::fs::read(this_problem_path.join(&testcase.input))
+                        .await
+                        .map_err(|e| {
+                            anyhow!("Failed to read input data: {}, {}", testcase.input, e)
+                        })?,
+                );
+                let mut score = 0;
+                let mut messages = vec![];
+                let mut feedback = vec![];
+                let line_comparator = builtin_comparator(&app.config, "line", false)?;
+                let token_comparator =
+                    builtin_comparator(&app.config, "token", problem_data.case_insensitive)?;
+                for (user_file, answer_file, file_score, comparator_name) in outputs.iter() {
+                    let user_out =
+                        match tokio::fs::File::open(working_dir_path.join(user_file)).await {
+                            Ok(mut f) => match f.metadata().await {
+                                Ok(d) => {
+                                    if d.len() > extra_config.output_file_size_limit as u64 {
+                                        testcase_result.update(
+                                            "output_size_limit_exceed",
+                                            &format!("输出文件过大: {}", user_file),
+                                        );
+                                        return Ok(());
+                                    }
+                                    let mut v: Vec<u8> = vec![];
+                                    match f.read_to_end(&mut v).await {
+                                        Ok(_) => v,
+                                        Err(_) => vec![],
+                                    }
+                                }
+                                Err(e) => {
+                                    error!("Failed to get metadata: {}", e);
+                                    vec![]
+                                }
+                            },
+                            Err(e) => {
+                                error!("Failed to open output file {}: {}", user_file, e);
+                                vec![]
+                            }
+                        };
+                    let answer_data = tokio::fs::read(this_problem_path.join(answer_file))
+                        .await
+                        .map_err(|e| {
+                            anyhow!("Failed to read answer data: {}, {}", answer_file, e)
+                        })?;
+                    let file_comparator: &dyn Comparator = match comparator_name.as_str() {
+                        "" => comparator,
+                        "line" => &*line_comparator,
+                        "token" => &*token_comparator,
+                        other => {
+                            // 只影响该测试点，其余测试点照常评测
+                            testcase_result.update(
+                                "judge_failed",
+                                &format!("Unknown comparator of {}: {}", user_file, other),
+                            );
                             return Ok(());
                         }
-                        let mut v: Vec<u8> = vec![];
-                        match f.read_to_end(&mut v).await {
-                            Ok(_) => v,
-                            Err(_) => vec![],
-                        }
+                    };
+                    let result = match file_comparator
+                        .compare(
+                            Arc::new(user_out),
+                            Arc::new(answer_data),
+                            input_data.clone(),
+                            *file_score,
+                            exit_code,
+                        )
+                        .await
+                    {
+                        Ok(v) => v,
+                        Err(e) => CompareResult {
+                            score: 0,
+                            message: e.to_string(),
+                            feedback: vec![],
+                        },
+                    };
+                    score += result.score;
+                    if outputs.len() == 1 {
+                        messages.push(result.message);
+                        feedback.extend(result.feedback);
+                    } else {
+                        messages.push(format!("{}: {}", user_file, result.message));
+                        // 多个输出文件时以输出文件名区分
+                        feedback.extend(result.feedback.into_iter().map(|mut v| {
+                            v.name = format!("{}/{}", user_file, v.name);
+                            v
+                        }));
                     }
-                    Err(e) => {
-                        error!("Failed to get metadata: {}", e);
-                        vec![]
-                    }
-                },
-                Err(e) => {
-                    error!("Failed to open output file: {}", e);
-                    vec![]
                 }
-            };
-            let full_score = testcase.full_score;
-            let input_data = tokio::fs::read(this_problem_path.join(&testcase.input))
-                .await
-                .map_err(|e| anyhow!("Failed to read input data: {}, {}", testcase.input, e))?;
-            let answer_data = tokio::fs::read(this_problem_path.join(&testcase.output))
-                .await
-                .map_err(|e| anyhow!("Failed to read answer data: {}, {}", testcase.output, e))?;
-            let CompareResult { score, message } = match comparator
-                .compare(
-                    Arc::new(user_out.into()),
-                    Arc::new(answer_data.into()),
-                    Arc::new(input_data.into()),
-                    full_score,
-                )
-                .await
-            {
-                Ok(v) => v,
-                Err(e) => CompareResult {
-                    score: 0,
-                    message: e.to_string(),
-                },
+                CompareResult {
+                    score,
+                    message: messages.join("\n"),
+                    feedback,
+                }
             };
             if score < full_score {
                 testcase_result.update_status("wrong_answer");
@@ -139,7 +590,22 @@ pub async fn handle_traditional(
                 testcase_result.update("unaccepted", &format!("Illegal score: {}", score));
             }
             testcase_result.score = score;
-            testcase_result.message = message;  
+            testcase_result.message = message;
+            testcase_result.feedback = feedback;
+        }
+        // 标准错误可能泄露测试数据，只在样例子任务的运行错误中附加
+        let is_sample = extra_config.samples_subtask.as_ref() == Some(&subtask.name);
+        if is_sample && testcase_result.status == "runtime_error" {
+            if let Some(excerpt) = run_result.stderr_excerpt(app.config.stderr_excerpt_length) {
+                testcase_result
+                    .message
+                    .push_str(&format!("\n标准错误输出: {}", excerpt));
+            }
+        }
+        for warning in run_result.warnings.iter() {
+            testcase_result
+                .message
+                .push_str(&format!("\n警告: {}", warning));
         }
         if testcase_result.status != "accepted" && subtask.method == "min" {
             *will_skip = true;
@@ -147,3 +613,184 @@ pub async fn handle_traditional(
     }
     return Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{run_verdict, RunVerdict};
+    use crate::core::runner::ExecuteResult;
+
+    // 时间限制1000ms，墙上时间限制2000ms
+    const TIME_LIMIT: i64 = 1_000_000;
+    const WALL_TIME_LIMIT: i64 = 2_000_000;
+
+    fn verdict(run_result: &ExecuteResult, mle: bool, idle: bool) -> RunVerdict {
+        return run_verdict(run_result, mle, idle, TIME_LIMIT, WALL_TIME_LIMIT, false);
+    }
+
+    #[test]
+    fn finished_just_below_limits() {
+        let result = ExecuteResult {
+            cpu_time_cost: TIME_LIMIT - 1,
+            time_cost: WALL_TIME_LIMIT - 1,
+            ..Default::default()
+        };
+        assert_eq!(verdict(&result, false, false), RunVerdict::Finished);
+    }
+
+    #[test]
+    fn cpu_time_at_limit_is_tle() {
+        let result = ExecuteResult {
+            cpu_time_cost: TIME_LIMIT,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, false),
+            RunVerdict::TimeLimitExceeded
+        );
+    }
+
+    #[test]
+    fn wall_time_at_limit_is_tle() {
+        let result = ExecuteResult {
+            time_cost: WALL_TIME_LIMIT,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, false),
+            RunVerdict::WallTimeLimitExceeded
+        );
+    }
+
+    #[test]
+    fn mle_before_tle() {
+        let result = ExecuteResult {
+            cpu_time_cost: TIME_LIMIT,
+            killed_by_watchdog: true,
+            exit_code: 137,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, true, false),
+            RunVerdict::MemoryLimitExceeded
+        );
+    }
+
+    #[test]
+    fn mle_before_idle() {
+        let result = ExecuteResult::default();
+        assert_eq!(
+            verdict(&result, true, true),
+            RunVerdict::MemoryLimitExceeded
+        );
+    }
+
+    #[test]
+    fn idle_before_runtime_error_and_tle() {
+        let result = ExecuteResult {
+            exit_code: 1,
+            cpu_time_cost: TIME_LIMIT,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, true),
+            RunVerdict::IdlenessLimitExceeded
+        );
+    }
+
+    #[test]
+    fn killed_by_watchdog_is_tle_not_re() {
+        // 被评测机杀死的程序退出代码为137，不能判为运行错误
+        let result = ExecuteResult {
+            exit_code: 137,
+            killed_by_watchdog: true,
+            cpu_time_cost: TIME_LIMIT - 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, false),
+            RunVerdict::WallTimeLimitExceeded
+        );
+    }
+
+    #[test]
+    fn cpu_tle_before_watchdog() {
+        let result = ExecuteResult {
+            exit_code: 137,
+            killed_by_watchdog: true,
+            cpu_time_cost: TIME_LIMIT,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, false),
+            RunVerdict::TimeLimitExceeded
+        );
+    }
+
+    #[test]
+    fn runtime_error_before_wall_time() {
+        // 自行退出的程序只有CPU时间超限才判为超时
+        let result = ExecuteResult {
+            exit_code: 1,
+            time_cost: WALL_TIME_LIMIT,
+            cpu_time_cost: TIME_LIMIT - 1,
+            ..Default::default()
+        };
+        assert_eq!(verdict(&result, false, false), RunVerdict::RuntimeError);
+    }
+
+    #[test]
+    fn exit_code_as_answer_is_not_runtime_error() {
+        let result = ExecuteResult {
+            exit_code: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_verdict(&result, false, false, TIME_LIMIT, WALL_TIME_LIMIT, true),
+            RunVerdict::Finished
+        );
+    }
+
+    #[test]
+    fn sigxfsz_is_output_limit() {
+        let result = ExecuteResult {
+            exit_code: 128 + libc::SIGXFSZ,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, false),
+            RunVerdict::FileSizeLimitExceeded
+        );
+    }
+
+    #[test]
+    fn pids_limit_only_when_program_failed() {
+        let failed = ExecuteResult {
+            exit_code: 1,
+            pids_limit_exceeded: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&failed, false, false),
+            RunVerdict::PidsLimitExceeded
+        );
+        let passed = ExecuteResult {
+            pids_limit_exceeded: true,
+            ..Default::default()
+        };
+        assert_eq!(verdict(&passed, false, false), RunVerdict::Finished);
+    }
+
+    #[test]
+    fn workdir_limit_before_tle() {
+        let result = ExecuteResult {
+            workdir_size_limit_exceeded: true,
+            killed_by_watchdog: true,
+            cpu_time_cost: TIME_LIMIT,
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&result, false, false),
+            RunVerdict::WorkdirSizeLimitExceeded
+        );
+    }
+}
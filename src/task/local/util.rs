@@ -1,13 +1,59 @@
-use std::{future::Future, sync::Arc, time::UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
+use lazy_static::lazy_static;
 use log::{error, info};
-use serde::Deserialize;
-use tokio::sync::Mutex;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::core::{misc::ResultType, state::AppState};
+use crate::core::{
+    archive::extract_to_dir,
+    config::JudgerConfig,
+    misc::{AsyncStatusUpdater, ResultType},
+    model::LanguageConfig,
+    runner::{ExecuteResult, IoLimit},
+    state::{AppState, LockRegistry, GLOBAL_APP_STATE},
+    util::{dir_size, with_retry},
+};
+
+use super::{
+    api::{JudgeStatusUpdate, ProblemFile, RejudgeBatchProgress},
+    message::truncate_text,
+    model::{ExtraJudgeConfig, ProblemInfo, SubmissionJudgeResult},
+    report::{changed_subtasks, mark_dirty, mark_reported, testcase_count},
+    shadow::record_shadow_status,
+    sql::SQL_PROBLEM_TYPE,
+    sync_state::{read_legacy_lock, remove_legacy_lock, SyncManifest, SyncedFile},
+    timing::timings_json,
+    RESOURCE_MOUNT_DIR,
+};
+// 最多暂存的失败状态数
+const MAX_PENDING_STATUS: usize = 1000;
+const STATUS_REPLAY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 重试后仍然发送失败的评测状态
+#[derive(Clone)]
+struct PendingStatus {
+    judge_result: SubmissionJudgeResult,
+    message: String,
+    extra_status: Option<String>,
+    timings: Option<String>,
+}
+
+lazy_static! {
+    // 键为提交ID，只保留每个提交最新的状态
+    static ref PENDING_STATUS: Mutex<BTreeMap<i64, PendingStatus>> = Mutex::new(BTreeMap::new());
+    // 同一个提交的状态依次发送，重新发送的旧状态不会覆盖新状态
+    static ref STATUS_LOCKS: LockRegistry<i64> = LockRegistry::new();
+}
 
-use super::model::{ProblemInfo, SubmissionInfo, SubmissionJudgeResult};
 pub async fn update_status(
     app: &AppState,
     judge_result: &SubmissionJudgeResult,
@@ -15,143 +61,821 @@ pub async fn update_status(
     extra_status: Option<&str>,
     submission_id: i64,
 ) {
-    let handle = async {
-        let url = app.config.suburl("/api/judge/update");
-        let text_resp = reqwest::Client::new()
-            .post(url)
-            .form(&[
-                ("uuid", &app.config.judger_uuid),
-                (
-                    "judge_result",
-                    &serde_json::to_string(judge_result).unwrap(),
-                ),
-                ("submission_id", &submission_id.to_string()),
-                ("message", &message.to_string()),
-                (
-                    "extra_status",
-                    &extra_status
-                        .map(|v| v.to_string())
-                        .unwrap_or("".to_string()),
-                ),
-            ])
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to send request: {}", e))?
-            .text()
-            .await
-            .map_err(|e| anyhow!("Failed to read response: {}", e))?;
-        #[derive(Deserialize)]
-        struct Local {
-            pub code: i64,
-            pub message: Option<String>,
-        }
-        let des = serde_json::from_str::<Local>(&text_resp)?;
-        if des.code != 0 {
-            return Err(anyhow!(
-                "Received failing message: {}",
-                des.message.unwrap_or("<Not available>".to_string())
-            ));
+    if !app.report_status {
+        info!(
+            "Status of {}: {} {:?}\n{}",
+            submission_id,
+            message,
+            extra_status,
+            serde_json::to_string(judge_result).unwrap_or_default()
+        );
+        if app.config.shadow_mode {
+            record_shadow_status(app, judge_result, message, extra_status, submission_id);
         }
-        return Ok(());
+        return;
+    }
+    // 其他信息超出长度时直接截断，避免服务端拒绝更新
+    let limit = app.config.status_message_limit;
+    let message = if limit != 0 && message.len() > limit {
+        truncate_text(message, limit)
+    } else {
+        message.to_string()
     };
-    let ret: ResultType<()> = handle.await;
+    let timings = timings_json(submission_id);
+    let _lock = STATUS_LOCKS.lock(&submission_id).await;
+    match send_status(
+        app,
+        judge_result,
+        &message,
+        extra_status,
+        timings.as_deref(),
+        submission_id,
+    )
+    .await
+    {
+        Ok(_) => {
+            PENDING_STATUS.lock().unwrap().remove(&submission_id);
+        }
+        Err(e) => {
+            error!("Failed to report status:\n{}", e);
+            let mut pending = PENDING_STATUS.lock().unwrap();
+            if pending.len() >= MAX_PENDING_STATUS && !pending.contains_key(&submission_id) {
+                error!(
+                    "Too many pending status updates, dropping {}",
+                    submission_id
+                );
+                return;
+            }
+            pending.insert(
+                submission_id,
+                PendingStatus {
+                    judge_result: judge_result.clone(),
+                    message,
+                    extra_status: extra_status.map(|v| v.to_string()),
+                    timings,
+                },
+            );
+        }
+    }
+}
+
+async fn send_status(
+    app: &AppState,
+    judge_result: &SubmissionJudgeResult,
+    message: &str,
+    extra_status: Option<&str>,
+    timings: Option<&str>,
+    submission_id: i64,
+) -> ResultType<()> {
+    let threshold = app.config.report_chunk_threshold;
+    if threshold != 0 && testcase_count(judge_result) > threshold {
+        return report_in_chunks(
+            app,
+            judge_result,
+            message,
+            extra_status,
+            timings,
+            submission_id,
+        )
+        .await;
+    }
+    let judge_result_str = serde_json::to_string(judge_result).unwrap();
+    return post_status(
+        app,
+        &judge_result_str,
+        message,
+        extra_status,
+        timings,
+        submission_id,
+        false,
+        "",
+    )
+    .await;
+}
+
+/// 重新发送之前失败的评测状态
+async fn replay_pending_status() -> ResultType<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app = guard.as_ref().ok_or(anyhow!("App state not initialized"))?;
+    let submission_ids = PENDING_STATUS
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<i64>>();
+    for submission_id in submission_ids.into_iter() {
+        let _lock = STATUS_LOCKS.lock(&submission_id).await;
+        // 加锁期间可能已经有更新的状态发送成功
+        let pending = match PENDING_STATUS.lock().unwrap().get(&submission_id) {
+            Some(v) => v.clone(),
+            None => continue,
+        };
+        send_status(
+            app,
+            &pending.judge_result,
+            &pending.message,
+            pending.extra_status.as_deref(),
+            pending.timings.as_deref(),
+            submission_id,
+        )
+        .await?;
+        info!("Replayed status of submission {}", submission_id);
+        PENDING_STATUS.lock().unwrap().remove(&submission_id);
+    }
+    return Ok(());
+}
+
+/// 定期重新发送失败的评测状态
+pub fn spawn_status_replay() {
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(STATUS_REPLAY_INTERVAL);
+        loop {
+            timer.tick().await;
+            if PENDING_STATUS.lock().unwrap().is_empty() {
+                continue;
+            }
+            if let Err(e) = replay_pending_status().await {
+                error!("Failed to replay status: {}", e);
+            }
+        }
+    });
+}
+
+/// 逐个子任务报告评测结果，只发送有变化的子任务，避免测试点很多时每次都序列化和发送整个结果
+async fn report_in_chunks(
+    app: &AppState,
+    judge_result: &SubmissionJudgeResult,
+    message: &str,
+    extra_status: Option<&str>,
+    timings: Option<&str>,
+    submission_id: i64,
+) -> ResultType<()> {
+    let encoding = app.config.report_encoding.as_str();
+    let chunks = changed_subtasks(submission_id, judge_result, encoding).await?;
+    if chunks.is_empty() {
+        // 只有message变化
+        return post_status(
+            app,
+            "{}",
+            message,
+            extra_status,
+            timings,
+            submission_id,
+            true,
+            encoding,
+        )
+        .await;
+    }
+    for (i, chunk) in chunks.iter().enumerate() {
+        if let Err(e) = post_status(
+            app,
+            &chunk.judge_result,
+            message,
+            extra_status,
+            timings,
+            submission_id,
+            true,
+            encoding,
+        )
+        .await
+        {
+            // 未报告成功的子任务在下次报告时重新发送
+            mark_dirty(submission_id, chunks[i..].iter().map(|v| v.name.clone())).await;
+            return Err(e);
+        }
+        mark_reported(submission_id, chunk).await;
+    }
+    return Ok(());
+}
+
+/// partial为true时judge_result只包含部分子任务，服务端将其合并到已有的结果中
+/// encoding为columnar时子任务的测试点逐列编码
+async fn post_status(
+    app: &AppState,
+    judge_result: &str,
+    message: &str,
+    extra_status: Option<&str>,
+    timings: Option<&str>,
+    submission_id: i64,
+    partial: bool,
+    encoding: &str,
+) -> ResultType<()> {
+    let update = JudgeStatusUpdate {
+        submission_id,
+        judge_result,
+        message,
+        extra_status,
+        partial,
+        timings,
+        encoding,
+    };
+    return with_retry(&app.config, "update_judge_status", || {
+        app.api.update_judge_status(&update)
+    })
+    .await
+    .map_err(|e| anyhow!("Failed to update judge status: {}", e));
+}
+
+/// 向服务端报告批量重测的进度
+pub async fn update_batch_progress(
+    app: &AppState,
+    batch_id: &str,
+    total: usize,
+    finished: usize,
+    failed: usize,
+) {
+    if !app.report_status {
+        info!(
+            "Rejudge batch {}: {}/{} finished, {} failed",
+            batch_id, finished, total, failed
+        );
+        return;
+    }
+    let ret = app
+        .api
+        .update_rejudge_batch(&RejudgeBatchProgress {
+            batch_id,
+            total,
+            finished,
+            failed,
+        })
+        .await;
     if let Err(e) = ret {
-        error!("Failed to report status:\n{}", e);
+        error!("Failed to report rejudge batch progress:\n{}", e);
     }
 }
 
-pub async fn get_problem_data(
-    http_client: &reqwest::Client,
+pub async fn get_problem_data(app: &AppState, problem_id: i64) -> ResultType<ProblemInfo> {
+    return with_retry(&app.config, "get_problem_info", || {
+        app.api.get_problem_info(problem_id)
+    })
+    .await
+    .map_err(|e| anyhow!("Failed to get problem info: {}", e));
+}
+/// 优先使用任务中携带的题目信息，无法解析或与题目ID不一致时仍从服务端获取
+pub async fn resolve_problem_data(
     app: &AppState,
-    sub_info: &SubmissionInfo,
+    problem_id: i64,
+    embedded: Option<&serde_json::Value>,
 ) -> ResultType<ProblemInfo> {
-    #[derive(Deserialize)]
-    struct ProblemInfoResp {
-        pub code: i64,
-        pub message: Option<String>,
-        pub data: Option<ProblemInfo>,
-    }
-    let problem_data_pack = serde_json::from_str::<ProblemInfoResp>(
-        &http_client
-            .post(app.config.suburl("/api/judge/get_problem_info"))
-            .form(&[
-                ("uuid", &app.config.judger_uuid),
-                ("problem_id", &sub_info.problem_id.to_string()),
-            ])
-            .send()
+    if let Some(value) = embedded {
+        match serde_json::from_value::<ProblemInfo>(value.clone()) {
+            Ok(v) if v.id == problem_id => {
+                info!("Using problem info carried by the task");
+                return Ok(v);
+            }
+            Ok(v) => error!(
+                "Problem info carried by the task is for problem {}, expected {}, fetching from server",
+                v.id, problem_id
+            ),
+            Err(e) => error!(
+                "Failed to parse problem info carried by the task, fetching from server: {}",
+                e
+            ),
+        }
+    }
+    return get_problem_data(app, problem_id).await;
+}
+/// 评测机配置与题目设置合并后的磁盘IO限制
+pub fn io_limit(config: &JudgerConfig, problem_data: &ProblemInfo) -> IoLimit {
+    let mut ret = config.io_limit();
+    if let Some(v) = problem_data.io_read_bps {
+        ret.read_bps = v;
+    }
+    if let Some(v) = problem_data.io_write_bps {
+        ret.write_bps = v;
+    }
+    return ret;
+}
+/// 用户程序的进程数上限，题目设置优先
+pub fn pids_limit(config: &JudgerConfig, problem_data: &ProblemInfo) -> i64 {
+    return problem_data.pids_limit.unwrap_or(config.pids_limit);
+}
+/// 是否按ICPC模式评测(在第一个未通过的测试点处停止)，评测任务(如比赛设置)中的设置优先于题目
+pub fn icpc_mode(problem_data: &ProblemInfo, extra_config: &ExtraJudgeConfig) -> bool {
+    return extra_config.icpc_mode.unwrap_or(problem_data.icpc_mode);
+}
+/// 评测任务指定的工作目录写入量上限，未指定时使用评测机的配置
+pub fn workdir_size_limit(config: &JudgerConfig, extra_config: &ExtraJudgeConfig) -> i64 {
+    if extra_config.workdir_size_limit > 0 {
+        return extra_config.workdir_size_limit;
+    }
+    return config.workdir_size_limit;
+}
+/// 用户程序使用的镜像与语言定义，题目为该语言指定了运行环境时使用其中的镜像与命令
+pub fn language_runtime(
+    config: &JudgerConfig,
+    problem_data: &ProblemInfo,
+    language: &str,
+    mut lang_config: LanguageConfig,
+) -> (String, LanguageConfig) {
+    let image = runtime_image(config, problem_data, language);
+    let runtime = match problem_data.language_runtimes.get(language) {
+        Some(v) => v,
+        None => return (image, lang_config),
+    };
+    info!(
+        "Using problem runtime for language {}: {:?}",
+        language, runtime
+    );
+    if !runtime.compile.is_empty() {
+        lang_config.compile = runtime.compile.clone();
+    }
+    if !runtime.run.is_empty() {
+        lang_config.run = runtime.run.clone();
+    }
+    return (image, lang_config);
+}
+/// 运行该语言的用户程序使用的镜像，不需要语言定义，可以在同步题目数据前检查
+pub fn runtime_image(config: &JudgerConfig, problem_data: &ProblemInfo, language: &str) -> String {
+    return match problem_data.language_runtimes.get(language) {
+        Some(v) if !v.image.is_empty() => v.image.clone(),
+        Some(_) => config.docker_image.clone(),
+        None if problem_data.problem_type == SQL_PROBLEM_TYPE && !config.sql_image.is_empty() => {
+            config.sql_image.clone()
+        }
+        None => config.docker_image.clone(),
+    };
+}
+/// 运行用户程序时容器的内存限制(字节)，jvm语言额外留出虚拟机本身占用的内存
+pub fn container_memory_limit(
+    config: &JudgerConfig,
+    lang_config: &LanguageConfig,
+    memory_limit: i64,
+) -> i64 {
+    if !lang_config.is_jvm() {
+        return memory_limit;
+    }
+    return (memory_limit as f64 * config.vm_memory_overhead_factor) as i64
+        + config.vm_memory_overhead * 1024 * 1024;
+}
+/// 用户程序是否超出内存限制: 被OOM杀死、内存占用达到容器的限制，或jvm语言的堆空间耗尽
+pub fn memory_limit_exceeded(
+    config: &JudgerConfig,
+    lang_config: &LanguageConfig,
+    run_result: &ExecuteResult,
+    memory_limit: i64,
+) -> bool {
+    return run_result.oom_killed
+        || run_result.memory_cost >= container_memory_limit(config, lang_config, memory_limit)
+        || (lang_config.is_jvm()
+            && run_result.exit_code != 0
+            && run_result.stderr.contains("java.lang.OutOfMemoryError"));
+}
+/// 题目数据中的文件名转换为题目数据目录下的相对路径，拒绝空文件名、绝对路径与..
+pub fn check_problem_file_name(name: &str) -> ResultType<PathBuf> {
+    let path = PathBuf::from(name);
+    if name.is_empty()
+        || path
+            .components()
+            .any(|v| !matches!(v, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!("Illegal file name: {}", name));
+    }
+    return Ok(path);
+}
+/// 用户程序在工作目录下产生的文件名，不能包含路径，不能以.开头
+pub fn check_work_file_name(name: &str) -> ResultType<()> {
+    if name.is_empty() || name.contains('/') || name.contains('\0') || name.starts_with('.') {
+        return Err(anyhow!("Illegal file name: {}", name));
+    }
+    return Ok(());
+}
+/// 题目数据中的文件在宿主机上的绝对路径，解析符号链接后必须仍位于题目数据目录下
+pub fn problem_file_path(this_problem_path: &Path, name: &str) -> ResultType<PathBuf> {
+    let relative = check_problem_file_name(name)?;
+    let base = this_problem_path
+        .canonicalize()
+        .map_err(|e| anyhow!("Failed to resolve problem data dir: {}", e))?;
+    let path = base
+        .join(relative)
+        .canonicalize()
+        .map_err(|e| anyhow!("Missing file {}: {}", name, e))?;
+    if !path.starts_with(&base) {
+        return Err(anyhow!("File outside of problem data dir: {}", name));
+    }
+    return Ok(path);
+}
+/// 题目声明的资源文件的挂载列表 (宿主机路径, 容器内路径)
+pub fn resource_mounts(
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+) -> ResultType<Vec<(String, String)>> {
+    let mut ret = vec![];
+    for name in problem_data.resource_files.iter() {
+        // docker只接受绝对路径
+        let source = problem_file_path(this_problem_path, name)
+            .map_err(|e| anyhow!("Invalid resource file: {}", e))?;
+        ret.push((
+            source
+                .to_str()
+                .ok_or(anyhow!("Invalid resource file path: {}", name))?
+                .to_string(),
+            format!("{}/{}", RESOURCE_MOUNT_DIR, name),
+        ));
+    }
+    return Ok(ret);
+}
+// 下载中断后最多续传的次数
+const DOWNLOAD_RETRY: usize = 3;
+
+/// 计算文件的sha256
+pub async fn file_sha256(path: &Path) -> ResultType<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let count = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buf[..count]);
+    }
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+/// 将文件下载到part_file，part_file已存在时使用Range请求从已下载的位置继续
+async fn download_to_part_file(
+    app: &AppState,
+    problem_id: i64,
+    file: &ProblemFile,
+    part_file: &Path,
+) -> ResultType<()> {
+    let mut downloaded = match tokio::fs::metadata(part_file).await {
+        Ok(v) => v.len(),
+        Err(_) => 0,
+    };
+    if file.size >= 0 && downloaded > file.size as u64 {
+        // 比服务端的文件还大，内容不可信
+        tokio::fs::remove_file(part_file).await.ok();
+        downloaded = 0;
+    }
+    if file.size >= 0 && downloaded > 0 && downloaded == file.size as u64 {
+        // 已经下载完整，由调用者校验
+        return Ok(());
+    }
+    if downloaded > 0 {
+        info!("Resuming {} from {} bytes", file.name, downloaded);
+    }
+    let mut resp = app
+        .api
+        .open_problem_file(problem_id, &file.name, downloaded)
+        .await
+        .map_err(|e| anyhow!("Failed to download {}: {}", file.name, e))?;
+    if resp.status() == 416 && downloaded > 0 {
+        // 已下载的部分与服务端的文件不一致(如文件已被修改)，从头下载
+        info!("Range not satisfiable for {}, restarting", file.name);
+        tokio::fs::remove_file(part_file)
             .await
-            .map_err(|e| anyhow!("Failed to send http request: {}", e))?
-            .text()
+            .map_err(|e| anyhow!("Failed to remove `{}`: {}", part_file.display(), e))?;
+        resp = app
+            .api
+            .open_problem_file(problem_id, &file.name, 0)
             .await
-            .map_err(|e| anyhow!("Failed to receive http response: {}", e))?,
-    )
-    .map_err(|e| anyhow!("Failed to deserialize problem data: {}", e))?;
-    if problem_data_pack.code != 0 {
+            .map_err(|e| anyhow!("Failed to download {}: {}", file.name, e))?;
+    }
+    // 服务端不支持Range时会返回整个文件
+    let append = resp.status() == 206;
+    if !(200..300).contains(&resp.status()) {
+        return Err(anyhow!(
+            "Failed to download {}: HTTP {}",
+            file.name,
+            resp.status()
+        ));
+    }
+    let mut output = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part_file)
+        .await
+        .map_err(|e| anyhow!("Failed to open `{}`: {}", part_file.display(), e))?;
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Failed to download {}: {}", file.name, e))?
+    {
+        output
+            .write_all(&chunk)
+            .await
+            .map_err(|e| anyhow!("Failed to save `{}`: {}", file.name, e))?;
+    }
+    output
+        .flush()
+        .await
+        .map_err(|e| anyhow!("Failed to save `{}`: {}", file.name, e))?;
+    return Ok(());
+}
+
+/// 下载题目文件并校验大小与sha256，校验通过后才会替换data_file
+async fn download_problem_file(
+    app: &AppState,
+    problem_id: i64,
+    file: &ProblemFile,
+    data_file: &Path,
+) -> ResultType<()> {
+    let mut part_file = data_file.as_os_str().to_owned();
+    part_file.push(".part");
+    let part_file = PathBuf::from(part_file);
+    let mut last_error = None;
+    for _ in 0..DOWNLOAD_RETRY {
+        match download_to_part_file(app, problem_id, file, &part_file).await {
+            Ok(_) => {
+                last_error = None;
+                break;
+            }
+            Err(e) => {
+                error!("Failed to download {}, retrying: {}", file.name, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(e) = last_error {
+        return Err(e);
+    }
+    let size = tokio::fs::metadata(&part_file)
+        .await
+        .map_err(|e| anyhow!("Failed to read `{}`: {}", part_file.display(), e))?
+        .len();
+    let checksum_error = if file.size >= 0 && size != file.size as u64 {
+        Some(format!("expected {} bytes, got {}", file.size, size))
+    } else if let Some(expected) = &file.sha256 {
+        let actual = file_sha256(&part_file).await?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            Some(format!("expected sha256 {}, got {}", expected, actual))
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    if let Some(e) = checksum_error {
+        // 已下载的内容不可信，下次从头下载
+        tokio::fs::remove_file(&part_file).await.ok();
+        return Err(anyhow!(
+            "Downloaded file `{}` is corrupted: {}",
+            file.name,
+            e
+        ));
+    }
+    tokio::fs::rename(&part_file, data_file)
+        .await
+        .map_err(|e| anyhow!("Failed to save `{}`: {}", file.name, e))?;
+    return Ok(());
+}
+
+// 整包同步时记录数据包版本的文件，位于题目数据目录下
+const ARCHIVE_VERSION_FILE: &str = ".archive_version";
+// 编译好的SPJ、交互器与grader的缓存目录，位于题目数据目录下
+pub const COMPILE_CACHE_DIR: &str = "spj-cache";
+
+/// 删除题目程序的编译缓存，返回释放的字节数
+/// 逐项持有编译缓存的锁，不影响正在复制缓存的评测
+pub async fn clear_problem_compile_cache(app: &AppState, data_path: &Path) -> ResultType<u64> {
+    let cache_root = data_path.join(COMPILE_CACHE_DIR);
+    if !cache_root.exists() {
+        return Ok(0);
+    }
+    let mut freed = 0;
+    let mut reader = tokio::fs::read_dir(&cache_root)
+        .await
+        .map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?;
+    while let Some(entry) = reader
+        .next_entry()
+        .await
+        .map_err(|e| anyhow!("Failed to read compile cache dir: {}", e))?
+    {
+        // 写入中的临时目录由写入方持有<key>的锁而非其本身的锁，不能删除
+        if entry.file_name().to_string_lossy().ends_with(".tmp") {
+            continue;
+        }
+        let path = entry.path();
+        let _guard = app
+            .compile_cache_locks
+            .lock(&path.to_str().unwrap_or("").to_string())
+            .await;
+        let size = dir_size(&path);
+        tokio::fs::remove_dir_all(&path)
+            .await
+            .map_err(|e| anyhow!("Failed to remove `{}`: {}", path.display(), e))?;
+        freed += size;
+    }
+    info!("Removed compile cache of {}", data_path.display());
+    return Ok(freed);
+}
+
+/// 将数据包边下载边写入path，超过data_archive_max_bytes时停止，返回数据包的sha256
+async fn download_data_archive(app: &AppState, problem_id: i64, path: &Path) -> ResultType<String> {
+    let mut resp = app
+        .api
+        .open_data_archive(problem_id)
+        .await
+        .map_err(|e| anyhow!("Failed to download data archive: {}", e))?;
+    if !(200..300).contains(&resp.status()) {
         return Err(anyhow!(
-            "Failed to get problem info: {}",
-            problem_data_pack.message.unwrap_or(String::from("<>"))
+            "Failed to download data archive: HTTP {}",
+            resp.status()
         ));
     }
-    let problem_data = problem_data_pack
-        .data
-        .ok_or(anyhow!("Missing data field!"))?;
-    return Ok(problem_data);
+    let mut output = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| anyhow!("Failed to create `{}`: {}", path.display(), e))?;
+    let limit = app.config.data_archive_max_bytes;
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Failed to download data archive: {}", e))?
+    {
+        size += chunk.len() as u64;
+        if limit != 0 && size > limit {
+            return Err(anyhow!(
+                "Data archive of problem {} exceeds {} bytes",
+                problem_id,
+                limit
+            ));
+        }
+        hasher.update(&chunk);
+        output
+            .write_all(&chunk)
+            .await
+            .map_err(|e| anyhow!("Failed to save data archive: {}", e))?;
+    }
+    output
+        .flush()
+        .await
+        .map_err(|e| anyhow!("Failed to save data archive: {}", e))?;
+    return Ok(format!("{:x}", hasher.finalize()));
 }
-#[derive(Deserialize)]
-pub struct ProblemFile {
-    pub name: String,
-    pub size: i64,
-    pub last_modified_time: f64,
+
+/// 将数据包解压到一个新建的目录并写入版本文件
+async fn extract_archive_to(
+    archive: &Path,
+    dir: &Path,
+    version: &str,
+    limit: u64,
+    updater: &dyn AsyncStatusUpdater,
+) -> ResultType<()> {
+    if dir.exists() {
+        tokio::fs::remove_dir_all(dir)
+            .await
+            .map_err(|e| anyhow!("Failed to remove stale directory: {}", e))?;
+    }
+    tokio::fs::create_dir(dir)
+        .await
+        .map_err(|e| anyhow!("Failed to create directory: {}", e))?;
+    extract_to_dir(archive, dir, &|_| true, limit, Some(updater))
+        .await
+        .map_err(|e| anyhow!("Failed to extract data archive: {}", e))?;
+    tokio::fs::write(dir.join(ARCHIVE_VERSION_FILE), version)
+        .await
+        .map_err(|e| anyhow!("Failed to write version file: {}", e))?;
+    return Ok(());
 }
-#[derive(Deserialize)]
-pub struct Resp {
-    pub code: i64,
-    pub message: Option<String>,
-    pub data: Option<Vec<ProblemFile>>,
+
+/// 使用renameat2(RENAME_EXCHANGE)原子地交换两个目录
+fn exchange_dirs(a: &Path, b: &Path) -> ResultType<()> {
+    let to_cstring = |path: &Path| {
+        std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| anyhow!("Invalid path: {}", path.display()))
+    };
+    let (a_str, b_str) = (to_cstring(a)?, to_cstring(b)?);
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a_str.as_ptr(),
+            libc::AT_FDCWD,
+            b_str.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret != 0 {
+        return Err(anyhow!(
+            "Failed to exchange `{}` and `{}`: {}",
+            a.display(),
+            b.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    return Ok(());
 }
-#[async_trait::async_trait]
-pub trait AsyncStatusUpdater: Sync + Send {
-    async fn update(&self, message: &str);
+
+/// 下载整个题目的数据包并解压，替换整个题目数据目录
+async fn sync_problem_archive(
+    problem_id: i64,
+    updater: &dyn AsyncStatusUpdater,
+    app: &AppState,
+) -> ResultType<()> {
+    let manifest = app
+        .api
+        .get_data_manifest(problem_id)
+        .await
+        .map_err(|e| anyhow!("Failed to get data manifest: {}", e))?;
+    let _guard = app.file_dir_locks.lock(&problem_id).await;
+    let data_path = app.testdata_dir.join(problem_id.to_string());
+    let local_version = tokio::fs::read_to_string(data_path.join(ARCHIVE_VERSION_FILE))
+        .await
+        .unwrap_or_default();
+    if local_version == manifest.version {
+        info!("Problem {} is up to date: {}", problem_id, manifest.version);
+        return Ok(());
+    }
+    info!(
+        "Downloading data archive of problem {}, version {}",
+        problem_id, manifest.version
+    );
+    updater.update("Downloading data archive..").await;
+    let archive_path = app.testdata_dir.join(format!("{}.archive", problem_id));
+    let result = download_data_archive(app, problem_id, &archive_path).await;
+    let result = match result {
+        Ok(actual) => match &manifest.sha256 {
+            Some(expected) if !actual.eq_ignore_ascii_case(expected) => Err(anyhow!(
+                "Downloaded data archive is corrupted: expected sha256 {}, got {}",
+                expected,
+                actual
+            )),
+            _ => Ok(()),
+        },
+        Err(e) => Err(e),
+    };
+    // 先解压到同级的临时目录，完成后再替换，避免评测读到解压了一半的数据
+    let temp_path = app.testdata_dir.join(format!("{}.extracting", problem_id));
+    let result = match result {
+        Ok(_) => {
+            extract_archive_to(
+                &archive_path,
+                &temp_path,
+                &manifest.version,
+                app.config.archive_max_extracted_bytes,
+                updater,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    tokio::fs::remove_file(&archive_path).await.ok();
+    if let Err(e) = result {
+        tokio::fs::remove_dir_all(&temp_path).await.ok();
+        return Err(e);
+    }
+    // 保留编译好的SPJ与生成器缓存，缓存以源代码的hash为键，数据更新后仍然可用
+    let cache_path = data_path.join(COMPILE_CACHE_DIR);
+    if cache_path.exists() && !temp_path.join(COMPILE_CACHE_DIR).exists() {
+        tokio::fs::rename(&cache_path, temp_path.join(COMPILE_CACHE_DIR))
+            .await
+            .map_err(|e| anyhow!("Failed to move compile cache: {}", e))?;
+    }
+    if data_path.exists() {
+        // 原子地交换两个目录，正在读取题目数据的评测不会看到目录不存在
+        exchange_dirs(&temp_path, &data_path)?;
+        tokio::fs::remove_dir_all(&temp_path)
+            .await
+            .map_err(|e| anyhow!("Failed to remove old problem data: {}", e))?;
+    } else {
+        tokio::fs::rename(&temp_path, &data_path)
+            .await
+            .map_err(|e| anyhow!("Failed to move problem data: {}", e))?;
+    }
+    info!(
+        "Problem {} synced to version {}",
+        problem_id, manifest.version
+    );
+    return Ok(());
 }
+
+/// 同步题目文件，only不为None时只同步其中的文件
 pub fn sync_problem_files<'a>(
     problem_id: i64,
+    only: Option<&'a [String]>,
     updater: &'a dyn AsyncStatusUpdater,
-    http_client: &'a reqwest::Client,
     app: &'a AppState,
 ) -> impl Future<Output = ResultType<()>> + 'a {
     async move {
-        let text = http_client
-            .post(app.config.suburl("/api/judge/get_file_list"))
-            .form(&[
-                ("uuid", app.config.judger_uuid.as_str()),
-                ("problem_id", &problem_id.to_string()),
-            ])
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to send http request when getting file list: {}", e))?
-            .text()
+        if app.config.testdata_sync_mode == "archive" && only.is_none() {
+            match sync_problem_archive(problem_id, updater, app).await {
+                Ok(_) => return Ok(()),
+                Err(e) => error!(
+                    "Failed to sync problem {} by archive, syncing files one by one: {}",
+                    problem_id, e
+                ),
+            }
+        }
+        let mut files = app
+            .api
+            .get_problem_files(problem_id)
             .await
-            .map_err(|e| anyhow!("Failed to read response: {}", e))?;
-        let parsed = serde_json::from_str::<Resp>(&text)
-            .map_err(|e| anyhow!("Failed to deserialize problem file list: {}", e))?;
-        if parsed.code != 0 {
-            return Err(anyhow!(
-                "Failed to get problem file list: {}",
-                parsed.message.unwrap_or(String::from("<>"))
-            ));
+            .map_err(|e| anyhow!("Failed to get problem file list: {}", e))?;
+        if let Some(only) = only {
+            files.retain(|v| only.contains(&v.name));
         }
-        let files = parsed.data.ok_or(anyhow!("Missing files!"))?;
-        let problem_lock = {
-            let mut lock = app.file_dir_locks.lock().await;
-            if !lock.contains_key(&problem_id) {
-                let v = Arc::new(Mutex::new(()));
-                lock.insert(problem_id, v.clone());
-                v
-            } else {
-                lock.get(&problem_id).unwrap().clone()
-            }
-        };
-        let _guard = problem_lock.lock().await;
+        let _guard = app.file_dir_locks.lock(&problem_id).await;
         info!("Syncing problem files for problem {}", problem_id);
         updater.update("Syncing files..").await;
         let data_path = app.testdata_dir.join(problem_id.to_string());
@@ -159,24 +883,21 @@ pub fn sync_problem_files<'a>(
             std::fs::create_dir(&data_path)
                 .map_err(|e| anyhow!("Failed to create problem data dir: {}", e))?;
         }
+        let mut manifest = SyncManifest::load(&data_path).await;
+        let mut updated = false;
         for file in files.into_iter() {
-            let lock_file = data_path.join(format!("{}.lock", file.name));
             let data_file = data_path.join(&file.name);
-            let should_download = if lock_file.exists() {
-                let lock_file_content =
-                    tokio::fs::read_to_string(&lock_file).await.map_err(|e| {
-                        anyhow!(
-                            "Failed to read lock file: {}\n{}",
-                            lock_file.to_str().unwrap_or(""),
-                            e
-                        )
-                    })?;
-                if let Ok(v) = lock_file_content.parse::<f64>() {
+            let should_download = if !data_file.exists() {
+                true
+            } else if let Some(synced) = manifest.files.get(&file.name) {
+                match &file.sha256 {
+                    // 内容没有变化时不需要重新下载
+                    Some(expected) => !synced.sha256.eq_ignore_ascii_case(expected),
                     // 硬盘上的文件太旧了
-                    v < file.last_modified_time
-                } else {
-                    true
+                    None => synced.synced_at < file.last_modified_time,
                 }
+            } else if let Some(v) = read_legacy_lock(&data_path, &file.name).await {
+                v < file.last_modified_time
             } else {
                 true
             };
@@ -185,40 +906,38 @@ pub fn sync_problem_files<'a>(
                 updater
                     .update(&format!("Syncing file: {}", file.name))
                     .await;
-                let data = http_client
-                    .post(app.config.suburl("/api/judge/download_file"))
-                    .form(&[
-                        ("problem_id", problem_id.to_string().as_str()),
-                        ("filename", file.name.as_str()),
-                        ("uuid", &app.config.judger_uuid),
-                    ])
-                    .send()
-                    .await
-                    .map_err(|e| {
-                        anyhow!("Failed to send http request when downloading data: {}", e)
-                    })?
-                    .bytes()
-                    .await
-                    .map_err(|e| anyhow!("Failed to read response: {}", e))?;
-                info!("Downloaded: {}, saving..", file.name);
-                tokio::fs::write(&data_file, data.to_vec())
-                    .await
-                    .map_err(|e| anyhow!("Failed to save `{}`: {}", file.name, e))?;
+                download_problem_file(app, problem_id, &file, &data_file).await?;
                 let current_timestamp = std::time::SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .map_err(|e| anyhow!("Failed to get timestamp: {}", e))?
                     .as_secs();
-                tokio::fs::write(&lock_file, format!("{}", current_timestamp))
+                let sha256 = match &file.sha256 {
+                    Some(v) => v.to_lowercase(),
+                    None => file_sha256(&data_file).await?,
+                };
+                let size = tokio::fs::metadata(&data_file)
                     .await
-                    .map_err(|_| {
-                        anyhow!(
-                            "Failed to write lock file: {}",
-                            lock_file.as_os_str().to_str().unwrap_or("")
-                        )
-                    })?;
+                    .map_err(|e| anyhow!("Failed to read `{}`: {}", data_file.display(), e))?
+                    .len();
+                manifest.files.insert(
+                    file.name.clone(),
+                    SyncedFile {
+                        synced_at: current_timestamp as f64,
+                        size,
+                        sha256,
+                    },
+                );
+                // 每下载完一个文件就保存，中断后已下载的文件不需要重新下载
+                manifest.save(&data_path).await?;
+                remove_legacy_lock(&data_path, &file.name).await?;
+                updated = true;
                 info!("Success: {}", file.name);
             }
         }
+        // 缓存的键已包含源文件的内容，这里只是及时清理不会再被使用的编译产物
+        if updated {
+            clear_problem_compile_cache(app, &data_path).await?;
+        }
         return Ok(());
     }
 }
@@ -0,0 +1,457 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::anyhow;
+use celery::{prelude::TaskError, task::TaskResult};
+use lazy_static::lazy_static;
+use log::{error, info};
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::core::{
+    compare::{special::compile_problem_program_cached, Comparator},
+    logging::{with_log_context, LogContext},
+    misc::{AsyncStatusUpdater, ResultType},
+    model::LanguageConfig,
+    runner::{seccomp::seccomp_security_opt, ExecuteOptions},
+    state::{AppState, GLOBAL_APP_STATE},
+    util::{get_language_config, with_retry},
+    workdir::create_work_dir,
+};
+
+use super::{
+    api::RunStatusUpdate,
+    custom_input::run_status,
+    executor::build_comparator,
+    janitor::use_problem,
+    model::{ExtraJudgeConfig, ProblemInfo, ProblemSubtask, ProblemTestcase},
+    sql::SQL_PROBLEM_TYPE,
+    util::{
+        container_memory_limit, io_limit, language_runtime, memory_limit_exceeded, pids_limit,
+        problem_file_path, resolve_problem_data, resource_mounts, sync_problem_files,
+        COMPILE_CACHE_DIR,
+    },
+};
+
+const SOLUTION_NAME: &str = "solution";
+const VALIDATOR_NAME: &str = "validator";
+
+pub async fn update_validation_status(
+    app: &AppState,
+    run_id: &str,
+    status: &str,
+    message: &str,
+    result: Option<&Value>,
+) {
+    let result = result.map(|v| v.to_string()).unwrap_or_default();
+    if !app.report_status {
+        info!(
+            "Problem validation {}: {} {} {}",
+            run_id, status, message, result
+        );
+        return;
+    }
+    let update = RunStatusUpdate {
+        run_id,
+        status,
+        message,
+        result: &result,
+    };
+    if let Err(e) = with_retry(&app.config, "update_problem_validation", || {
+        app.api.update_problem_validation(&update)
+    })
+    .await
+    {
+        error!("Failed to report problem validation status: {}", e);
+    }
+}
+
+struct ValidationUpdater<'a> {
+    pub run_id: &'a str,
+}
+#[async_trait::async_trait]
+impl<'a> AsyncStatusUpdater for ValidationUpdater<'a> {
+    async fn update(&self, message: &str) {
+        let guard = GLOBAL_APP_STATE.read().await;
+        let app_state_guard = guard.as_ref().unwrap();
+        update_validation_status(app_state_guard, self.run_id, "running", message, None).await;
+    }
+}
+
+/// 在评测环境中检查题目数据: 用校验器检查每个测试点的输入，并用标程在题目的限制下运行、与答案比较
+/// solution为标程的文件名(solution_语言ID.xxx)，validator为校验器的文件名(validator_语言ID.xxx)，可以为空
+/// 校验器从标准输入读取输入数据，退出代码不为0时输入不合法
+#[celery::task(name = "judgers.local.validate_problem")]
+pub async fn validate_problem_handler(
+    run_id: String,
+    problem_id: i64,
+    solution: String,
+    validator: String,
+    extra_config: ExtraJudgeConfig,
+) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app_state_guard = guard.as_ref().unwrap();
+    let _semaphore_guard = app_state_guard.task_count_lock.acquire().await.unwrap();
+    let context = LogContext {
+        run_id: Some(run_id.clone()),
+        ..Default::default()
+    };
+    if let Err(e) = with_log_context(
+        context,
+        handle(
+            app_state_guard,
+            &run_id,
+            problem_id,
+            &solution,
+            &validator,
+            &extra_config,
+        ),
+    )
+    .await
+    {
+        let err_str = e.to_string();
+        update_validation_status(app_state_guard, &run_id, "done", &err_str, None).await;
+        return Err(TaskError::UnexpectedError(err_str));
+    }
+    return Ok(());
+}
+
+/// 题目提供的程序: 从文件名中取出语言ID并下载语言定义
+async fn problem_program_language(
+    app: &AppState,
+    filename: &str,
+    name: &str,
+) -> ResultType<(String, LanguageConfig)> {
+    lazy_static! {
+        static ref PROGRAM_FILENAME_REGEX: Regex = Regex::new(r#"^([a-z]+)_(.+)\..*"#).unwrap();
+    };
+    let lang = PROGRAM_FILENAME_REGEX
+        .captures(filename)
+        .filter(|v| v.get(1).map(|v| v.as_str()) == Some(name))
+        .and_then(|v| v.get(2))
+        .ok_or(anyhow!("Invalid {} filename: {}", name, filename))?
+        .as_str()
+        .to_string();
+    info!("{} language: {}", name, lang);
+    let lang_config = get_language_config(app, &lang)
+        .await
+        .map_err(|e| anyhow!("Failed to get {} language definition: {}", name, e))?;
+    return Ok((lang, lang_config));
+}
+
+/// 编译好的题目程序与其运行环境
+struct CompiledProgram<'a> {
+    working_dir: &'a Path,
+    lang_config: &'a LanguageConfig,
+    image: &'a str,
+    options: &'a ExecuteOptions,
+}
+
+/// 检查测试点使用的程序
+struct ValidationPrograms<'a> {
+    solution: CompiledProgram<'a>,
+    // 未提供校验器时为None
+    validator: Option<CompiledProgram<'a>>,
+    comparator: &'a dyn Comparator,
+}
+
+/// 检查一个测试点，返回检查结果与是否有问题
+async fn validate_testcase(
+    app: &AppState,
+    problem_data: &ProblemInfo,
+    this_problem_path: &Path,
+    extra_config: &ExtraJudgeConfig,
+    subtask: &ProblemSubtask,
+    testcase: &ProblemTestcase,
+    programs: &ValidationPrograms<'_>,
+) -> ResultType<(Value, bool)> {
+    let input_data = tokio::fs::read(this_problem_path.join(&testcase.input))
+        .await
+        .map_err(|e| anyhow!("Failed to read input data: {}, {}", testcase.input, e))?;
+    let mut ret = json!({ "input": testcase.input });
+    let mut failed = false;
+    if let Some(CompiledProgram {
+        working_dir,
+        lang_config,
+        image,
+        options,
+    }) = programs.validator
+    {
+        tokio::fs::write(working_dir.join("input"), &input_data)
+            .await
+            .map_err(|e| anyhow!("Failed to write input data: {}", e))?;
+        let profile = &app.config.trusted_profile;
+        let cmdline = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            lang_config
+                .with_container_limit(&app.config, profile.memory_limit.as_bytes())
+                .run_s(&lang_config.output(VALIDATOR_NAME), "< input"),
+        ];
+        let result = app
+            .runner
+            .execute(
+                image,
+                working_dir.to_str().unwrap_or(""),
+                &cmdline,
+                profile.memory_limit.as_bytes(),
+                profile
+                    .run_time_limit(extra_config.spj_execute_time_limit)
+                    .as_micros(),
+                1024,
+                options,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run validator: {}", e))?;
+        ret["valid"] = json!(result.exit_code == 0);
+        ret["validator_message"] = json!(result.output);
+        failed |= result.exit_code != 0;
+    }
+    let CompiledProgram {
+        working_dir,
+        lang_config,
+        image,
+        options,
+    } = programs.solution;
+    if !testcase.outputs.is_empty() {
+        ret["status"] = json!("skipped");
+        ret["message"] = json!("不支持检查输出多个文件的测试点");
+        return Ok((ret, failed));
+    }
+    let file_io = problem_data.using_file_io == 1;
+    let (input_file, output_file) = if file_io {
+        (
+            problem_data.input_file_name.as_str(),
+            problem_data.output_file_name.as_str(),
+        )
+    } else {
+        ("in", "out")
+    };
+    let output_path = working_dir.join(output_file);
+    if output_path.exists() {
+        tokio::fs::remove_file(&output_path)
+            .await
+            .map_err(|e| anyhow!("Failed to remove stale output: {}", e))?;
+    }
+    tokio::fs::write(working_dir.join(input_file), &input_data)
+        .await
+        .map_err(|e| anyhow!("Failed to write input data: {}", e))?;
+    let time_scale = extra_config.time_scale.unwrap_or(app.time_scale);
+    let time_limit = testcase
+        .time_limit
+        .unwrap_or(subtask.time_limit)
+        .scale(time_scale);
+    let memory_limit = testcase.memory_limit.unwrap_or(subtask.memory_limit);
+    let wall_time_limit = time_limit.scale(app.config.wall_time_limit_factor);
+    // 标程与用户程序相同，jvm语言以内存限制作为堆大小
+    let lang_config = lang_config.with_memory_limit(memory_limit.as_bytes());
+    let container_limit =
+        container_memory_limit(&app.config, &lang_config, memory_limit.as_bytes());
+    let cmdline = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        lang_config.run_s(
+            &lang_config.output(SOLUTION_NAME),
+            &(if file_io {
+                "".to_string()
+            } else {
+                format!("< {} > {}", input_file, output_file)
+            }),
+        ),
+    ];
+    let result = app
+        .runner
+        .execute(
+            image,
+            working_dir.to_str().unwrap_or(""),
+            &cmdline,
+            container_limit,
+            wall_time_limit.as_micros(),
+            1000,
+            options,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to run solution: {}", e))?;
+    let mut status =
+        if memory_limit_exceeded(&app.config, &lang_config, &result, memory_limit.as_bytes()) {
+            "memory_limit_exceed"
+        } else {
+            run_status(
+                &result,
+                container_limit,
+                time_limit.as_micros(),
+                wall_time_limit.as_micros(),
+            )
+        };
+    ret["time_cost"] = json!((result.cpu_time_cost as f64 / 1000.0).ceil() as i64);
+    ret["memory_cost"] = json!(result.memory_cost);
+    if status == "ok" {
+        let user_out = tokio::fs::read(&output_path).await.unwrap_or_default();
+        let answer = tokio::fs::read(this_problem_path.join(&testcase.output))
+            .await
+            .map_err(|e| anyhow!("Failed to read answer data: {}, {}", testcase.output, e))?;
+        let compare_result = programs
+            .comparator
+            .compare(
+                Arc::new(user_out),
+                Arc::new(answer),
+                Arc::new(input_data),
+                testcase.full_score,
+                None,
+            )
+            .await?;
+        if compare_result.score < testcase.full_score {
+            status = "wrong_answer";
+            ret["message"] = json!(compare_result.message);
+        }
+    }
+    ret["status"] = json!(status);
+    return Ok((ret, failed || status != "ok"));
+}
+
+async fn handle(
+    app: &AppState,
+    run_id: &str,
+    problem_id: i64,
+    solution: &str,
+    validator: &str,
+    extra_config: &ExtraJudgeConfig,
+) -> ResultType<()> {
+    info!("Received validation {} of problem {}", run_id, problem_id);
+    let problem_data =
+        resolve_problem_data(app, problem_id, extra_config.problem_info.as_ref()).await?;
+    // 交互题、通信题与SQL题的运行需要题目提供的其他程序或数据
+    if ["interactive", "communication", SQL_PROBLEM_TYPE]
+        .contains(&problem_data.problem_type.as_str())
+        || extra_config.submit_answer
+    {
+        return Err(anyhow!(
+            "Validation is not supported for {} problems",
+            problem_data.problem_type
+        ));
+    }
+    let this_problem_path = app.testdata_dir.join(problem_id.to_string());
+    let _problem_guard = use_problem(problem_id);
+    if extra_config.auto_sync_files {
+        sync_problem_files(problem_id, None, &ValidationUpdater { run_id }, app)
+            .await
+            .map_err(|e| anyhow!("Error occurred when syncing problem files:\n{}", e))?;
+    }
+    let comparator = build_comparator(app, &problem_data, &this_problem_path, extra_config).await?;
+    let cache_root = this_problem_path.join(COMPILE_CACHE_DIR);
+    update_validation_status(app, run_id, "running", "Compiling..", None).await;
+    // 标程与用户程序使用相同的运行环境
+    let (solution_lang, solution_config) =
+        problem_program_language(app, solution, SOLUTION_NAME).await?;
+    let (solution_image, solution_config) =
+        language_runtime(&app.config, &problem_data, &solution_lang, solution_config);
+    let solution_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+    compile_problem_program_cached(
+        app,
+        &*app.runner,
+        &solution_image,
+        &cache_root,
+        solution_dir.path(),
+        &problem_file_path(&this_problem_path, solution)?,
+        &solution_config,
+        SOLUTION_NAME,
+    )
+    .await
+    .map_err(|e| anyhow!("Error occurred when compiling solution:\n{}", e))?;
+    let solution_options = ExecuteOptions {
+        readonly_mounts: resource_mounts(&problem_data, this_problem_path.as_path())?,
+        io_limit: io_limit(&app.config, &problem_data),
+        pids_limit: pids_limit(&app.config, &problem_data),
+        seccomp: seccomp_security_opt(&app.config, &solution_lang)?,
+        ..Default::default()
+    };
+    let validator = if validator.is_empty() {
+        None
+    } else {
+        let (_, validator_config) =
+            problem_program_language(app, validator, VALIDATOR_NAME).await?;
+        let validator_dir = create_work_dir(&app.config)
+            .map_err(|e| anyhow!("Failed to create working directory: {}", e))?;
+        compile_problem_program_cached(
+            app,
+            &*app.runner,
+            &app.config.docker_image,
+            &cache_root,
+            validator_dir.path(),
+            &problem_file_path(&this_problem_path, validator)?,
+            &validator_config,
+            VALIDATOR_NAME,
+        )
+        .await
+        .map_err(|e| anyhow!("Error occurred when compiling validator:\n{}", e))?;
+        Some((validator_dir, validator_config))
+    };
+    // 校验器在受信任的默认环境中运行
+    let validator_options = ExecuteOptions::default();
+    let programs = ValidationPrograms {
+        solution: CompiledProgram {
+            working_dir: solution_dir.path(),
+            lang_config: &solution_config,
+            image: &solution_image,
+            options: &solution_options,
+        },
+        validator: validator.as_ref().map(|(dir, config)| CompiledProgram {
+            working_dir: dir.path(),
+            lang_config: config,
+            image: &app.config.docker_image,
+            options: &validator_options,
+        }),
+        comparator: comparator.as_ref(),
+    };
+    let total = problem_data
+        .subtasks
+        .iter()
+        .map(|v| v.testcases.len())
+        .sum::<usize>();
+    let mut results = vec![];
+    let mut failed = 0;
+    for subtask in problem_data.subtasks.iter() {
+        for (i, testcase) in subtask.testcases.iter().enumerate() {
+            update_validation_status(
+                app,
+                run_id,
+                "running",
+                &format!(
+                    "Checking testcase #{} of {} ({}/{})..",
+                    i + 1,
+                    subtask.name,
+                    results.len() + 1,
+                    total
+                ),
+                None,
+            )
+            .await;
+            let (mut result, testcase_failed) = validate_testcase(
+                app,
+                &problem_data,
+                &this_problem_path,
+                extra_config,
+                subtask,
+                testcase,
+                &programs,
+            )
+            .await?;
+            result["subtask"] = json!(subtask.name);
+            result["index"] = json!(i);
+            if testcase_failed {
+                failed += 1;
+            }
+            results.push(result);
+        }
+    }
+    update_validation_status(
+        app,
+        run_id,
+        "done",
+        &format!("检查完成，{} 个测试点中 {} 个存在问题", total, failed),
+        Some(&json!({ "failed": failed, "testcases": results })),
+    )
+    .await;
+    info!("Problem validation done: {}", run_id);
+    return Ok(());
+}
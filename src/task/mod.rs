@@ -1,2 +1,4 @@
+pub mod admin;
 pub mod local;
 pub mod online_ide;
+pub mod stress;
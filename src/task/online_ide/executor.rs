@@ -1,16 +1,21 @@
 use crate::core::{
+    logging::{with_log_context, LogContext},
     misc::ResultType,
-    runner::docker::execute_in_docker,
+    runner::{seccomp::seccomp_security_opt, ExecuteOptions},
     state::{AppState, GLOBAL_APP_STATE},
-    util::get_language_config,
+    util::{get_language_config, shell_quote},
+    workdir::create_work_dir,
 };
 use anyhow::anyhow;
 use celery::{prelude::TaskError, task::TaskResult};
 use log::info;
-use tempfile::tempdir;
 use tokio::io::AsyncReadExt;
 
-use super::{model::ExtraIDERunConfig, util::update_ide_status};
+use super::{
+    model::ExtraIDERunConfig,
+    samples::{run_samples, CompiledProgram},
+    util::update_ide_status,
+};
 
 #[celery::task(name = "judgers.ide_run.run")]
 pub async fn online_ide_handler(
@@ -23,13 +28,20 @@ pub async fn online_ide_handler(
     let guard = GLOBAL_APP_STATE.read().await;
     let app_state_guard = guard.as_ref().unwrap();
     let _semaphore_guard = app_state_guard.task_count_lock.acquire().await.unwrap();
-    if let Err(e) = handle(
-        lang_id,
-        run_id.clone(),
-        code,
-        input,
-        extra_config,
-        app_state_guard,
+    let context = LogContext {
+        run_id: Some(run_id.clone()),
+        ..Default::default()
+    };
+    if let Err(e) = with_log_context(
+        context,
+        handle(
+            lang_id,
+            run_id.clone(),
+            code,
+            input,
+            extra_config,
+            app_state_guard,
+        ),
     )
     .await
     {
@@ -53,8 +65,8 @@ async fn handle(
 ) -> ResultType<()> {
     info!("Received IDE run task: {}", run_id);
     info!("Extra config: {:#?}", extra_config);
-    let http_client = reqwest::Client::new();
-    let work_dir = tempdir().map_err(|e| anyhow!("Failed to create temporary directory: {}", e))?;
+    let work_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create temporary directory: {}", e))?;
     update_ide_status(
         app,
         &run_id,
@@ -62,12 +74,36 @@ async fn handle(
         "running",
     )
     .await;
-    let lang_config = get_language_config(app, &lang_id, &http_client)
+    let raw_lang_config = get_language_config(app, &lang_id)
         .await
         .map_err(|e| anyhow!("Failed to get language definitions: {}", e))?;
-    update_ide_status(app, &run_id, "Compiling..", "running").await;
+    let lang_config =
+        raw_lang_config.with_container_limit(&app.config, extra_config.memory_limit.as_bytes());
+    let seccomp = seccomp_security_opt(&app.config, &lang_id)?;
     let app_source_file = lang_config.source(IDE_RUN_PROG_NAME);
     let app_output_file = lang_config.output(IDE_RUN_PROG_NAME);
+    for file in extra_config.files.iter() {
+        if file.name.is_empty()
+            || file.name.contains('/')
+            || file.name == "."
+            || file.name == ".."
+            || [
+                app_source_file.as_str(),
+                app_output_file.as_str(),
+                IDE_RUN_INPUT,
+                IDE_RUN_OUTPUT,
+            ]
+            .contains(&file.name.as_str())
+        {
+            return Err(anyhow!("Illegal file name: {}", file.name));
+        }
+        let content = base64::decode(&file.content)
+            .map_err(|e| anyhow!("Failed to decode file {}: {}", file.name, e))?;
+        tokio::fs::write(work_dir.path().join(&file.name), content)
+            .await
+            .map_err(|e| anyhow!("Failed to write file {}: {}", file.name, e))?;
+    }
+    update_ide_status(app, &run_id, "Compiling..", "running").await;
     tokio::fs::write(work_dir.path().join(&app_source_file), &code)
         .await
         .map_err(|e| anyhow!("Failed to write code: {}", e))?;
@@ -77,16 +113,24 @@ async fn handle(
         lang_config.compile_s(&app_source_file, &app_output_file, &extra_config.parameter),
     ];
     info!("Compile with: {:?}", compile_cmdline);
-    let compile_result = execute_in_docker(
-        &app.config.docker_image,
-        work_dir.path().to_str().unwrap(),
-        &compile_cmdline,
-        extra_config.memory_limit * 1024 * 1024,
-        extra_config.time_limit * 1000,
-        extra_config.compile_result_length_limit as usize,
-    )
-    .await
-    .map_err(|e| anyhow!("Failed to compile: {}", e))?;
+    let compile_result = app
+        .runner
+        .execute(
+            &app.config.docker_image,
+            work_dir.path().to_str().unwrap(),
+            &compile_cmdline,
+            extra_config.memory_limit.as_bytes(),
+            extra_config.time_limit.as_micros(),
+            extra_config.compile_result_length_limit as usize,
+            &ExecuteOptions {
+                io_limit: app.config.io_limit(),
+                pids_limit: app.config.pids_limit,
+                seccomp: seccomp.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to compile: {}", e))?;
     info!("Compile result: {:#?}", compile_result);
     if compile_result.exit_code != 0 {
         update_ide_status(
@@ -109,6 +153,22 @@ async fn handle(
         .await;
         return Ok(());
     }
+    let arguments = extra_config
+        .arguments
+        .iter()
+        .map(|v| shell_quote(v))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if let Some(problem_id) = extra_config.problem_id {
+        let program = CompiledProgram {
+            working_dir: work_dir.path(),
+            lang_config: &raw_lang_config,
+            program_file: &app_output_file,
+            arguments: &arguments,
+            seccomp: seccomp.as_deref(),
+        };
+        return run_samples(app, &run_id, &program, &extra_config, problem_id).await;
+    }
     tokio::fs::write(work_dir.path().join(IDE_RUN_INPUT), &input)
         .await
         .map_err(|e| anyhow!("Failed to write user input: {}", e))?;
@@ -118,26 +178,33 @@ async fn handle(
         "-c".to_string(),
         lang_config.run_s(
             &app_output_file,
-            &format!("< {} > {}", IDE_RUN_INPUT, IDE_RUN_OUTPUT),
+            &format!("{} < {} > {}", arguments, IDE_RUN_INPUT, IDE_RUN_OUTPUT),
         ),
     ];
     info!("Run with: {:?}", run_cmdline);
-    let run_result = execute_in_docker(
-        &app.config.docker_image,
-        work_dir.path().to_str().unwrap(),
-        &run_cmdline,
-        extra_config.memory_limit * 1024 * 1024,
-        extra_config.time_limit * 1000,
-        extra_config.result_length_limit as usize,
-    )
-    .await
-    .map_err(|e| anyhow!("Failed to run: {}", e))?;
+    let run_result = app
+        .runner
+        .execute(
+            &app.config.docker_image,
+            work_dir.path().to_str().unwrap(),
+            &run_cmdline,
+            extra_config.memory_limit.as_bytes(),
+            extra_config.time_limit.as_micros(),
+            extra_config.result_length_limit as usize,
+            &ExecuteOptions {
+                io_limit: app.config.io_limit(),
+                pids_limit: app.config.pids_limit,
+                seccomp: seccomp.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to run: {}", e))?;
     let app_stdout = {
         let mut file = tokio::fs::File::open(work_dir.path().join(IDE_RUN_OUTPUT))
             .await
             .map_err(|e| anyhow!("Failed to open output file: {}", e))?;
-        let mut buf = Vec::<u8>::new();
-        buf.resize(extra_config.result_length_limit as usize, 0);
+        let mut buf = vec![0u8; extra_config.result_length_limit as usize];
         let sread = file
             .read(&mut buf[..])
             .await
@@ -1,4 +1,5 @@
 pub mod executor;
 pub mod model;
+pub mod samples;
 pub mod util;
 pub use executor::online_ide_handler;
@@ -1,12 +1,35 @@
 use serde::{Deserialize, Serialize};
 
+use crate::core::limits::{MemoryLimit, TimeLimit};
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ExtraIDERunConfig {
-    pub compile_time_limit: i64,
+    pub compile_time_limit: TimeLimit,
     pub compile_result_length_limit: i64,
-    //milliseconds
-    pub time_limit: i64,
-    pub memory_limit: i64,
+    pub time_limit: TimeLimit,
+    pub memory_limit: MemoryLimit,
     pub result_length_limit: i64,
     pub parameter: String,
+    // 运行时传给程序的命令行参数
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    // 编译前写入工作目录的附加文件
+    #[serde(default)]
+    pub files: Vec<IDEAttachedFile>,
+    // 不为None时不使用input，而是使用该题目的样例测试程序
+    #[serde(default)]
+    pub problem_id: Option<i64>,
+    // 样例所在的子任务，为空时使用第一个子任务
+    #[serde(default)]
+    pub samples_subtask: String,
+    // 使用的样例在子任务中的下标(从0开始)，为空时使用全部
+    #[serde(default)]
+    pub sample_indices: Vec<usize>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct IDEAttachedFile {
+    // 只能是工作目录下的文件名，不能包含路径
+    pub name: String,
+    // in base64
+    pub content: String,
 }
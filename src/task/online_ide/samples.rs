@@ -0,0 +1,232 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::{
+    core::{
+        compare::builtin_comparator,
+        misc::{AsyncStatusUpdater, ResultType},
+        model::LanguageConfig,
+        runner::ExecuteOptions,
+        state::{AppState, GLOBAL_APP_STATE},
+    },
+    task::local::{
+        janitor::use_problem,
+        model::ProblemTestcase,
+        util::{
+            container_memory_limit, get_problem_data, memory_limit_exceeded, sync_problem_files,
+        },
+    },
+};
+
+use super::{model::ExtraIDERunConfig, util::update_ide_status};
+
+struct IDEUpdater<'a> {
+    pub run_id: &'a str,
+}
+#[async_trait::async_trait]
+impl<'a> AsyncStatusUpdater for IDEUpdater<'a> {
+    async fn update(&self, message: &str) {
+        let guard = GLOBAL_APP_STATE.read().await;
+        let app_state_guard = guard.as_ref().unwrap();
+        update_ide_status(app_state_guard, self.run_id, message, "running").await;
+    }
+}
+
+/// 在线IDE编译好的程序
+pub struct CompiledProgram<'a> {
+    pub working_dir: &'a Path,
+    // 未替换{memory_limit}的语言定义
+    pub lang_config: &'a LanguageConfig,
+    pub program_file: &'a str,
+    // 已转义的命令行参数
+    pub arguments: &'a str,
+    pub seccomp: Option<&'a str>,
+}
+
+/// 使用题目的样例运行已编译的程序，逐个报告是否通过
+pub async fn run_samples(
+    app: &AppState,
+    run_id: &str,
+    program: &CompiledProgram<'_>,
+    extra_config: &ExtraIDERunConfig,
+    problem_id: i64,
+) -> ResultType<()> {
+    let CompiledProgram {
+        working_dir,
+        program_file,
+        arguments,
+        seccomp,
+        ..
+    } = *program;
+    let problem_data = get_problem_data(app, problem_id).await?;
+    if !problem_data.spj_filename.is_empty()
+        || problem_data.problem_type == "interactive"
+        || problem_data.problem_type == "communication"
+    {
+        return Err(anyhow!(
+            "Testing against samples is not supported for this problem"
+        ));
+    }
+    let subtask = if extra_config.samples_subtask.is_empty() {
+        problem_data.subtasks.first()
+    } else {
+        problem_data
+            .subtasks
+            .iter()
+            .find(|v| v.name == extra_config.samples_subtask)
+    }
+    .ok_or(anyhow!(
+        "Samples subtask not found: {}",
+        extra_config.samples_subtask
+    ))?;
+    // (下标, 测试点)
+    let testcases = if extra_config.sample_indices.is_empty() {
+        subtask
+            .testcases
+            .iter()
+            .enumerate()
+            .collect::<Vec<(usize, &ProblemTestcase)>>()
+    } else {
+        extra_config
+            .sample_indices
+            .iter()
+            .map(|i| {
+                subtask
+                    .testcases
+                    .get(*i)
+                    .map(|v| (*i, v))
+                    .ok_or(anyhow!("Sample not found: {}", i))
+            })
+            .collect::<ResultType<Vec<(usize, &ProblemTestcase)>>>()?
+    };
+    let files = testcases
+        .iter()
+        .flat_map(|(_, v)| [v.input.clone(), v.output.clone()])
+        .collect::<Vec<String>>();
+    let _problem_guard = use_problem(problem_id);
+    sync_problem_files(problem_id, Some(&files), &IDEUpdater { run_id }, app)
+        .await
+        .map_err(|e| anyhow!("Error occurred when syncing sample files:\n{}", e))?;
+    let this_problem_path = app.testdata_dir.join(problem_id.to_string());
+    let comparator = builtin_comparator(
+        &app.config,
+        &problem_data.comparator,
+        problem_data.case_insensitive,
+    )?;
+    let (input_file, output_file, redirect) = if problem_data.using_file_io == 1 {
+        (
+            problem_data.input_file_name.as_str(),
+            problem_data.output_file_name.as_str(),
+            "".to_string(),
+        )
+    } else {
+        ("in", "out", "< in > out".to_string())
+    };
+    // 与评测相同: jvm语言以内存限制作为堆大小，容器的内存限制另加虚拟机的开销
+    let memory_limit = extra_config.memory_limit.as_bytes();
+    let lang_config = program.lang_config.with_memory_limit(memory_limit);
+    let container_limit = container_memory_limit(&app.config, &lang_config, memory_limit);
+    let run_cmdline = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        lang_config.run_s(program_file, &format!("{} {}", arguments, redirect)),
+    ];
+    info!("Run samples with: {:?}", run_cmdline);
+    // 与评测相同: 时间限制按time_scale缩放后作用于CPU时间，墙上时间超过其wall_time_limit_factor倍时强制结束
+    let time_limit = extra_config.time_limit.scale(app.time_scale);
+    let wall_time_limit = time_limit.scale(app.config.wall_time_limit_factor);
+    let mut passed = 0;
+    let mut messages = vec![];
+    for (index, testcase) in testcases.iter() {
+        update_ide_status(
+            app,
+            run_id,
+            &format!("Running sample #{}..", index + 1),
+            "running",
+        )
+        .await;
+        let output_path = working_dir.join(output_file);
+        if output_path.exists() {
+            tokio::fs::remove_file(&output_path)
+                .await
+                .map_err(|e| anyhow!("Failed to remove stale output: {}", e))?;
+        }
+        let input_data = tokio::fs::read(this_problem_path.join(&testcase.input))
+            .await
+            .map_err(|e| anyhow!("Failed to read input data: {}, {}", testcase.input, e))?;
+        tokio::fs::write(working_dir.join(input_file), &input_data)
+            .await
+            .map_err(|e| anyhow!("Failed to write input data: {}", e))?;
+        let run_result = app
+            .runner
+            .execute(
+                &app.config.docker_image,
+                working_dir.to_str().ok_or(anyhow!("?"))?,
+                &run_cmdline,
+                container_limit,
+                wall_time_limit.as_micros(),
+                extra_config.result_length_limit as usize,
+                &ExecuteOptions {
+                    io_limit: app.config.io_limit(),
+                    pids_limit: app.config.pids_limit,
+                    seccomp: seccomp.map(|v| v.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to run: {}", e))?;
+        let verdict = if memory_limit_exceeded(&app.config, &lang_config, &run_result, memory_limit)
+        {
+            "超出内存限制".to_string()
+        } else if run_result.killed_by_watchdog
+            || run_result.cpu_time_cost >= time_limit.as_micros()
+        {
+            "超出时间限制".to_string()
+        } else if run_result.exit_code != 0 {
+            format!("运行错误，退出代码: {}", run_result.exit_code)
+        } else {
+            let user_out = tokio::fs::read(&output_path).await.unwrap_or_default();
+            let answer_data = tokio::fs::read(this_problem_path.join(&testcase.output))
+                .await
+                .map_err(|e| anyhow!("Failed to read answer data: {}, {}", testcase.output, e))?;
+            let result = comparator
+                .compare(
+                    Arc::new(user_out),
+                    Arc::new(answer_data),
+                    Arc::new(input_data),
+                    testcase.full_score,
+                    None,
+                )
+                .await?;
+            if result.score >= testcase.full_score {
+                passed += 1;
+                "通过".to_string()
+            } else {
+                format!("答案错误\n{}", result.message)
+            }
+        };
+        messages.push(format!(
+            "样例 #{}: {} (时间占用: {} ms, 内存占用: {} KB)",
+            index + 1,
+            verdict,
+            run_result.time_cost / 1000,
+            run_result.memory_cost / 1024
+        ));
+    }
+    update_ide_status(
+        app,
+        run_id,
+        &format!(
+            "样例测试完成！通过 {}/{}\n{}",
+            passed,
+            testcases.len(),
+            messages.join("\n")
+        ),
+        "done",
+    )
+    .await;
+    info!("Task done: {}", run_id);
+    return Ok(());
+}
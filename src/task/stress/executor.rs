@@ -0,0 +1,393 @@
+use std::{path::Path, sync::Arc};
+
+use crate::core::{
+    compare::builtin_comparator,
+    limits::COMPILE_MEMORY_LIMIT,
+    logging::{with_log_context, LogContext},
+    misc::ResultType,
+    model::LanguageConfig,
+    runner::{open_session, seccomp::seccomp_security_opt, ExecuteOptions, Session},
+    state::{AppState, GLOBAL_APP_STATE},
+    util::get_language_config,
+    workdir::create_work_dir,
+};
+use anyhow::anyhow;
+use celery::{prelude::TaskError, task::TaskResult};
+use log::{error, info};
+
+use super::{
+    model::{ExtraStressConfig, StressCounterexample, StressProgram},
+    util::update_stress_status,
+};
+
+#[celery::task(name = "judgers.stress.run")]
+pub async fn stress_test_handler(
+    run_id: String,
+    solution: StressProgram,
+    reference: StressProgram,
+    generator: StressProgram,
+    checker: Option<StressProgram>,
+    extra_config: ExtraStressConfig,
+) -> TaskResult<()> {
+    let guard = GLOBAL_APP_STATE.read().await;
+    let app_state_guard = guard.as_ref().unwrap();
+    let _semaphore_guard = app_state_guard.task_count_lock.acquire().await.unwrap();
+    let context = LogContext {
+        run_id: Some(run_id.clone()),
+        ..Default::default()
+    };
+    if let Err(e) = with_log_context(
+        context,
+        handle(
+            run_id.clone(),
+            solution,
+            reference,
+            generator,
+            checker,
+            extra_config,
+            app_state_guard,
+        ),
+    )
+    .await
+    {
+        let err_str = e.to_string();
+        update_stress_status(app_state_guard, &run_id, &err_str, "done", None).await;
+        return Err(TaskError::UnexpectedError(err_str.clone()));
+    }
+    return Ok(());
+}
+
+const SOLUTION_PROG_NAME: &str = "solution";
+const REFERENCE_PROG_NAME: &str = "reference";
+const GENERATOR_PROG_NAME: &str = "generator";
+const CHECKER_PROG_NAME: &str = "checker";
+const STRESS_INPUT: &str = "in";
+const STRESS_USER_OUTPUT: &str = "user_out";
+const STRESS_ANSWER: &str = "answer";
+
+/// 编译一个程序，编译失败时返回展示给用户的信息
+async fn compile(
+    session: &dyn Session,
+    work_dir: &Path,
+    lang_config: &LanguageConfig,
+    program: &StressProgram,
+    name: &str,
+    extra_config: &ExtraStressConfig,
+) -> ResultType<Option<String>> {
+    let source_file = lang_config.source(name);
+    let output_file = lang_config.output(name);
+    tokio::fs::write(work_dir.join(&source_file), &program.code)
+        .await
+        .map_err(|e| anyhow!("Failed to write code: {}", e))?;
+    let compile_cmdline = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        lang_config.compile_s(&source_file, &output_file, &program.parameter),
+    ];
+    info!("Compile {} with: {:?}", name, compile_cmdline);
+    let compile_result = session
+        .execute(
+            &compile_cmdline,
+            COMPILE_MEMORY_LIMIT.as_bytes(),
+            extra_config.compile_time_limit.as_micros(),
+            extra_config.result_length_limit as usize,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to compile {}: {}", name, e))?;
+    if compile_result.exit_code != 0 {
+        return Ok(Some(format!(
+            "{} 编译失败！\n{}{}",
+            name,
+            compile_result.output,
+            if compile_result.output_truncated {
+                "[已截断]"
+            } else {
+                ""
+            }
+        )));
+    }
+    return Ok(None);
+}
+
+/// 运行一个程序，超出限制或非零退出时返回原因
+async fn run(
+    session: &dyn Session,
+    lang_config: &LanguageConfig,
+    name: &str,
+    redirect: &str,
+    extra_config: &ExtraStressConfig,
+    app: &AppState,
+) -> ResultType<Option<String>> {
+    let scaled_time = extra_config.time_limit.scale(app.time_scale);
+    let wall_time_limit = scaled_time.scale(app.config.wall_time_limit_factor);
+    let cmdline = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        lang_config
+            .with_container_limit(&app.config, extra_config.memory_limit.as_bytes())
+            .run_s(&lang_config.output(name), redirect),
+    ];
+    let result = session
+        .execute(
+            &cmdline,
+            extra_config.memory_limit.as_bytes(),
+            wall_time_limit.as_micros(),
+            extra_config.result_length_limit as usize,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to run {}: {}", name, e))?;
+    if result.memory_cost >= extra_config.memory_limit.as_bytes() {
+        return Ok(Some("超出内存限制".to_string()));
+    } else if result.killed_by_watchdog
+        || result.cpu_time_cost >= scaled_time.as_micros()
+        || result.time_cost >= wall_time_limit.as_micros()
+    {
+        return Ok(Some("超出时间限制".to_string()));
+    } else if result.exit_code != 0 {
+        return Ok(Some(format!(
+            "退出代码: {}\n{}",
+            result.exit_code, result.output
+        )));
+    }
+    return Ok(None);
+}
+
+async fn read_file(path: &Path) -> ResultType<Vec<u8>> {
+    return tokio::fs::read(path)
+        .await
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e));
+}
+
+/// 截断为最多limit个字符
+fn truncate(data: &[u8], limit: usize) -> String {
+    let text = String::from_utf8_lossy(data);
+    if text.chars().count() > limit {
+        return format!("{}[已截断]", String::from_iter(text.chars().take(limit)));
+    }
+    return text.to_string();
+}
+
+async fn handle(
+    run_id: String,
+    solution: StressProgram,
+    reference: StressProgram,
+    generator: StressProgram,
+    checker: Option<StressProgram>,
+    extra_config: ExtraStressConfig,
+    app: &AppState,
+) -> ResultType<()> {
+    info!("Received stress test task: {}", run_id);
+    info!("Extra config: {:#?}", extra_config);
+    if extra_config.result_length_limit < 0 {
+        return Err(anyhow!(
+            "Invalid result length limit: {}",
+            extra_config.result_length_limit
+        ));
+    }
+    let work_dir = create_work_dir(&app.config)
+        .map_err(|e| anyhow!("Failed to create temporary directory: {}", e))?;
+    update_stress_status(
+        app,
+        &run_id,
+        "Downloading language definitions..",
+        "running",
+        None,
+    )
+    .await;
+    let mut programs = vec![
+        (SOLUTION_PROG_NAME, &solution),
+        (REFERENCE_PROG_NAME, &reference),
+        (GENERATOR_PROG_NAME, &generator),
+    ];
+    if let Some(v) = checker.as_ref() {
+        programs.push((CHECKER_PROG_NAME, v));
+    }
+    let mut lang_configs = vec![];
+    for (name, program) in programs.iter() {
+        let lang_config = get_language_config(app, &program.lang_id)
+            .await
+            .map_err(|e| anyhow!("Failed to get language definitions of {}: {}", name, e))?;
+        lang_configs.push(lang_config);
+    }
+    let session = open_session(
+        &app.runner,
+        &app.config.docker_image,
+        work_dir.path().to_str().ok_or(anyhow!("?"))?,
+        &ExecuteOptions {
+            detect_suspicious: app.config.detect_suspicious_processes,
+            io_limit: app.config.io_limit(),
+            pids_limit: app.config.pids_limit,
+            // 多个程序在同一个沙箱中运行，使用全局的seccomp配置
+            seccomp: seccomp_security_opt(&app.config, "")?,
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|e| anyhow!("Failed to create sandbox session: {}", e))?;
+    let result = run_rounds(
+        &run_id,
+        &*session,
+        work_dir.path(),
+        &programs,
+        &lang_configs,
+        &extra_config,
+        app,
+    )
+    .await;
+    if let Err(e) = session.close().await {
+        error!("Failed to close sandbox session: {}", e);
+    }
+    result?;
+    info!("Task done: {}", run_id);
+    return Ok(());
+}
+
+async fn run_rounds(
+    run_id: &str,
+    session: &dyn Session,
+    work_dir: &Path,
+    programs: &[(&str, &StressProgram)],
+    lang_configs: &[LanguageConfig],
+    extra_config: &ExtraStressConfig,
+    app: &AppState,
+) -> ResultType<()> {
+    update_stress_status(app, run_id, "Compiling..", "running", None).await;
+    for ((name, program), lang_config) in programs.iter().zip(lang_configs.iter()) {
+        let lang_config =
+            lang_config.with_container_limit(&app.config, COMPILE_MEMORY_LIMIT.as_bytes());
+        if let Some(message) =
+            compile(session, work_dir, &lang_config, program, name, extra_config).await?
+        {
+            update_stress_status(app, run_id, &message, "done", None).await;
+            return Ok(());
+        }
+    }
+    let solution_lang = &lang_configs[0];
+    let reference_lang = &lang_configs[1];
+    let generator_lang = &lang_configs[2];
+    let checker_lang = lang_configs.get(3);
+    let comparator = builtin_comparator(&app.config, "line", false)?;
+    for round in 0..extra_config.rounds {
+        let seed = extra_config.seed + round;
+        update_stress_status(
+            app,
+            run_id,
+            &format!("对拍: 第 {} 轮", round + 1),
+            "running",
+            None,
+        )
+        .await;
+        if let Some(message) = run(
+            session,
+            generator_lang,
+            GENERATOR_PROG_NAME,
+            &format!("{} > {}", seed, STRESS_INPUT),
+            extra_config,
+            app,
+        )
+        .await?
+        {
+            update_stress_status(
+                app,
+                run_id,
+                &format!("数据生成器运行失败 (种子 {})\n{}", seed, message),
+                "done",
+                None,
+            )
+            .await;
+            return Ok(());
+        }
+        if let Some(message) = run(
+            session,
+            reference_lang,
+            REFERENCE_PROG_NAME,
+            &format!("< {} > {}", STRESS_INPUT, STRESS_ANSWER),
+            extra_config,
+            app,
+        )
+        .await?
+        {
+            update_stress_status(
+                app,
+                run_id,
+                &format!("标准程序运行失败 (种子 {})\n{}", seed, message),
+                "done",
+                None,
+            )
+            .await;
+            return Ok(());
+        }
+        let mut mismatch = run(
+            session,
+            solution_lang,
+            SOLUTION_PROG_NAME,
+            &format!("< {} > {}", STRESS_INPUT, STRESS_USER_OUTPUT),
+            extra_config,
+            app,
+        )
+        .await?;
+        let input = read_file(&work_dir.join(STRESS_INPUT)).await?;
+        let user_output = read_file(&work_dir.join(STRESS_USER_OUTPUT))
+            .await
+            .unwrap_or_default();
+        let answer = read_file(&work_dir.join(STRESS_ANSWER)).await?;
+        if mismatch.is_none() {
+            // 有checker时由checker判断，约定与testlib相同: checker input output answer，退出代码非零表示错误
+            mismatch = if let Some(lang_config) = checker_lang {
+                run(
+                    session,
+                    lang_config,
+                    CHECKER_PROG_NAME,
+                    &format!("{} {} {}", STRESS_INPUT, STRESS_USER_OUTPUT, STRESS_ANSWER),
+                    extra_config,
+                    app,
+                )
+                .await?
+            } else {
+                let compare_result = comparator
+                    .compare(
+                        Arc::new(user_output.clone()),
+                        Arc::new(answer.clone()),
+                        Arc::new(input.clone()),
+                        100,
+                        None,
+                    )
+                    .await?;
+                if compare_result.score != 100 {
+                    Some(compare_result.message)
+                } else {
+                    None
+                }
+            };
+        }
+        if let Some(message) = mismatch {
+            info!("Counterexample found at round {}, seed {}", round + 1, seed);
+            let counterexample = StressCounterexample {
+                round: round + 1,
+                seed,
+                input: truncate(&input, extra_config.result_length_limit as usize),
+                user_output: truncate(&user_output, extra_config.result_length_limit as usize),
+                answer: truncate(&answer, extra_config.result_length_limit as usize),
+                message,
+            };
+            update_stress_status(
+                app,
+                run_id,
+                &format!("第 {} 轮发现反例 (种子 {})", round + 1, seed),
+                "done",
+                Some(&counterexample),
+            )
+            .await;
+            return Ok(());
+        }
+    }
+    update_stress_status(
+        app,
+        run_id,
+        &format!("对拍 {} 轮，未发现反例", extra_config.rounds),
+        "done",
+        None,
+    )
+    .await;
+    return Ok(());
+}
@@ -0,0 +1,4 @@
+pub mod executor;
+pub mod model;
+pub mod util;
+pub use executor::stress_test_handler;
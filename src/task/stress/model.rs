@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::limits::{MemoryLimit, TimeLimit};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StressProgram {
+    pub lang_id: String,
+    pub code: String,
+    // 编译参数
+    #[serde(default)]
+    pub parameter: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExtraStressConfig {
+    // 对拍轮数
+    pub rounds: i64,
+    // 第i轮时传给数据生成器的随机种子为 seed + i
+    #[serde(default)]
+    pub seed: i64,
+    pub compile_time_limit: TimeLimit,
+    // 各程序单次运行的限制
+    pub time_limit: TimeLimit,
+    pub memory_limit: MemoryLimit,
+    // 上报的输入/输出的最大长度
+    pub result_length_limit: i64,
+}
+
+/// 找到的反例
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StressCounterexample {
+    pub round: i64,
+    pub seed: i64,
+    pub input: String,
+    pub user_output: String,
+    pub answer: String,
+    pub message: String,
+}
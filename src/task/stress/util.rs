@@ -0,0 +1,58 @@
+use crate::core::{
+    api::{ApiRequest, ApiResult, Hj3ApiClient},
+    state::AppState,
+};
+use log::{error, info};
+
+use super::model::StressCounterexample;
+
+impl Hj3ApiClient {
+    pub async fn update_stress_run(
+        &self,
+        run_id: &str,
+        message: &str,
+        status: &str,
+        // JSON
+        counterexample: &str,
+    ) -> ApiResult<()> {
+        return self
+            .call_empty(
+                ApiRequest::new("stress/update")
+                    .param("run_id", run_id)
+                    .param("message", message)
+                    .param("status", status)
+                    .param("counterexample", counterexample),
+            )
+            .await;
+    }
+}
+
+pub async fn update_stress_status(
+    app: &AppState,
+    run_id: &str,
+    message: &str,
+    status: &str,
+    counterexample: Option<&StressCounterexample>,
+) {
+    if !app.report_status {
+        info!("Stress run {}: {} {}", run_id, status, message);
+        return;
+    }
+    let counterexample_str = match counterexample {
+        Some(v) => match serde_json::to_string(v) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to serialize counterexample: {}", e);
+                return;
+            }
+        },
+        None => "null".to_string(),
+    };
+    if let Err(e) = app
+        .api
+        .update_stress_run(run_id, message, status, &counterexample_str)
+        .await
+    {
+        error!("Failed to report stress test status: {}", e);
+    }
+}
@@ -0,0 +1,53 @@
+//! 通过评测机可执行文件的compare-helper命令运行内置比较器
+
+use std::sync::Arc;
+
+use hellojudge3_judger::core::{compare::builtin_comparator, config::JudgerConfig};
+
+fn isolated_config() -> JudgerConfig {
+    let mut config = JudgerConfig::default();
+    config.isolated_compare = true;
+    config.compare_helper_path = env!("CARGO_BIN_EXE_hellojudge3-judger").to_string();
+    return config;
+}
+
+async fn compare(name: &str, user_out: &[u8], answer: &[u8]) -> (i64, String) {
+    let comparator = builtin_comparator(&isolated_config(), name, false).unwrap();
+    let result = comparator
+        .compare(
+            Arc::new(user_out.to_vec()),
+            Arc::new(answer.to_vec()),
+            Arc::new(vec![]),
+            100,
+            None,
+        )
+        .await
+        .unwrap();
+    return (result.score, result.message);
+}
+
+#[tokio::test]
+async fn line_compare_in_helper() {
+    assert_eq!(compare("line", b"1 2\n3\n", b"1 2\n3").await.0, 100);
+    let (score, message) = compare("line", b"1 2\n4\n", b"1 2\n3\n").await;
+    assert_eq!(score, 0);
+    assert!(!message.is_empty());
+}
+
+#[tokio::test]
+async fn token_compare_in_helper() {
+    assert_eq!(compare("token", b"1   2\n\n3", b"1 2 3\n").await.0, 100);
+    assert_eq!(compare("token", b"1 2", b"1 2 3").await.0, 0);
+}
+
+#[tokio::test]
+async fn empty_outputs_in_helper() {
+    assert_eq!(compare("line", b"", b"").await.0, 100);
+}
+
+#[tokio::test]
+async fn unresolved_helper_path() {
+    let mut config = isolated_config();
+    config.compare_helper_path = "".to_string();
+    assert!(builtin_comparator(&config, "line", false).is_err());
+}